@@ -0,0 +1,12 @@
+//! Builds static sites from XML templates and pages. The `oeuvre` executable is a thin
+//! command line over this library, which programs can also use directly, for example to
+//! render their own oeuvre elements with a `RenderPlugin`.
+
+pub mod minidom;
+#[cfg(feature = "serve")]
+pub mod serve;
+mod site;
+
+pub use site::{
+  diagnostics_json, validate_file, RenderContext, RenderPlugin, Severity, Site, SiteConfig,
+};
@@ -1,47 +1,304 @@
-mod minidom;
-mod site;
-
-use crate::site::{Site, SiteConfig};
+#[cfg(feature = "serve")]
+use oeuvre::serve;
+use oeuvre::{diagnostics_json, validate_file, Severity, Site, SiteConfig};
 use anyhow::{bail, Result};
-use log::info;
+#[cfg(feature = "serve")]
+use clap::Subcommand;
+use clap::Parser;
+use log::{error, info, warn, LevelFilter};
 use path_clean::PathClean;
 use simple_logger::SimpleLogger;
 use std::env;
 use std::path::Path;
 use std::path::PathBuf;
 
+/// The command line arguments.
+#[derive(Parser)]
+#[command(version, about = "Builds a static site from XML templates and pages")]
+struct Args {
+  /// The path to the config file or its directory
+  #[arg(value_name = "CONFIG", conflicts_with = "config")]
+  input_path: Option<String>,
+  /// The path to the config file or its directory, given as an option
+  #[arg(global = true, long, value_name = "PATH")]
+  config: Option<String>,
+  /// The output path of a page to write as a self-contained bundle instead of building the site
+  #[arg(long, value_name = "PAGE")]
+  bundle: Option<String>,
+  /// The path of a zip archive or gzipped tarball to write the output to instead of the output
+  /// directory
+  #[arg(long, value_name = "PATH", value_parser = working_dir_path)]
+  archive: Option<PathBuf>,
+  /// The source path of a page to render alone instead of building the whole site
+  #[arg(long, value_name = "PAGE")]
+  only: Option<String>,
+  /// The name of a template to render the `--only` page with instead of its own
+  #[arg(long, value_name = "NAME", requires = "only")]
+  template: Option<String>,
+  /// Check the site for problems instead of building it
+  #[arg(long)]
+  check: bool,
+  /// The path of a single source file to validate instead of building the site
+  #[arg(long, value_name = "FILE")]
+  validate: Option<PathBuf>,
+  /// Print the site's diagnostics as JSON instead of building it
+  #[arg(long)]
+  diagnostics_json: bool,
+  /// Print the build hash instead of building the site
+  #[arg(long)]
+  print_hash: bool,
+  /// Print a config file with every option set to its default instead of building the site
+  #[arg(long)]
+  print_default_config: bool,
+  /// The locale to use for pages that don't name one, overriding the config file's
+  #[arg(global = true, long, value_name = "NAME")]
+  locale: Option<String>,
+  /// A global that overrides the config file's, which may be given more than once
+  #[arg(global = true, long = "define", value_name = "NAME=VALUE", value_parser = parse_define)]
+  defines: Vec<(String, String)>,
+  /// The output directory, relative to the input directory, overriding the config file's
+  #[arg(global = true, long = "output", value_name = "DIR")]
+  output_dir: Option<String>,
+  /// Log debugging details as well as the usual progress
+  #[arg(global = true, long, conflicts_with = "quiet")]
+  verbose: bool,
+  /// Log only warnings and errors
+  #[arg(global = true, long)]
+  quiet: bool,
+  /// Fail the build on any problem found while rendering, overriding the config file
+  #[arg(global = true, long)]
+  strict: bool,
+  /// Empty the output directory before writing, overriding the config file
+  #[arg(global = true, long)]
+  clean: bool,
+  /// Render the site and log the files it would write, without writing them
+  #[arg(long, conflicts_with_all = ["only", "bundle", "archive"])]
+  dry_run: bool,
+  /// A command to run instead of building the site once
+  #[cfg(feature = "serve")]
+  #[command(subcommand)]
+  command: Option<Command>,
+}
+
+/// The subcommands, which take the config path and overrides of the command they follow.
+#[cfg(feature = "serve")]
+#[derive(Subcommand)]
+enum Command {
+  /// Serve the site and rebuild it when its files change
+  Serve {
+    /// The port to serve the site on, or the first of those to try if it is in use
+    #[arg(long, default_value_t = 8000)]
+    port: u16,
+  },
+}
+
 fn main() -> Result<()> {
-  SimpleLogger::new().init().unwrap();
+  let args = parse_args(env::args()).unwrap_or_else(|err| err.exit());
+  if args.print_default_config {
+    print!("{}", SiteConfig::default_toml());
+    return Ok(());
+  }
+  // Only errors are logged when printing the hash, so that the hash is all that's printed,
+  // and nothing is logged when printing diagnostics, so that the JSON is.
+  let level = if args.diagnostics_json {
+    LevelFilter::Off
+  } else if args.print_hash {
+    LevelFilter::Error
+  } else if args.quiet {
+    LevelFilter::Warn
+  } else if args.verbose {
+    LevelFilter::Debug
+  } else {
+    LevelFilter::Info
+  };
+  SimpleLogger::new().with_level(level).init().unwrap();
+  if let Some(path) = args.validate {
+    info!("Validating {}", path.display());
+    let diagnostics = validate_file(&path);
+    for diagnostic in &diagnostics {
+      match diagnostic.severity {
+        Severity::Error => error!("- {}", diagnostic),
+        Severity::Warning => warn!("- {}", diagnostic),
+      }
+    }
+    let error_count = diagnostics
+      .iter()
+      .filter(|diagnostic| diagnostic.severity == Severity::Error)
+      .count();
+    if error_count > 0 {
+      bail!("Found {} error(s)", error_count);
+    }
+    info!("No errors found");
+    return Ok(());
+  }
   info!("Looking for config file");
-  let config_path = find_config_file(&env::args().nth(1))?;
+  let config_path = find_config_file(&args.input_path, &env::current_dir()?)?;
   let config_dir = config_path.parent().unwrap();
+  #[cfg(feature = "serve")]
+  if let Some(Command::Serve { port }) = args.command {
+    return serve::serve(&config_path, port, |config| apply_overrides(config, &args));
+  }
   info!("Reading config file {}", config_path.display());
-  let config = SiteConfig::load(&config_path)?;
+  let mut config = SiteConfig::load(&config_path)?;
+  apply_overrides(&mut config, &args);
+
+  let mut site = Site::load(config, config_dir)?;
+  if args.print_hash {
+    println!("{}", site.build_hash);
+    return Ok(());
+  }
+  if args.diagnostics_json {
+    let diagnostics = site.check();
+    print!("{}", diagnostics_json(&diagnostics));
+    let error_count = diagnostics
+      .iter()
+      .filter(|diagnostic| diagnostic.severity == Severity::Error)
+      .count();
+    if error_count > 0 {
+      bail!("Found {} error(s)", error_count);
+    }
+    return Ok(());
+  }
+  if args.check {
+    info!("Checking site");
+    let diagnostics = site.check();
+    for diagnostic in &diagnostics {
+      error!("- {}", diagnostic);
+    }
+    if !diagnostics.is_empty() {
+      bail!("Found {} problem(s)", diagnostics.len());
+    }
+    info!("No problems found");
+    return Ok(());
+  }
+  if let Some(source_path) = args.only {
+    info!("Rendering page {}", source_path);
+    let report = site.render_only(Path::new(&source_path), args.template.as_deref())?;
+    info!(
+      "Built page {} with {} render issue(s)",
+      source_path, report.issue_count
+    );
+    return Ok(());
+  }
+  match args.bundle {
+    Some(page_path) => {
+      info!("Bundling page {}", page_path);
+      let bundle_path = site.write_bundle(&page_path)?;
+      info!("Wrote bundle {}", bundle_path.display());
+      Ok(())
+    }
+    None => {
+      let report = match args.archive {
+        Some(archive_path) => {
+          info!("Writing archive {}", archive_path.display());
+          site.write_archive(&archive_path)?
+        }
+        None if args.dry_run => {
+          info!("Rendering site without writing");
+          site.dry_run()?
+        }
+        None => site.render()?,
+      };
+      if !report.largest_outputs.is_empty() {
+        info!("Largest output files");
+        for (path, size) in &report.largest_outputs {
+          info!("- {} ({} bytes)", path, size);
+        }
+      }
+      for page_size in &report.over_budget {
+        warn!("{}", site.budget_warning(page_size));
+      }
+      if args.dry_run {
+        if report.issue_count > 0 {
+          bail!("Dry run found {} render issue(s)", report.issue_count);
+        }
+        info!("Dry run of site {} found no render issues", report.hash);
+        return Ok(());
+      }
+      info!(
+        "Built site {} with {} render issue(s)",
+        report.hash, report.issue_count
+      );
+      Ok(())
+    }
+  }
+}
 
-  let site = Site::load(config, config_dir)?;
-  site.render()
+/// Parses the command line arguments, starting with the executable name, and folds a
+/// config path given as `--config <path>` into the input path.
+fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<Args, clap::Error> {
+  let mut parsed = Args::try_parse_from(args)?;
+  if let Some(config_path) = parsed.config.take() {
+    parsed.input_path = Some(config_path);
+  }
+  Ok(parsed)
+}
+
+/// Parses a `--define` value in the form `<name>=<value>`, where the value may be empty.
+fn parse_define(define: &str) -> Result<(String, String), String> {
+  match define.split_once('=') {
+    Some((name, value)) if !name.trim().is_empty() => {
+      Ok((name.trim().to_string(), value.to_string()))
+    }
+    _ => Err("expected a value in the form <name>=<value>".to_string()),
+  }
+}
+
+/// Resolves `path` against the working directory, which loading the site changes.
+fn working_dir_path(path: &str) -> Result<PathBuf, String> {
+  let current_dir = env::current_dir().map_err(|err| err.to_string())?;
+  Ok(current_dir.join(path).clean())
+}
+
+/// Applies the options given on the command line to `config`, the site's config file,
+/// overriding its values.
+fn apply_overrides(config: &mut SiteConfig, args: &Args) {
+  config.globals.extend(args.defines.iter().cloned());
+  if args.locale.is_some() {
+    config.locale = args.locale.clone();
+  }
+  if let Some(output_dir) = &args.output_dir {
+    config.output_dir = output_dir.clone();
+  }
+  if args.strict {
+    config.strict = true;
+  }
+  if args.clean {
+    config.clean = true;
+  }
 }
 
 /// Finds the root path to the config file in one of the following places,
 /// or otherwise returns an `Err`:
 /// - `input_path` if `input_path` corresponds to a file
 /// - `input_path`/site.toml if `input_path` corresponds to a directory
-/// - `"./site.toml"` if `input_path` is `None` and `"./site.toml"` corresponds to a file
-fn find_config_file(input_path: &Option<String>) -> Result<PathBuf> {
+/// - the nearest site.toml in `current_dir` or one of its ancestors if `input_path` is `None`,
+///   stopping at the filesystem root or at a directory containing `.git`
+fn find_config_file(input_path: &Option<String>, current_dir: &Path) -> Result<PathBuf> {
   const DEFAULT_FILE_NAME: &str = "site.toml";
 
-  let mut path = PathBuf::new();
-  path.push(env::current_dir()?);
-
-  match input_path {
-    None => path.push(DEFAULT_FILE_NAME),
-    Some(input_path) => {
-      path.push(input_path);
-      if !Path::new(input_path).is_file() {
-        path.push(DEFAULT_FILE_NAME);
+  let mut path = match input_path {
+    Some(input_path) => current_dir.join(input_path),
+    None => {
+      for dir in current_dir.ancestors() {
+        let path = dir.join(DEFAULT_FILE_NAME);
+        if path.is_file() {
+          return Ok(path.clean());
+        }
+        if dir.join(".git").exists() {
+          break;
+        }
       }
+      bail!(
+        "{} not found in {} or its parent directories.",
+        DEFAULT_FILE_NAME,
+        current_dir.display()
+      )
     }
   };
+  if !path.is_file() {
+    path.push(DEFAULT_FILE_NAME);
+  }
 
   if path.is_file() {
     Ok(path.clean())
@@ -49,3 +306,198 @@ fn find_config_file(input_path: &Option<String>) -> Result<PathBuf> {
     bail!("{} not found.", path.display())
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use clap::error::ErrorKind;
+  use std::fs;
+  use std::sync::{Mutex, MutexGuard};
+  use tempfile::TempDir;
+
+  /// Held for the lifetime of each `TestSite`, because loading a site changes the working directory.
+  static CWD_LOCK: Mutex<()> = Mutex::new(());
+
+  /// A throwaway site directory, like the library's own test helper, which is private to it.
+  struct TestSite {
+    dir: TempDir,
+    _guard: MutexGuard<'static, ()>,
+  }
+
+  impl TestSite {
+    fn new() -> TestSite {
+      let guard = CWD_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+      TestSite {
+        dir: TempDir::new().unwrap(),
+        _guard: guard,
+      }
+    }
+
+    /// Writes `contents` to `path` within the site directory, creating parent directories as needed.
+    fn file(&self, path: &str, contents: &str) -> &TestSite {
+      let path = self.dir.path().join(path);
+      fs::create_dir_all(path.parent().unwrap()).unwrap();
+      fs::write(path, contents).unwrap();
+      self
+    }
+
+    /// Loads the site using an already parsed `config`.
+    fn load_config(&self, config: SiteConfig) -> Result<Site> {
+      Site::load(config, self.dir.path())
+    }
+
+    /// Reads `path` from the default output directory.
+    fn read_output(&self, path: &str) -> String {
+      fs::read_to_string(self.dir.path().join("output").join(path)).unwrap()
+    }
+  }
+
+  fn args(args: &[&str]) -> Result<Args> {
+    let args = ["oeuvre"].iter().chain(args).map(|arg| arg.to_string());
+    Ok(parse_args(args)?)
+  }
+
+  #[test]
+  fn defined_value_is_interpolated_into_output() {
+    let args = args(&["site", "--define", "commit=abc123", "--define", "env=prod"]).unwrap();
+    let mut config = toml::from_str::<SiteConfig>("globals = { env = \"dev\" }").unwrap();
+    apply_overrides(&mut config, &args);
+
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        r#"<html oeuvre-name="main" data-env="{{ env }}"><meta name="commit" content="{{ commit }}"/></html>"#,
+      )
+      .file("index.xml", r#"<oeuvre-page oeuvre-template="main" oeuvre-path="index.html"/>"#);
+    site.load_config(config).unwrap().render().unwrap();
+    assert_eq!(
+      site.read_output("index.html"),
+      concat!(
+        "<!DOCTYPE html>\r\n",
+        r#"<html data-env="prod"><meta content="abc123" name="commit"></html>"#
+      )
+    );
+  }
+
+  #[test]
+  fn config_file_is_found_in_parent_directory() {
+    let dir = TempDir::new().unwrap();
+    let root = dir.path();
+    fs::create_dir_all(root.join("project/.git")).unwrap();
+    fs::create_dir_all(root.join("project/content/posts")).unwrap();
+    fs::write(root.join("project/site.toml"), "").unwrap();
+    assert_eq!(
+      find_config_file(&None, &root.join("project/content/posts")).unwrap(),
+      root.join("project/site.toml")
+    );
+
+    // The search stops at the repository root.
+    fs::create_dir_all(root.join("other/.git")).unwrap();
+    fs::create_dir_all(root.join("other/content")).unwrap();
+    fs::write(root.join("site.toml"), "").unwrap();
+    assert!(find_config_file(&None, &root.join("other/content")).is_err());
+  }
+
+  #[test]
+  fn command_line_overrides_take_precedence_over_the_config_file() {
+    let config_with = |toml: &str, arguments: &[&str]| {
+      let mut config = toml::from_str::<SiteConfig>(toml).unwrap();
+      apply_overrides(&mut config, &args(arguments).unwrap());
+      config
+    };
+    assert_eq!(config_with("", &[]).output_dir, "output/");
+    assert_eq!(config_with("output_dir = \"public/\"", &[]).output_dir, "public/");
+    assert_eq!(config_with("", &["--output", "dist"]).output_dir, "dist");
+    assert_eq!(
+      config_with("output_dir = \"public/\"", &["--output", "dist"]).output_dir,
+      "dist"
+    );
+
+    let config = config_with(
+      "locale = \"en\"\nglobals = { env = \"dev\", name = \"Site\" }",
+      &["--locale", "fr", "--define", "env=prod"],
+    );
+    assert_eq!(config.locale.as_deref(), Some("fr"));
+    assert_eq!(config.globals["env"], "prod");
+    assert_eq!(config.globals["name"], "Site");
+
+    assert!(!config_with("", &[]).strict);
+    assert!(config_with("", &["--strict"]).strict);
+    assert!(config_with("strict = true", &[]).strict);
+    assert!(!config_with("", &[]).clean);
+    assert!(config_with("", &["--clean"]).clean);
+  }
+
+  #[test]
+  fn command_line_overrides_change_the_build_hash() {
+    let site = TestSite::new();
+    site.file("index.xml", "<oeuvre-page/>");
+    let hash = |arguments: &[&str]| {
+      let mut config = toml::from_str::<SiteConfig>("globals = { env = \"dev\" }").unwrap();
+      apply_overrides(&mut config, &args(arguments).unwrap());
+      site.load_config(config).unwrap().build_hash
+    };
+    let first = hash(&[]);
+    assert_eq!(hash(&[]), first);
+    assert_ne!(hash(&["--define", "env=prod"]), first);
+    assert_ne!(hash(&["--locale", "fr"]), first);
+    assert_ne!(hash(&["--strict"]), first);
+  }
+
+  #[test]
+  fn config_path_can_be_given_as_an_option_or_an_argument() {
+    assert_eq!(args(&["site"]).unwrap().input_path.as_deref(), Some("site"));
+    assert_eq!(args(&["--config", "site"]).unwrap().input_path.as_deref(), Some("site"));
+    assert!(args(&["--config", "site", "other"]).is_err());
+    assert!(args(&["other", "--config", "site"]).is_err());
+    assert!(args(&["--config"]).is_err());
+    assert!(args(&["--verbose", "--quiet"]).is_err());
+  }
+
+  #[test]
+  fn dry_run_cannot_be_combined_with_other_outputs() {
+    // The archive path is resolved against the working directory, which tests that load
+    // sites change, so the test holds a site of its own to work within.
+    let site = TestSite::new();
+    env::set_current_dir(site.dir.path()).unwrap();
+    assert!(args(&["--dry-run"]).unwrap().dry_run);
+    for flag in ["--only", "--bundle", "--archive"] {
+      let err = parse_args(["oeuvre", "--dry-run", flag, "index.html"].map(String::from));
+      assert_eq!(err.err().unwrap().kind(), ErrorKind::ArgumentConflict);
+    }
+  }
+
+  #[cfg(feature = "serve")]
+  #[test]
+  fn serve_takes_the_config_path_and_overrides_before_or_after_it() {
+    let parsed = args(&["site", "serve", "--port", "9000", "--define", "env=dev"]).unwrap();
+    assert!(matches!(parsed.command, Some(Command::Serve { port: 9000 })));
+    assert_eq!(parsed.input_path.as_deref(), Some("site"));
+    assert_eq!(parsed.defines, [("env".to_string(), "dev".to_string())]);
+
+    let parsed = args(&["serve", "--config", "site", "--strict"]).unwrap();
+    assert!(matches!(parsed.command, Some(Command::Serve { port: 8000 })));
+    assert_eq!(parsed.input_path.as_deref(), Some("site"));
+    assert!(parsed.strict);
+  }
+
+  #[test]
+  fn help_and_version_are_available() {
+    let kind = |flag: &str| parse_args(["oeuvre", flag].map(String::from)).err().unwrap().kind();
+    assert_eq!(kind("--help"), ErrorKind::DisplayHelp);
+    assert_eq!(kind("--version"), ErrorKind::DisplayVersion);
+    assert_eq!(kind("--unknown"), ErrorKind::UnknownArgument);
+  }
+
+  #[test]
+  fn define_requires_name_and_value() {
+    assert!(args(&["--define"]).is_err());
+    assert!(args(&["--define", "commit"]).is_err());
+    assert!(args(&["--define", "=abc123"]).is_err());
+    assert_eq!(
+      args(&["--define", "empty="]).unwrap().defines,
+      [("empty".to_string(), String::new())]
+    );
+  }
+}
@@ -13,7 +13,9 @@
 //! Provides an `Element` type, which represents DOM nodes, and a builder to create them with.
 
 use super::convert::IntoAttributeValue;
+use super::entities::unescape;
 use super::error::{Error, Result};
+use super::formatting::Formatting;
 use super::namespaces::NSChoice;
 use super::node::Node;
 use super::prefixes::{Namespace, Prefix, Prefixes};
@@ -24,7 +26,7 @@ use std::io::Write;
 use std::borrow::Cow;
 use std::str;
 
-use quick_xml::events::{BytesDecl, BytesEnd, BytesStart, Event};
+use quick_xml::events::{BytesDecl, BytesEnd, BytesStart, BytesText, Event};
 use quick_xml::Reader as EventReader;
 use quick_xml::Writer as EventWriter;
 
@@ -34,18 +36,29 @@ use std::str::FromStr;
 
 use std::slice;
 
-/// helper function to escape a `&[u8]` and replace all
-/// xml special characters (<, >, &, ', ") with their corresponding
-/// xml escaped value.
-pub fn escape(raw: &[u8]) -> Cow<[u8]> {
+/// helper function to escape an attribute value and replace the characters
+/// that can't appear in it as is, < and &, along with `quote`, the character
+/// that delimits the value, with their corresponding xml escaped value.
+pub fn escape(raw: &[u8], quote: AttrQuotes) -> Cow<'_, [u8]> {
+  let quote = quote.byte();
+  escape_where(raw, |i| matches!(raw[i], b'<' | b'&') || raw[i] == quote)
+}
+
+/// helper function to escape a text node and replace the characters that
+/// can't appear in it as is, < and &, along with the > that ends a `]]>`,
+/// with their corresponding xml escaped value.
+pub fn escape_text(raw: &[u8]) -> Cow<'_, [u8]> {
+  escape_where(raw, |i| {
+    matches!(raw[i], b'<' | b'&') || (raw[i] == b'>' && raw[..i].ends_with(b"]]"))
+  })
+}
+
+fn escape_where(raw: &[u8], to_escape: impl Fn(usize) -> bool) -> Cow<'_, [u8]> {
   let mut escapes: Vec<(usize, &'static [u8])> = Vec::new();
-  let mut bytes = raw.iter();
-  fn to_escape(b: u8) -> bool {
-    matches!(b, b'<' | b'>' | b'\'' | b'&' | b'"')
-  }
+  let mut bytes = raw.iter().enumerate();
 
   let mut loc = 0;
-  while let Some(i) = bytes.position(|&b| to_escape(b)) {
+  while let Some(i) = bytes.position(|(i, _)| to_escape(i)) {
     loc += i;
     match raw[loc] {
       b'<' => escapes.push((loc, b"&lt;")),
@@ -88,6 +101,82 @@ pub struct Element {
   prefixes: Prefixes,
   attributes: BTreeMap<String, String>,
   children: Vec<Node>,
+  /// The contents of the XML declaration that preceded this element in its source document,
+  /// if this element was the root of a parsed document that had one.
+  declaration: Option<String>,
+}
+
+/// Options that control how an `Element` is serialized as a document.
+#[derive(Clone, Debug, Default)]
+pub struct WriteOptions {
+  /// Whether to emit the XML declaration retained from the parsed source, if there was one.
+  pub xml_declaration: bool,
+  /// Text to emit verbatim after the declaration and before the root element, such as a
+  /// doctype and the line break after it.
+  pub prolog: Option<String>,
+  /// A comment to emit after the prolog and before the root element.
+  pub comment: Option<String>,
+  /// The character that delimits attribute values.
+  pub attr_quotes: AttrQuotes,
+  /// Whether to write the document as HTML, in which void elements such as `br` have
+  /// no end tag or closing slash and every other element has an end tag, even if empty.
+  pub html: bool,
+  /// How the whitespace between nodes is laid out.
+  pub formatting: Formatting,
+}
+
+/// The HTML elements that never have contents, and so are written without an end tag.
+const VOID_ELEMENTS: [&str; 14] = [
+  "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
+  "track", "wbr",
+];
+
+/// Escapes the slash of anything in `text` that would end the raw text of an HTML element
+/// named `name`, such as `</script` within a script, as `<\/`, which means the same in
+/// JavaScript strings and regular expressions and in CSS.
+fn escape_raw_text_end<'a>(name: &str, text: &'a str) -> Cow<'a, str> {
+  let ends_raw_text = |at: usize| {
+    text[at + 2..]
+      .get(..name.len())
+      .is_some_and(|tag| tag.eq_ignore_ascii_case(name))
+  };
+  let ends: Vec<usize> = text
+    .match_indices("</")
+    .map(|(at, _)| at)
+    .filter(|&at| ends_raw_text(at))
+    .collect();
+  if ends.is_empty() {
+    return Cow::Borrowed(text);
+  }
+  let mut escaped = String::with_capacity(text.len() + ends.len());
+  let mut from = 0;
+  for at in ends {
+    escaped.push_str(&text[from..at + 1]);
+    escaped.push('\\');
+    from = at + 1;
+  }
+  escaped.push_str(&text[from..]);
+  Cow::Owned(escaped)
+}
+
+/// The character that delimits attribute values when an `Element` is serialized.
+/// Occurrences of that character within a value are escaped, and the other is left as is.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AttrQuotes {
+  /// Values are delimited by `"`.
+  #[default]
+  Double,
+  /// Values are delimited by `'`.
+  Single,
+}
+
+impl AttrQuotes {
+  fn byte(self) -> u8 {
+    match self {
+      AttrQuotes::Double => b'"',
+      AttrQuotes::Single => b'\'',
+    }
+  }
 }
 
 impl<'a> From<&'a Element> for String {
@@ -153,6 +242,7 @@ impl Element {
       prefixes: prefixes.into(),
       attributes,
       children,
+      declaration: None,
     }
   }
 
@@ -220,6 +310,79 @@ impl Element {
     &self.name
   }
 
+  /// Changes the local name of this element, keeping its namespace, attributes and children.
+  ///
+  /// # Examples
+  ///
+  /// ```rust
+  /// use minidom::Element;
+  ///
+  /// let mut elem: Element = "<b>bold</b>".parse().unwrap();
+  /// elem.set_name("strong");
+  ///
+  /// assert_eq!(String::from(&elem), "<strong>bold</strong>");
+  /// ```
+  pub fn set_name<S: Into<String>>(&mut self, name: S) {
+    self.name = name.into();
+  }
+
+  /// Adds `class` to the space-separated classes in this element's class attribute,
+  /// unless it is already present.
+  pub fn add_class(&mut self, class: &str) {
+    match self.attributes.get_mut("class") {
+      Some(classes) if classes.split_whitespace().any(|existing| existing == class) => (),
+      Some(classes) if classes.trim().is_empty() => *classes = class.to_string(),
+      Some(classes) => {
+        classes.push(' ');
+        classes.push_str(class);
+      }
+      None => {
+        self.attributes.insert("class".to_string(), class.to_string());
+      }
+    }
+  }
+
+  /// Renames this element and each of its descendants named `from` to `to`, adding
+  /// `class` to each one's classes if it is given, and returns how many were renamed.
+  /// This turns authoring shorthand into plain markup in a single pass.
+  ///
+  /// # Examples
+  ///
+  /// ```rust
+  /// use minidom::Element;
+  ///
+  /// let mut elem: Element = "<div><oeuvre-note>Hi</oeuvre-note></div>".parse().unwrap();
+  ///
+  /// assert_eq!(elem.rename_all("oeuvre-note", "aside", Some("note")), 1);
+  /// assert_eq!(String::from(&elem), r#"<div><aside class="note">Hi</aside></div>"#);
+  /// ```
+  pub fn rename_all(&mut self, from: &str, to: &str, class: Option<&str>) -> usize {
+    let mut renamed = 0;
+    if self.name == from {
+      self.set_name(to);
+      if let Some(class) = class {
+        self.add_class(class);
+      }
+      renamed += 1;
+    }
+    for child in self.children_mut() {
+      renamed += child.rename_all(from, to, class);
+    }
+    renamed
+  }
+
+  /// Returns the contents of the XML declaration retained from this element's source document,
+  /// such as `xml version="1.0"`, if there was one.
+  pub fn declaration(&self) -> Option<&str> {
+    self.declaration.as_deref()
+  }
+
+  /// Sets or clears the XML declaration emitted before this element when it is written
+  /// as a document.
+  pub fn set_declaration(&mut self, declaration: Option<String>) {
+    self.declaration = declaration;
+  }
+
   /// Returns a reference to the namespace of this element.
   pub fn ns(&self) -> Option<String> {
     self.namespace.clone()
@@ -233,6 +396,27 @@ impl Element {
     None
   }
 
+  /// Returns the value of the given attribute parsed as a boolean (`true` or `false`),
+  /// `None` if it doesn't exist, or an error if it can't be parsed.
+  pub fn attr_bool(&self, name: &str) -> Option<Result<bool>> {
+    self.attr_parsed(name, "a boolean")
+  }
+
+  /// Returns the value of the given attribute parsed as an integer,
+  /// `None` if it doesn't exist, or an error if it can't be parsed.
+  pub fn attr_int(&self, name: &str) -> Option<Result<i64>> {
+    self.attr_parsed(name, "an integer")
+  }
+
+  fn attr_parsed<T: FromStr>(&self, name: &str, expected: &'static str) -> Option<Result<T>> {
+    let value = self.attr(name)?;
+    Some(value.trim().parse().map_err(|_| Error::InvalidAttribute {
+      name: name.to_string(),
+      value: value.to_string(),
+      expected,
+    }))
+  }
+
   /// Returns an iterator over the attributes of this element.
   ///
   /// # Example
@@ -247,7 +431,7 @@ impl Element {
   /// assert_eq!(iter.next().unwrap(), ("a", "b"));
   /// assert_eq!(iter.next(), None);
   /// ```
-  pub fn attrs(&self) -> Attrs {
+  pub fn attrs(&self) -> Attrs<'_> {
     Attrs {
       iter: self.attributes.iter(),
     }
@@ -255,7 +439,7 @@ impl Element {
 
   /// Returns an iterator over the attributes of this element, with the value being a mutable
   /// reference.
-  pub fn attrs_mut(&mut self) -> AttrsMut {
+  pub fn attrs_mut(&mut self) -> AttrsMut<'_> {
     AttrsMut {
       iter: self.attributes.iter_mut(),
     }
@@ -328,25 +512,30 @@ impl Element {
     let mut buf = Vec::new();
 
     let mut prefixes = BTreeMap::new();
-    let root: Element = loop {
+    let mut declaration = None;
+    let mut root: Element = loop {
       let e = reader.read_event(&mut buf)?;
       match e {
         Event::Empty(ref e) | Event::Start(ref e) => {
-          break build_element(reader, e, &mut prefixes)?;
+          break build_element(e, &mut prefixes)?;
         }
         Event::Eof => {
           return Err(Error::EndOfDocument);
         }
+        Event::Decl(ref e) => {
+          declaration = Some(str::from_utf8(e)?.to_owned());
+        }
         Event::Text { .. }
         | Event::Comment { .. }
         | Event::End { .. }
         | Event::CData { .. }
-        | Event::Decl { .. }
         | Event::PI { .. }
         | Event::DocType { .. } => (), // TODO: may need more errors
       }
     };
 
+    root.declaration = declaration;
+
     let mut stack = vec![root];
     let mut prefix_stack = vec![prefixes];
 
@@ -354,13 +543,13 @@ impl Element {
       match reader.read_event(&mut buf)? {
         Event::Empty(ref e) => {
           let mut prefixes = prefix_stack.last().unwrap().clone();
-          let elem = build_element(reader, e, &mut prefixes)?;
+          let elem = build_element(e, &mut prefixes)?;
           // Since there is no Event::End after, directly append it to the current node
           stack.last_mut().unwrap().append_child(elem);
         }
         Event::Start(ref e) => {
           let mut prefixes = prefix_stack.last().unwrap().clone();
-          let elem = build_element(reader, e, &mut prefixes)?;
+          let elem = build_element(e, &mut prefixes)?;
           stack.push(elem);
           prefix_stack.push(prefixes);
         }
@@ -414,25 +603,24 @@ impl Element {
           }
         }
         Event::Text(s) => {
-          let text = s.unescape_and_decode(reader)?;
+          let text = unescape(str::from_utf8(s.escaped())?).into_owned();
           if !text.is_empty() {
             let current_elem = stack.last_mut().unwrap();
             current_elem.append_text_node(text);
           }
         }
+        // CDATA sections are kept apart from text, so that they can be written back
+        // unchanged.
         Event::CData(s) => {
           let text = s.unescape_and_decode(reader)?;
-          if !text.is_empty() {
-            let current_elem = stack.last_mut().unwrap();
-            current_elem.append_text_node(text);
-          }
+          let current_elem = stack.last_mut().unwrap();
+          current_elem.append_cdata_node(text);
         }
+        // Comments are kept verbatim, since markup and entities within them are not parsed.
         Event::Comment(s) => {
-          let text = s.unescape_and_decode(reader)?;
-          if !text.is_empty() {
-            let current_elem = stack.last_mut().unwrap();
-            current_elem.append_comment_node(text);
-          }
+          let text = str::from_utf8(s.escaped())?.to_owned();
+          let current_elem = stack.last_mut().unwrap();
+          current_elem.append_comment_node(text);
         }
         Event::Eof => {
           break;
@@ -453,22 +641,59 @@ impl Element {
     self.to_writer_decl(&mut EventWriter::new(writer))
   }
 
+  /// Output a document to a `Writer`, preceded by the declaration, prolog and comment
+  /// requested by `options`.
+  pub fn write_to_with_options<W: Write>(&self, writer: &mut W, options: &WriteOptions) -> Result<()> {
+    let mut writer = EventWriter::new(writer);
+    if options.xml_declaration {
+      if let Some(declaration) = &self.declaration {
+        let start = BytesStart::borrowed(declaration.as_bytes(), 3);
+        writer.write_event(Event::Decl(BytesDecl::from_start(start)))?;
+        writer.inner().write_all(b"\r\n")?;
+      }
+    }
+    if let Some(prolog) = &options.prolog {
+      writer.inner().write_all(prolog.as_bytes())?;
+    }
+    if let Some(comment) = &options.comment {
+      writer.write_event(Event::Comment(BytesText::from_plain_str(comment)))?;
+      writer.inner().write_all(b"\r\n")?;
+    }
+    let formatted;
+    let root = match options.formatting {
+      Formatting::Preserve => self,
+      formatting => {
+        let mut copy = self.clone();
+        formatting.apply(&mut copy, 0);
+        formatted = copy;
+        &formatted
+      }
+    };
+    root.write_to_inner(&mut writer, &mut BTreeMap::new(), options.attr_quotes, options.html)
+  }
+
   /// Output the document to quick-xml `Writer`
   pub fn to_writer<W: Write>(&self, writer: &mut EventWriter<W>) -> Result<()> {
-    self.write_to_inner(writer, &mut BTreeMap::new())
+    self.write_to_inner(writer, &mut BTreeMap::new(), AttrQuotes::default(), false)
   }
 
   /// Output the document to quick-xml `Writer`
   pub fn to_writer_decl<W: Write>(&self, writer: &mut EventWriter<W>) -> Result<()> {
     writer.write_event(Event::Decl(BytesDecl::new(b"1.0", Some(b"utf-8"), None)))?;
-    self.write_to_inner(writer, &mut BTreeMap::new())
+    self.write_to_inner(writer, &mut BTreeMap::new(), AttrQuotes::default(), false)
   }
 
-  /// Like `write_to()` but without the `<?xml?>` prelude
+  /// Like `write_to()` but without the `<?xml?>` prelude, delimiting attribute
+  /// values with `quotes` and, if `html` is true, writing empty elements as HTML
+  /// does. Namespace declarations and attributes are written in order of their
+  /// names and children in document order, so identical trees always serialize
+  /// to identical bytes.
   pub fn write_to_inner<W: Write>(
     &self,
     writer: &mut EventWriter<W>,
     all_prefixes: &mut BTreeMap<Prefix, Namespace>,
+    quotes: AttrQuotes,
+    html: bool,
   ) -> Result<()> {
     let local_prefixes: &BTreeMap<Option<String>, String> = self.prefixes.declared_prefixes();
 
@@ -514,18 +739,28 @@ impl Element {
       (Some(ref prefix), _) => Cow::Owned(format!("{}:{}", prefix, self.name)),
       _ => Cow::Borrowed(&self.name),
     };
-    let mut start = BytesStart::borrowed(name.as_bytes(), name.len());
+    // The start tag is assembled by hand, since quick-xml always delimits attributes with
+    // double quotes.
+    let mut start = name.as_bytes().to_vec();
+    let mut push_attribute = |key: &[u8], value: &[u8]| {
+      start.push(b' ');
+      start.extend_from_slice(key);
+      start.push(b'=');
+      start.push(quotes.byte());
+      start.extend_from_slice(&escape(value, quotes));
+      start.push(quotes.byte());
+    };
 
     if let Some(namespace) = &self.namespace {
       match self_prefix {
         (Some(ref p), true) => {
           let key = format!("xmlns:{}", p);
-          start.push_attribute((key.as_bytes(), namespace.as_bytes()));
+          push_attribute(key.as_bytes(), namespace.as_bytes());
           all_prefixes.insert(self_prefix.0, namespace.clone());
         }
         (None, true) => {
           let key = String::from("xmlns");
-          start.push_attribute((key.as_bytes(), namespace.as_bytes()));
+          push_attribute(key.as_bytes(), namespace.as_bytes());
           all_prefixes.insert(self_prefix.0, namespace.clone());
         }
         _ => (),
@@ -542,25 +777,43 @@ impl Element {
             Some(p) => format!("xmlns:{}", p),
           };
 
-          start.push_attribute((key.as_bytes(), ns.as_ref()));
+          push_attribute(key.as_bytes(), ns.as_bytes());
           all_prefixes.insert(prefix.clone(), ns.clone());
         }
       }
     }
 
     for (key, value) in &self.attributes {
-      start.push_attribute((key.as_bytes(), escape(value.as_bytes()).as_ref()));
+      push_attribute(key.as_bytes(), value.as_bytes());
     }
 
+    let start = BytesStart::owned(start, name.len());
+
     if self.children.is_empty() {
-      writer.write_event(Event::Empty(start))?;
+      if !html {
+        writer.write_event(Event::Empty(start))?;
+      } else if VOID_ELEMENTS.contains(&self.name.as_str()) {
+        writer.write_event(Event::Start(start))?;
+      } else {
+        writer.write_event(Event::Start(start))?;
+        writer.write_event(Event::End(BytesEnd::borrowed(name.as_bytes())))?;
+      }
       return Ok(());
     }
 
     writer.write_event(Event::Start(start))?;
 
+    // HTML reads the contents of script and style elements as raw text, so in HTML, their
+    // text is written unescaped.
+    let raw_text = html && (self.name == "script" || self.name == "style");
     for child in &self.children {
-      child.write_to_inner(writer, &mut all_prefixes.clone())?;
+      match child {
+        Node::Text(text) | Node::CData(text) if raw_text => {
+          let text = escape_raw_text_end(&self.name, text);
+          writer.write_event(Event::Text(BytesText::from_escaped_str(text)))?;
+        }
+        child => child.write_to_inner(writer, &mut all_prefixes.clone(), quotes, html)?,
+      }
     }
 
     writer.write_event(Event::End(BytesEnd::borrowed(name.as_bytes())))?;
@@ -586,13 +839,13 @@ impl Element {
   /// assert_eq!(iter.next(), None);
   /// ```
   #[inline]
-  pub fn nodes(&self) -> Nodes {
+  pub fn nodes(&self) -> Nodes<'_> {
     self.children.iter()
   }
 
   /// Returns an iterator over mutable references to every child node of this element.
   #[inline]
-  pub fn nodes_mut(&mut self) -> NodesMut {
+  pub fn nodes_mut(&mut self) -> NodesMut<'_> {
     self.children.iter_mut()
   }
 
@@ -612,7 +865,7 @@ impl Element {
   /// assert_eq!(iter.next(), None);
   /// ```
   #[inline]
-  pub fn children(&self) -> Children {
+  pub fn children(&self) -> Children<'_> {
     Children {
       iter: self.children.iter(),
     }
@@ -620,7 +873,7 @@ impl Element {
 
   /// Returns an iterator over mutable references to every child element of this element.
   #[inline]
-  pub fn children_mut(&mut self) -> ChildrenMut {
+  pub fn children_mut(&mut self) -> ChildrenMut<'_> {
     ChildrenMut {
       iter: self.children.iter_mut(),
     }
@@ -641,7 +894,7 @@ impl Element {
   /// assert_eq!(iter.next(), None);
   /// ```
   #[inline]
-  pub fn texts(&self) -> Texts {
+  pub fn texts(&self) -> Texts<'_> {
     Texts {
       iter: self.children.iter(),
     }
@@ -649,7 +902,7 @@ impl Element {
 
   /// Returns an iterator over mutable references to every text node of this element.
   #[inline]
-  pub fn texts_mut(&mut self) -> TextsMut {
+  pub fn texts_mut(&mut self) -> TextsMut<'_> {
     TextsMut {
       iter: self.children.iter_mut(),
     }
@@ -687,6 +940,66 @@ impl Element {
     }
   }
 
+  /// Removes all child nodes from the `Element` and returns them in order.
+  ///
+  /// # Examples
+  ///
+  /// ```rust
+  /// use minidom::Element;
+  ///
+  /// let mut elem: Element = "<root>a<b/></root>".parse().unwrap();
+  ///
+  /// let nodes = elem.take_nodes();
+  ///
+  /// assert_eq!(nodes.len(), 2);
+  /// assert_eq!(String::from(&elem), "<root/>");
+  /// ```
+  pub fn take_nodes(&mut self) -> Vec<Node> {
+    std::mem::take(&mut self.children)
+  }
+
+  /// Inserts a child element before all other child nodes of the `Element`, returning the
+  /// inserted element.
+  ///
+  /// # Examples
+  ///
+  /// ```rust
+  /// use minidom::Element;
+  ///
+  /// let mut elem: Element = "<root><b/></root>".parse().unwrap();
+  ///
+  /// elem.prepend_child(Element::bare("a", None::<String>));
+  ///
+  /// assert_eq!(String::from(&elem), "<root><a/><b/></root>");
+  /// ```
+  pub fn prepend_child(&mut self, child: Element) -> &mut Element {
+    self.insert_child_at(0, child)
+  }
+
+  /// Inserts a child element at position `index` among all child nodes of the `Element`,
+  /// including text and comment nodes, returning the inserted element.
+  ///
+  /// # Panics
+  ///
+  /// Panics if `index` is greater than the number of child nodes.
+  pub fn insert_child_at(&mut self, index: usize, child: Element) -> &mut Element {
+    self.insert_node_at(index, Node::Element(child));
+    if let Node::Element(ref mut cld) = self.children[index] {
+      cld
+    } else {
+      unreachable!()
+    }
+  }
+
+  /// Inserts a node at position `index` among all child nodes of the `Element`.
+  ///
+  /// # Panics
+  ///
+  /// Panics if `index` is greater than the number of child nodes.
+  pub fn insert_node_at(&mut self, index: usize, node: Node) {
+    self.children.insert(index, node);
+  }
+
   /// Appends a text node to an `Element`.
   ///
   /// # Examples
@@ -711,6 +1024,11 @@ impl Element {
     self.children.push(Node::Comment(child.into()));
   }
 
+  /// Appends a CDATA section to an `Element`.
+  pub fn append_cdata_node<S: Into<String>>(&mut self, child: S) {
+    self.children.push(Node::CData(child.into()));
+  }
+
   /// Appends a node to an `Element`.
   ///
   /// # Examples
@@ -743,6 +1061,95 @@ impl Element {
     self.texts().fold(String::new(), |ret, new| ret + new)
   }
 
+  /// Returns the serialized markup of the `Element` and its subtree, as
+  /// `String::from(&element)` does.
+  ///
+  /// # Examples
+  ///
+  /// ```rust
+  /// use minidom::Element;
+  ///
+  /// let elem: Element = "<p>hello, <b>world</b>!</p>".parse().unwrap();
+  ///
+  /// assert_eq!(elem.outer_html(), "<p>hello, <b>world</b>!</p>");
+  /// ```
+  pub fn outer_html(&self) -> String {
+    String::from(self)
+  }
+
+  /// Returns the serialized markup of the `Element` and its subtree, as
+  /// `write_to_with_options` writes it with `options`.
+  ///
+  /// # Examples
+  ///
+  /// ```rust
+  /// use minidom::{Element, WriteOptions};
+  ///
+  /// let elem: Element = "<p>hello,<br/>world</p>".parse().unwrap();
+  /// let options = WriteOptions { html: true, ..WriteOptions::default() };
+  ///
+  /// assert_eq!(elem.outer_html_with_options(&options), "<p>hello,<br>world</p>");
+  /// ```
+  pub fn outer_html_with_options(&self, options: &WriteOptions) -> String {
+    let mut writer = Vec::new();
+    self.write_to_with_options(&mut writer, options).unwrap();
+    String::from_utf8(writer).unwrap()
+  }
+
+  /// Returns the serialized markup of the children of the `Element`, without the
+  /// element itself.
+  ///
+  /// # Examples
+  ///
+  /// ```rust
+  /// use minidom::Element;
+  ///
+  /// let elem: Element = "<p>hello, <b>world</b>!</p>".parse().unwrap();
+  ///
+  /// assert_eq!(elem.inner_html(), "hello, <b>world</b>!");
+  /// ```
+  pub fn inner_html(&self) -> String {
+    self.inner_html_with_options(&WriteOptions::default())
+  }
+
+  /// Returns the serialized markup of the children of the `Element`, without the
+  /// element itself, delimiting attribute values, writing empty elements and laying
+  /// out whitespace as `options` requests. The declaration, prolog and comment that
+  /// `options` may request are only written before whole documents, so they are ignored.
+  ///
+  /// # Examples
+  ///
+  /// ```rust
+  /// use minidom::{Element, WriteOptions};
+  ///
+  /// let elem: Element = "<p>hello,<br/>world</p>".parse().unwrap();
+  /// let options = WriteOptions { html: true, ..WriteOptions::default() };
+  ///
+  /// assert_eq!(elem.inner_html_with_options(&options), "hello,<br>world");
+  /// ```
+  pub fn inner_html_with_options(&self, options: &WriteOptions) -> String {
+    let formatted;
+    let element = match options.formatting {
+      Formatting::Preserve => self,
+      formatting => {
+        let mut copy = self.clone();
+        formatting.apply(&mut copy, 0);
+        formatted = copy;
+        &formatted
+      }
+    };
+    let mut writer = Vec::new();
+    {
+      let mut writer = EventWriter::new(&mut writer);
+      for node in &element.children {
+        node
+          .write_to_inner(&mut writer, &mut BTreeMap::new(), options.attr_quotes, options.html)
+          .unwrap();
+      }
+    }
+    String::from_utf8(writer).unwrap()
+  }
+
   /// Returns a reference to the first child element with the specific name and namespace, if it
   /// exists in the direct descendants of this `Element`, else returns `None`.
   ///
@@ -858,8 +1265,7 @@ fn split_element_name<S: AsRef<str>>(s: S) -> Result<(Option<String>, String)> {
   }
 }
 
-fn build_element<R: BufRead>(
-  reader: &EventReader<R>,
+fn build_element(
   event: &BytesStart,
   prefixes: &mut BTreeMap<Prefix, Namespace>,
 ) -> Result<Element> {
@@ -871,7 +1277,7 @@ fn build_element<R: BufRead>(
     .map(|o| {
       let o = o?;
       let key = str::from_utf8(o.key)?.to_owned();
-      let value = o.unescape_and_decode_value(reader)?;
+      let value = unescape(str::from_utf8(&o.value)?).into_owned();
       Ok((key, value))
     })
     .filter(|o| match *o {
@@ -947,7 +1353,7 @@ impl<'a> Iterator for ChildrenMut<'a> {
   }
 }
 
-/// An iterator over references to child text nodes of an `Element`.
+/// An iterator over references to child text nodes and CDATA sections of an `Element`.
 pub struct Texts<'a> {
   iter: slice::Iter<'a, Node>,
 }
@@ -957,7 +1363,7 @@ impl<'a> Iterator for Texts<'a> {
 
   fn next(&mut self) -> Option<&'a str> {
     for item in &mut self.iter {
-      if let Node::Text(ref child) = *item {
+      if let Node::Text(ref child) | Node::CData(ref child) = *item {
         return Some(child);
       }
     }
@@ -1077,7 +1483,7 @@ mod tests {
       Some("namespace".to_owned()),
       None,
       (None, "namespace".to_owned()),
-      BTreeMap::from_iter(vec![("name".to_string(), "value".to_string())].into_iter()),
+      BTreeMap::from_iter(vec![("name".to_string(), "value".to_string())]),
       Vec::new(),
     );
 
@@ -42,6 +42,16 @@ pub enum Error {
 
     /// An error which is returned when a prefixed is defined twice
     DuplicatePrefix,
+
+    /// An error which is returned when an attribute value can't be parsed as the requested type
+    InvalidAttribute {
+        /// The name of the attribute.
+        name: String,
+        /// The unparsed value of the attribute.
+        value: String,
+        /// A description of the requested type.
+        expected: &'static str,
+    },
 }
 
 impl StdError for Error {
@@ -55,6 +65,7 @@ impl StdError for Error {
             Error::InvalidElement => None,
             Error::InvalidPrefix => None,
             Error::DuplicatePrefix => None,
+            Error::InvalidAttribute { .. } => None,
         }
     }
 }
@@ -74,6 +85,15 @@ impl std::fmt::Display for Error {
             Error::InvalidElement => write!(fmt, "the XML element is invalid"),
             Error::InvalidPrefix => write!(fmt, "the prefix is invalid"),
             Error::DuplicatePrefix => write!(fmt, "the prefix is already defined"),
+            Error::InvalidAttribute {
+                name,
+                value,
+                expected,
+            } => write!(
+                fmt,
+                "the {} attribute value `{}` is not {}",
+                name, value, expected
+            ),
         }
     }
 }
@@ -0,0 +1,156 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Reformats the whitespace between the nodes of an HTML element tree before it is written.
+
+use super::element::Element;
+use super::node::Node;
+
+/// How the whitespace of an element tree is laid out when it is written.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Formatting {
+  /// Whitespace is written exactly as it is in the tree.
+  #[default]
+  Preserve,
+  /// Each block-level element that contains only other block-level elements has them
+  /// written on lines of their own, indented to their depth.
+  Pretty,
+  /// Runs of whitespace are collapsed to a single space, and whitespace beside
+  /// block-level elements or within the head, which a browser would not render,
+  /// is removed.
+  Minified,
+}
+
+/// The elements whose whitespace is significant, and so is never reformatted.
+const PRESERVED_ELEMENTS: [&str; 4] = ["pre", "script", "style", "textarea"];
+
+/// The elements that a browser lays out as blocks, so that the whitespace around them
+/// isn't rendered.
+const BLOCK_ELEMENTS: [&str; 39] = [
+  "address", "article", "aside", "blockquote", "body", "dd", "details", "dialog", "div", "dl",
+  "dt", "fieldset", "figcaption", "figure", "footer", "form", "h1", "h2", "h3", "h4", "h5",
+  "h6", "head", "header", "hgroup", "hr", "html", "li", "main", "nav", "noscript", "ol", "p",
+  "pre", "section", "summary", "table", "title", "ul",
+];
+
+/// The elements that a browser doesn't lay out at all. Whitespace on either side of one is
+/// rendered as though the element weren't there, so it is kept when minifying, as in
+/// `foo <script></script> bar`, but they are put on lines of their own like blocks.
+const UNRENDERED_ELEMENTS: [&str; 6] = ["base", "link", "meta", "script", "style", "template"];
+
+impl Formatting {
+  /// Reformats the whitespace within `element`, which is nested `depth` levels deep.
+  pub(crate) fn apply(self, element: &mut Element, depth: usize) {
+    match self {
+      Formatting::Preserve => (),
+      Formatting::Pretty => indent(element, depth),
+      Formatting::Minified => minify(element),
+    }
+  }
+}
+
+fn is_block(node: Option<&Node>) -> bool {
+  match node {
+    Some(Node::Element(element)) => BLOCK_ELEMENTS.contains(&element.name()),
+    _ => false,
+  }
+}
+
+/// Whether `name` is an element that pretty-printing puts on a line of its own.
+fn is_line(name: &str) -> bool {
+  BLOCK_ELEMENTS.contains(&name) || UNRENDERED_ELEMENTS.contains(&name)
+}
+
+fn is_whitespace(node: &Node) -> bool {
+  node
+    .as_text()
+    .is_some_and(|text| text.chars().all(|c| c.is_ascii_whitespace()))
+}
+
+fn indent(element: &mut Element, depth: usize) {
+  if PRESERVED_ELEMENTS.contains(&element.name()) {
+    return;
+  }
+  let mut nodes = element.take_nodes();
+  let breaks_lines = is_line(element.name())
+    && nodes.iter().any(|node| node.as_element().is_some())
+    && nodes.iter().all(|node| match node {
+      Node::Element(child) => is_line(child.name()),
+      Node::Comment(_) => true,
+      node => is_whitespace(node),
+    });
+  if breaks_lines {
+    nodes.retain(|node| !is_whitespace(node));
+  }
+  for mut node in nodes {
+    if let Node::Element(child) = &mut node {
+      indent(child, depth + 1);
+    }
+    if breaks_lines {
+      element.append_text_node(format!("\r\n{}", "  ".repeat(depth + 1)));
+    }
+    element.append_node(node);
+  }
+  if breaks_lines {
+    element.append_text_node(format!("\r\n{}", "  ".repeat(depth)));
+  }
+}
+
+fn minify(element: &mut Element) {
+  if PRESERVED_ELEMENTS.contains(&element.name()) {
+    return;
+  }
+  // Adjacent text nodes are merged first, so that the whitespace between them collapses too.
+  let mut nodes: Vec<Node> = Vec::new();
+  for node in element.take_nodes() {
+    match (nodes.last_mut(), node) {
+      (Some(Node::Text(previous)), Node::Text(text)) => previous.push_str(&text),
+      (_, Node::Element(mut child)) => {
+        minify(&mut child);
+        nodes.push(Node::Element(child));
+      }
+      (_, node) => nodes.push(node),
+    }
+  }
+  let in_block = BLOCK_ELEMENTS.contains(&element.name());
+  let in_head = element.name() == "head";
+  let texts: Vec<Option<String>> = nodes
+    .iter()
+    .enumerate()
+    .map(|(index, node)| {
+      let text = collapse_whitespace(node.as_text()?);
+      let previous = index.checked_sub(1).and_then(|index| nodes.get(index));
+      let next = nodes.get(index + 1);
+      let mut text = text.as_str();
+      if (previous.is_none() && in_block) || is_block(previous) {
+        text = text.trim_start_matches(' ');
+      }
+      if (next.is_none() && in_block) || is_block(next) {
+        text = text.trim_end_matches(' ');
+      }
+      Some(text.to_string())
+    })
+    .collect();
+  for (node, text) in nodes.into_iter().zip(texts) {
+    match text {
+      Some(text) if text.is_empty() || (in_head && text == " ") => (),
+      Some(text) => element.append_text_node(text),
+      None => element.append_node(node),
+    }
+  }
+}
+
+/// Replaces each run of HTML whitespace in `text` with a single space. Non-breaking
+/// spaces are left alone, since they are rendered.
+fn collapse_whitespace(text: &str) -> String {
+  let mut collapsed = String::with_capacity(text.len());
+  for c in text.chars() {
+    if !c.is_ascii_whitespace() {
+      collapsed.push(c);
+    } else if !collapsed.ends_with(' ') {
+      collapsed.push(' ');
+    }
+  }
+  collapsed
+}
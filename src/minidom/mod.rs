@@ -78,7 +78,9 @@
 
 pub mod convert;
 pub mod element;
+mod entities;
 pub mod error;
+mod formatting;
 mod namespaces;
 pub mod node;
 mod prefixes;
@@ -86,4 +88,5 @@ mod prefixes;
 #[cfg(test)]
 mod tests;
 
-pub use element::Element;
+pub use element::{AttrQuotes, Element, WriteOptions};
+pub use formatting::Formatting;
@@ -8,7 +8,7 @@
 
 //! Provides the `Node` struct, which represents a node in the DOM.
 
-use super::element::{Element, ElementBuilder};
+use super::element::{escape_text, AttrQuotes, Element, ElementBuilder};
 use super::error::Result;
 
 use std::collections::BTreeMap;
@@ -24,8 +24,10 @@ pub enum Node {
     Element(Element),
     /// A text node.
     Text(String),
-    /// A text node.
+    /// A comment, whose text is kept verbatim rather than escaped.
     Comment(String),
+    /// A CDATA section, whose text is kept verbatim rather than escaped.
+    CData(String),
 }
 
 impl Node {
@@ -48,6 +50,7 @@ impl Node {
             Node::Element(ref e) => Some(e),
             Node::Text(_) => None,
             Node::Comment(_) => None,
+            Node::CData(_) => None,
         }
     }
 
@@ -70,6 +73,7 @@ impl Node {
             Node::Element(ref mut e) => Some(e),
             Node::Text(_) => None,
             Node::Comment(_) => None,
+            Node::CData(_) => None,
         }
     }
 
@@ -92,6 +96,7 @@ impl Node {
             Node::Element(e) => Some(e),
             Node::Text(_) => None,
             Node::Comment(_) => None,
+            Node::CData(_) => None,
         }
     }
 
@@ -114,6 +119,7 @@ impl Node {
             Node::Element(_) => None,
             Node::Text(ref s) => Some(s),
             Node::Comment(_) => None,
+            Node::CData(_) => None,
         }
     }
 
@@ -142,6 +148,7 @@ impl Node {
             Node::Element(_) => None,
             Node::Text(ref mut s) => Some(s),
             Node::Comment(_) => None,
+            Node::CData(_) => None,
         }
     }
 
@@ -164,6 +171,7 @@ impl Node {
             Node::Element(_) => None,
             Node::Text(s) => Some(s),
             Node::Comment(_) => None,
+            Node::CData(_) => None,
         }
     }
 
@@ -172,14 +180,24 @@ impl Node {
         &self,
         writer: &mut EventWriter<W>,
         prefixes: &mut BTreeMap<Option<String>, String>,
+        quotes: AttrQuotes,
+        html: bool,
     ) -> Result<()> {
         match *self {
-            Node::Element(ref elmt) => elmt.write_to_inner(writer, prefixes)?,
+            Node::Element(ref elmt) => elmt.write_to_inner(writer, prefixes, quotes, html)?,
             Node::Text(ref s) => {
-                writer.write_event(Event::Text(BytesText::from_plain_str(s)))?;
+                writer.write_event(Event::Text(BytesText::from_escaped(escape_text(s.as_bytes()))))?;
             }
             Node::Comment(ref c) => {
-                writer.write_event(Event::Comment(BytesText::from_plain_str(c)))?;
+                writer.write_event(Event::Comment(BytesText::from_escaped_str(c)))?;
+            }
+            // HTML only allows CDATA sections within foreign content such as SVG, so
+            // their text is written as ordinary text instead.
+            Node::CData(ref c) if html => {
+                writer.write_event(Event::Text(BytesText::from_escaped(escape_text(c.as_bytes()))))?;
+            }
+            Node::CData(ref c) => {
+                writer.write_event(Event::CData(BytesText::from_escaped_str(c)))?;
             }
         }
 
@@ -217,8 +235,9 @@ impl From<ElementBuilder> for Node {
 impl PartialEq for Node {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
-            (&Node::Element(ref elem1), &Node::Element(ref elem2)) => elem1 == elem2,
-            (&Node::Text(ref text1), &Node::Text(ref text2)) => text1 == text2,
+            (Node::Element(elem1), Node::Element(elem2)) => elem1 == elem2,
+            (Node::Text(text1), Node::Text(text2)) => text1 == text2,
+            (Node::CData(text1), Node::CData(text2)) => text1 == text2,
             _ => false,
         }
     }
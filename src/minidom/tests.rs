@@ -10,11 +10,13 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
-use super::element::Element;
+use super::element::{AttrQuotes, Element, WriteOptions};
+use super::formatting::Formatting;
+use super::node::Node;
 
 use quick_xml::Reader;
 
-const TEST_STRING: &'static str = r#"<root xmlns="root_ns" a="b" xml:lang="en">meow<child c="d"/><child xmlns="child_ns" d="e" xml:lang="fr"/>nya</root>"#;
+const TEST_STRING: &str = r#"<root xmlns="root_ns" a="b" xml:lang="en">meow<child c="d"/><child xmlns="child_ns" d="e" xml:lang="fr"/>nya</root>"#;
 
 fn build_test_tree() -> Element {
   let mut root = Element::builder("root", Some("root_ns"))
@@ -54,18 +56,16 @@ fn reader_deduplicate_prefixes() {
     .parse()
     .unwrap();
 
-  match r#"<p1:root xmlns:p1="ns1"><child/></p1:root>"#.parse::<Element>() {
-    Err(err) => panic!("No or wrong error: {:?}", err),
-    _ => ()
+  if let Err(err) = r#"<p1:root xmlns:p1="ns1"><child/></p1:root>"#.parse::<Element>() {
+    panic!("No or wrong error: {:?}", err)
   }
 }
 
 #[test]
 fn reader_no_deduplicate_sibling_prefixes() {
   // The reader shouldn't reuse the sibling's prefixes
-  match r#"<root xmlns="ns1"><p1:child1 xmlns:p1="ns2"/><p1:child2/></root>"#.parse::<Element>() {
-    Err(err) => panic!("No or wrong error: {:?}", err),
-    _ => ()
+  if let Err(err) = r#"<root xmlns="ns1"><p1:child1 xmlns:p1="ns2"/><p1:child2/></root>"#.parse::<Element>() {
+    panic!("No or wrong error: {:?}", err)
   }
 }
 
@@ -432,6 +432,154 @@ fn comments_written() {
   assert_eq!(text, String::from(&elem));
 }
 
+#[test]
+fn comments_keep_markup_and_entities_verbatim() {
+  let text = "<foo xmlns=\"ns1\"><!--[if IE]><p class=\"old\">Tom &amp; Jerry & co</p><![endif]--><!----></foo>";
+  let elem: Element = text.parse().unwrap();
+  assert_eq!(text, String::from(&elem));
+}
+
+#[test]
+fn cdata_sections_keep_their_text_verbatim() {
+  let text = "<script xmlns=\"ns1\"><![CDATA[if (a < b && c) {}]]></script>";
+  let elem: Element = text.parse().unwrap();
+  assert_eq!(text, String::from(&elem));
+
+  let options = WriteOptions {
+    html: true,
+    ..WriteOptions::default()
+  };
+  let mut writer = Vec::new();
+  elem.write_to_with_options(&mut writer, &options).unwrap();
+  assert_eq!(
+    String::from_utf8(writer).unwrap(),
+    "<script xmlns=\"ns1\">if (a < b && c) {}</script>"
+  );
+}
+
+#[test]
+fn script_text_is_raw_only_in_html() {
+  let text = "<div><script>if (a &lt; b) { s = '&lt;/SCRIPT>'; }</script><style>p::after { content: '&lt;/style' }</style></div>";
+  let elem: Element = text.parse().unwrap();
+  assert_eq!(String::from(&elem), text);
+  assert_eq!(
+    elem.outer_html_with_options(&WriteOptions {
+      html: true,
+      ..WriteOptions::default()
+    }),
+    r"<div><script>if (a < b) { s = '<\/SCRIPT>'; }</script><style>p::after { content: '<\/style' }</style></div>"
+  );
+}
+
+#[test]
+fn entities_are_decoded_and_encoded_exactly_once() {
+  let text = r#"<p title="Tom &amp; Jerry &#169; &quot;co&quot;">Tom &amp; Jerry &#xA9; 2020&nbsp;& co</p>"#;
+  let elem: Element = text.parse().unwrap();
+  assert_eq!(elem.attr("title"), Some("Tom & Jerry © \"co\""));
+  assert_eq!(elem.text(), "Tom & Jerry © 2020\u{a0}& co");
+
+  let written = String::from(&elem);
+  assert_eq!(
+    written,
+    "<p title=\"Tom &amp; Jerry © &quot;co&quot;\">Tom &amp; Jerry © 2020\u{a0}&amp; co</p>"
+  );
+  let reparsed: Element = written.parse().unwrap();
+  assert_eq!(String::from(&reparsed), written);
+}
+
+#[test]
+fn every_named_html_entity_is_decoded() {
+  let elem: Element = "<p>&hellip;&rarr;&NotEqualTilde;&CounterClockwiseContourIntegral;</p>"
+    .parse()
+    .unwrap();
+  assert_eq!(elem.text(), "…→\u{2242}\u{338}\u{2233}");
+  assert_eq!(String::from(&elem), "<p>…→\u{2242}\u{338}\u{2233}</p>");
+}
+
+#[test]
+fn unknown_entities_are_kept_as_literal_text() {
+  let elem: Element = "<p>&bogus; &#xZZ; &</p>".parse().unwrap();
+  assert_eq!(elem.text(), "&bogus; &#xZZ; &");
+  assert_eq!(String::from(&elem), "<p>&amp;bogus; &amp;#xZZ; &amp;</p>");
+}
+
+const FORMATTED_DOCUMENT: &str = "<html><head><title>T</title></head><body>\n  <div class=\"a\">\n    \
+  <p>Hello   <b>big</b>\n world</p>\n<pre>  keep\n  this </pre>\n  </div>\n\
+  <script>if (a)\n  b();</script></body></html>";
+
+fn write_formatted(formatting: Formatting) -> String {
+  let elem: Element = FORMATTED_DOCUMENT.parse().unwrap();
+  let options = WriteOptions {
+    html: true,
+    formatting,
+    ..WriteOptions::default()
+  };
+  let mut writer = Vec::new();
+  elem.write_to_with_options(&mut writer, &options).unwrap();
+  String::from_utf8(writer).unwrap()
+}
+
+#[test]
+fn preserved_formatting_writes_whitespace_as_is() {
+  assert_eq!(write_formatted(Formatting::Preserve), FORMATTED_DOCUMENT);
+}
+
+#[test]
+fn pretty_formatting_indents_nested_blocks() {
+  assert_eq!(
+    write_formatted(Formatting::Pretty),
+    concat!(
+      "<html>\r\n",
+      "  <head>\r\n",
+      "    <title>T</title>\r\n",
+      "  </head>\r\n",
+      "  <body>\r\n",
+      "    <div class=\"a\">\r\n",
+      "      <p>Hello   <b>big</b>\n world</p>\r\n",
+      "      <pre>  keep\n  this </pre>\r\n",
+      "    </div>\r\n",
+      "    <script>if (a)\n  b();</script>\r\n",
+      "  </body>\r\n",
+      "</html>"
+    )
+  );
+}
+
+#[test]
+fn minified_formatting_collapses_whitespace_outside_pre_and_script() {
+  assert_eq!(
+    write_formatted(Formatting::Minified),
+    concat!(
+      "<html><head><title>T</title></head><body><div class=\"a\"><p>Hello <b>big</b> world</p>",
+      "<pre>  keep\n  this </pre></div><script>if (a)\n  b();</script></body></html>"
+    )
+  );
+}
+
+#[test]
+fn minified_formatting_keeps_whitespace_beside_unrendered_elements() {
+  let elem: Element = concat!(
+    "<html><head>\n  <meta charset=\"utf-8\"/>\n  <link rel=\"icon\"/>\n  <title>T</title>\n</head>",
+    "<body><p>foo <script>x()</script> bar <link rel=\"x\"/>\n baz</p></body></html>"
+  )
+  .parse()
+  .unwrap();
+  let options = WriteOptions {
+    html: true,
+    formatting: Formatting::Minified,
+    ..WriteOptions::default()
+  };
+  let mut writer = Vec::new();
+  elem.write_to_with_options(&mut writer, &options).unwrap();
+  assert_eq!(
+    String::from_utf8(writer).unwrap(),
+    concat!(
+      "<html><head><meta charset=\"utf-8\"><link rel=\"icon\"><title>T</title></head>",
+      "<body><p>foo <script>x()</script> bar <link rel=\"x\"> baz</p></body></html>"
+    )
+  );
+}
+
 #[test]
 fn xml_error() {
   match "<a xmlns='ns1'></b>".parse::<Element>() {
@@ -455,8 +603,200 @@ fn invalid_element_error() {
 
 #[test]
 fn empty_namespace_parses() {
-  match "<a/>".parse::<Element>() {
-    Err(err) => panic!("Empty namespace threw error: {:?}", err),
-    _ => (),
+  if let Err(err) = "<a/>".parse::<Element>() {
+    panic!("Empty namespace threw error: {:?}", err)
   }
 }
+
+#[test]
+fn declaration_dropped_by_default() {
+  let elem: Element = r#"<?xml version="1.0"?><root a="b"/>"#.parse().unwrap();
+  assert_eq!(elem.declaration(), Some(r#"xml version="1.0""#));
+  let mut writer = Vec::new();
+  elem
+    .write_to_with_options(&mut writer, &WriteOptions::default())
+    .unwrap();
+  assert_eq!(String::from_utf8(writer).unwrap(), r#"<root a="b"/>"#);
+}
+
+#[test]
+fn declaration_round_trips_when_requested() {
+  let elem: Element = r#"<?xml version="1.0" encoding="utf-8"?><root a="b"/>"#.parse().unwrap();
+  let options = WriteOptions {
+    xml_declaration: true,
+    prolog: Some("<!DOCTYPE html>\r\n".to_owned()),
+    comment: None,
+    attr_quotes: AttrQuotes::Double,
+    html: false,
+    formatting: Formatting::Preserve,
+  };
+  let mut writer = Vec::new();
+  elem.write_to_with_options(&mut writer, &options).unwrap();
+  assert_eq!(
+    String::from_utf8(writer).unwrap(),
+    "<?xml version=\"1.0\" encoding=\"utf-8\"?>\r\n<!DOCTYPE html>\r\n<root a=\"b\"/>"
+  );
+}
+
+#[test]
+fn set_name_keeps_attributes_and_children() {
+  let mut elem: Element = r#"<b id="x" title="t">bold <i>and italic</i></b>"#.parse().unwrap();
+  elem.set_name("strong");
+  assert_eq!(elem.name(), "strong");
+  assert_eq!(
+    String::from(&elem),
+    r#"<strong id="x" title="t">bold <i>and italic</i></strong>"#
+  );
+}
+
+#[test]
+fn rename_all_renames_descendants_and_adds_class() {
+  let mut elem: Element = r#"<div><oeuvre-note class="wide">A <em>note</em></oeuvre-note><p><oeuvre-note>B</oeuvre-note></p></div>"#
+    .parse()
+    .unwrap();
+  assert_eq!(elem.rename_all("oeuvre-note", "aside", Some("note")), 2);
+  assert_eq!(
+    String::from(&elem),
+    r#"<div><aside class="wide note">A <em>note</em></aside><p><aside class="note">B</aside></p></div>"#
+  );
+}
+
+#[test]
+fn html_void_elements_have_no_end_tag_and_other_empty_elements_do() {
+  let elem: Element = r#"<div><br/><img src="a.png" alt=""/><p/><div class="empty"></div></div>"#
+    .parse()
+    .unwrap();
+  let options = WriteOptions {
+    html: true,
+    ..WriteOptions::default()
+  };
+  let mut writer = Vec::new();
+  elem.write_to_with_options(&mut writer, &options).unwrap();
+  assert_eq!(
+    String::from_utf8(writer).unwrap(),
+    r#"<div><br><img alt="" src="a.png"><p></p><div class="empty"></div></div>"#
+  );
+  assert_eq!(
+    String::from(&elem),
+    r#"<div><br/><img alt="" src="a.png"/><p/><div class="empty"/></div>"#
+  );
+}
+
+fn write_with_quotes(elem: &Element, attr_quotes: AttrQuotes) -> String {
+  let options = WriteOptions {
+    attr_quotes,
+    ..WriteOptions::default()
+  };
+  let mut writer = Vec::new();
+  elem.write_to_with_options(&mut writer, &options).unwrap();
+  String::from_utf8(writer).unwrap()
+}
+
+#[test]
+fn double_quoted_attribute_escapes_only_double_quotes() {
+  let elem = Element::builder("root", None::<String>)
+    .attr("title", r#"It's "quoted""#)
+    .build();
+  assert_eq!(
+    write_with_quotes(&elem, AttrQuotes::Double),
+    r#"<root title="It's &quot;quoted&quot;"/>"#
+  );
+}
+
+#[test]
+fn single_quoted_attribute_escapes_only_single_quotes() {
+  let elem = Element::builder("root", None::<String>)
+    .attr("title", r#"It's "quoted""#)
+    .build();
+  assert_eq!(
+    write_with_quotes(&elem, AttrQuotes::Single),
+    r#"<root title='It&apos;s "quoted"'/>"#
+  );
+}
+
+#[test]
+fn outer_and_inner_html_of_nested_element() {
+  let elem: Element = r#"<div class="card"><h2>Title</h2>Some <em>nested <b>text</b></em> &amp; more<!--note--></div>"#
+    .parse()
+    .unwrap();
+  assert_eq!(
+    elem.outer_html(),
+    r#"<div class="card"><h2>Title</h2>Some <em>nested <b>text</b></em> &amp; more<!--note--></div>"#
+  );
+  assert_eq!(
+    elem.inner_html(),
+    "<h2>Title</h2>Some <em>nested <b>text</b></em> &amp; more<!--note-->"
+  );
+  assert_eq!(elem.outer_html(), String::from(&elem));
+  let empty: Element = "<br/>".parse().unwrap();
+  assert_eq!(empty.outer_html(), "<br/>");
+  assert_eq!(empty.inner_html(), "");
+}
+
+#[test]
+fn outer_and_inner_html_honor_write_options() {
+  let elem: Element = r#"<div class="card"><p>One<br/>two</p><span/></div>"#.parse().unwrap();
+  let options = WriteOptions {
+    attr_quotes: AttrQuotes::Single,
+    html: true,
+    ..WriteOptions::default()
+  };
+  assert_eq!(
+    elem.outer_html_with_options(&options),
+    "<div class='card'><p>One<br>two</p><span></span></div>"
+  );
+  assert_eq!(elem.inner_html_with_options(&options), "<p>One<br>two</p><span></span>");
+  assert_eq!(elem.inner_html_with_options(&WriteOptions::default()), "<p>One<br/>two</p><span/>");
+}
+
+#[test]
+fn insert_child_at_front_middle_and_end() {
+  let mut root: Element = "<root><b/><d/></root>".parse().unwrap();
+  root.prepend_child(Element::bare("a", None::<String>));
+  root.insert_child_at(2, Element::bare("c", None::<String>));
+  root.insert_child_at(4, Element::bare("e", None::<String>));
+  assert_eq!(String::from(&root), "<root><a/><b/><c/><d/><e/></root>");
+}
+
+#[test]
+fn insert_node_at_counts_text_nodes() {
+  let mut root: Element = "<root>one<b/>three</root>".parse().unwrap();
+  root.insert_node_at(1, Node::Text("two".to_owned()));
+  let inserted = root.insert_child_at(0, Element::bare("a", None::<String>));
+  inserted.append_text_node("zero");
+  assert_eq!(String::from(&root), "<root><a>zero</a>onetwo<b/>three</root>");
+}
+
+#[test]
+fn attr_bool_parses_valid_invalid_and_absent_values() {
+  let elem: Element = r#"<root yes="true" no=" false " bad="yes"/>"#.parse().unwrap();
+  assert!(elem.attr_bool("yes").unwrap().unwrap());
+  assert!(!elem.attr_bool("no").unwrap().unwrap());
+  assert_eq!(
+    elem.attr_bool("bad").unwrap().unwrap_err().to_string(),
+    "the bad attribute value `yes` is not a boolean"
+  );
+  assert!(elem.attr_bool("missing").is_none());
+}
+
+#[test]
+fn attr_int_parses_valid_invalid_and_absent_values() {
+  let elem: Element = r#"<root count="42" offset="-3" bad="4.5"/>"#.parse().unwrap();
+  assert_eq!(elem.attr_int("count").unwrap().unwrap(), 42);
+  assert_eq!(elem.attr_int("offset").unwrap().unwrap(), -3);
+  assert_eq!(
+    elem.attr_int("bad").unwrap().unwrap_err().to_string(),
+    "the bad attribute value `4.5` is not an integer"
+  );
+  assert!(elem.attr_int("missing").is_none());
+}
+
+#[test]
+fn take_nodes_empties_element() {
+  let mut root: Element = "<root>one<b/><!--c--></root>".parse().unwrap();
+  let nodes = root.take_nodes();
+  assert_eq!(nodes.len(), 3);
+  assert_eq!(nodes[0].as_text(), Some("one"));
+  assert_eq!(nodes[1].as_element().unwrap().name(), "b");
+  assert_eq!(String::from(&root), "<root/>");
+}
@@ -0,0 +1,362 @@
+use crate::site::{content_hash, media_type, percent_decode, Site, SiteConfig};
+use anyhow::{bail, Result};
+use log::{error, info, warn};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// The url path of the event stream that tells served pages to reload.
+const RELOAD_PATH: &str = "/__oeuvre/reload";
+/// The script added to each served HTML page, which reloads it after a rebuild.
+const RELOAD_SCRIPT: &str = concat!(
+  r#"<script>new EventSource("/__oeuvre/reload")"#,
+  r#".onmessage = function () { location.reload(); };</script>"#
+);
+/// How many ports, starting with the requested one, to try before giving up.
+const PORT_ATTEMPTS: u16 = 20;
+/// How often to look for changed input files.
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Builds the site whose config file is at `config_path`, serves its output directory
+/// on `port`, or the next free port after it, and rebuilds the site whenever a file in
+/// the config file's directory or the input directory changes, telling connected browsers
+/// to reload.
+/// `configure` is applied to the config before each build. Only returns on failure.
+pub fn serve(config_path: &Path, port: u16, configure: impl Fn(&mut SiteConfig)) -> Result<()> {
+  let config_dir = config_path.parent().unwrap();
+  let build = || -> Result<(PathBuf, Vec<PathBuf>)> {
+    let mut config = SiteConfig::load(config_path)?;
+    configure(&mut config);
+    let site = Site::load(config, config_dir)?;
+    let report = site.render()?;
+    info!(
+      "Built site {} with {} render issue(s)",
+      report.hash, report.issue_count
+    );
+    Ok((site.output_dir, vec![config_dir.to_path_buf(), site.input_dir]))
+  };
+
+  let (output_dir, mut watched_dirs) = build()?;
+  let output_dir = Arc::new(Mutex::new(output_dir));
+  let clients = Arc::new(Mutex::new(Vec::<TcpStream>::new()));
+  let listener = bind(port)?;
+  info!("Serving site at http://{}/", listener.local_addr()?);
+  {
+    let output_dir = Arc::clone(&output_dir);
+    let clients = Arc::clone(&clients);
+    thread::spawn(move || {
+      for stream in listener.incoming() {
+        let stream = match stream {
+          Ok(stream) => stream,
+          Err(err) => {
+            warn!("Connection failed. Cause: {}", err);
+            continue;
+          }
+        };
+        let output_dir = output_dir.lock().unwrap().clone();
+        let clients = Arc::clone(&clients);
+        thread::spawn(move || {
+          if let Err(err) = handle(stream, &output_dir, &clients) {
+            warn!("Request could not be answered. Cause: {}", err);
+          }
+        });
+      }
+    });
+  }
+
+  let mut last_snapshot = snapshot(&watched_dirs, &output_dir.lock().unwrap());
+  loop {
+    thread::sleep(WATCH_INTERVAL);
+    let current_snapshot = snapshot(&watched_dirs, &output_dir.lock().unwrap());
+    if current_snapshot == last_snapshot {
+      continue;
+    }
+    last_snapshot = current_snapshot;
+    info!("Rebuilding site");
+    match build() {
+      Ok((dir, dirs)) => {
+        *output_dir.lock().unwrap() = dir;
+        watched_dirs = dirs;
+        clients
+          .lock()
+          .unwrap()
+          .retain_mut(|client| client.write_all(b"data: reload\n\n").is_ok());
+      }
+      Err(err) => error!("{}", err),
+    }
+  }
+}
+
+/// Binds a listener to `port` on the loopback address or, if it is in use,
+/// to the first free port among the next few.
+fn bind(port: u16) -> Result<TcpListener> {
+  for candidate in port..port.saturating_add(PORT_ATTEMPTS) {
+    match TcpListener::bind(("127.0.0.1", candidate)) {
+      Ok(listener) => return Ok(listener),
+      Err(err) if err.kind() == io::ErrorKind::AddrInUse => {
+        warn!("Port {} is in use", candidate)
+      }
+      Err(err) => bail!("Port {} could not be bound. Cause: {}", candidate, err),
+    }
+  }
+  bail!(
+    "No free port found from {} to {}",
+    port,
+    port.saturating_add(PORT_ATTEMPTS - 1)
+  )
+}
+
+/// Returns the modification time and size of every file within `watched_dirs`, except for
+/// those in `output_dir` or in hidden directories such as `.git`.
+fn snapshot(watched_dirs: &[PathBuf], output_dir: &Path) -> BTreeMap<PathBuf, (SystemTime, u64)> {
+  let mut files = BTreeMap::new();
+  let mut dirs = watched_dirs.to_vec();
+  while let Some(dir) = dirs.pop() {
+    let entries = match fs::read_dir(&dir) {
+      Ok(entries) => entries,
+      Err(_) => continue,
+    };
+    for entry in entries.flatten() {
+      let path = entry.path();
+      let hidden = entry.file_name().to_string_lossy().starts_with('.');
+      match entry.metadata() {
+        Ok(metadata) if metadata.is_dir() => {
+          if !hidden && !path.starts_with(output_dir) {
+            dirs.push(path);
+          }
+        }
+        Ok(metadata) => {
+          let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+          files.insert(path, (modified, metadata.len()));
+        }
+        Err(_) => {}
+      }
+    }
+  }
+  files
+}
+
+/// Answers a single request for a file in `output_dir` or, for the reload event
+/// stream, adds the connection to `clients`, leaving it open.
+fn handle(
+  mut stream: TcpStream,
+  output_dir: &Path,
+  clients: &Mutex<Vec<TcpStream>>,
+) -> io::Result<()> {
+  let mut reader = BufReader::new(stream.try_clone()?);
+  let mut request_line = String::new();
+  reader.read_line(&mut request_line)?;
+  let mut if_none_match = None;
+  loop {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+      break;
+    }
+    if let Some((name, value)) = line.split_once(':') {
+      if name.trim().eq_ignore_ascii_case("If-None-Match") {
+        if_none_match = Some(value.trim().to_string());
+      }
+    }
+  }
+
+  let mut parts = request_line.split_whitespace();
+  let method = parts.next().unwrap_or_default();
+  let target = parts.next().unwrap_or_default();
+  let head = method == "HEAD";
+  if method != "GET" && !head {
+    return respond(&mut stream, "405 Method Not Allowed", &[], b"Method not allowed", head);
+  }
+  let url_path = target.split(['?', '#']).next().unwrap_or_default();
+  if url_path == RELOAD_PATH {
+    stream.write_all(concat!(
+      "HTTP/1.1 200 OK\r\n",
+      "Content-Type: text/event-stream\r\n",
+      "Cache-Control: no-cache\r\n\r\n"
+    ).as_bytes())?;
+    clients.lock().unwrap().push(stream);
+    return Ok(());
+  }
+
+  let path = match resolve(output_dir, url_path) {
+    Some(path) => path,
+    None => return respond(&mut stream, "404 Not Found", &[], b"Not found", head),
+  };
+  let mut body = match fs::read(&path) {
+    Ok(body) => body,
+    Err(_) => return respond(&mut stream, "404 Not Found", &[], b"Not found", head),
+  };
+  let content_type = media_type(&path);
+  if content_type.starts_with("text/html") {
+    body = with_reload_script(body);
+  }
+  let etag = format!("\"{}\"", content_hash(&body));
+  if if_none_match.as_deref() == Some(etag.as_str()) {
+    return respond(&mut stream, "304 Not Modified", &[("ETag", &etag)], b"", head);
+  }
+  let headers = [("Content-Type", content_type), ("ETag", &etag), ("Cache-Control", "no-cache")];
+  respond(&mut stream, "200 OK", &headers, &body, head)
+}
+
+/// Writes a response with `status`, `headers` and, unless answering a HEAD request, `body`.
+fn respond(
+  stream: &mut TcpStream,
+  status: &str,
+  headers: &[(&str, &str)],
+  body: &[u8],
+  head: bool,
+) -> io::Result<()> {
+  let mut response = format!(
+    "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+    status,
+    body.len()
+  );
+  for (name, value) in headers {
+    response.push_str(&format!("{}: {}\r\n", name, value));
+  }
+  response.push_str("\r\n");
+  stream.write_all(response.as_bytes())?;
+  if !head {
+    stream.write_all(body)?;
+  }
+  Ok(())
+}
+
+/// Returns the file in `output_dir` that `url_path` names, using `index.html` for
+/// directories, or None if the path is malformed or leaves `output_dir`.
+fn resolve(output_dir: &Path, url_path: &str) -> Option<PathBuf> {
+  let relative_path = PathBuf::from(percent_decode(url_path.trim_start_matches('/'))?);
+  if !relative_path.components().all(|component| matches!(component, Component::Normal(_))) {
+    return None;
+  }
+  let path = output_dir.join(relative_path);
+  match path.is_dir() {
+    true => Some(path.join("index.html")),
+    false => Some(path),
+  }
+}
+
+/// Adds the reload script to an HTML page, before its closing body tag if it has one.
+fn with_reload_script(body: Vec<u8>) -> Vec<u8> {
+  let mut html = match String::from_utf8(body) {
+    Ok(html) => html,
+    Err(err) => return err.into_bytes(),
+  };
+  match html.to_ascii_lowercase().rfind("</body>") {
+    Some(index) => html.insert_str(index, RELOAD_SCRIPT),
+    None => html.push_str(RELOAD_SCRIPT),
+  }
+  html.into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::io::Read;
+  use tempfile::TempDir;
+
+  /// Sends `request` to a server for `output_dir` and returns the whole response.
+  fn request(output_dir: &Path, request: &str) -> String {
+    let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+    let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    client.write_all(request.as_bytes()).unwrap();
+    let (stream, _) = listener.accept().unwrap();
+    handle(stream, output_dir, &Mutex::new(Vec::new())).unwrap();
+    let mut response = String::new();
+    client.read_to_string(&mut response).unwrap();
+    response
+  }
+
+  #[test]
+  fn pages_are_served_with_a_content_type_an_etag_and_the_reload_script() {
+    let dir = TempDir::new().unwrap();
+    fs::create_dir(dir.path().join("blog")).unwrap();
+    fs::write(dir.path().join("blog/index.html"), "<html><body><p>Hi</p></body></html>").unwrap();
+    let response = request(dir.path(), "GET /blog/?page=2 HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.contains("Content-Type: text/html; charset=utf-8\r\n"));
+    assert!(response.ends_with(&format!("<p>Hi</p>{}</body></html>", RELOAD_SCRIPT)));
+
+    let etag = response
+      .lines()
+      .find_map(|line| line.strip_prefix("ETag: "))
+      .unwrap()
+      .to_string();
+    let response = request(
+      dir.path(),
+      &format!("GET /blog/ HTTP/1.1\r\nIf-None-Match: {}\r\n\r\n", etag),
+    );
+    assert!(response.starts_with("HTTP/1.1 304 Not Modified\r\n"));
+    assert!(response.ends_with("\r\n\r\n"));
+  }
+
+  #[test]
+  fn stylesheets_are_served_as_css_and_revalidated_by_etag() {
+    let dir = TempDir::new().unwrap();
+    fs::write(dir.path().join("main.css"), "body { margin: 0; }").unwrap();
+    let response = request(dir.path(), "GET /main.css HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.contains("Content-Type: text/css; charset=utf-8\r\n"));
+    assert!(response.ends_with("\r\n\r\nbody { margin: 0; }"));
+
+    let etag = response
+      .lines()
+      .find_map(|line| line.strip_prefix("ETag: "))
+      .unwrap()
+      .to_string();
+    let revalidate = |etag: &str| {
+      request(dir.path(), &format!("GET /main.css HTTP/1.1\r\nIf-None-Match: {}\r\n\r\n", etag))
+    };
+    assert!(revalidate(&etag).starts_with("HTTP/1.1 304 Not Modified\r\n"));
+    assert!(revalidate("\"stale\"").starts_with("HTTP/1.1 200 OK\r\n"));
+
+    fs::write(dir.path().join("main.css"), "body { margin: 1em; }").unwrap();
+    assert!(revalidate(&etag).starts_with("HTTP/1.1 200 OK\r\n"));
+  }
+
+  #[test]
+  fn paths_outside_the_output_directory_are_not_served() {
+    let dir = TempDir::new().unwrap();
+    fs::create_dir(dir.path().join("output")).unwrap();
+    fs::write(dir.path().join("site.toml"), "").unwrap();
+    let output_dir = dir.path().join("output");
+    assert_eq!(resolve(&output_dir, "/../site.toml"), None);
+    assert_eq!(resolve(&output_dir, "/%2e%2e/site.toml"), None);
+    assert_eq!(resolve(&output_dir, "/a%20b.css"), Some(output_dir.join("a b.css")));
+    assert_eq!(resolve(&output_dir, "/"), Some(output_dir.join("index.html")));
+    assert!(request(&output_dir, "GET /../site.toml HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 404"));
+  }
+
+  #[test]
+  fn changes_in_an_input_directory_outside_the_config_directory_are_seen() {
+    let dir = TempDir::new().unwrap();
+    let config_dir = dir.path().join("config");
+    let input_dir = dir.path().join("content");
+    let output_dir = config_dir.join("output");
+    fs::create_dir_all(&output_dir).unwrap();
+    fs::create_dir(&input_dir).unwrap();
+    fs::write(config_dir.join("site.toml"), "dir = \"../content\"").unwrap();
+    fs::write(input_dir.join("index.xml"), "<oeuvre-page/>").unwrap();
+    fs::write(output_dir.join("index.html"), "<html/>").unwrap();
+
+    let watched_dirs = [config_dir.clone(), input_dir.clone()];
+    let before = snapshot(&watched_dirs, &output_dir);
+    assert!(before.contains_key(&input_dir.join("index.xml")));
+    assert!(!before.contains_key(&output_dir.join("index.html")));
+    fs::write(input_dir.join("index.xml"), "<oeuvre-page oeuvre-path=\"home.html\"/>").unwrap();
+    assert_ne!(snapshot(&watched_dirs, &output_dir), before);
+  }
+
+  #[test]
+  fn the_next_port_is_used_when_one_is_taken() {
+    let taken = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+    let port = taken.local_addr().unwrap().port();
+    let listener = bind(port).unwrap();
+    assert_ne!(listener.local_addr().unwrap().port(), port);
+  }
+}
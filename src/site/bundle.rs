@@ -0,0 +1,119 @@
+use crate::minidom::node::Node;
+use crate::minidom::Element;
+use crate::site::media_type;
+use base64::prelude::{Engine, BASE64_STANDARD};
+use log::error;
+use path_clean::PathClean;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Inlines the local stylesheets and images referenced by `element`, a page rendered
+/// to `page_path`, so that the page can be viewed without the rest of the site.
+/// Stylesheet links become `<style>` elements and image sources become data URIs.
+/// Images larger than `max_image_bytes` and resources that cannot be read stay linked.
+pub fn inline_resources(element: &mut Element, page_path: &Path, max_image_bytes: u64) {
+  for node in element.nodes_mut() {
+    let child = match node {
+      Node::Element(child) => child,
+      _ => continue,
+    };
+    match child.name() {
+      "link" => {
+        if let Some(style) = inline_stylesheet(child, page_path) {
+          *child = style;
+        }
+      }
+      "img" => inline_image(child, page_path, max_image_bytes),
+      _ => inline_resources(child, page_path, max_image_bytes),
+    }
+  }
+}
+
+/// Returns a `<style>` element with the contents of the stylesheet linked by `link`,
+/// or None if `link` is not a link to a local stylesheet.
+fn inline_stylesheet(link: &Element, page_path: &Path) -> Option<Element> {
+  let is_stylesheet = link
+    .attr("rel")
+    .is_some_and(|rel| rel.split_whitespace().any(|rel| rel.eq_ignore_ascii_case("stylesheet")));
+  if !is_stylesheet {
+    return None;
+  }
+  let path = local_path(link.attr("href")?, page_path)?;
+  let css = match fs::read_to_string(&path) {
+    Ok(css) => css,
+    Err(err) => {
+      error!("Stylesheet {} could not be inlined. Cause: {}", path.display(), err);
+      return None;
+    }
+  };
+  let mut style = Element::bare("style", link.ns());
+  if let Some(media) = link.attr("media") {
+    style.set_attr("media", media);
+  }
+  style.append_text_node(css);
+  Some(style)
+}
+
+/// Replaces the source of `img` with a data URI, if it names a local image
+/// no larger than `max_image_bytes`.
+fn inline_image(img: &mut Element, page_path: &Path, max_image_bytes: u64) {
+  let path = match img.attr("src").and_then(|src| local_path(src, page_path)) {
+    Some(path) => path,
+    None => return,
+  };
+  match fs::metadata(&path) {
+    Ok(metadata) if metadata.len() > max_image_bytes => return,
+    Ok(_) => (),
+    Err(err) => {
+      error!("Image {} could not be inlined. Cause: {}", path.display(), err);
+      return;
+    }
+  };
+  match fs::read(&path) {
+    Ok(bytes) => {
+      let uri = format!("data:{};base64,{}", media_type(&path), BASE64_STANDARD.encode(&bytes));
+      img.set_attr("src", uri);
+    }
+    Err(err) => error!("Image {} could not be inlined. Cause: {}", path.display(), err),
+  }
+}
+
+/// Resolves a url found in a page rendered to `page_path` to a path relative to
+/// the input directory, or returns None if the url does not refer to a local file.
+pub fn local_path(url: &str, page_path: &Path) -> Option<PathBuf> {
+  if url.is_empty() || url.starts_with("//") || url.starts_with('#') || url.contains(':') {
+    return None;
+  }
+  let url = url.split(['?', '#']).next().unwrap();
+  match url.strip_prefix('/') {
+    Some(url) => Some(PathBuf::from(url)),
+    None => Some(page_path.parent().unwrap_or_else(|| Path::new("")).join(url).clean()),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::site::test_utils::TestSite;
+
+  #[test]
+  fn bundle_inlines_stylesheets_and_small_images() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        r#"<html oeuvre-name="main"><head><link rel="stylesheet" href="../assets/main.css"/></head><body><img src="/assets/dot.png"/><img src="/assets/big.png"/></body></html>"#,
+      )
+      .file("blog/post.xml", r#"<oeuvre-page oeuvre-template="main" oeuvre-path="post.html"/>"#)
+      .file("assets/main.css", "body { margin: 0; }")
+      .file("assets/dot.png", "dot")
+      .file("assets/big.png", "a larger image");
+    let bundle = site.load("bundle_max_image_bytes = 8").unwrap().render_bundle("blog/post.html");
+    assert_eq!(
+      bundle.unwrap(),
+      concat!(
+        "<!DOCTYPE html>\r\n<html><head><style>body { margin: 0; }</style></head>",
+        r#"<body><img src="data:image/png;base64,ZG90"><img src="/assets/big.png"></body></html>"#
+      )
+    );
+  }
+}
@@ -0,0 +1,566 @@
+use crate::minidom::Element;
+use itertools::Itertools;
+use quick_xml::Reader as EventReader;
+use serde_derive::Serialize;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::dataset::is_json;
+use super::output::url_path;
+use super::render::{allows, is_rendered_element, UNKNOWN_ELEMENT};
+use super::Site;
+use super::validate_root;
+
+/// A problem found while checking a loaded site, along with the file it was found in
+/// and, where it is known, its position within that file.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Diagnostic {
+  pub path: PathBuf,
+  pub position: Option<Position>,
+  pub severity: Severity,
+  /// The kind of problem, such as `unknown-element`, for tools that filter diagnostics.
+  pub code: &'static str,
+  pub message: String,
+}
+
+impl Diagnostic {
+  /// Returns an error diagnostic for `path` without a position.
+  pub fn error(path: &Path, code: &'static str, message: String) -> Diagnostic {
+    Diagnostic {
+      path: path.to_path_buf(),
+      position: None,
+      severity: Severity::Error,
+      code,
+      message,
+    }
+  }
+}
+
+impl fmt::Display for Diagnostic {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.path.display())?;
+    if let Some(position) = &self.position {
+      write!(f, ":{}:{}", position.line, position.column)?;
+    }
+    write!(f, ": {}: {}", self.severity.name(), self.message)
+  }
+}
+
+/// A position within a source file. Lines and columns are counted from 1, and
+/// columns count characters rather than bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Position {
+  pub line: usize,
+  pub column: usize,
+}
+
+impl Position {
+  /// Returns the position of the byte offset `offset` within `text`.
+  fn at(text: &str, offset: usize) -> Position {
+    let before = &text[..offset];
+    let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+    Position {
+      line: before.matches('\n').count() + 1,
+      column: before[line_start..].chars().count() + 1,
+    }
+  }
+}
+
+/// How serious a diagnostic is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+  Error,
+  Warning,
+}
+
+impl Severity {
+  fn name(self) -> &'static str {
+    match self {
+      Severity::Error => "error",
+      Severity::Warning => "warning",
+    }
+  }
+}
+
+/// The code for diagnostics about files that could not be read.
+const UNREADABLE_FILE: &str = "unreadable-file";
+/// The code for diagnostics about files that could not be parsed as xml.
+const PARSE_ERROR: &str = "parse-error";
+/// The code for diagnostics about root elements without their required attribute.
+const INVALID_ROOT: &str = "invalid-root";
+/// The code for diagnostics about references to datasets that do not exist.
+const UNKNOWN_DATASET: &str = "unknown-dataset";
+/// The code for diagnostics about data rows whose dataset does not exist.
+pub const ORPHANED_ROW: &str = "orphaned-row";
+/// The code for diagnostics about data rows that do not fit the fields of their dataset.
+pub const INVALID_ROW: &str = "invalid-row";
+/// The code for diagnostics about data rows whose name another row of the dataset already has.
+pub const DUPLICATE_ROW_NAME: &str = "duplicate-row-name";
+/// The code for diagnostics about pages whose output path another page already has.
+pub const PATH_COLLISION: &str = "path-collision";
+
+/// The oeuvre elements that are read as a site loads, rather than replaced while rendering.
+const STRUCTURAL_ELEMENTS: [&str; 8] = [
+  "oeuvre-page",
+  "oeuvre-snippet",
+  "oeuvre-dataset",
+  "oeuvre-datarow",
+  "oeuvre-field",
+  "oeuvre-value",
+  "oeuvre-format",
+  "oeuvre-fragment",
+];
+
+/// Checks the text of a JSON dataset or data row at `path` for parse errors.
+fn validate_json(path: &Path, text: &str) -> Vec<Diagnostic> {
+  match serde_json::from_str::<serde_json::Value>(text) {
+    Ok(_) => Vec::new(),
+    Err(err) => vec![Diagnostic {
+      position: Some(Position { line: err.line(), column: err.column() }),
+      ..Diagnostic::error(
+        path,
+        PARSE_ERROR,
+        format!("Could not be parsed as json. Cause: {}", err),
+      )
+    }],
+  }
+}
+
+/// Whether `name` is an oeuvre element that may appear in source files: one that is
+/// read as the site loads or replaced while rendering, or, given the loaded `site`,
+/// one of its shorthand elements or an element that one of its plugins renders.
+fn is_known_element(name: &str, site: Option<&Site>) -> bool {
+  STRUCTURAL_ELEMENTS.contains(&name)
+    || is_rendered_element(name)
+    || site.is_some_and(|site| {
+      site.config.elements.contains_key(name)
+        || site.plugins.iter().any(|plugin| plugin.handles(name))
+    })
+}
+
+/// Checks a single source file on its own, without loading the rest of its site,
+/// and returns the problems found, sorted by position. The file's category is
+/// inferred from its root element, and it is checked for parse errors, a root
+/// element without its required attribute, and unknown oeuvre elements.
+/// JSON datasets and data rows are only checked for parse errors.
+pub fn validate_file(path: &Path) -> Vec<Diagnostic> {
+  validate_source(path, None)
+}
+
+/// Checks a single source file like `validate_file`, also accepting the shorthand
+/// elements of `site`, if it is given.
+fn validate_source(path: &Path, site: Option<&Site>) -> Vec<Diagnostic> {
+  let text = match fs::read_to_string(path) {
+    Ok(text) => text,
+    Err(err) => {
+      return vec![Diagnostic::error(
+        path,
+        UNREADABLE_FILE,
+        format!("{} could not be read. Cause: {}", path.display(), err),
+      )]
+    }
+  };
+  if is_json(path) {
+    return validate_json(path, &text);
+  }
+  let mut reader = EventReader::from_str(&text);
+  let root = match Element::from_reader(&mut reader) {
+    Ok(root) => root,
+    Err(err) => {
+      let offset = reader.buffer_position().min(text.len());
+      return vec![Diagnostic {
+        position: Some(Position::at(&text, floor_char_boundary(&text, offset))),
+        ..Diagnostic::error(
+          path,
+          PARSE_ERROR,
+          format!("Could not be parsed as xml. Cause: {}", err),
+        )
+      }];
+    }
+  };
+
+  // Each start tag in the text belongs to the element at the same place in document order.
+  let mut elements = Vec::<&Element>::new();
+  collect_elements(&root, &mut elements);
+  let tag_offsets = start_tag_offsets(&text);
+  let position_of = |index: usize| {
+    if tag_offsets.len() == elements.len() {
+      Some(Position::at(&text, tag_offsets[index]))
+    } else {
+      None
+    }
+  };
+
+  let mut diagnostics = Vec::<Diagnostic>::new();
+  let (category, expected_name, required_attr) = match root.name() {
+    "oeuvre-page" => ("page", Some("oeuvre-page"), "oeuvre-template"),
+    "oeuvre-snippet" => ("snippet", Some("oeuvre-snippet"), "oeuvre-name"),
+    "oeuvre-dataset" => ("dataset", Some("oeuvre-dataset"), "oeuvre-name"),
+    "oeuvre-datarow" => ("data row", Some("oeuvre-datarow"), "oeuvre-dataset"),
+    _ => ("template", None, "oeuvre-name"),
+  };
+  if let Err(err) = validate_root(&root, category, expected_name, required_attr) {
+    diagnostics.push(Diagnostic {
+      position: position_of(0),
+      ..Diagnostic::error(path, INVALID_ROOT, err.to_string())
+    });
+  }
+  for (index, element) in elements.iter().enumerate() {
+    let name = element.name();
+    if name.starts_with("oeuvre-")
+      && !is_known_element(name, site)
+      && !allows(element, UNKNOWN_ELEMENT)
+    {
+      diagnostics.push(Diagnostic {
+        position: position_of(index),
+        ..Diagnostic::error(
+          path,
+          UNKNOWN_ELEMENT,
+          format!("Unknown oeuvre element found: {}", name),
+        )
+      });
+    }
+  }
+  diagnostics.sort();
+  diagnostics
+}
+
+/// Adds `element` and its descendants to `elements`, in document order.
+fn collect_elements<'a>(element: &'a Element, elements: &mut Vec<&'a Element>) {
+  elements.push(element);
+  for child in element.children() {
+    collect_elements(child, elements);
+  }
+}
+
+/// Returns the byte offset of each start or empty-element tag in `text`, in order,
+/// skipping comments, CDATA sections, processing instructions and doctypes.
+fn start_tag_offsets(text: &str) -> Vec<usize> {
+  let mut offsets = Vec::new();
+  let mut at = 0;
+  while let Some(found) = text[at..].find('<') {
+    let start = at + found;
+    let rest = &text[start..];
+    let skip_to = |terminator: &str| rest.find(terminator).map(|end| start + end + terminator.len());
+    let next = if rest.starts_with("<!--") {
+      skip_to("-->")
+    } else if rest.starts_with("<![CDATA[") {
+      skip_to("]]>")
+    } else if rest.starts_with("<?") {
+      skip_to("?>")
+    } else if rest.starts_with("<!") || rest.starts_with("</") {
+      skip_to(">")
+    } else {
+      offsets.push(start);
+      Some(start + 1)
+    };
+    match next {
+      Some(next) => at = next,
+      None => break,
+    }
+  }
+  offsets
+}
+
+/// Returns the largest offset no greater than `offset` that lies on a character boundary.
+fn floor_char_boundary(text: &str, mut offset: usize) -> usize {
+  while !text.is_char_boundary(offset) {
+    offset -= 1;
+  }
+  offset
+}
+
+/// Checks a loaded site for problems that would otherwise only surface while rendering,
+/// along with problems in each of its source files, including those that could not be
+/// loaded, and returns them sorted by file.
+pub fn check(site: &Site) -> Vec<Diagnostic> {
+  let mut diagnostics = site.row_diagnostics.clone();
+  diagnostics.extend(site.path_collisions.iter().cloned());
+  diagnostics.extend(check_config_dataset_references(site));
+  for path in &site.source_paths {
+    diagnostics.extend(validate_source(path, Some(site)));
+  }
+  for template in site.templates.values() {
+    check_dataset_references(site, &template.element, &template.path, &mut diagnostics);
+  }
+  for snippet in site.snippets.values() {
+    check_dataset_references(site, &snippet.element, &snippet.path, &mut diagnostics);
+  }
+  for page in site.pages.values() {
+    for slot_value in page.slot_values().values() {
+      check_dataset_references(site, slot_value, page.source_path(), &mut diagnostics);
+    }
+  }
+  // A page element that fills several slots is checked once for each.
+  diagnostics.into_iter().sorted().dedup().collect()
+}
+
+/// A diagnostic as `diagnostics_json` writes it, with its path as a url path.
+#[derive(Serialize)]
+struct DiagnosticJson<'a> {
+  path: String,
+  line: Option<usize>,
+  column: Option<usize>,
+  severity: &'static str,
+  message: &'a str,
+  code: &'static str,
+}
+
+/// Returns `diagnostics` as a JSON array with an object for each diagnostic, listing its
+/// path, line and column, which are null if unknown, severity, message and code.
+pub fn diagnostics_json(diagnostics: &[Diagnostic]) -> String {
+  let entries: Vec<DiagnosticJson> = diagnostics
+    .iter()
+    .map(|diagnostic| DiagnosticJson {
+      path: url_path(&diagnostic.path),
+      line: diagnostic.position.as_ref().map(|position| position.line),
+      column: diagnostic.position.as_ref().map(|position| position.column),
+      severity: diagnostic.severity.name(),
+      message: &diagnostic.message,
+      code: diagnostic.code,
+    })
+    .collect();
+  // Serializing plain strings and numbers cannot fail.
+  serde_json::to_string_pretty(&entries).unwrap() + "\n"
+}
+
+/// Returns a diagnostic for each feed, archive and set of transforms in the site's config
+/// that names a dataset which was not loaded, reported against the config file.
+pub fn check_config_dataset_references(site: &Site) -> Vec<Diagnostic> {
+  let config_path = site.config.path.clone().unwrap_or_else(|| PathBuf::from("site.toml"));
+  let config = &site.config;
+  let references = config
+    .feeds
+    .iter()
+    .map(|feed| (format!("Feed {} references", feed.path), &feed.dataset))
+    .chain(
+      config
+        .archives
+        .iter()
+        .map(|archive| (format!("Archive {} references", archive.path), &archive.dataset)),
+    )
+    .chain(
+      config
+        .transforms
+        .keys()
+        .map(|dataset_name| ("Transforms reference".to_string(), dataset_name)),
+    );
+  references
+    .filter(|(_, dataset_name)| !site.datasets.contains_key(dataset_name.as_str()))
+    .map(|(referrer, dataset_name)| {
+      Diagnostic::error(
+        &config_path,
+        UNKNOWN_DATASET,
+        format!("{} dataset {}, which does not exist", referrer, dataset_name),
+      )
+    })
+    .sorted()
+    .collect()
+}
+
+/// Reports each oeuvre-dataset attribute in `element` or its descendants
+/// that names a dataset which was not loaded.
+fn check_dataset_references(
+  site: &Site,
+  element: &Element,
+  path: &Path,
+  diagnostics: &mut Vec<Diagnostic>,
+) {
+  if let Some(dataset_name) = element.attr("oeuvre-dataset") {
+    if element.name() != "oeuvre-datarow" && !site.datasets.contains_key(dataset_name) {
+      diagnostics.push(Diagnostic::error(
+        path,
+        UNKNOWN_DATASET,
+        format!(
+          "<{}> references dataset {}, which does not exist",
+          element.name(),
+          dataset_name
+        ),
+      ));
+    }
+  }
+  for child in element.children() {
+    check_dataset_references(site, child, path, diagnostics);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::site::test_utils::TestSite;
+  use crate::site::SiteConfig;
+
+  #[test]
+  fn unknown_dataset_reference_is_flagged() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        r#"<html oeuvre-name="main"><oeuvre-nav oeuvre-dataset="posts"/><oeuvre-slot oeuvre-name="nav"/></html>"#,
+      )
+      .file(
+        "data/posts.xml",
+        r#"<oeuvre-dataset oeuvre-name="posts"><oeuvre-field oeuvre-name="title" oeuvre-type="string"/></oeuvre-dataset>"#,
+      )
+      .file(
+        "index.xml",
+        r#"<oeuvre-page oeuvre-template="main"><oeuvre-nav oeuvre-slot="nav" oeuvre-dataset="psots"/></oeuvre-page>"#,
+      );
+    assert_eq!(
+      check(&site.load("").unwrap()),
+      vec![Diagnostic::error(
+        Path::new("index.xml"),
+        UNKNOWN_DATASET,
+        "<oeuvre-nav> references dataset psots, which does not exist".to_string(),
+      )]
+    );
+  }
+
+  #[test]
+  fn unknown_datasets_in_the_config_are_flagged() {
+    let site = TestSite::new();
+    site
+      .file(
+        "data/posts.xml",
+        r#"<oeuvre-dataset oeuvre-name="posts"><oeuvre-field oeuvre-name="tags" oeuvre-type="string"/></oeuvre-dataset>"#,
+      )
+      .file("templates/main.xml", r#"<html oeuvre-name="main"/>"#);
+    let config = concat!(
+      "[[feeds]]\ndataset = \"psots\"\npath = \"feed.json\"\ntitle = \"Posts\"\n",
+      "[[archives]]\ndataset = \"posts\"\ngroup_by = \"tags\"\ntemplate = \"main\"\npath = \"tags\"\n",
+      "[[archives]]\ndataset = \"tgas\"\ngroup_by = \"tags\"\ntemplate = \"main\"\npath = \"topics\"\n",
+      "[[transforms.post]]\nfield = \"title\"\nvalue = \"Untitled\"\n",
+    );
+    let mut config = toml::from_str::<SiteConfig>(config).unwrap();
+    config.path = Some(PathBuf::from("site.toml"));
+    let unknown = |message: &str| {
+      Diagnostic::error(Path::new("site.toml"), UNKNOWN_DATASET, message.to_string())
+    };
+    assert_eq!(
+      check(&site.load_config(config).unwrap()),
+      vec![
+        unknown("Archive topics references dataset tgas, which does not exist"),
+        unknown("Feed feed.json references dataset psots, which does not exist"),
+        unknown("Transforms reference dataset post, which does not exist"),
+      ]
+    );
+    let err = site.build("strict = true\n[[transforms.post]]\nfield = \"a\"\nvalue = \"b\"").err().unwrap();
+    assert_eq!(
+      err.to_string(),
+      "Strict build failed with 1 config reference(s) to unknown datasets"
+    );
+  }
+
+  #[test]
+  fn template_without_name_is_reported_with_position() {
+    let site = TestSite::new();
+    site.file(
+      "templates/main.xml",
+      "<!-- The main layout -->\n  <html><body><oeuvre-widget/></body></html>",
+    );
+    let path = site.path("templates/main.xml");
+    assert_eq!(
+      validate_file(&path),
+      vec![
+        Diagnostic {
+          position: Some(Position { line: 2, column: 3 }),
+          ..Diagnostic::error(
+            &path,
+            INVALID_ROOT,
+            "Expected a template root element with an oeuvre-name attribute, but found <html> without one"
+              .to_string(),
+          )
+        },
+        Diagnostic {
+          position: Some(Position { line: 2, column: 15 }),
+          ..Diagnostic::error(
+            &path,
+            UNKNOWN_ELEMENT,
+            "Unknown oeuvre element found: oeuvre-widget".to_string(),
+          )
+        },
+      ]
+    );
+  }
+
+  #[test]
+  fn rendered_and_shorthand_elements_are_known() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        r#"<html oeuvre-name="main"><oeuvre-head/><oeuvre-counter-reset oeuvre-name="figure"/><oeuvre-lead>Hi</oeuvre-lead></html>"#,
+      )
+      .file("index.xml", r#"<oeuvre-page oeuvre-template="main"/>"#);
+    let config = "[elements]\noeuvre-lead = { tag = \"p\", class = \"lead\" }";
+    assert_eq!(check(&site.load(config).unwrap()), Vec::new());
+
+    // Without the site's config, the shorthand element is unknown.
+    let diagnostics = validate_file(&site.path("templates/main.xml"));
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].message, "Unknown oeuvre element found: oeuvre-lead");
+  }
+
+  #[test]
+  fn json_sources_are_checked_as_json() {
+    let site = TestSite::new();
+    site
+      .file(
+        "data/posts.json",
+        r#"{ "name": "posts", "fields": { "title": { "type": "string" } } }"#,
+      )
+      .file(
+        "data/posts/first.json",
+        r#"{ "dataset": "posts", "values": { "title": "First" } }"#,
+      )
+      .file(
+        "data/posts/second.json",
+        "{\n  \"dataset\": \"posts\",\n  \"values\": { \"title\": 01 }\n}",
+      );
+    let config = concat!(
+      "datasets = [\"data/*.json\"]\n",
+      "datarows = [\"data/*/**/*.json\"]"
+    );
+    let path = PathBuf::from("data/posts/second.json");
+    assert_eq!(
+      check(&site.load(config).unwrap()),
+      vec![Diagnostic {
+        position: Some(Position { line: 3, column: 25 }),
+        ..Diagnostic::error(
+          &path,
+          PARSE_ERROR,
+          "Could not be parsed as json. Cause: invalid number at line 3 column 25".to_string(),
+        )
+      }]
+    );
+  }
+
+  #[test]
+  fn diagnostics_are_written_as_json() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        r#"<html oeuvre-name="main"><oeuvre-slot oeuvre-name="body"/></html>"#,
+      )
+      .file(
+        "broken.xml",
+        "<oeuvre-page oeuvre-template=\"main\">\n  <p oeuvre-slot=\"body\">Hi</div>\n</oeuvre-page>",
+      );
+    let diagnostics = check(&site.load("").unwrap());
+    let written: serde_json::Value = serde_json::from_str(&diagnostics_json(&diagnostics)).unwrap();
+    assert_eq!(
+      written,
+      serde_json::json!([{
+        "path": "broken.xml",
+        "line": 2,
+        "column": 29,
+        "severity": "error",
+        "message": "Could not be parsed as xml. Cause: XML error: Expecting </p> found </div>",
+        "code": "parse-error"
+      }])
+    );
+    assert_eq!(diagnostics_json(&[]), "[]\n");
+  }
+}
@@ -1,21 +1,38 @@
-use super::load_xml;
+use super::check::{Diagnostic, Severity, DUPLICATE_ROW_NAME, INVALID_ROW, ORPHANED_ROW};
+use super::render::interpolate_with;
+use super::site_config::TransformConfig;
+use super::{load_xml, read_source, validate_root, SiteConfig};
 use crate::minidom::Element;
-use anyhow::{bail, Result};
-use log::{error, info};
-use std::collections::HashMap;
+use anyhow::{anyhow, bail, Result};
+use log::{error, info, warn};
+use serde_json::Value as JsonValue;
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
 use std::path::PathBuf;
 
+#[derive(Clone)]
 pub enum FieldType {
   String,
   Fragment,
 }
 
+#[derive(Clone)]
 pub enum FieldValue {
   String(String),
   Fragment(Element),
 }
 
+impl FieldValue {
+  /// Returns the value as plain text; fragments yield their text content.
+  pub fn text(&self) -> String {
+    match self {
+      FieldValue::String(value) => value.clone(),
+      FieldValue::Fragment(element) => element.text(),
+    }
+  }
+}
+
+#[derive(Clone)]
 pub struct Field {
   pub name: String,
   pub required: bool,
@@ -23,21 +40,22 @@ pub struct Field {
   pub default: Option<FieldValue>,
 }
 
+/// A single entry in a dataset, mapping field names to their values.
+pub type DataRow = HashMap<String, FieldValue>;
+
 /// A set of structured data.
 pub struct Dataset {
     pub name: String,
     pub fields: HashMap<String, Field>,
+    pub rows: Vec<DataRow>,
+    /// The index in `rows` of each row named by an oeuvre-name attribute, keyed by name.
+    pub row_names: HashMap<String, usize>,
 }
 
 impl Dataset {
   fn new(element: Element, datasets: &HashMap<String, Dataset>) -> Result<Dataset> {
-    let name = match element.attr("oeuvre-name") {
-      None => {
-        bail!("Dataset requires a root element with an oeuvre-name attribute");
-      }
-      Some(attr_value) => attr_value
-    }
-    .to_string();
+    validate_root(&element, "dataset", Some("oeuvre-dataset"), "oeuvre-name")?;
+    let name = element.attr("oeuvre-name").unwrap().to_string();
 
     if datasets.contains_key(&name) {
       bail!(
@@ -46,10 +64,21 @@ impl Dataset {
       );
     }
 
-    let mut fields = HashMap::<String, Field>::new();
+    // Fields inherited from a base dataset can be overridden, but not redefined.
+    let mut fields = match element.attr("oeuvre-extends") {
+      Some(base_name) => match datasets.get(base_name) {
+        Some(base) => base.fields.clone(),
+        None => bail!(
+          "Dataset {} extends dataset {}, which does not exist",
+          name,
+          base_name
+        ),
+      },
+      None => HashMap::<String, Field>::new(),
+    };
+    let mut own_field_names = HashSet::<String>::new();
 
     for child in element.children() {
-      let field_name = child.attr("oeuvre-name");
       let field_name = match child.attr("oeuvre-name") {
         None => {
           error!("Data field requires an oeuvre-name attribute");
@@ -58,22 +87,20 @@ impl Dataset {
         Some(attr_value) => attr_value.to_string()
       };
 
-      if fields.contains_key(&field_name) {
+      if !own_field_names.insert(field_name.clone()) {
         error!(
           "Data field has the oeuvre-name attribute value {}, which is already in use by another field",
-          name
+          field_name
         );
         continue;
       }
 
-      let required = match child.attr("oeuvre-required") {
-        Some(attr_value) => match attr_value.parse() {
-          Ok(parsed_value) => parsed_value,
-          Err(err) => {
-            error!("-- {}", err);
-            continue;
-          }
-        },
+      let required = match child.attr_bool("oeuvre-required") {
+        Some(Ok(parsed_value)) => parsed_value,
+        Some(Err(err)) => {
+          error!("-- {}", err);
+          continue;
+        }
         None => false
       };
 
@@ -90,46 +117,779 @@ impl Dataset {
         }
       };
       
+      // A default is given by an oeuvre-default attribute or, failing that, by the
+      // field element's own contents.
+      let has_contents = child
+        .nodes()
+        .any(|node| node.as_text().is_none_or(|text| !text.trim().is_empty()));
+      let default = match (child.attr("oeuvre-default"), &field_type) {
+        (Some(value), FieldType::String) => Some(FieldValue::String(value.to_string())),
+        (Some(value), FieldType::Fragment) => {
+          let mut fragment = Element::bare("oeuvre-fragment", None::<String>);
+          fragment.append_text_node(value);
+          Some(FieldValue::Fragment(fragment))
+        }
+        (None, FieldType::String) if has_contents => Some(FieldValue::String(child.text())),
+        (None, FieldType::Fragment) if has_contents => Some(FieldValue::Fragment(child.clone())),
+        (None, _) => None,
+      };
+
       fields.insert(field_name.clone(), Field {
         name: field_name,
         required,
         field_type,
+        default,
       });
     }
 
-    Ok(Dataset { name, fields })
+    Ok(Dataset {
+      name,
+      fields,
+      rows: Vec::new(),
+      row_names: HashMap::new(),
+    })
   }
 
-  fn load(path: &Path, datasets: &HashMap<String, Dataset>) -> Result<Dataset> {
-    let element = load_xml(path)?;
-    Dataset::new(element, datasets)
+  /// Returns the row named `row_name` by its oeuvre-name attribute, if there is one.
+  pub fn row(&self, row_name: &str) -> Option<&DataRow> {
+    self.row_names.get(row_name).map(|&index| &self.rows[index])
   }
 
   /// Loads and parses the datasets indicated by `dataset_paths` and returns them
-  /// in a HashMap using the dataset name as the key.
-  pub fn load_many(dataset_paths: &[PathBuf]) -> HashMap<String, Dataset> {
-    let mut datasets = HashMap::<String, Dataset>::new();
+  /// in a HashMap using the dataset name as the key. A dataset that extends another
+  /// is created after its base, whatever their order; datasets whose oeuvre-extends
+  /// attributes form a cycle are not loaded.
+  pub fn load_many(dataset_paths: &[PathBuf], config: &SiteConfig) -> HashMap<String, Dataset> {
+    let mut pending = Vec::<(&PathBuf, Element)>::new();
     for dataset_path in dataset_paths {
       info!("- Reading {}", dataset_path.display());
-      let dataset = match Dataset::load(dataset_path, &datasets) {
-        Ok(dataset) => dataset,
+      match load_dataset(dataset_path, config) {
+        Ok(element) => pending.push((dataset_path, element)),
+        Err(err) => error!("-- {}", err),
+      };
+    }
+
+    let mut datasets = HashMap::<String, Dataset>::new();
+    while !pending.is_empty() {
+      let pending_names: HashSet<&str> = pending
+        .iter()
+        .filter_map(|(_, element)| element.attr("oeuvre-name"))
+        .collect();
+      let (ready, waiting): (Vec<_>, Vec<_>) =
+        pending.iter().partition(|(_, element)| match element.attr("oeuvre-extends") {
+          Some(base_name) => !pending_names.contains(base_name),
+          None => true,
+        });
+      if ready.is_empty() {
+        for (dataset_path, element) in waiting {
+          error!(
+            "-- Dataset {} from {} extends a dataset that extends it in turn",
+            element.attr("oeuvre-name").unwrap_or_default(),
+            dataset_path.display()
+          );
+        }
+        break;
+      }
+
+      for (dataset_path, element) in ready {
+        let dataset = match Dataset::new(element.clone(), &datasets) {
+          Ok(dataset) => dataset,
+          Err(err) => {
+            error!("-- {}", err);
+            continue;
+          }
+        };
+        info!(
+          "-- Loaded dataset {} from {}",
+          dataset.name,
+          dataset_path.display(),
+        );
+        datasets.insert(dataset.name.clone(), dataset);
+      }
+      pending = waiting.into_iter().cloned().collect();
+    }
+    datasets
+  }
+  
+  /// Parses the fields of a data row element for this dataset.
+  /// Each child element provides the value of the field named by its oeuvre-name attribute,
+  /// and fields that the row leaves out take their defaults, if they have one.
+  fn parse_row(&self, element: &Element) -> Result<DataRow> {
+    let mut row = DataRow::new();
+    for child in element.children() {
+      let field_name = match child.attr("oeuvre-name") {
+        Some(attr_value) => attr_value,
+        None => bail!("Data row value requires an oeuvre-name attribute"),
+      };
+      let field = match self.fields.get(field_name) {
+        Some(field) => field,
+        None => bail!("Dataset {} has no field named {}", self.name, field_name),
+      };
+      let value = match field.field_type {
+        FieldType::String => FieldValue::String(child.text()),
+        FieldType::Fragment => FieldValue::Fragment(child.clone()),
+      };
+      row.insert(field_name.to_string(), value);
+    }
+    for field in self.fields.values() {
+      if let (false, Some(default)) = (row.contains_key(&field.name), &field.default) {
+        row.insert(field.name.clone(), default.clone());
+      }
+    }
+    Ok(row)
+  }
+
+  /// Returns an error naming the first of this dataset's required fields, in name
+  /// order, that `row` lacks. Since defaults are filled in as rows are parsed, only
+  /// required fields without a default can be missing.
+  fn validate_row(&self, row: &DataRow) -> Result<()> {
+    let missing = self
+      .fields
+      .values()
+      .filter(|field| field.required && !row.contains_key(&field.name))
+      .map(|field| field.name.as_str())
+      .min();
+    if let Some(field_name) = missing {
+      bail!(
+        "Data row for dataset {} is missing required field {}",
+        self.name,
+        field_name
+      );
+    }
+    Ok(())
+  }
+
+  /// Loads a data row file and returns its root element. A JSON data row is converted
+  /// to the `<oeuvre-datarow>` element it stands for.
+  fn load_row(
+    path: &Path,
+    config: &SiteConfig,
+    datasets: &HashMap<String, Dataset>,
+  ) -> Result<Element> {
+    let element = match is_json(path) {
+      true => row_element(&load_json(path, config)?, datasets)
+        .map_err(|err| anyhow!("{} is not a valid data row. Cause: {}", path.display(), err))?,
+      false => load_xml(path, config)?,
+    };
+    validate_root(&element, "data row", Some("oeuvre-datarow"), "oeuvre-dataset")?;
+    Ok(element)
+  }
+
+  /// Loads and parses the data rows indicated by `datarow_paths`
+  /// and add each to its dataset in `datasets`.
+  /// Returns a diagnostic for each orphaned row, whose dataset was not loaded, for each
+  /// row that does not fit the fields of its dataset, which is skipped, and for each row
+  /// whose name is already in use, which is loaded but cannot be looked up by name.
+  pub fn load_rows(
+    datarow_paths: &[PathBuf],
+    datasets: &mut HashMap<String, Dataset>,
+    config: &SiteConfig,
+  ) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::<Diagnostic>::new();
+    for datarow_path in datarow_paths {
+      info!("- Reading {}", datarow_path.display());
+      let element = match Dataset::load_row(datarow_path, config, datasets) {
+        Ok(element) => element,
         Err(err) => {
           error!("-- {}", err);
           continue;
         }
       };
+      let dataset_name = element.attr("oeuvre-dataset").unwrap();
+      let dataset = match datasets.get_mut(dataset_name) {
+        Some(dataset) => dataset,
+        None => {
+          let orphan = Diagnostic {
+            path: datarow_path.clone(),
+            position: None,
+            severity: Severity::Warning,
+            code: ORPHANED_ROW,
+            message: format!(
+              "Orphaned data row belongs to dataset {}, which does not exist",
+              dataset_name
+            ),
+          };
+          warn!("-- {}", orphan);
+          diagnostics.push(orphan);
+          continue;
+        }
+      };
+      let row = dataset.parse_row(&element).and_then(|mut row| {
+        if let Some(transforms) = config.transforms.get(dataset_name) {
+          apply_transforms(&mut row, transforms);
+        }
+        dataset.validate_row(&row)?;
+        Ok(row)
+      });
+      let row = match row {
+        Ok(row) => row,
+        Err(err) => {
+          let invalid = Diagnostic::error(datarow_path, INVALID_ROW, err.to_string());
+          error!("-- {}", invalid);
+          diagnostics.push(invalid);
+          continue;
+        }
+      };
+      if let Some(row_name) = element.attr("oeuvre-name") {
+        if dataset.row_names.contains_key(row_name) {
+          let duplicate = Diagnostic {
+            path: datarow_path.clone(),
+            position: None,
+            severity: Severity::Warning,
+            code: DUPLICATE_ROW_NAME,
+            message: format!(
+              "Data row has the oeuvre-name attribute value {}, which is already in use by another row of dataset {}",
+              row_name, dataset_name
+            ),
+          };
+          warn!("-- {}", duplicate);
+          diagnostics.push(duplicate);
+        } else {
+          dataset.row_names.insert(row_name.to_string(), dataset.rows.len());
+        }
+      }
+      dataset.rows.push(row);
       info!(
-        "-- Loaded dataset {} from {}",
-        dataset.name,
-        dataset_path.display(),
+        "-- Loaded data row for {} from {}",
+        dataset_name,
+        datarow_path.display()
       );
-      datasets.insert(dataset.name.clone(), dataset);
     }
-    datasets
+    diagnostics
   }
-  
-  /// Loads and parses the data rows indicated by `datarow_paths`
-  /// and add each to its dataset in `datasets`.
-  pub fn load_rows(datarow_paths: &[PathBuf], datasets: &HashMap<String, Dataset>) {
+}
+
+/// Whether the file at `path` holds JSON rather than XML, judging by its extension.
+pub fn is_json(path: &Path) -> bool {
+  path
+    .extension()
+    .is_some_and(|extension| extension.eq_ignore_ascii_case("json"))
+}
+
+/// Loads and parses the JSON document at `path`.
+fn load_json(path: &Path, config: &SiteConfig) -> Result<JsonValue> {
+  match serde_json::from_str(&read_source(path, config)?) {
+    Ok(json) => Ok(json),
+    Err(err) => bail!("{} could not be parsed as json. Cause: {}", path.display(), err),
+  }
+}
+
+/// Loads a dataset file and returns its root element. A JSON dataset is converted to
+/// the `<oeuvre-dataset>` element it stands for.
+fn load_dataset(path: &Path, config: &SiteConfig) -> Result<Element> {
+  if !is_json(path) {
+    return load_xml(path, config);
+  }
+  dataset_element(&load_json(path, config)?)
+    .map_err(|err| anyhow!("{} is not a valid dataset. Cause: {}", path.display(), err))
+}
+
+/// Returns the members of the object named `name` within `json`, which may be left out,
+/// in the order they were written.
+fn json_members<'a>(json: &'a JsonValue, name: &str) -> Result<Vec<(&'a String, &'a JsonValue)>> {
+  match json.get(name) {
+    Some(JsonValue::Object(members)) => Ok(members.iter().collect()),
+    None => Ok(Vec::new()),
+    Some(_) => bail!("Expected {} to be an object", name),
+  }
+}
+
+/// Returns the text of a string, the digits of a number as written, or `true` or `false`
+/// for a boolean. Null, arrays and objects have no text.
+fn json_text(value: &JsonValue) -> Option<String> {
+  match value {
+    JsonValue::Bool(value) => Some(value.to_string()),
+    JsonValue::Number(number) => Some(number.to_string()),
+    JsonValue::String(string) => Some(string.clone()),
+    _ => None,
+  }
+}
+
+/// Converts a JSON dataset, such as `{ "name": "authors", "fields": { "bio": { "type":
+/// "fragment", "required": true } } }`, to the equivalent `<oeuvre-dataset>` element.
+/// A field may also name a `default`, which is parsed as markup for fragment fields.
+fn dataset_element(json: &JsonValue) -> Result<Element> {
+  let name = match json.get("name") {
+    Some(JsonValue::String(name)) => name,
+    _ => bail!("Expected an object with a name string"),
+  };
+  let mut element = Element::bare("oeuvre-dataset", None::<String>);
+  element.set_attr("oeuvre-name", name.as_str());
+  if let Some(base_name) = json.get("extends").and_then(json_text) {
+    element.set_attr("oeuvre-extends", base_name);
+  }
+  for (field_name, field) in json_members(json, "fields")? {
+    let mut child = Element::bare("oeuvre-field", None::<String>);
+    child.set_attr("oeuvre-name", field_name.as_str());
+    for (member, attr) in [("type", "oeuvre-type"), ("required", "oeuvre-required")] {
+      if let Some(value) = field.get(member).and_then(json_text) {
+        child.set_attr(attr, value);
+      }
+    }
+    if let Some(default) = field.get("default").and_then(json_text) {
+      match child.attr("oeuvre-type") {
+        Some("fragment") => append_markup(&mut child, field_name, &default)?,
+        _ => child.set_attr("oeuvre-default", default),
+      }
+    }
+    element.append_child(child);
+  }
+  Ok(element)
+}
+
+/// Converts a JSON data row, such as `{ "dataset": "authors", "name": "wcwedin",
+/// "values": { "bio": "<p>Hi</p>" } }`, to the equivalent `<oeuvre-datarow>` element.
+/// Values of the dataset's fragment fields are parsed as markup, and the rest are kept
+/// as text. Numbers and booleans are kept as they are written, and null values are left out.
+fn row_element(json: &JsonValue, datasets: &HashMap<String, Dataset>) -> Result<Element> {
+  let dataset_name = match json.get("dataset") {
+    Some(JsonValue::String(dataset_name)) => dataset_name,
+    _ => bail!("Expected an object with a dataset string"),
+  };
+  let mut element = Element::bare("oeuvre-datarow", None::<String>);
+  element.set_attr("oeuvre-dataset", dataset_name.as_str());
+  if let Some(row_name) = json.get("name").and_then(json_text) {
+    element.set_attr("oeuvre-name", row_name);
+  }
+  let fields = datasets.get(dataset_name).map(|dataset| &dataset.fields);
+  for (field_name, value) in json_members(json, "values")? {
+    let text = match (value, json_text(value)) {
+      (JsonValue::Null, _) => continue,
+      (_, Some(text)) => text,
+      (_, None) => bail!("Expected the value of {} to be a string, number or boolean", field_name),
+    };
+    let mut child = Element::bare("oeuvre-value", None::<String>);
+    child.set_attr("oeuvre-name", field_name.as_str());
+    let field_type = fields
+      .and_then(|fields| fields.get(field_name))
+      .map(|field| &field.field_type);
+    match field_type {
+      Some(FieldType::Fragment) => append_markup(&mut child, field_name, &text)?,
+      _ => child.append_text_node(text),
+    }
+    element.append_child(child);
+  }
+  Ok(element)
+}
+
+/// Appends the nodes that `markup`, the value of the field `field_name`, parses to.
+fn append_markup(element: &mut Element, field_name: &str, markup: &str) -> Result<()> {
+  let fragment = match format!("<oeuvre-fragment>{}</oeuvre-fragment>", markup).parse::<Element>() {
+    Ok(fragment) => fragment,
+    Err(err) => bail!("The value of {} could not be parsed as markup. Cause: {}", field_name, err),
+  };
+  for node in fragment.nodes() {
+    element.append_node(node.clone());
+  }
+  Ok(())
+}
+
+/// Applies `transforms` to `row` in order, so that each can use the fields set by
+/// those before it.
+fn apply_transforms(row: &mut DataRow, transforms: &[TransformConfig]) {
+  for transform in transforms {
+    if transform.if_missing && row.contains_key(&transform.field) {
+      continue;
+    }
+    let value = interpolate_with(&transform.value, |expression| evaluate_field(expression, row));
+    row.insert(transform.field.clone(), FieldValue::String(value));
+  }
+}
+
+/// Returns the value of a transform expression: the text of a field, like `date`, or
+/// the result of a function of one, like `year(date)`, `month(date)`, `day(date)`
+/// or `slug(title)`. Returns None if the field or function is not recognized.
+fn evaluate_field(expression: &str, row: &DataRow) -> Option<String> {
+  let (function, field_name) = match expression
+    .strip_suffix(')')
+    .and_then(|call| call.split_once('('))
+  {
+    Some((function, field_name)) => (function.trim(), field_name.trim()),
+    None => ("", expression),
+  };
+  let text = match row.get(field_name)? {
+    FieldValue::String(text) => text.clone(),
+    FieldValue::Fragment(element) => element.text(),
+  };
+  match function {
+    "" => Some(text),
+    "year" => date_part(&text, 0),
+    "month" => date_part(&text, 1),
+    "day" => date_part(&text, 2),
+    "slug" => Some(slug(&text)),
+    _ => None,
+  }
+}
+
+/// Returns the part of an ISO 8601 date, like `2021-03-14`, at `index`, or None if
+/// there is no such part.
+fn date_part(date: &str, index: usize) -> Option<String> {
+  let date = date.trim().split('T').next()?;
+  date
+    .split('-')
+    .nth(index)
+    .filter(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()))
+    .map(str::to_string)
+}
+
+/// Returns `text` in lowercase, with each run of characters other than letters and
+/// digits replaced by a single hyphen, e.g. `Hello, World!` becomes `hello-world`.
+pub fn slug(text: &str) -> String {
+  text
+    .split(|c: char| !c.is_alphanumeric())
+    .filter(|word| !word.is_empty())
+    .map(str::to_lowercase)
+    .collect::<Vec<_>>()
+    .join("-")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::site::test_utils::TestSite;
+  use itertools::Itertools;
+  use std::fs;
+  use tempfile::TempDir;
+
+  #[test]
+  fn template_root_is_rejected_as_dataset() {
+    let element: Element = r#"<html oeuvre-name="main"><body/></html>"#.parse().unwrap();
+    let err = Dataset::new(element, &HashMap::new()).err().unwrap();
+    assert_eq!(
+      err.to_string(),
+      "Expected a dataset root element <oeuvre-dataset> with an oeuvre-name attribute, but found <html>"
+    );
+  }
+
+  #[test]
+  fn dataset_root_without_name_is_rejected() {
+    let element: Element = "<oeuvre-dataset/>".parse().unwrap();
+    let err = Dataset::new(element, &HashMap::new()).err().unwrap();
+    assert_eq!(
+      err.to_string(),
+      "Expected a dataset root element <oeuvre-dataset> with an oeuvre-name attribute, but found <oeuvre-dataset> without one"
+    );
+  }
+
+  fn load_schemas(schemas: &[&str]) -> HashMap<String, Dataset> {
+    let dir = TempDir::new().unwrap();
+    let mut paths = Vec::new();
+    for (index, schema) in schemas.iter().enumerate() {
+      let path = dir.path().join(format!("{}.xml", index));
+      fs::write(&path, schema).unwrap();
+      paths.push(path);
+    }
+    Dataset::load_many(&paths, &toml::from_str::<SiteConfig>("").unwrap())
+  }
+
+  #[test]
+  fn dataset_inherits_and_overrides_base_fields() {
+    let datasets = load_schemas(&[
+      r#"<oeuvre-dataset oeuvre-name="article" oeuvre-extends="base-content"><oeuvre-field oeuvre-name="date" oeuvre-type="fragment"/><oeuvre-field oeuvre-name="body" oeuvre-type="fragment"/></oeuvre-dataset>"#,
+      r#"<oeuvre-dataset oeuvre-name="base-content"><oeuvre-field oeuvre-name="title" oeuvre-type="string" oeuvre-required="true"/><oeuvre-field oeuvre-name="date" oeuvre-type="string"/></oeuvre-dataset>"#,
+    ]);
+    let fields = &datasets["article"].fields;
+    assert_eq!(fields.keys().sorted().collect::<Vec<_>>(), ["body", "date", "title"]);
+    assert!(fields["title"].required);
+    assert!(matches!(fields["title"].field_type, FieldType::String));
+    assert!(matches!(fields["date"].field_type, FieldType::Fragment));
+    assert_eq!(datasets["base-content"].fields.len(), 2);
+  }
+
+  #[test]
+  fn extension_cycles_are_not_loaded() {
+    let datasets = load_schemas(&[
+      r#"<oeuvre-dataset oeuvre-name="a" oeuvre-extends="b"/>"#,
+      r#"<oeuvre-dataset oeuvre-name="b" oeuvre-extends="a"/>"#,
+      r#"<oeuvre-dataset oeuvre-name="c" oeuvre-extends="c"/>"#,
+      r#"<oeuvre-dataset oeuvre-name="d"/>"#,
+    ]);
+    assert_eq!(datasets.keys().collect::<Vec<_>>(), ["d"]);
+  }
+
+  #[test]
+  fn orphaned_rows_are_reported() {
+    let site = TestSite::new();
+    site
+      .file(
+        "data/posts.xml",
+        r#"<oeuvre-dataset oeuvre-name="posts"><oeuvre-field oeuvre-name="title" oeuvre-type="string"/></oeuvre-dataset>"#,
+      )
+      .file(
+        "data/posts/first.xml",
+        r#"<oeuvre-datarow oeuvre-dataset="posts"><oeuvre-value oeuvre-name="title">First</oeuvre-value></oeuvre-datarow>"#,
+      )
+      .file(
+        "data/notes/old.xml",
+        r#"<oeuvre-datarow oeuvre-dataset="notes"><oeuvre-value oeuvre-name="title">Old</oeuvre-value></oeuvre-datarow>"#,
+      );
+    let orphan = Diagnostic {
+      path: PathBuf::from("data/notes/old.xml"),
+      position: None,
+      severity: Severity::Warning,
+      code: ORPHANED_ROW,
+      message: "Orphaned data row belongs to dataset notes, which does not exist".to_string(),
+    };
+    let loaded = site.load("").unwrap();
+    assert_eq!(loaded.row_diagnostics, [orphan]);
+    assert_eq!(loaded.datasets["posts"].rows.len(), 1);
+    let err = site.build("strict = true").err().unwrap();
+    assert_eq!(err.to_string(), "Strict build failed with 1 orphaned data row(s)");
+  }
+
+  #[test]
+  fn rows_load_field_values_and_skip_rows_missing_required_fields() {
+    let site = TestSite::new();
+    site
+      .file(
+        "data/posts.xml",
+        r#"<oeuvre-dataset oeuvre-name="posts"><oeuvre-field oeuvre-name="title" oeuvre-type="string" oeuvre-required="true"/><oeuvre-field oeuvre-name="body" oeuvre-type="fragment"/></oeuvre-dataset>"#,
+      )
+      .file(
+        "data/posts/first.xml",
+        r#"<oeuvre-datarow oeuvre-dataset="posts"><oeuvre-value oeuvre-name="title">First post</oeuvre-value><oeuvre-value oeuvre-name="body"><p>Hi</p></oeuvre-value></oeuvre-datarow>"#,
+      )
+      .file(
+        "data/posts/untitled.xml",
+        r#"<oeuvre-datarow oeuvre-dataset="posts"><oeuvre-value oeuvre-name="body"><p>Hi</p></oeuvre-value></oeuvre-datarow>"#,
+      );
+    let loaded = site.load("").unwrap();
+    let rows = &loaded.datasets["posts"].rows;
+    assert_eq!(rows.len(), 1);
+    assert!(matches!(&rows[0]["title"], FieldValue::String(title) if title == "First post"));
+    match &rows[0]["body"] {
+      FieldValue::Fragment(body) => assert_eq!(body.inner_html(), "<p>Hi</p>"),
+      FieldValue::String(_) => panic!("Fragment field was loaded as a string"),
+    }
+    assert_eq!(
+      loaded.row_diagnostics,
+      [Diagnostic::error(
+        Path::new("data/posts/untitled.xml"),
+        INVALID_ROW,
+        "Data row for dataset posts is missing required field title".to_string(),
+      )]
+    );
+    let err = site.build("strict = true").err().unwrap();
+    assert_eq!(err.to_string(), "Strict build failed with 1 invalid data row(s)");
+  }
+
+  #[test]
+  fn rows_are_found_by_name_and_duplicate_names_are_reported() {
+    let site = TestSite::new();
+    site
+      .file(
+        "data/posts.xml",
+        r#"<oeuvre-dataset oeuvre-name="posts"><oeuvre-field oeuvre-name="title" oeuvre-type="string"/></oeuvre-dataset>"#,
+      )
+      .file(
+        "data/posts/a.xml",
+        r#"<oeuvre-datarow oeuvre-dataset="posts" oeuvre-name="first"><oeuvre-value oeuvre-name="title">A</oeuvre-value></oeuvre-datarow>"#,
+      )
+      .file(
+        "data/posts/b.xml",
+        r#"<oeuvre-datarow oeuvre-dataset="posts" oeuvre-name="first"><oeuvre-value oeuvre-name="title">B</oeuvre-value></oeuvre-datarow>"#,
+      )
+      .file(
+        "data/posts/c.xml",
+        r#"<oeuvre-datarow oeuvre-dataset="posts"><oeuvre-value oeuvre-name="title">C</oeuvre-value></oeuvre-datarow>"#,
+      );
+    let duplicate = Diagnostic {
+      path: PathBuf::from("data/posts/b.xml"),
+      position: None,
+      severity: Severity::Warning,
+      code: DUPLICATE_ROW_NAME,
+      message: "Data row has the oeuvre-name attribute value first, which is already in use by another row of dataset posts".to_string(),
+    };
+    let loaded = site.load("").unwrap();
+    assert_eq!(loaded.row_diagnostics, [duplicate]);
+    let posts = &loaded.datasets["posts"];
+    assert_eq!(posts.rows.len(), 3);
+    assert_eq!(posts.row("first").unwrap()["title"].text(), "A");
+    assert!(posts.row("second").is_none());
+  }
+
+  /// Builds a site listing the authors dataset and its one row, given as the files at
+  /// `dataset` and `row`, and returns the page written.
+  fn render_authors(dataset: (&str, &str), row: (&str, &str)) -> String {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        concat!(
+          r#"<html oeuvre-name="main"><body><oeuvre-for oeuvre-dataset="authors">"#,
+          r#"<h1><oeuvre-slot oeuvre-name="name"/></h1><oeuvre-slot oeuvre-name="bio"/>"#,
+          r#"<p><oeuvre-slot oeuvre-name="role"/> since <oeuvre-slot oeuvre-name="since"/></p>"#,
+          r#"</oeuvre-for><oeuvre-data oeuvre-dataset="authors" oeuvre-row="wcwedin" oeuvre-field="name"/>"#,
+          r#"</body></html>"#
+        ),
+      )
+      .file("index.xml", r#"<oeuvre-page oeuvre-template="main" oeuvre-path="index.html"/>"#)
+      .file(dataset.0, dataset.1)
+      .file(row.0, row.1);
+    site
+      .build(concat!(
+        "datasets = [\"data/*.xml\", \"data/*.json\"]\n",
+        "datarows = [\"data/*/**/*.xml\", \"data/*/**/*.json\"]"
+      ))
+      .unwrap();
+    site.read_output("index.html")
+  }
+
+  #[test]
+  fn json_datasets_render_like_xml_ones() {
+    let from_xml = render_authors(
+      (
+        "data/authors.xml",
+        concat!(
+          r#"<oeuvre-dataset oeuvre-name="authors">"#,
+          r#"<oeuvre-field oeuvre-name="name" oeuvre-type="string" oeuvre-required="true"/>"#,
+          r#"<oeuvre-field oeuvre-name="bio" oeuvre-type="fragment"/>"#,
+          r#"<oeuvre-field oeuvre-name="role" oeuvre-type="string" oeuvre-default="Author"/>"#,
+          r#"<oeuvre-field oeuvre-name="since" oeuvre-type="string"/></oeuvre-dataset>"#
+        ),
+      ),
+      (
+        "data/authors/wcwedin.xml",
+        concat!(
+          r#"<oeuvre-datarow oeuvre-dataset="authors" oeuvre-name="wcwedin">"#,
+          r#"<oeuvre-value oeuvre-name="name">W. C. Wedin</oeuvre-value>"#,
+          r#"<oeuvre-value oeuvre-name="bio"><p>Writes <em>oeuvre</em> &amp; more.</p></oeuvre-value>"#,
+          r#"<oeuvre-value oeuvre-name="since">2021</oeuvre-value></oeuvre-datarow>"#
+        ),
+      ),
+    );
+    let from_json = render_authors(
+      (
+        "data/authors.json",
+        r#"{
+          "name": "authors",
+          "fields": {
+            "name": { "type": "string", "required": true },
+            "bio": { "type": "fragment" },
+            "role": { "type": "string", "default": "Author" },
+            "since": { "type": "string" }
+          }
+        }"#,
+      ),
+      (
+        "data/authors/wcwedin.json",
+        r#"{
+          "dataset": "authors",
+          "name": "wcwedin",
+          "values": {
+            "name": "W. C. Wedin",
+            "bio": "<p>Writes <em>oeuvre</em> &amp; more.</p>",
+            "since": 2021
+          }
+        }"#,
+      ),
+    );
+    assert_eq!(
+      from_xml,
+      concat!(
+        "<!DOCTYPE html>\r\n",
+        r#"<html><body><h1>W. C. Wedin</h1><p>Writes <em>oeuvre</em> &amp; more.</p>"#,
+        r#"<p>Author since 2021</p>W. C. Wedin</body></html>"#
+      )
+    );
+    assert_eq!(from_json, from_xml);
+  }
+
+  fn load_rows_with_fields(fields: &str, values: &str) -> Vec<DataRow> {
+    let site = TestSite::new();
+    site
+      .file(
+        "data/posts.xml",
+        &format!(r#"<oeuvre-dataset oeuvre-name="posts">{}</oeuvre-dataset>"#, fields),
+      )
+      .file(
+        "data/posts/row.xml",
+        &format!(r#"<oeuvre-datarow oeuvre-dataset="posts">{}</oeuvre-datarow>"#, values),
+      );
+    site.load("").unwrap().datasets.remove("posts").unwrap().rows
+  }
+
+  #[test]
+  fn required_field_without_default_rejects_row() {
+    let rows = load_rows_with_fields(
+      r#"<oeuvre-field oeuvre-name="title" oeuvre-type="string" oeuvre-required="true"/><oeuvre-field oeuvre-name="body" oeuvre-type="string"/>"#,
+      r#"<oeuvre-value oeuvre-name="body">Hi</oeuvre-value>"#,
+    );
+    assert!(rows.is_empty());
+  }
+
+  #[test]
+  fn missing_fields_take_their_defaults() {
+    let rows = load_rows_with_fields(
+      concat!(
+        r#"<oeuvre-field oeuvre-name="title" oeuvre-type="string" oeuvre-required="true" oeuvre-default="Untitled"/>"#,
+        r#"<oeuvre-field oeuvre-name="layout" oeuvre-type="string" oeuvre-default="wide"/>"#,
+        r#"<oeuvre-field oeuvre-name="footer" oeuvre-type="fragment"><p>Thanks for reading</p></oeuvre-field>"#,
+      ),
+      r#"<oeuvre-value oeuvre-name="layout">narrow</oeuvre-value>"#,
+    );
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0]["title"].text(), "Untitled");
+    assert_eq!(rows[0]["layout"].text(), "narrow");
+    match &rows[0]["footer"] {
+      FieldValue::Fragment(footer) => assert_eq!(footer.inner_html(), "<p>Thanks for reading</p>"),
+      FieldValue::String(_) => panic!("Fragment default was loaded as a string"),
+    }
+  }
+
+  #[test]
+  fn missing_fields_without_defaults_are_absent() {
+    let rows = load_rows_with_fields(
+      r#"<oeuvre-field oeuvre-name="title" oeuvre-type="string"/><oeuvre-field oeuvre-name="body" oeuvre-type="fragment"/>"#,
+      r#"<oeuvre-value oeuvre-name="title">Hi</oeuvre-value>"#,
+    );
+    assert_eq!(rows.len(), 1);
+    assert!(!rows[0].contains_key("body"));
+  }
+
+  #[test]
+  fn transforms_derive_fields_in_declared_order() {
+    let site = TestSite::new();
+    site
+      .file(
+        "data/posts.xml",
+        r#"<oeuvre-dataset oeuvre-name="posts"><oeuvre-field oeuvre-name="title" oeuvre-type="string"/><oeuvre-field oeuvre-name="date" oeuvre-type="string"/></oeuvre-dataset>"#,
+      )
+      .file(
+        "data/posts/first.xml",
+        r#"<oeuvre-datarow oeuvre-dataset="posts"><oeuvre-value oeuvre-name="title">Hello, World!</oeuvre-value><oeuvre-value oeuvre-name="date">2021-03-14</oeuvre-value></oeuvre-datarow>"#,
+      )
+      .file(
+        "templates/main.xml",
+        r#"<html oeuvre-name="main"><body><script>const POSTS = <oeuvre-json oeuvre-dataset="posts"/>;</script></body></html>"#,
+      )
+      .file(
+        "index.xml",
+        r#"<oeuvre-page oeuvre-template="main" oeuvre-path="index.html"/>"#,
+      );
+    site
+      .build(
+        r#"
+        [[transforms.posts]]
+        field = "year"
+        value = "{{ year(date) }}"
+        [[transforms.posts]]
+        field = "slug"
+        value = "{{ year }}/{{ slug(title) }}"
+        if_missing = true
+        [[transforms.posts]]
+        field = "title"
+        value = "Replaced"
+        if_missing = true
+        "#,
+      )
+      .unwrap();
+    assert_eq!(
+      site.read_output("index.html"),
+      concat!(
+        "<!DOCTYPE html>\r\n",
+        r#"<html><body><script>const POSTS = [{"date":"2021-03-14","slug":"2021/hello-world","title":"Hello, World!","year":"2021"}];</script></body></html>"#
+      )
+    );
   }
 }
@@ -0,0 +1,239 @@
+use anyhow::{bail, Result};
+use log::{error, info};
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::minidom::WriteOptions;
+use super::dataset::FieldValue;
+use super::output::OutputSink;
+use super::site_config::{FeedConfig, FeedFormat};
+use super::Site;
+
+/// Writes each of the site's configured feeds to `sink`.
+pub fn write_feeds(site: &Site, sink: &dyn OutputSink) {
+  for feed in &site.config.feeds {
+    info!("- Writing feed {}", feed.path);
+    let result = match feed.format {
+      FeedFormat::Json => json_feed(site, feed),
+    };
+    match result.and_then(|contents| Ok(sink.write(Path::new(&feed.path), contents.as_bytes())?)) {
+      Ok(()) => info!("-- Wrote feed {}", feed.path),
+      Err(err) => error!("-- {}", err),
+    }
+  }
+}
+
+/// The item properties of JSON Feed 1.1 that hold a single string.
+const ITEM_PROPERTIES: &[&str] = &[
+  "banner_image",
+  "content_html",
+  "content_text",
+  "date_modified",
+  "date_published",
+  "external_url",
+  "id",
+  "image",
+  "language",
+  "summary",
+  "title",
+  "url",
+];
+
+/// Returns a JSON Feed 1.1 document with an item for each row of the feed's dataset.
+/// Each field of a row named after one of the `ITEM_PROPERTIES` fills that property,
+/// with fragment fields written as HTML, and a `tags` field's comma-separated values
+/// fill the `tags` array; other fields are left out. Every row must have a
+/// `content_html` or `content_text` field, as the spec requires. A url that begins
+/// with `/` is joined with the site's `base_url`, and an item without an `id` field
+/// uses its url instead.
+fn json_feed(site: &Site, feed: &FeedConfig) -> Result<String> {
+  let dataset = match site.datasets.get(&feed.dataset) {
+    Some(dataset) => dataset,
+    None => bail!(
+      "Feed {} lists dataset {}, which does not exist",
+      feed.path,
+      feed.dataset
+    ),
+  };
+  let base_url = site.config.base_url.trim_end_matches('/');
+  let absolute_url = |url: String| {
+    if url.starts_with('/') {
+      format!("{}{}", base_url, url)
+    } else {
+      url
+    }
+  };
+
+  let html_options = WriteOptions {
+    html: true,
+    ..WriteOptions::default()
+  };
+  let mut items = Vec::<Value>::new();
+  for (index, row) in dataset.rows.iter().enumerate() {
+    let mut properties = BTreeMap::<&str, String>::new();
+    for (name, value) in row {
+      if !ITEM_PROPERTIES.contains(&name.as_str()) {
+        continue;
+      }
+      let value = match value {
+        FieldValue::String(text) => text.clone(),
+        FieldValue::Fragment(element) => element.inner_html_with_options(&html_options),
+      };
+      properties.insert(name, value);
+    }
+    if !properties.contains_key("content_html") && !properties.contains_key("content_text") {
+      bail!(
+        "Feed {} lists row {} of dataset {}, which has neither a content_html nor a content_text field",
+        feed.path,
+        index,
+        feed.dataset
+      );
+    }
+    if let Some(url) = properties.remove("url") {
+      properties.insert("url", absolute_url(url));
+    }
+    if !properties.contains_key("id") {
+      let id = properties
+        .get("url")
+        .cloned()
+        .unwrap_or_else(|| index.to_string());
+      properties.insert("id", id);
+    }
+    let mut properties: BTreeMap<&str, Value> = properties
+      .into_iter()
+      .map(|(name, value)| (name, Value::String(value)))
+      .collect();
+    if let Some(tags) = row.get("tags") {
+      let tags = tags.text();
+      let tags: Vec<&str> = tags
+        .split(',')
+        .map(str::trim)
+        .filter(|tag| !tag.is_empty())
+        .collect();
+      properties.insert("tags", json!(tags));
+    }
+    items.push(json!(properties));
+  }
+
+  let mut document = Map::new();
+  document.insert("version".to_string(), json!("https://jsonfeed.org/version/1.1"));
+  document.insert("title".to_string(), json!(feed.title));
+  if !base_url.is_empty() {
+    document.insert("home_page_url".to_string(), json!(format!("{}/", base_url)));
+    let feed_url = absolute_url(format!("/{}", feed.path.trim_start_matches('/')));
+    document.insert("feed_url".to_string(), json!(feed_url));
+  }
+  document.insert("items".to_string(), Value::Array(items));
+  Ok(serde_json::to_string_pretty(&document)? + "\n")
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::site::test_utils::TestSite;
+
+  #[test]
+  fn json_feed_lists_dataset_rows() {
+    let site = TestSite::new();
+    site
+      .file(
+        "data/posts.xml",
+        concat!(
+          r#"<oeuvre-dataset oeuvre-name="posts">"#,
+          r#"<oeuvre-field oeuvre-name="title" oeuvre-type="string"/>"#,
+          r#"<oeuvre-field oeuvre-name="url" oeuvre-type="string"/>"#,
+          r#"<oeuvre-field oeuvre-name="date_published" oeuvre-type="string"/>"#,
+          r#"<oeuvre-field oeuvre-name="content_html" oeuvre-type="fragment"/>"#,
+          r#"<oeuvre-field oeuvre-name="content_text" oeuvre-type="string"/>"#,
+          r#"<oeuvre-field oeuvre-name="tags" oeuvre-type="string"/>"#,
+          r#"<oeuvre-field oeuvre-name="draft" oeuvre-type="string"/>"#,
+          "</oeuvre-dataset>"
+        ),
+      )
+      .file(
+        "data/posts/first.xml",
+        concat!(
+          r#"<oeuvre-datarow oeuvre-dataset="posts">"#,
+          r#"<oeuvre-value oeuvre-name="title">First</oeuvre-value>"#,
+          r#"<oeuvre-value oeuvre-name="url">/posts/first.html</oeuvre-value>"#,
+          r#"<oeuvre-value oeuvre-name="date_published">2024-01-01T00:00:00Z</oeuvre-value>"#,
+          r#"<oeuvre-value oeuvre-name="content_html"><p>Hello &amp;<br/>welcome</p></oeuvre-value>"#,
+          r#"<oeuvre-value oeuvre-name="tags">news, rust</oeuvre-value>"#,
+          r#"<oeuvre-value oeuvre-name="draft">no</oeuvre-value>"#,
+          "</oeuvre-datarow>"
+        ),
+      )
+      .file(
+        "data/posts/second.xml",
+        concat!(
+          r#"<oeuvre-datarow oeuvre-dataset="posts">"#,
+          r#"<oeuvre-value oeuvre-name="title">Second</oeuvre-value>"#,
+          r#"<oeuvre-value oeuvre-name="url">https://elsewhere.example/second</oeuvre-value>"#,
+          r#"<oeuvre-value oeuvre-name="content_text">Hello again</oeuvre-value>"#,
+          "</oeuvre-datarow>"
+        ),
+      );
+    site
+      .build(concat!(
+        "base_url = \"https://example.com/\"\n",
+        "[[feeds]]\n",
+        "dataset = \"posts\"\n",
+        "path = \"feed.json\"\n",
+        "title = \"Posts\"\n",
+        "format = \"json\"\n",
+      ))
+      .unwrap();
+    let feed: serde_json::Value = serde_json::from_str(&site.read_output("feed.json")).unwrap();
+    assert_eq!(
+      feed,
+      serde_json::json!({
+        "version": "https://jsonfeed.org/version/1.1",
+        "title": "Posts",
+        "home_page_url": "https://example.com/",
+        "feed_url": "https://example.com/feed.json",
+        "items": [
+          {
+            "content_html": "<p>Hello &amp;<br>welcome</p>",
+            "date_published": "2024-01-01T00:00:00Z",
+            "id": "https://example.com/posts/first.html",
+            "tags": ["news", "rust"],
+            "title": "First",
+            "url": "https://example.com/posts/first.html"
+          },
+          {
+            "content_text": "Hello again",
+            "id": "https://elsewhere.example/second",
+            "title": "Second",
+            "url": "https://elsewhere.example/second"
+          }
+        ]
+      })
+    );
+  }
+
+  #[test]
+  fn json_feed_requires_content_for_every_item() {
+    let site = TestSite::new();
+    site
+      .file(
+        "data/posts.xml",
+        concat!(
+          r#"<oeuvre-dataset oeuvre-name="posts">"#,
+          r#"<oeuvre-field oeuvre-name="title" oeuvre-type="string"/>"#,
+          "</oeuvre-dataset>"
+        ),
+      )
+      .file(
+        "data/posts/first.xml",
+        concat!(
+          r#"<oeuvre-datarow oeuvre-dataset="posts">"#,
+          r#"<oeuvre-value oeuvre-name="title">First</oeuvre-value>"#,
+          "</oeuvre-datarow>"
+        ),
+      );
+    site
+      .build("[[feeds]]\ndataset = \"posts\"\npath = \"feed.json\"\ntitle = \"Posts\"\n")
+      .unwrap();
+    assert!(!site.output_exists("feed.json"));
+  }
+}
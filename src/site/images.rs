@@ -0,0 +1,108 @@
+use anyhow::{bail, Result};
+use image::imageops::FilterType;
+use image::ImageFormat;
+use log::{error, info};
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+use super::output::OutputSink;
+use super::Site;
+
+/// Parses an oeuvre-responsive attribute, a comma-separated list of widths in pixels.
+pub fn parse_widths(widths: &str) -> Result<Vec<u32>> {
+  let mut parsed = Vec::new();
+  for width in widths.split(',').map(str::trim) {
+    match width.parse::<u32>() {
+      Ok(width) if width > 0 => parsed.push(width),
+      _ => bail!("{} is not a valid image width", width),
+    }
+  }
+  Ok(parsed)
+}
+
+/// Returns `name` with a width suffix before its extension, so that
+/// `assets/hero.jpg` at 400 pixels wide becomes `assets/hero-400w.jpg`.
+pub fn width_suffixed(name: &str, width: u32) -> String {
+  let file_start = name.rfind('/').map_or(0, |index| index + 1);
+  match name[file_start..].rfind('.') {
+    Some(dot) => format!(
+      "{}-{}w{}",
+      &name[..file_start + dot],
+      width,
+      &name[file_start + dot..]
+    ),
+    None => format!("{}-{}w", name, width),
+  }
+}
+
+/// Writes each resized image requested while rendering pages to `sink`,
+/// beside the image it was resized from.
+pub fn write_resized_images(site: &Site, sink: &dyn OutputSink) {
+  let requests = site.resized_images.lock().unwrap();
+  let mut written = 0;
+  for (path, width) in requests.iter() {
+    match resize(path, *width) {
+      Ok((resized_path, contents)) => match sink.write(&resized_path, &contents) {
+        Ok(()) => written += 1,
+        Err(err) => error!("-- Could not write image {}: {}", resized_path.display(), err),
+      },
+      Err(err) => error!("-- {}", err),
+    }
+  }
+  info!("- Wrote {} resized image(s)", written);
+}
+
+/// Reads the image at `path`, relative to the input directory, and returns its
+/// width-suffixed path and its contents resized to `width`, in the same format.
+fn resize(path: &Path, width: u32) -> Result<(PathBuf, Vec<u8>)> {
+  let format = match ImageFormat::from_path(path) {
+    Ok(format) => format,
+    Err(err) => bail!("Image {} could not be resized. Cause: {}", path.display(), err),
+  };
+  let image = match image::open(path) {
+    Ok(image) => image,
+    Err(err) => bail!("Image {} could not be opened. Cause: {}", path.display(), err),
+  };
+  let height = (image.height() as u64 * width as u64 / image.width().max(1) as u64).max(1);
+  let resized = image.resize_exact(width, height as u32, FilterType::Lanczos3);
+  let mut contents = Vec::new();
+  if let Err(err) = resized.write_to(&mut Cursor::new(&mut contents), format) {
+    bail!("Image {} could not be resized. Cause: {}", path.display(), err);
+  }
+  let resized_path = PathBuf::from(width_suffixed(&path.to_string_lossy(), width));
+  Ok((resized_path, contents))
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::site::test_utils::TestSite;
+  use std::fs;
+
+  #[test]
+  fn responsive_image_lists_each_width_and_writes_resized_copies() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        r#"<html oeuvre-name="main"><body><oeuvre-slot oeuvre-name="body"/></body></html>"#,
+      )
+      .file(
+        "index.xml",
+        r#"<oeuvre-page oeuvre-template="main" oeuvre-path="index.html"><p oeuvre-slot="body"><img src="assets/hero.jpg" alt="Hero" oeuvre-responsive="400,800,1200"/></p></oeuvre-page>"#,
+      );
+    fs::create_dir_all(site.path("assets")).unwrap();
+    image::RgbImage::new(1600, 900).save(site.path("assets/hero.jpg")).unwrap();
+    site.build("").unwrap();
+    assert_eq!(
+      site.read_output("index.html"),
+      concat!(
+        "<!DOCTYPE html>\r\n",
+        r#"<html><body><p><img alt="Hero" sizes="100vw" src="assets/hero.jpg" srcset="assets/hero-400w.jpg 400w, assets/hero-800w.jpg 800w, assets/hero-1200w.jpg 1200w"></p></body></html>"#
+      )
+    );
+    for (name, height) in [("hero-400w.jpg", 225), ("hero-800w.jpg", 450), ("hero-1200w.jpg", 675)] {
+      let resized = image::open(site.path("output/assets").join(name)).unwrap();
+      assert_eq!(resized.height(), height);
+    }
+  }
+}
@@ -0,0 +1,224 @@
+use crate::minidom::node::Node;
+use crate::minidom::Element;
+use anyhow::{bail, Result};
+use itertools::Itertools;
+use log::{error, info, warn};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use super::render::interpolate_with;
+use super::Site;
+
+/// The translated strings of a single locale, keyed by dotted names like `nav.home`.
+pub type StringTable = HashMap<String, String>;
+
+/// Loads the string table of each locale in `locales`, which maps each locale to the
+/// path of its toml file, relative to the input directory.
+pub fn load_string_tables(locales: &HashMap<String, String>) -> HashMap<String, StringTable> {
+  let mut tables = HashMap::<String, StringTable>::new();
+  for (locale, path) in locales.iter().sorted() {
+    info!("- Reading {}", path);
+    match load_string_table(Path::new(path)) {
+      Ok(table) => {
+        info!("-- Loaded {} string(s) for locale {}", table.len(), locale);
+        tables.insert(locale.clone(), table);
+      }
+      Err(err) => error!("-- {}", err),
+    }
+  }
+  tables
+}
+
+/// Reads a toml file of strings. Nested tables qualify the names of their strings,
+/// so that `home` within a `[nav]` table is named `nav.home`.
+fn load_string_table(path: &Path) -> Result<StringTable> {
+  let text = match fs::read_to_string(path) {
+    Ok(text) => text,
+    Err(err) => bail!("{} could not be opened. Cause: {}", path.display(), err),
+  };
+  let table = match toml::from_str::<toml::value::Table>(&text) {
+    Ok(table) => table,
+    Err(err) => bail!(
+      "{} could not be parsed as a string table. Cause: {}",
+      path.display(),
+      err
+    ),
+  };
+  let mut strings = StringTable::new();
+  flatten(&table, None, &mut strings, path)?;
+  Ok(strings)
+}
+
+fn flatten(
+  table: &toml::value::Table,
+  scope: Option<&str>,
+  strings: &mut StringTable,
+  path: &Path,
+) -> Result<()> {
+  for (name, value) in table {
+    let name = match scope {
+      Some(scope) => format!("{}.{}", scope, name),
+      None => name.clone(),
+    };
+    match value {
+      toml::Value::String(text) => {
+        strings.insert(name, text.clone());
+      }
+      toml::Value::Table(table) => flatten(table, Some(&name), strings, path)?,
+      _ => bail!("{} has a value for {} that is not a string", path.display(), name),
+    }
+  }
+  Ok(())
+}
+
+/// Replaces each oeuvre-t element within `element`, a page rendered to `page_path`,
+/// with the string named by its oeuvre-key attribute in the string table of `locale`.
+/// A string that cannot be found is replaced by its key, with a warning.
+/// `{{ locale }}` in attribute values is replaced by the name of the locale.
+pub fn localize(element: &mut Element, site: &Site, locale: Option<&str>, page_path: &Path) {
+  if let Some(locale) = locale {
+    let attrs: Vec<(String, String)> = element
+      .attrs()
+      .filter(|attr| attr.1.contains("{{"))
+      .map(|attr| (attr.0.to_string(), attr.1.to_string()))
+      .collect();
+    for (name, value) in attrs {
+      let value = interpolate_with(&value, |expression| match expression {
+        "locale" => Some(locale.to_string()),
+        _ => None,
+      });
+      element.set_attr(name, value);
+    }
+  }
+  for node in element.take_nodes() {
+    match node {
+      Node::Element(child) if child.name() == "oeuvre-t" => {
+        let key = match child.attr("oeuvre-key") {
+          Some(key) => key,
+          None => {
+            error!(
+              "Page {} has an oeuvre-t element without an oeuvre-key attribute",
+              page_path.display()
+            );
+            continue;
+          }
+        };
+        element.append_text_node(translate(key, site, locale, page_path));
+      }
+      Node::Element(mut child) => {
+        localize(&mut child, site, locale, page_path);
+        element.append_child(child);
+      }
+      node => element.append_node(node),
+    }
+  }
+}
+
+/// Returns the string named `key` in the string table of `locale`, or else `key` itself.
+fn translate(key: &str, site: &Site, locale: Option<&str>, page_path: &Path) -> String {
+  let locale = match locale {
+    Some(locale) => locale,
+    None => {
+      warn!(
+        "Page {} uses string {}, but no locale is active",
+        page_path.display(),
+        key
+      );
+      return key.to_string();
+    }
+  };
+  match site.strings.get(locale).and_then(|table| table.get(key)) {
+    Some(text) => text.clone(),
+    None => {
+      warn!(
+        "Page {} uses string {}, which is missing from locale {}",
+        page_path.display(),
+        key,
+        locale
+      );
+      key.to_string()
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::site::test_utils::TestSite;
+
+  #[test]
+  fn template_renders_strings_of_each_locale() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        r#"<html oeuvre-name="main"><a href="/"><oeuvre-t oeuvre-key="nav.home"/></a><p><oeuvre-t oeuvre-key="footer"/></p></html>"#,
+      )
+      .file(
+        "index.xml",
+        r#"<oeuvre-page oeuvre-template="main" oeuvre-path="index.html"/>"#,
+      )
+      .file(
+        "fr/index.xml",
+        r#"<oeuvre-page oeuvre-template="main" oeuvre-path="index.html" oeuvre-locale="fr"/>"#,
+      )
+      .file("locales/en.toml", "footer = \"Thanks\"\n[nav]\nhome = \"Home\"\n")
+      .file("locales/fr.toml", "[nav]\nhome = \"Accueil\"\n");
+    site
+      .build(concat!(
+        "locale = \"en\"\n",
+        "[locales]\n",
+        "en = \"locales/en.toml\"\n",
+        "fr = \"locales/fr.toml\"\n",
+      ))
+      .unwrap();
+    assert_eq!(
+      site.read_output("index.html"),
+      "<!DOCTYPE html>\r\n<html><a href=\"/\">Home</a><p>Thanks</p></html>"
+    );
+    assert_eq!(
+      site.read_output("fr/index.html"),
+      "<!DOCTYPE html>\r\n<html><a href=\"/\">Accueil</a><p>footer</p></html>"
+    );
+  }
+
+  #[test]
+  fn each_locale_is_written_to_its_own_tree() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        r#"<html oeuvre-name="main" lang="{{ locale }}"><h1><oeuvre-t oeuvre-key="greeting"/></h1></html>"#,
+      )
+      .file(
+        "blog/post.xml",
+        r#"<oeuvre-page oeuvre-template="main" oeuvre-path="post.html"/>"#,
+      )
+      .file("locales/de.toml", "greeting = \"Hallo\"\n")
+      .file("locales/en.toml", "greeting = \"Hello\"\n")
+      .file("locales/fr.toml", "greeting = \"Bonjour\"\n");
+    site
+      .build(concat!(
+        "locale_dirs = true\n",
+        "default_locale = \"en\"\n",
+        "[locales]\n",
+        "de = \"locales/de.toml\"\n",
+        "en = \"locales/en.toml\"\n",
+        "fr = \"locales/fr.toml\"\n",
+      ))
+      .unwrap();
+    assert_eq!(
+      site.read_output("blog/post.html"),
+      "<!DOCTYPE html>\r\n<html lang=\"en\"><h1>Hello</h1></html>"
+    );
+    assert_eq!(
+      site.read_output("de/blog/post.html"),
+      "<!DOCTYPE html>\r\n<html lang=\"de\"><h1>Hallo</h1></html>"
+    );
+    assert_eq!(
+      site.read_output("fr/blog/post.html"),
+      "<!DOCTYPE html>\r\n<html lang=\"fr\"><h1>Bonjour</h1></html>"
+    );
+    assert!(!site.output_exists("en/blog/post.html"));
+  }
+}
@@ -0,0 +1,83 @@
+use log::{log, Level};
+use std::sync::Mutex;
+
+/// Held while a buffer is being flushed, so that lines from different buffers never interleave.
+static FLUSH_LOCK: Mutex<()> = Mutex::new(());
+
+/// Collects the log lines produced by a single unit of work, such as writing a page,
+/// and emits them as one contiguous block once that work is complete.
+pub struct LogBuffer {
+  target: &'static str,
+  lines: Vec<(Level, String)>,
+}
+
+impl LogBuffer {
+  /// Creates an empty buffer whose lines will be logged under `target`,
+  /// which is typically the `module_path!()` of the caller.
+  pub fn new(target: &'static str) -> LogBuffer {
+    LogBuffer {
+      target,
+      lines: Vec::new(),
+    }
+  }
+
+  /// Buffers a line at the info level.
+  pub fn info<S: Into<String>>(&mut self, line: S) {
+    self.lines.push((Level::Info, line.into()));
+  }
+
+  /// Buffers a line at the error level.
+  pub fn error<S: Into<String>>(&mut self, line: S) {
+    self.lines.push((Level::Error, line.into()));
+  }
+
+  /// Sends all buffered lines to the logger as a single block.
+  pub fn flush(self) {
+    let target = self.target;
+    self.flush_with(|level, line| log!(target: target, level, "{}", line));
+  }
+
+  /// Passes all buffered lines to `emit` while holding the flush lock.
+  fn flush_with<F: FnMut(Level, &str)>(self, mut emit: F) {
+    let _guard = FLUSH_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+    for (level, line) in &self.lines {
+      emit(*level, line);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::thread;
+
+  #[test]
+  fn flushed_lines_stay_contiguous() {
+    const UNITS: usize = 8;
+    const LINES: usize = 20;
+    let output = Mutex::new(Vec::<usize>::new());
+
+    thread::scope(|scope| {
+      for unit in 0..UNITS {
+        let output = &output;
+        scope.spawn(move || {
+          let mut buffer = LogBuffer::new(module_path!());
+          for line in 0..LINES {
+            buffer.info(format!("unit {} line {}", unit, line));
+            thread::yield_now();
+          }
+          buffer.flush_with(|_, _| {
+            output.lock().unwrap().push(unit);
+            thread::yield_now();
+          });
+        });
+      }
+    });
+
+    let output = output.into_inner().unwrap();
+    assert_eq!(output.len(), UNITS * LINES);
+    for block in output.chunks(LINES) {
+      assert!(block.iter().all(|unit| *unit == block[0]));
+    }
+  }
+}
@@ -0,0 +1,83 @@
+use crate::minidom::Element;
+use itertools::Itertools;
+use log::{error, info};
+use std::collections::HashMap;
+
+use super::Snippet;
+use super::Template;
+
+/// A reusable fragment defined inline by an oeuvre-macro element in a template or snippet.
+/// When called, each of its parameters is bound as a slot value while its contents are expanded.
+pub struct Macro {
+  pub name: String,
+  pub params: Vec<String>,
+  pub element: Element,
+}
+
+impl Macro {
+  fn new(element: &Element) -> Option<Macro> {
+    let name = match element.attr("oeuvre-name") {
+      Some(attr_value) => attr_value.to_string(),
+      None => {
+        error!("Found an oeuvre-macro element without an identifying oeuvre-name attribute.");
+        return None;
+      }
+    };
+
+    let params = match element.attr("oeuvre-params") {
+      Some(attr_value) => attr_value
+        .split(',')
+        .map(|param| param.trim().to_string())
+        .filter(|param| !param.is_empty())
+        .collect(),
+      None => Vec::new(),
+    };
+
+    Some(Macro {
+      name,
+      params,
+      element: element.clone(),
+    })
+  }
+
+  /// Registers every oeuvre-macro element found within `element` and its descendants.
+  pub fn collect(element: &Element, macros: &mut HashMap<String, Macro>) {
+    for child in element.children() {
+      if child.name() != "oeuvre-macro" {
+        Macro::collect(child, macros);
+        continue;
+      }
+      let new_macro = match Macro::new(child) {
+        Some(new_macro) => new_macro,
+        None => continue,
+      };
+      if macros.contains_key(&new_macro.name) {
+        error!(
+          "Macro has the oeuvre-name attribute value {}, which is already in use by another macro",
+          new_macro.name
+        );
+        continue;
+      }
+      info!("-- Registered macro {}", new_macro.name);
+      macros.insert(new_macro.name.clone(), new_macro);
+    }
+  }
+
+  /// Registers the macros defined in all templates and snippets and returns them
+  /// in a HashMap using the macro name as the key.
+  pub fn load_many(
+    templates: &HashMap<String, Template>,
+    snippets: &HashMap<String, Snippet>,
+  ) -> HashMap<String, Macro> {
+    let mut macros = HashMap::<String, Macro>::new();
+    for template in templates.values().sorted_by_key(|template| &template.name) {
+      info!("- Searching template {}", template.name);
+      Macro::collect(&template.element, &mut macros);
+    }
+    for snippet in snippets.values().sorted_by_key(|snippet| &snippet.name) {
+      info!("- Searching snippet {}", snippet.name);
+      Macro::collect(&snippet.element, &mut macros);
+    }
+    macros
+  }
+}
@@ -1,8 +1,11 @@
 use crate::minidom::Element;
 use anyhow::{bail, Result};
+use log::warn;
 use std::fs;
+use std::io::Read;
 use std::path::Path;
 
+#[allow(clippy::module_inception)]
 mod site;
 pub use site::Site;
 mod page;
@@ -11,22 +14,104 @@ mod template;
 use template::Template;
 mod snippet;
 use snippet::Snippet;
+mod macros;
+use macros::Macro;
 mod dataset;
 use dataset::Dataset;
 mod site_config;
 pub use site_config::SiteConfig;
+use site_config::NestedOutputPolicy;
 mod render;
+pub use render::{RenderContext, RenderPlugin};
+mod bundle;
+mod sanitize;
+mod check;
+mod output;
+mod feed;
+mod images;
+mod locale;
+use check::Diagnostic;
+pub use check::{diagnostics_json, validate_file, Severity};
+mod log_buffer;
+use log_buffer::LogBuffer;
+#[cfg(test)]
+pub mod test_utils;
 
 /// Loads and parses the XML document at the provided path,
-/// or else an Err if loading or parsing fail.
-fn load_xml(path: &Path) -> Result<Element> {
-  let template_text = match fs::read_to_string(&path) {
-    Ok(text) => text,
+/// or else an Err if loading or parsing fail. Documents larger than
+/// the configured `max_file_bytes` are rejected without being read in full.
+/// Problems that don't prevent loading, such as a byte order mark, are logged as warnings.
+fn load_xml(path: &Path, config: &SiteConfig) -> Result<Element> {
+  let (element, warnings) = load_xml_with_warnings(path, config)?;
+  for warning in warnings {
+    warn!("{}", warning);
+  }
+  Ok(element)
+}
+
+/// Reads the text of the source file at `path`, or else an Err if it cannot be read or is
+/// larger than the configured `max_file_bytes`.
+fn read_source(path: &Path, config: &SiteConfig) -> Result<String> {
+  let mut file = match fs::File::open(path) {
+    Ok(file) => file,
     Err(err) => bail!("{} could not be opened. Cause: {}", path.display(), err),
   };
+  // The limit is checked before decoding, since it may fall within a multi-byte character.
+  let mut bytes = Vec::new();
+  let read_result = match config.max_file_bytes {
+    // Reading one byte past the limit is enough to tell that the file exceeds it.
+    Some(max_file_bytes) => file.take(max_file_bytes + 1).read_to_end(&mut bytes),
+    None => file.read_to_end(&mut bytes),
+  };
+  if let Err(err) = read_result {
+    bail!("{} could not be read. Cause: {}", path.display(), err);
+  }
+  if let Some(max_file_bytes) = config.max_file_bytes {
+    if bytes.len() as u64 > max_file_bytes {
+      bail!(
+        "{} is larger than the max_file_bytes limit of {} bytes",
+        path.display(),
+        max_file_bytes
+      );
+    }
+  }
+  match String::from_utf8(bytes) {
+    Ok(text) => Ok(text),
+    Err(err) => bail!("{} could not be read. Cause: {}", path.display(), err),
+  }
+}
+
+/// Like `load_xml`, but returns the warnings instead of logging them.
+fn load_xml_with_warnings(path: &Path, config: &SiteConfig) -> Result<(Element, Vec<String>)> {
+  let mut template_text = read_source(path, config)?;
+
+  // A leading byte order mark would otherwise be parsed as text before the root element.
+  let mut warnings = Vec::new();
+  if let Some(text) = template_text.strip_prefix('\u{feff}') {
+    warnings.push(format!(
+      "{} begins with a byte order mark, which was removed",
+      path.display()
+    ));
+    template_text = text.to_string();
+  }
+  if let Some(offset) = template_text.find('\u{feff}') {
+    warnings.push(format!(
+      "{} contains a byte order mark on line {}, which will be copied to the output",
+      path.display(),
+      template_text[..offset].matches('\n').count() + 1
+    ));
+  }
 
   match template_text.parse::<Element>() {
-    Ok(el) => Ok(el),
+    Ok(el) => {
+      if has_mixed_pre_indentation(&el) {
+        warnings.push(format!(
+          "{} has a <pre> element indented with both tabs and spaces",
+          path.display()
+        ));
+      }
+      Ok((el, warnings))
+    }
     Err(err) => bail!(
       "{} could not be parsed as xml. Cause: {}",
       path.display(),
@@ -34,3 +119,216 @@ fn load_xml(path: &Path) -> Result<Element> {
     ),
   }
 }
+
+/// Whether any pre element within `element` has some lines indented with tabs
+/// and others with spaces, which will look inconsistent in the output.
+fn has_mixed_pre_indentation(element: &Element) -> bool {
+  if element.name() == "pre" {
+    let text = element.text();
+    let indents = text.lines().filter_map(|line| line.chars().next());
+    let (mut tabs, mut spaces) = (false, false);
+    for indent in indents {
+      tabs |= indent == '\t';
+      spaces |= indent == ' ';
+    }
+    if tabs && spaces {
+      return true;
+    }
+  }
+  element.children().any(has_mixed_pre_indentation)
+}
+
+/// The root element names that identify each category of input document.
+const RESERVED_ROOT_NAMES: [&str; 4] = [
+  "oeuvre-page",
+  "oeuvre-snippet",
+  "oeuvre-dataset",
+  "oeuvre-datarow",
+];
+
+/// Checks that `element` has the shape expected of the root of a `category` document:
+/// it must be named `expected_name` (or, if that is `None`, not be the root of
+/// another category) and carry `required_attr`. The resulting error describes what was
+/// expected and what was found, so that misfiled documents are easy to identify.
+fn validate_root(
+  element: &Element,
+  category: &str,
+  expected_name: Option<&str>,
+  required_attr: &str,
+) -> Result<()> {
+  let expected = match expected_name {
+    Some(name) => format!(
+      "a {} root element <{}> with an {} attribute",
+      category, name, required_attr
+    ),
+    None => format!("a {} root element with an {} attribute", category, required_attr),
+  };
+  let name_matches = match expected_name {
+    Some(name) => element.name() == name,
+    None => !RESERVED_ROOT_NAMES.contains(&element.name()),
+  };
+  if !name_matches {
+    bail!("Expected {}, but found <{}>", expected, element.name());
+  }
+  if element.attr(required_attr).is_none() {
+    bail!("Expected {}, but found <{}> without one", expected, element.name());
+  }
+  Ok(())
+}
+
+/// An incremental 64-bit FNV-1a hasher. Its hashes are stable across builds and
+/// platforms, which makes them suitable for cache busting, but they are not
+/// cryptographically secure.
+struct ContentHasher(u64);
+
+impl ContentHasher {
+  fn new() -> ContentHasher {
+    ContentHasher(0xcbf2_9ce4_8422_2325)
+  }
+
+  fn write(&mut self, bytes: &[u8]) {
+    for byte in bytes {
+      self.0 ^= u64::from(*byte);
+      self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+    }
+  }
+
+  /// Returns the hash of everything written so far, hex-encoded.
+  fn finish(&self) -> String {
+    format!("{:016x}", self.0)
+  }
+}
+
+/// Runs `work` in a rayon thread pool of `jobs` threads, so that parallel iterators inside
+/// it use no more threads than the jobs option allows. If the pool cannot be started, the
+/// work runs in the global pool instead.
+#[cfg(feature = "parallel")]
+pub fn in_thread_pool<R: Send>(jobs: usize, work: impl FnOnce() -> R + Send) -> R {
+  match rayon::ThreadPoolBuilder::new().num_threads(jobs.max(1)).build() {
+    Ok(pool) => pool.install(work),
+    Err(err) => {
+      warn!("Could not start a pool of {} threads: {}", jobs, err);
+      work()
+    }
+  }
+}
+
+/// Returns a hex-encoded hash of `bytes`.
+pub fn content_hash(bytes: &[u8]) -> String {
+  let mut hasher = ContentHasher::new();
+  hasher.write(bytes);
+  hasher.finish()
+}
+
+/// Returns the media type of the file at `path`, based on its extension, with a utf-8
+/// charset for text. Both the dev server and bundled pages' data uris use it.
+pub fn media_type(path: &Path) -> &'static str {
+  let extension = path.extension().map(|extension| extension.to_string_lossy().to_lowercase());
+  match extension.as_deref() {
+    Some("html") | Some("htm") => "text/html; charset=utf-8",
+    Some("css") => "text/css; charset=utf-8",
+    Some("js") | Some("mjs") => "text/javascript; charset=utf-8",
+    Some("json") => "application/json",
+    Some("xml") => "application/xml",
+    Some("txt") => "text/plain; charset=utf-8",
+    Some("svg") => "image/svg+xml",
+    Some("png") => "image/png",
+    Some("jpg") | Some("jpeg") => "image/jpeg",
+    Some("gif") => "image/gif",
+    Some("webp") => "image/webp",
+    Some("avif") => "image/avif",
+    Some("ico") => "image/x-icon",
+    Some("woff") => "font/woff",
+    Some("woff2") => "font/woff2",
+    Some("pdf") => "application/pdf",
+    Some("zip") => "application/zip",
+    Some("wasm") => "application/wasm",
+    _ => "application/octet-stream",
+  }
+}
+
+/// Decodes the `%XX` escapes in a url path, or returns None if they are malformed
+/// or do not decode to UTF-8.
+pub fn percent_decode(text: &str) -> Option<String> {
+  let bytes = text.as_bytes();
+  let mut decoded = Vec::with_capacity(bytes.len());
+  let mut index = 0;
+  while index < bytes.len() {
+    match bytes[index] {
+      b'%' => {
+        let hex = std::str::from_utf8(bytes.get(index + 1..index + 3)?).ok()?;
+        decoded.push(u8::from_str_radix(hex, 16).ok()?);
+        index += 3;
+      }
+      byte => {
+        decoded.push(byte);
+        index += 1;
+      }
+    }
+  }
+  String::from_utf8(decoded).ok()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::site::test_utils::TestSite;
+  use tempfile::TempDir;
+
+  fn config(max_file_bytes: Option<u64>) -> SiteConfig {
+    let mut config = toml::from_str::<SiteConfig>("").unwrap();
+    config.max_file_bytes = max_file_bytes;
+    config
+  }
+
+  #[test]
+  fn load_xml_rejects_files_over_the_size_limit() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("big.xml");
+    fs::write(&path, "<p>0123456789</p>").unwrap();
+
+    let err = load_xml(&path, &config(Some(16))).err().unwrap();
+    assert_eq!(
+      err.to_string(),
+      format!("{} is larger than the max_file_bytes limit of 16 bytes", path.display())
+    );
+    assert!(load_xml(&path, &config(Some(17))).is_ok());
+    assert!(load_xml(&path, &config(None)).is_ok());
+
+    // The limit falls within the two bytes of the é.
+    fs::write(&path, "<p>é</p>").unwrap();
+    let err = load_xml(&path, &config(Some(4))).err().unwrap();
+    assert_eq!(
+      err.to_string(),
+      format!("{} is larger than the max_file_bytes limit of 4 bytes", path.display())
+    );
+  }
+
+  #[test]
+  fn leading_byte_order_mark_is_removed_with_a_warning() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        "\u{feff}<html oeuvre-name=\"main\"><body><oeuvre-slot oeuvre-name=\"body\"/></body></html>",
+      )
+      .file(
+        "index.xml",
+        r#"<oeuvre-page oeuvre-template="main" oeuvre-path="index.html"><p oeuvre-slot="body">Hi</p></oeuvre-page>"#,
+      );
+    let path = site.path("templates/main.xml");
+    let (_, warnings) = load_xml_with_warnings(&path, &config(None)).unwrap();
+    assert_eq!(
+      warnings,
+      vec![format!(
+        "{} begins with a byte order mark, which was removed",
+        path.display()
+      )]
+    );
+    site.build("").unwrap();
+    assert_eq!(
+      site.read_output("index.html"),
+      "<!DOCTYPE html>\r\n<html><body><p>Hi</p></body></html>"
+    );
+  }
+}
@@ -0,0 +1,401 @@
+use super::site_config::AssetCompare;
+use super::{content_hash, ContentHasher};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use log::info;
+use path_clean::PathClean;
+use serde_json::{json, Value as JsonValue};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+/// A destination for a site's output files, which are named by their paths
+/// relative to the root of the output.
+pub trait OutputSink: Sync {
+  /// Writes `contents` to `path`.
+  fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()>;
+
+  /// Copies the input file at `source` to `path`.
+  fn copy(&self, source: &Path, path: &Path) -> io::Result<()> {
+    self.write(path, &fs::read(source)?)
+  }
+}
+
+/// Writes output files to a directory tree, creating parent directories as needed.
+pub struct DirectorySink {
+  dir: PathBuf,
+  compare: AssetCompare,
+  hashes: Mutex<HashMap<PathBuf, String>>,
+}
+
+impl DirectorySink {
+  pub fn new(dir: &Path) -> DirectorySink {
+    DirectorySink::incremental(dir, AssetCompare::Always, HashMap::new())
+  }
+
+  /// Returns a sink that skips copying files whose existing copies are up to date
+  /// according to `compare`. With AssetCompare::Hash, `hashes` holds the content
+  /// hash of each file as of its last copy, keyed by its path in the output.
+  pub fn incremental(
+    dir: &Path,
+    compare: AssetCompare,
+    hashes: HashMap<PathBuf, String>,
+  ) -> DirectorySink {
+    DirectorySink {
+      dir: dir.to_path_buf(),
+      compare,
+      hashes: Mutex::new(hashes),
+    }
+  }
+
+  /// Returns the content hash of each file copied, or found up to date, so far.
+  pub fn hashes(&self) -> HashMap<PathBuf, String> {
+    self.hashes.lock().unwrap().clone()
+  }
+
+  /// Creates the parent directories of `path`. This is safe to do from several threads at
+  /// once, since `create_dir_all` tolerates directories that appear while it runs.
+  fn prepare(&self, path: &Path) -> io::Result<PathBuf> {
+    let output_path = self.dir.join(path);
+    fs::create_dir_all(output_path.parent().unwrap())?;
+    Ok(output_path)
+  }
+}
+
+impl OutputSink for DirectorySink {
+  fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+    fs::write(self.prepare(path)?, contents)
+  }
+
+  /// Streams the copy, so that large files are never read into memory whole.
+  /// Files whose copies are already up to date are skipped.
+  fn copy(&self, source: &Path, path: &Path) -> io::Result<()> {
+    let output_path = self.prepare(path)?;
+    match self.compare {
+      AssetCompare::Always => (),
+      AssetCompare::Mtime => {
+        if is_modified_since(&output_path, source)? {
+          return Ok(());
+        }
+      }
+      AssetCompare::Hash => {
+        let (_, hash) = file_hash(source)?;
+        let previous = self.hashes.lock().unwrap().insert(path.to_path_buf(), hash.clone());
+        if previous == Some(hash) && output_path.exists() {
+          return Ok(());
+        }
+      }
+    }
+    fs::copy(source, output_path)?;
+    Ok(())
+  }
+}
+
+/// Logs the output files that would be written to a directory tree, without writing them.
+pub struct DryRunSink {
+  dir: PathBuf,
+}
+
+impl DryRunSink {
+  pub fn new(dir: &Path) -> DryRunSink {
+    DryRunSink {
+      dir: dir.to_path_buf(),
+    }
+  }
+}
+
+impl OutputSink for DryRunSink {
+  fn write(&self, path: &Path, _contents: &[u8]) -> io::Result<()> {
+    info!("- Would write {}", self.dir.join(path).display());
+    Ok(())
+  }
+
+  fn copy(&self, source: &Path, path: &Path) -> io::Result<()> {
+    info!("- Would copy {} to {}", source.display(), self.dir.join(path).display());
+    Ok(())
+  }
+}
+
+/// Collects output files in memory to be written as a single archive, either a zip
+/// archive or, for a path ending in `.tar.gz` or `.tgz`, a gzipped tarball. Entries
+/// are sorted by path and dated 1980-01-01, so that identical builds produce
+/// identical archives.
+#[derive(Default)]
+pub struct ArchiveSink {
+  entries: Mutex<Vec<(String, Vec<u8>)>>,
+}
+
+impl OutputSink for ArchiveSink {
+  fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+    let entry = (url_path(path), contents.to_vec());
+    self.entries.lock().unwrap().push(entry);
+    Ok(())
+  }
+}
+
+impl ArchiveSink {
+  /// Writes the collected files to an archive at `archive_path`, in the format its
+  /// extension names.
+  pub fn finish(self, archive_path: &Path) -> io::Result<()> {
+    let mut entries = self.entries.into_inner().unwrap();
+    entries.sort();
+    let file = fs::File::create(archive_path)?;
+    let name = archive_path.to_string_lossy().to_lowercase();
+    if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+      ArchiveSink::write_tar_gz(file, &entries)
+    } else {
+      ArchiveSink::write_zip(file, &entries)
+    }
+  }
+
+  fn write_zip(file: fs::File, entries: &[(String, Vec<u8>)]) -> io::Result<()> {
+    let mut archive = ZipWriter::new(file);
+    for (name, contents) in entries {
+      let options = SimpleFileOptions::default()
+        .compression_method(CompressionMethod::Deflated)
+        .last_modified_time(zip::DateTime::default())
+        .large_file(contents.len() as u64 >= u64::from(u32::MAX));
+      archive.start_file(name, options)?;
+      archive.write_all(contents)?;
+    }
+    archive.finish()?;
+    Ok(())
+  }
+
+  fn write_tar_gz(file: fs::File, entries: &[(String, Vec<u8>)]) -> io::Result<()> {
+    let mut archive = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+    for (name, contents) in entries {
+      let mut header = tar::Header::new_gnu();
+      header.set_size(contents.len() as u64);
+      header.set_mode(0o644);
+      // 1980-01-01, the earliest date a zip archive can hold.
+      header.set_mtime(315_532_800);
+      archive.append_data(&mut header, name, contents.as_slice())?;
+    }
+    archive.into_inner()?.finish()?;
+    Ok(())
+  }
+}
+
+/// Passes output files through to another sink while recording the path, size and
+/// content hash of each, for the build manifest.
+pub struct ManifestSink<'a> {
+  inner: &'a dyn OutputSink,
+  entries: Mutex<Vec<(String, u64, String)>>,
+}
+
+impl<'a> ManifestSink<'a> {
+  pub fn new(inner: &'a dyn OutputSink) -> ManifestSink<'a> {
+    ManifestSink {
+      inner,
+      entries: Mutex::new(Vec::new()),
+    }
+  }
+
+  fn record(&self, path: &Path, size: u64, hash: String) {
+    let entry = (url_path(path), size, hash);
+    self.entries.lock().unwrap().push(entry);
+  }
+
+  /// Returns the manifest as a JSON document listing each recorded file, sorted by path.
+  pub fn to_json(&self) -> String {
+    let mut entries = self.entries.lock().unwrap().clone();
+    entries.sort();
+    let files: Vec<JsonValue> = entries
+      .into_iter()
+      .map(|(path, size, hash)| json!({ "path": path, "size": size, "hash": hash }))
+      .collect();
+    serde_json::to_string_pretty(&json!({ "files": files })).unwrap() + "\n"
+  }
+}
+
+impl OutputSink for ManifestSink<'_> {
+  fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+    self.inner.write(path, contents)?;
+    self.record(path, contents.len() as u64, content_hash(contents));
+    Ok(())
+  }
+
+  fn copy(&self, source: &Path, path: &Path) -> io::Result<()> {
+    self.inner.copy(source, path)?;
+    let (size, hash) = file_hash(source)?;
+    self.record(path, size, hash);
+    Ok(())
+  }
+}
+
+/// Whether the file at `path` exists and was modified no earlier than the one at `other`.
+fn is_modified_since(path: &Path, other: &Path) -> io::Result<bool> {
+  let modified = match fs::metadata(path) {
+    Ok(metadata) => metadata.modified()?,
+    Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+    Err(err) => return Err(err),
+  };
+  Ok(modified >= fs::metadata(other)?.modified()?)
+}
+
+/// Returns the size and content hash of the file at `path`, which is hashed in
+/// chunks, so that large files are never read into memory whole.
+fn file_hash(path: &Path) -> io::Result<(u64, String)> {
+  let mut file = fs::File::open(path)?;
+  let mut hasher = ContentHasher::new();
+  let mut buffer = [0; 8192];
+  let mut size = 0;
+  loop {
+    let read = file.read(&mut buffer)?;
+    if read == 0 {
+      break;
+    }
+    hasher.write(&buffer[..read]);
+    size += read as u64;
+  }
+  Ok((size, hasher.finish()))
+}
+
+/// The path of an output file and its size in bytes.
+pub type FileSize = (String, u64);
+
+/// Passes output files through to another sink while recording the size of each,
+/// so that the largest can be reported.
+pub struct SizeSink<'a> {
+  inner: &'a dyn OutputSink,
+  sizes: Mutex<Vec<FileSize>>,
+}
+
+impl<'a> SizeSink<'a> {
+  pub fn new(inner: &'a dyn OutputSink) -> SizeSink<'a> {
+    SizeSink {
+      inner,
+      sizes: Mutex::new(Vec::new()),
+    }
+  }
+
+  /// Returns the path and size of each recorded file, largest first, then by path.
+  pub fn by_size(&self) -> Vec<FileSize> {
+    let mut sizes = self.sizes.lock().unwrap().clone();
+    sizes.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    sizes
+  }
+}
+
+impl OutputSink for SizeSink<'_> {
+  fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+    self.inner.write(path, contents)?;
+    let entry = (url_path(path), contents.len() as u64);
+    self.sizes.lock().unwrap().push(entry);
+    Ok(())
+  }
+
+  fn copy(&self, source: &Path, path: &Path) -> io::Result<()> {
+    self.inner.copy(source, path)?;
+    let entry = (url_path(path), fs::metadata(source)?.len());
+    self.sizes.lock().unwrap().push(entry);
+    Ok(())
+  }
+}
+
+/// Returns `path` with its components joined by forward slashes, as used within
+/// archives and manifests.
+pub fn url_path(path: &Path) -> String {
+  path
+    .to_path_buf()
+    .clean()
+    .iter()
+    .map(|component| component.to_string_lossy())
+    .collect::<Vec<_>>()
+    .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::site::test_utils::TestSite;
+  use flate2::read::GzDecoder;
+
+  /// Reads the names and contents of the entries in the archive at `path`, which is
+  /// written by `ArchiveSink` in the format its extension names.
+  fn read_archive(path: &str) -> Vec<(String, String)> {
+    let file = fs::File::open(path).unwrap();
+    let mut entries = Vec::new();
+    if path.ends_with(".zip") {
+      let mut archive = zip::ZipArchive::new(file).unwrap();
+      for index in 0..archive.len() {
+        let mut entry = archive.by_index(index).unwrap();
+        let mut contents = String::new();
+        entry.read_to_string(&mut contents).unwrap();
+        entries.push((entry.name().to_string(), contents));
+      }
+    } else {
+      let mut archive = tar::Archive::new(GzDecoder::new(file));
+      for entry in archive.entries().unwrap() {
+        let mut entry = entry.unwrap();
+        let mut contents = String::new();
+        entry.read_to_string(&mut contents).unwrap();
+        entries.push((entry.path().unwrap().to_string_lossy().into_owned(), contents));
+      }
+    }
+    entries
+  }
+
+  #[test]
+  fn build_manifest_lists_pages_and_assets() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        r#"<html oeuvre-name="main"><body><oeuvre-slot oeuvre-name="body"/></body></html>"#,
+      )
+      .file("index.xml", r#"<oeuvre-page oeuvre-template="main" oeuvre-path="index.html"><p oeuvre-slot="body">Hi</p></oeuvre-page>"#)
+      .file("assets/main.css", "body { margin: 0; }");
+    site.build("build_manifest = \"build.json\"").unwrap();
+    let page = "<!DOCTYPE html>\r\n<html><body><p>Hi</p></body></html>";
+    assert_eq!(site.read_output("index.html"), page);
+    let manifest: JsonValue = serde_json::from_str(&site.read_output("build.json")).unwrap();
+    assert_eq!(
+      manifest,
+      json!({
+        "files": [
+          { "path": "assets/main.css", "size": 19, "hash": content_hash(b"body { margin: 0; }") },
+          { "path": "index.html", "size": 52, "hash": content_hash(page.as_bytes()) }
+        ]
+      })
+    );
+  }
+
+  #[test]
+  fn site_is_written_to_zip_and_tar_gz_archives() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        r#"<html oeuvre-name="main"><body><oeuvre-slot oeuvre-name="body"/></body></html>"#,
+      )
+      .file("blog/post.xml", r#"<oeuvre-page oeuvre-template="main" oeuvre-path="post.html"><p oeuvre-slot="body">Hi</p></oeuvre-page>"#)
+      .file("assets/main.css", "body { margin: 0; }");
+    let loaded = site.load("").unwrap();
+    for archive_path in ["site.zip", "site.tar.gz"] {
+      let archive = ArchiveSink::default();
+      loaded.render_to(&archive).unwrap();
+      archive.finish(Path::new(archive_path)).unwrap();
+      assert_eq!(
+        read_archive(archive_path),
+        [
+          (
+            "assets/main.css".to_string(),
+            "body { margin: 0; }".to_string()
+          ),
+          (
+            "blog/post.html".to_string(),
+            "<!DOCTYPE html>\r\n<html><body><p>Hi</p></body></html>".to_string()
+          ),
+        ]
+      );
+    }
+    assert!(!site.output_exists("blog/post.html"));
+  }
+}
@@ -1,135 +1,1646 @@
-use super::load_xml;
-use crate::minidom::Element;
-use crate::PathBuf;
-use anyhow::{bail, Result};
-use log::{error, info};
-use std::collections::HashMap;
-use std::fs;
-use std::path::Path;
+use super::check::{Diagnostic, Severity, PATH_COLLISION};
+use super::dataset::{slug, DataRow};
+use super::site_config::{ArchiveConfig, AttrQuoteStyle, OutputFormat};
+use super::{content_hash, load_xml, percent_decode, validate_root, SiteConfig};
+use crate::minidom::node::Node;
+use crate::minidom::{AttrQuotes, Element, Formatting, WriteOptions};
+use std::path::PathBuf;
+use anyhow::{anyhow, bail, Result};
+use itertools::Itertools;
+use log::{error, info, warn};
+use serde_json::{json, Value as JsonValue};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{self, Write};
 use path_clean::PathClean;
+use std::path::{Component, Path};
+use std::sync::OnceLock;
+#[cfg(feature = "parallel")]
+use super::in_thread_pool;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
 
-use super::Snippet;
-use super::Template;
 use super::Site;
-use super::render::render_template;
+use super::LogBuffer;
+use super::render::{
+  add_canonical_link, hoist_head_blocks, number_counters, render_template, RenderContext,
+};
+use super::render::IssueSink;
+use super::render::{SOURCE_MAP_END, SOURCE_MAP_START};
+use super::bundle::{inline_resources, local_path};
+use super::locale::localize;
+use super::output::{url_path, OutputSink};
 
 /// A single page, as represented by its target template and associated slot values.
 /// Each page will render a single XML document to disk.
 pub struct Page {
   path: PathBuf,
+  source_path: PathBuf,
   template: String,
   slot_values: HashMap<String, Element>,
+  /// The output format: `html`, `txt` for plain text, or the name of another markup format.
+  format: String,
+  /// The locale whose strings fill the page's oeuvre-t elements, if the page names one.
+  locale: Option<String>,
 }
 
 impl Page {
-  fn new(element: Element, input_path: &Path) -> Result<Page> {
-    let template = match element.attr("oeuvre-template") {
-      Some(attr_value) => attr_value.to_string(),
-      None => bail!("Page requires a root element with an oeuvre-template attribute"),
-    };
+  fn new(element: &Element, input_path: &Path) -> Result<Page> {
+    validate_root(element, "page", Some("oeuvre-page"), "oeuvre-template")?;
+    let template = element.attr("oeuvre-template").unwrap().to_string();
 
     let output_path = match element.attr("oeuvre-path") {
-      Some(attr_value) => {
-        if attr_value.starts_with('/') { PathBuf::from(attr_value[1..].to_string()) }
-        else { input_path.parent().unwrap().join(attr_value) }
-      },
+      Some(attr_value) => Page::resolve_output_path(attr_value, input_path)?,
       None => input_path.to_path_buf()
     };
 
     let mut slot_values: HashMap<String, Element> = HashMap::new();
-    for child in element.children() {
-      let slot_names = child.attr("oeuvre-slot");
-      if let Some(slot_names) = slot_names {
-        for slot_name in slot_names.to_string().split(',') {
-          slot_values.insert(slot_name.trim().to_string(), child.clone());
-        }
-      }
-    }
+    Page::collect_slot_values(element, None, &mut slot_values);
 
     Ok(Page {
       path: output_path,
+      source_path: input_path.to_path_buf(),
       template,
       slot_values,
+      format: "html".to_string(),
+      locale: element.attr("oeuvre-locale").map(String::from),
+    })
+  }
+
+  /// Resolves an oeuvre-path attribute value: relative to the site root if it
+  /// begins with a slash, or else relative to the page's source file. The result is
+  /// normalized, and must name a file within the output directory.
+  fn resolve_output_path(attr_value: &str, input_path: &Path) -> Result<PathBuf> {
+    let output_path = match attr_value.strip_prefix('/') {
+      Some(attr_value) => PathBuf::from(attr_value),
+      None => input_path.parent().unwrap().join(attr_value),
+    }
+    .clean();
+    if !output_path.components().all(|component| matches!(component, Component::Normal(_))) {
+      bail!(
+        "Page {} has oeuvre-path {}, which is not within the output directory",
+        input_path.display(),
+        attr_value
+      );
+    }
+    Ok(output_path)
+  }
+
+  /// Returns a copy of this page in the alternate format described by an oeuvre-format
+  /// element. The element names the format and may override the page's template and
+  /// output path; by default, the output path only takes the format's extension. With
+  /// the output_extension option, an output path without an extension is given the
+  /// format's, or the configured one for HTML.
+  fn alternate_format(&self, element: &Element, config: &SiteConfig) -> Result<Page> {
+    let format = match element.attr("oeuvre-name") {
+      Some(attr_value) => attr_value.to_string(),
+      None => bail!(
+        "Page {} has an oeuvre-format element without an identifying oeuvre-name attribute",
+        self.source_path.display()
+      ),
+    };
+    let template = element.attr("oeuvre-template").unwrap_or(&self.template);
+    let output_path = match element.attr("oeuvre-path") {
+      Some(attr_value) => {
+        let mut output_path = Page::resolve_output_path(attr_value, &self.source_path)?;
+        if let Some(extension) = &config.output_extension {
+          if output_path.extension().is_none() {
+            output_path.set_extension(if format == "html" { extension } else { &format });
+          }
+        }
+        output_path
+      }
+      None => self.path.with_extension(&format),
+    };
+
+    Ok(Page {
+      path: output_path,
+      source_path: self.source_path.clone(),
+      template: template.to_string(),
+      slot_values: self.slot_values.clone(),
+      format,
+      locale: self.locale.clone(),
     })
   }
 
-  fn load(path: &Path) -> Result<Page> {
-    let element = load_xml(path)?;
-    Page::new(element, path)
+  /// Returns a copy of this page that uses the strings of `locale`, written within the
+  /// directory `prefix` if one is given.
+  fn for_locale(&self, locale: &str, prefix: Option<&str>) -> Page {
+    let path = match prefix {
+      Some(prefix) => Path::new(prefix).join(&self.path),
+      None => self.path.clone(),
+    };
+    Page {
+      path,
+      source_path: self.source_path.clone(),
+      template: self.template.clone(),
+      slot_values: self.slot_values.clone(),
+      format: self.format.clone(),
+      locale: Some(locale.to_string()),
+    }
+  }
+
+  /// Returns a copy of each of the site's pages for each of its locales, within a
+  /// directory named after the locale, or at the root for the `default_locale`.
+  fn for_each_locale(site: &Site) -> Vec<Page> {
+    let mut pages = Vec::new();
+    for locale in site.config.locales.keys().sorted() {
+      let prefix = if site.config.default_locale.as_ref() == Some(locale) {
+        None
+      } else {
+        Some(locale.as_str())
+      };
+      for page in site.pages.values().sorted_by_key(|page| &page.path) {
+        pages.push(page.for_locale(locale, prefix));
+      }
+    }
+    pages
+  }
+
+  /// Returns a listing page, rendered with `template`, for each directory that holds
+  /// HTML pages but no index.html. Each listing fills the `title` slot with the
+  /// directory's url path and the `listing` slot with a list of links to its pages.
+  fn directory_listings(pages: &[&Page], template: &str) -> Vec<Page> {
+    let mut directories = BTreeMap::<&Path, Vec<&str>>::new();
+    for page in pages.iter().filter(|page| page.format == "html") {
+      let directory = page.path.parent().unwrap_or_else(|| Path::new(""));
+      let file_name = page.path.file_name().and_then(|name| name.to_str());
+      if let Some(file_name) = file_name {
+        directories.entry(directory).or_default().push(file_name);
+      }
+    }
+
+    let mut listings = Vec::new();
+    for (directory, mut file_names) in directories {
+      if file_names.contains(&"index.html") {
+        continue;
+      }
+      file_names.sort_unstable();
+      let mut list = Element::bare("ul", None::<String>);
+      for file_name in file_names {
+        let mut link = Element::bare("a", None::<String>);
+        link.set_attr("href", file_name);
+        link.append_text_node(file_name);
+        let mut item = Element::bare("li", None::<String>);
+        item.append_child(link);
+        list.append_child(item);
+      }
+      let mut title = Element::bare("oeuvre-fragment", None::<String>);
+      title.append_text_node(match url_path(directory).as_str() {
+        "" => "/".to_string(),
+        directory => format!("/{}/", directory),
+      });
+      let path = directory.join("index.html");
+      listings.push(Page {
+        path: path.clone(),
+        source_path: path,
+        template: template.to_string(),
+        slot_values: HashMap::from([("title".to_string(), title), ("listing".to_string(), list)]),
+        format: "html".to_string(),
+        locale: None,
+      });
+    }
+    listings
+  }
+
+  /// Returns the pages of `archive`. For each distinct value of the archive's `group_by`
+  /// field among the rows of its dataset, the rows with that value are listed across
+  /// numbered pages of at most `per_page` rows, at `<path>/<value>/page/<n>.html`, and
+  /// the first of them is also written as the group's landing page, at
+  /// `<path>/<value>/index.html`. Each page fills the `group` slot with the value, the
+  /// `items` slot with a list of links to its rows, the `pagination` slot with links to
+  /// the previous and next pages, and the `page_number` and `page_count` slots.
+  /// A value whose slug is empty, or the same as that of a value sorted before it, as
+  /// with `C` and `C++`, would have no directory of its own, so it is skipped with a
+  /// warning.
+  fn archive_pages(site: &Site, archive: &ArchiveConfig) -> Vec<Page> {
+    let dataset = match site.datasets.get(&archive.dataset) {
+      Some(dataset) => dataset,
+      None => {
+        error!(
+          "Archive {} lists dataset {}, which does not exist",
+          archive.path, archive.dataset
+        );
+        return Vec::new();
+      }
+    };
+    let mut rows_by_value = BTreeMap::<String, Vec<&DataRow>>::new();
+    for row in &dataset.rows {
+      let values = row.get(&archive.group_by).map(|value| value.text()).unwrap_or_default();
+      let values = values.split(',').map(str::trim).filter(|value| !value.is_empty());
+      for value in values.unique() {
+        rows_by_value.entry(value.to_string()).or_default().push(row);
+      }
+    }
+    // Groups are keyed by the slug of their value, which names their directory.
+    let mut groups = BTreeMap::<String, (String, Vec<&DataRow>)>::new();
+    for (value, rows) in rows_by_value {
+      let group_slug = slug(&value);
+      if group_slug.is_empty() {
+        warn!(
+          "-- Archive {} skips group {}, which has no letters or digits to name its directory",
+          archive.path, value
+        );
+      } else if let Some((existing, _)) = groups.get(&group_slug) {
+        warn!(
+          "-- Archive {} skips group {}, whose directory {} is already used by group {}",
+          archive.path, value, group_slug, existing
+        );
+      } else {
+        groups.insert(group_slug, (value, rows));
+      }
+    }
+
+    let text = |text: &str| {
+      let mut fragment = Element::bare("oeuvre-fragment", None::<String>);
+      fragment.append_text_node(text);
+      fragment
+    };
+    let mut pages = Vec::new();
+    for (group_slug, (group, rows)) in groups {
+      let directory = Path::new(archive.path.trim_matches('/')).join(&group_slug);
+      let page_url =
+        |number: usize| format!("/{}", url_path(&directory.join(format!("page/{}.html", number))));
+      let chunks: Vec<&[&DataRow]> = rows.chunks(archive.per_page.max(1)).collect();
+      for (index, chunk) in chunks.iter().enumerate() {
+        let number = index + 1;
+        let mut list = Element::bare("ul", None::<String>);
+        for row in chunk.iter() {
+          let field = |name: &str| row.get(name).map(|value| value.text());
+          let url = field("url").unwrap_or_default();
+          let mut link = Element::bare("a", None::<String>);
+          link.set_attr("href", url.as_str());
+          link.append_text_node(field("title").unwrap_or(url));
+          let mut item = Element::bare("li", None::<String>);
+          item.append_child(link);
+          list.append_child(item);
+        }
+        let mut pagination = Element::bare("nav", None::<String>);
+        let mut pagination_link = |rel: &str, label: &str, number: usize| {
+          let mut link = Element::bare("a", None::<String>);
+          link.set_attr("rel", rel);
+          link.set_attr("href", page_url(number));
+          link.append_text_node(label);
+          pagination.append_child(link);
+        };
+        if number > 1 {
+          pagination_link("prev", "Previous", number - 1);
+        }
+        if number < chunks.len() {
+          pagination_link("next", "Next", number + 1);
+        }
+        let slot_values = HashMap::from([
+          ("group".to_string(), text(&group)),
+          ("items".to_string(), list),
+          ("pagination".to_string(), pagination),
+          ("page_number".to_string(), text(&number.to_string())),
+          ("page_count".to_string(), text(&chunks.len().to_string())),
+        ]);
+        let mut paths = vec![directory.join(format!("page/{}.html", number))];
+        if number == 1 {
+          paths.push(directory.join("index.html"));
+        }
+        for path in paths {
+          pages.push(Page {
+            path: path.clone(),
+            source_path: path,
+            template: archive.template.clone(),
+            slot_values: slot_values.clone(),
+            format: "html".to_string(),
+            locale: None,
+          });
+        }
+      }
+    }
+    pages
+  }
+
+  /// Adds each child of `parent` that names slots in its oeuvre-slot attribute to
+  /// `slot_values`. A child with an oeuvre-slot-scope attribute instead groups its own
+  /// children's slots under that scope, so that a `title` slot within a `sidebar` scope
+  /// fills the `sidebar.title` slot. Scopes may be nested.
+  fn collect_slot_values(
+    parent: &Element,
+    scope: Option<&str>,
+    slot_values: &mut HashMap<String, Element>,
+  ) {
+    let qualify = |name: &str| match scope {
+      Some(scope) => format!("{}.{}", scope, name),
+      None => name.to_string(),
+    };
+    for child in parent.children() {
+      if let Some(slot_names) = child.attr("oeuvre-slot") {
+        for slot_name in Page::duplicate_slot_names(slot_names) {
+          warn!(
+            "-- Page element <{}> lists slot {} more than once in its oeuvre-slot attribute",
+            child.name(),
+            qualify(slot_name)
+          );
+        }
+        for slot_name in slot_names.split(',') {
+          slot_values.insert(qualify(slot_name.trim()), child.clone());
+        }
+      } else if let Some(child_scope) = child.attr("oeuvre-slot-scope") {
+        Page::collect_slot_values(child, Some(&qualify(child_scope.trim())), slot_values);
+      }
+    }
+  }
+
+  /// Returns each name that appears more than once in an oeuvre-slot attribute value.
+  fn duplicate_slot_names(slot_names: &str) -> Vec<&str> {
+    slot_names.split(',').map(str::trim).duplicates().collect()
+  }
+
+  /// The path of the file the page was loaded from.
+  pub fn source_path(&self) -> &Path {
+    &self.source_path
+  }
+
+  /// The page's output path, relative to the output directory.
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+
+  /// Renders the page with the template named `template` instead of its own.
+  pub fn set_template(&mut self, template: &str) {
+    self.template = template.to_string();
+  }
+
+  /// The values the page provides for its template's slots, keyed by slot name.
+  pub fn slot_values(&self) -> &HashMap<String, Element> {
+    &self.slot_values
+  }
+
+  /// Loads the page at `path`, followed by a page for each alternate format it lists.
+  fn load(path: &Path, config: &SiteConfig) -> Result<Vec<Page>> {
+    let element = load_xml(path, config)?;
+    let mut page = Page::new(&element, path)?;
+    if let Some(extension) = &config.output_extension {
+      if element.attr("oeuvre-path").is_some() && page.path.extension().is_none() {
+        page.path.set_extension(extension);
+      }
+    }
+    let mut formats = Vec::new();
+    for child in element.children().filter(|child| child.name() == "oeuvre-format") {
+      formats.push(page.alternate_format(child, config)?);
+    }
+    formats.insert(0, page);
+    Ok(formats)
   }
 
   /// Loads and parses the pages indicated by `page_paths` and returns them
-  /// in a HashMap using the relative path to the file as the key.
-  pub fn load_many(page_paths: &[PathBuf]) -> HashMap<String, Page> {
-    let mut pages = HashMap::<String, Page>::new();
+  /// in a HashMap using the output path of each page as the key. Paths are compared
+  /// as they are, rather than as displayed, so that paths that are not valid UTF-8
+  /// cannot collide. When two pages share an output path, the one loaded first is
+  /// kept, and a diagnostic is returned for the other.
+  pub fn load_many(
+    page_paths: &[PathBuf],
+    config: &SiteConfig,
+  ) -> (HashMap<PathBuf, Page>, Vec<Diagnostic>) {
+    let mut pages = HashMap::<PathBuf, Page>::new();
+    let mut collisions = Vec::<Diagnostic>::new();
     for page_path in page_paths {
       info!("- Loading page {}", page_path.display());
-      let page = match Page::load(page_path) {
-        Ok(page) => page,
+      let formats = match Page::load(page_path, config) {
+        Ok(formats) => formats,
         Err(err) => {
           error!("-- {}", err);
           continue;
         }
       };
       info!("-- Loaded page {}", page_path.display());
-      pages.insert(page.path.display().to_string(), page);
+      for page in formats {
+        if let Some(existing) = pages.get(&page.path) {
+          let collision = Diagnostic {
+            path: page_path.clone(),
+            position: None,
+            severity: Severity::Warning,
+            code: PATH_COLLISION,
+            message: format!(
+              "Page output path {} is already used by {}, so this page is skipped",
+              page.path.display(),
+              existing.source_path.display()
+            ),
+          };
+          warn!("-- {}", collision);
+          collisions.push(collision);
+          continue;
+        }
+        pages.insert(page.path.clone(), page);
+      }
     }
-    pages
+    (pages, collisions)
   }
 
-  fn render(
+  /// Renders the page to be embedded in another, whose own rendering is embedded in
+  /// each of the `embedding` pages, outermost first, and returns it along with whether
+  /// it skipped an embed of one of those pages, which would have embedded itself. The
+  /// result is kept unless it did, so the page is rendered only once however many pages
+  /// embed it, unless pages rendered in parallel race to be first.
+  pub fn render_embedded(
     &self,
-    templates: &HashMap<String, Template>,
-    snippets: &HashMap<String, Snippet>,
-  ) -> Result<String> {
-    let template = match templates.get(&self.template) {
+    site: &Site,
+    issues: &mut IssueSink,
+    embedding: &[PathBuf],
+  ) -> Result<(Element, bool)> {
+    let kept = site.embedded_pages.get(&self.path);
+    if let Some(rendered) = kept.and_then(OnceLock::get) {
+      return Ok((rendered.clone(), false));
+    }
+    let (rendered, hit_embed_cycle) = self.render_within(site, issues, embedding)?;
+    Ok(match kept {
+      Some(kept) if !hit_embed_cycle => (kept.get_or_init(|| rendered).clone(), false),
+      _ => (rendered, hit_embed_cycle),
+    })
+  }
+
+  /// Renders the page, reusing the result if the page was already rendered to be
+  /// embedded in another.
+  fn render_once(&self, site: &Site, issues: &mut IssueSink) -> Result<Element> {
+    match site.embedded_pages.get(&self.path).and_then(OnceLock::get) {
+      Some(rendered) => Ok(rendered.clone()),
+      None => self.render(site, issues),
+    }
+  }
+
+  fn render(&self, site: &Site, issues: &mut IssueSink) -> Result<Element> {
+    Ok(self.render_within(site, issues, &[])?.0)
+  }
+
+  /// Renders the page within the rendering of each of the `embedding` pages, and returns
+  /// it along with whether it skipped an embed of one of them.
+  fn render_within(
+    &self,
+    site: &Site,
+    issues: &mut IssueSink,
+    embedding: &[PathBuf],
+  ) -> Result<(Element, bool)> {
+    let template = match site.templates.get(&self.template) {
       Some(template) => template,
       None => {
-        bail!(
+        let message = format!(
           "Page `{}` requested template `{}`, which does not exist",
           self.path.display(),
           self.template
         );
+        if !site.config.placeholder_on_missing_template {
+          bail!(message);
+        }
+        issues.record(message.clone());
+        let mut ctx = RenderContext::new(&self.slot_values, site, &self.path, issues);
+        return Ok((render_template(&self.placeholder_template(&message), &mut ctx), false));
       }
     };
-    let result = render_template(&template.element, &self.slot_values, snippets);
-    Ok(String::from(&result))
+    let mut ctx = RenderContext::new(&self.slot_values, site, &self.path, issues);
+    ctx.embedding_pages = embedding.to_vec();
+    let mut result = render_template(&template.element, &mut ctx);
+    if ctx.is_over_size_limit() {
+      bail!(
+        "Page {} is larger than the max_output_bytes limit of {} bytes",
+        self.path.display(),
+        site.config.max_output_bytes.unwrap_or_default()
+      );
+    }
+    let locale = self.locale.as_deref().or(site.config.locale.as_deref());
+    localize(&mut result, site, locale, &self.path);
+    number_counters(&mut result, ctx.issues);
+    hoist_head_blocks(&mut result);
+    if site.config.canonical && self.format == "html" {
+      add_canonical_link(&mut result, site, &self.path);
+    }
+    result.set_declaration(template.element.declaration().map(String::from));
+    Ok((result, ctx.hit_embed_cycle()))
   }
 
+  /// Returns a minimal template that shows `message` followed by each of the page's
+  /// slot values in order of name, to stand in for a template that does not exist.
+  fn placeholder_template(&self, message: &str) -> Element {
+    let mut title = Element::bare("title", None::<String>);
+    title.append_text_node(message);
+    let mut head = Element::bare("head", None::<String>);
+    head.append_child(title);
+
+    let mut heading = Element::bare("p", None::<String>);
+    heading.append_text_node(message);
+    let mut body = Element::bare("body", None::<String>);
+    body.append_child(heading);
+    for slot_name in self.slot_values.keys().sorted() {
+      let mut slot = Element::bare("oeuvre-slot", None::<String>);
+      slot.set_attr("oeuvre-name", slot_name);
+      body.append_child(slot);
+    }
+
+    let mut html = Element::bare("html", None::<String>);
+    html.append_child(head);
+    html.append_child(body);
+    html
+  }
+
+  /// Serializes a rendered page as a complete document, as configured for the site.
+  /// Only HTML documents begin with the configured doctype, and plain text documents consist of
+  /// nothing but the text of the rendered page.
+  fn serialize(&self, rendered: &Element, site: &Site) -> Result<Vec<u8>> {
+    let too_large = || {
+      anyhow!(
+        "Page {} is larger than the max_output_bytes limit of {} bytes",
+        self.path.display(),
+        site.config.max_output_bytes.unwrap_or_default()
+      )
+    };
+    if self.format == "txt" {
+      let text = text_content(rendered);
+      if site.config.max_output_bytes.is_some_and(|max| text.len() as u64 > max) {
+        return Err(too_large());
+      }
+      return Ok(text.into_bytes());
+    }
+    let comment = if site.config.source_comments {
+      Some(format!(
+        " oeuvre: src={} template={} ",
+        self.source_path.display(),
+        self.template
+      ))
+    } else {
+      None
+    };
+    let options = WriteOptions {
+      xml_declaration: site.config.xml_declaration,
+      prolog: if self.format == "html" && !site.config.doctype.is_empty() {
+        Some(site.config.doctype.clone())
+      } else {
+        None
+      },
+      comment,
+      attr_quotes: match site.config.attr_quotes {
+        AttrQuoteStyle::Double => AttrQuotes::Double,
+        AttrQuoteStyle::Single => AttrQuotes::Single,
+      },
+      html: self.format == "html",
+      formatting: match site.config.output_format {
+        _ if self.format != "html" => Formatting::Preserve,
+        OutputFormat::Preserve => Formatting::Preserve,
+        OutputFormat::Pretty => Formatting::Pretty,
+        OutputFormat::Minified => Formatting::Minified,
+      },
+    };
+    let mut document = LimitedWriter {
+      bytes: Vec::new(),
+      max_bytes: site.config.max_output_bytes,
+    };
+    if let Err(err) = rendered.write_to_with_options(&mut document, &options) {
+      if document.is_full() {
+        return Err(too_large());
+      }
+      bail!("Failed to serialize page {}. Cause: {}", &self.path.display(), err);
+    }
+    Ok(document.bytes)
+  }
+
+  /// Renders the page and writes it to `sink`, returning the path it was written to.
+  /// With `hash_pages`, an HTML page is written under a name that includes a hash of
+  /// its contents, and a stub that redirects to it is written at its usual path.
   fn write(
     &self,
     site: &Site,
-  ) -> Result<()> {
-    const DOCTYPE_HEADER: &str = "<!DOCTYPE html>\r\n";
-    let rendered = match self.render(&site.templates, &site.snippets) {
+    sink: &dyn OutputSink,
+    issues: &mut IssueSink,
+  ) -> Result<PathBuf> {
+    let rendered = match self.render_once(site, issues) {
       Ok(rendered_page) => rendered_page,
       Err(err) => {
         bail!("Failed to render page {}. Cause: {}", &self.path.display(), err);
       }
     };
+    if !site.config.allow_empty && is_empty_render(&rendered) {
+      bail!(
+        "Page {} rendered to an empty document, so it was not written",
+        &self.path.display()
+      );
+    }
+
+    let mut document = self.serialize(&rendered, site)?;
+    let mut sections = None;
+    if site.config.source_map && self.format != "txt" {
+      let (stripped, found) = split_source_markers(&String::from_utf8(document)?);
+      document = stripped.into_bytes();
+      sections = Some(found);
+    }
+    let path = if site.config.hash_pages && self.format == "html" {
+      let hash = &content_hash(&document)[..8];
+      let hashed_path = self.path.with_extension(format!("{}.html", hash));
+      let file_name = hashed_path.file_name().unwrap().to_string_lossy();
+      if let Err(err) = sink.write(&self.path, &redirect_stub(&file_name)) {
+        bail!("Failed to write page {}. Cause: {}", &self.path.display(), err);
+      }
+      hashed_path
+    } else {
+      self.path.clone()
+    };
+    if let Err(err) = sink.write(&path, &document) {
+      bail!("Failed to write page {}. Cause: {}", &path.display(), err);
+    }
+    if let Some(sections) = sections {
+      let mut map_name = path.file_name().unwrap().to_os_string();
+      map_name.push(".map");
+      let map = self.source_map_json(&path, sections, site);
+      if let Err(err) = sink.write(&path.with_file_name(map_name), map.as_bytes()) {
+        bail!("Failed to write the source map of page {}. Cause: {}", &path.display(), err);
+      }
+    }
+    // Only pages that were written keep the assets they link to.
+    if site.config.prune_unused_assets {
+      let mut referenced_paths = site.referenced_paths.lock().unwrap();
+      collect_local_links(&rendered, &self.path, &site.config.base_url, &mut referenced_paths);
+    }
+    Ok(path)
+  }
+
+  /// Returns a JSON document that lists each section of the page written to `path` by
+  /// its byte offsets and starting line, along with the source file that produced it:
+  /// the page itself for the content of its slots, or else its template.
+  fn source_map_json(&self, path: &Path, sections: Vec<SourceSection>, site: &Site) -> String {
+    let template_path = match site.templates.get(&self.template) {
+      Some(template) => template.path.as_path(),
+      None => self.source_path.as_path(),
+    };
+    let mut entries = Vec::<(SourceSection, &Path)>::new();
+    for section in sections {
+      let source = match &section.slot {
+        Some(slot) if self.slot_values.contains_key(slot) => self.source_path.as_path(),
+        _ => template_path,
+      };
+      match entries.last_mut() {
+        Some((last, last_source)) if *last_source == source && last.slot == section.slot => {
+          last.end = section.end;
+        }
+        _ => entries.push((section, source)),
+      }
+    }
+    let entries: Vec<JsonValue> = entries
+      .iter()
+      .map(|(section, source)| {
+        let mut entry = json!({
+          "start": section.start,
+          "end": section.end,
+          "line": section.line,
+          "source": url_path(source),
+        });
+        if let Some(slot) = &section.slot {
+          entry["slot"] = json!(slot);
+        }
+        entry
+      })
+      .collect();
+    let map = json!({ "file": url_path(path), "sections": entries });
+    serde_json::to_string_pretty(&map).unwrap() + "\n"
+  }
 
-    let output_path = &site.output_dir.join(&self.path).clean();
-    if let Err(err) = fs::create_dir_all(output_path.parent().unwrap()) {
-      error!("-- {}", err);
+  /// Renders the page as a single self-contained document,
+  /// with its local stylesheets and small images inlined.
+  pub fn render_bundle(&self, site: &Site) -> Result<String> {
+    let mut rendered = match self.render(site, &mut IssueSink::default()) {
+      Ok(rendered_page) => rendered_page,
+      Err(err) => {
+        bail!("Failed to render page {}. Cause: {}", &self.path.display(), err);
+      }
     };
+    inline_resources(&mut rendered, &self.path, site.config.bundle_max_image_bytes);
+    let document = String::from_utf8(self.serialize(&rendered, site)?)?;
+    if site.config.source_map {
+      return Ok(split_source_markers(&document).0);
+    }
+    Ok(document)
+  }
 
-    match fs::write(output_path, format!("{}{}", DOCTYPE_HEADER, rendered)) {
-      Ok(_) => Ok(()),
+  /// Writes the page, buffering its log lines instead of emitting them.
+  fn write_logged(&self, site: &Site, sink: &dyn OutputSink) -> WrittenPage {
+    let mut log = LogBuffer::new(module_path!());
+    let mut issues = IssueSink::held();
+    let mut route = None;
+    let mut failed = false;
+    log.info(format!("- Writing page {}", &self.path.display()));
+    let written = self.write(site, sink, &mut issues);
+    for message in issues.take_held() {
+      log.error(format!("-- {}", message));
+    }
+    match written {
+      Ok(path) => {
+        log.info(format!("-- Wrote page {}", &path.display()));
+        route = Some((url_path(&self.path), url_path(&path)));
+      }
       Err(err) => {
-        bail!("Failed to write page {}. Cause: {}", &self.path.display(), err);
+        log.error(format!("-- {}", err));
+        failed = true;
       }
+    };
+    WrittenPage {
+      log,
+      route,
+      // A page that could not be written counts as at least one issue, even if the
+      // problem was not one recorded while rendering, such as a missing template.
+      issue_count: issues.count().max(usize::from(failed)),
     }
   }
 
-  /// Writes all of a site's pages to disk.
-  pub fn write_many(site: &Site) {
-    for page in site.pages.values() {
-      info!("- Writing page {}", &page.path.display());
-      if let Err(err) = page.write(site) {
-        error!("-- {}", err);
-        continue;
-      };
-      info!("-- Wrote page {}", &page.path.display());
+  /// Writes `pages` on a pool of `jobs` threads, which is safe since the site is only read
+  /// while rendering. The results are returned in the order of `pages`, so that the log and
+  /// the routes manifest come out the same as they would from writing one page at a time.
+  #[cfg(feature = "parallel")]
+  fn write_parallel(pages: &[&Page], site: &Site, sink: &dyn OutputSink) -> Vec<WrittenPage> {
+    in_thread_pool(site.config.jobs, || {
+      pages.par_iter().map(|page| page.write_logged(site, sink)).collect()
+    })
+  }
+
+  /// Removes each page from `pages` whose output path is already used by a page before
+  /// it, such as an archive page generated at the path of one of the site's own pages,
+  /// with a warning naming both, and returns the number of pages removed.
+  fn skip_collisions(pages: &mut Vec<&Page>) -> usize {
+    let mut sources = HashMap::<&Path, &Path>::new();
+    let page_count = pages.len();
+    pages.retain(|page| match sources.get(page.path.as_path()) {
+      Some(existing) => {
+        let collision = Diagnostic {
+          path: page.source_path.clone(),
+          position: None,
+          severity: Severity::Warning,
+          code: PATH_COLLISION,
+          message: format!(
+            "Page output path {} is already used by {}, so this page is skipped",
+            page.path.display(),
+            existing.display()
+          ),
+        };
+        warn!("-- {}", collision);
+        false
+      }
+      None => {
+        sources.insert(&page.path, &page.source_path);
+        true
+      }
+    });
+    page_count - pages.len()
+  }
+
+  /// Writes all of a site's pages to `sink` and returns the number of problems found
+  /// while rendering them, counting each page skipped because its output path is taken. Each page's log lines are buffered and emitted together
+  /// once the page is done. With `locale_dirs`, each page is written once for each locale.
+  /// Pages are written in order of their output paths, rather than the site's hash map
+  /// order, so that the logs and anything else that depends on that order are the same
+  /// from one build to the next.
+  pub fn write_many(site: &Site, sink: &dyn OutputSink) -> usize {
+    let localized;
+    let pages: Vec<&Page> = if site.config.locale_dirs {
+      localized = Page::for_each_locale(site);
+      localized.iter().collect()
+    } else {
+      site.pages.values().sorted_by_key(|page| &page.path).collect()
+    };
+    let mut generated = Vec::new();
+    if let Some(template) = &site.config.dir_index {
+      generated.extend(Page::directory_listings(&pages, template));
+    }
+    for archive in &site.config.archives {
+      generated.extend(Page::archive_pages(site, archive));
+    }
+    let mut pages: Vec<&Page> = pages.into_iter().chain(&generated).collect();
+    let collision_count = Page::skip_collisions(&mut pages);
+
+    #[cfg(feature = "parallel")]
+    let written = Page::write_parallel(&pages, site, sink).into_iter();
+    #[cfg(not(feature = "parallel"))]
+    let written = pages.iter().map(|page| page.write_logged(site, sink));
+
+    let mut issue_count = collision_count;
+    let mut routes = Vec::<(String, String)>::new();
+    for written_page in written {
+      written_page.log.flush();
+      routes.extend(written_page.route);
+      issue_count += written_page.issue_count;
+    }
+    if site.config.hash_pages {
+      info!("Writing routes manifest {}", ROUTES_PATH);
+      if let Err(err) = sink.write(Path::new(ROUTES_PATH), routes_json(routes).as_bytes()) {
+        error!("{} could not be written. Cause: {}", ROUTES_PATH, err);
+      }
+    }
+    issue_count
+  }
+}
+
+/// The outcome of writing a single page.
+struct WrittenPage {
+  log: LogBuffer,
+  /// The page's usual url path and the one it was actually written to, if it was written.
+  route: Option<(String, String)>,
+  issue_count: usize,
+}
+
+/// A section of a serialized page, as a range of byte offsets, along with the line it
+/// begins on and the name of the innermost slot whose content it is, if any.
+struct SourceSection {
+  start: usize,
+  end: usize,
+  line: usize,
+  slot: Option<String>,
+}
+
+/// Removes the marker comments that enclose the content of filled slots with the
+/// source_map option from a serialized page, and returns the page along with the
+/// sections that the markers divided it into.
+fn split_source_markers(document: &str) -> (String, Vec<SourceSection>) {
+  let start_marker = format!("<!--{}", SOURCE_MAP_START);
+  let end_marker = format!("<!--{}-->", SOURCE_MAP_END);
+  let mut output = String::with_capacity(document.len());
+  let mut sections = Vec::<SourceSection>::new();
+  let mut slots = Vec::<&str>::new();
+  let mut rest = document;
+  loop {
+    let marker = rest
+      .match_indices("<!--")
+      .map(|(at, _)| at)
+      .find(|at| rest[*at..].starts_with(&start_marker) || rest[*at..].starts_with(&end_marker));
+    let text = &rest[..marker.unwrap_or(rest.len())];
+    if !text.is_empty() {
+      sections.push(SourceSection {
+        start: output.len(),
+        end: output.len() + text.len(),
+        line: output.matches('\n').count() + 1,
+        slot: slots.last().map(|slot| slot.to_string()),
+      });
+      output.push_str(text);
+    }
+    let at = match marker {
+      Some(at) => at,
+      None => break,
+    };
+    rest = &rest[at..];
+    if rest.starts_with(&end_marker) {
+      slots.pop();
+      rest = &rest[end_marker.len()..];
+    } else {
+      let end = rest.find("-->").unwrap();
+      slots.push(&rest[start_marker.len()..end]);
+      rest = &rest[end + 3..];
+    }
+  }
+  (output, sections)
+}
+
+/// The output path of the manifest of routes written with `hash_pages`.
+const ROUTES_PATH: &str = "routes.json";
+
+/// Returns a JSON document mapping the usual path of each page to the path of the
+/// file it was written to, sorted by route.
+fn routes_json(mut routes: Vec<(String, String)>) -> String {
+  routes.sort();
+  let routes: Vec<JsonValue> = routes
+    .into_iter()
+    .map(|(route, file)| json!({ "route": route, "file": file }))
+    .collect();
+  serde_json::to_string_pretty(&json!({ "routes": routes })).unwrap() + "\n"
+}
+
+/// Returns an HTML document that immediately redirects to `file_name`,
+/// a file in the same directory.
+fn redirect_stub(file_name: &str) -> Vec<u8> {
+  let mut meta = Element::bare("meta", None::<String>);
+  meta.set_attr("http-equiv", "refresh");
+  meta.set_attr("content", format!("0; url={}", file_name));
+  let mut head = Element::bare("head", None::<String>);
+  head.append_child(meta);
+  let mut html = Element::bare("html", None::<String>);
+  html.append_child(head);
+  let options = WriteOptions {
+    prolog: Some("<!DOCTYPE html>\r\n".to_string()),
+    html: true,
+    ..WriteOptions::default()
+  };
+  let mut document = Vec::new();
+  html.write_to_with_options(&mut document, &options).unwrap();
+  document
+}
+
+/// Collects serialized output, failing once more than `max_bytes` would be written,
+/// so that a runaway page is abandoned before all of it is held in memory.
+struct LimitedWriter {
+  bytes: Vec<u8>,
+  max_bytes: Option<u64>,
+}
+
+impl LimitedWriter {
+  fn is_full(&self) -> bool {
+    self.max_bytes.is_some_and(|max_bytes| self.bytes.len() as u64 >= max_bytes)
+  }
+}
+
+impl Write for LimitedWriter {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    let room = match self.max_bytes {
+      Some(max_bytes) => max_bytes.saturating_sub(self.bytes.len() as u64),
+      None => u64::MAX,
+    };
+    if buf.len() as u64 > room {
+      self.bytes.extend_from_slice(&buf[..room as usize]);
+      return Err(io::Error::other("output size limit exceeded"));
+    }
+    self.bytes.extend_from_slice(buf);
+    Ok(buf.len())
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
+
+/// Adds the local file that each href, src or srcset url within `element`, a page
+/// rendered to `page_path`, links to, relative to the input directory, to `paths`.
+/// Urls within the site's `base_url` are local as well, and `%XX` escapes are decoded.
+fn collect_local_links(
+  element: &Element,
+  page_path: &Path,
+  base_url: &str,
+  paths: &mut HashSet<PathBuf>,
+) {
+  let urls = element
+    .attr("href")
+    .into_iter()
+    .chain(element.attr("src"))
+    .chain(element.attr("srcset").into_iter().flat_map(|srcset| {
+      srcset
+        .split(',')
+        .filter_map(|candidate| candidate.split_whitespace().next())
+    }));
+  let base_url = base_url.trim_end_matches('/');
+  let local_url = |url: &str| match url.strip_prefix(base_url) {
+    Some(path) if !base_url.is_empty() && (path.is_empty() || path.starts_with('/')) => {
+      format!("/{}", path.trim_start_matches('/'))
+    }
+    _ => url.to_string(),
+  };
+  paths.extend(urls.filter_map(|url| {
+    let path = local_path(&local_url(url), page_path)?;
+    Some(PathBuf::from(percent_decode(path.to_str()?)?))
+  }));
+  for child in element.children() {
+    collect_local_links(child, page_path, base_url, paths);
+  }
+}
+
+/// Whether a rendered page has no content: no child elements and nothing but
+/// whitespace for text. Such a page usually means that something went wrong upstream.
+fn is_empty_render(rendered: &Element) -> bool {
+  rendered.children().next().is_none() && rendered.texts().all(|text| text.trim().is_empty())
+}
+
+/// Returns the text of `element` and all of its descendants, in document order.
+fn text_content(element: &Element) -> String {
+  let mut text = String::new();
+  for node in element.nodes() {
+    match node {
+      Node::Element(child) => text.push_str(&text_content(child)),
+      Node::Text(value) | Node::CData(value) => text.push_str(value),
+      Node::Comment(_) => (),
+    }
+  }
+  text
+}
+
+#[cfg(test)]
+mod tests {
+  use super::Page;
+  use crate::site::check::{Diagnostic, Severity, PATH_COLLISION};
+  use crate::site::content_hash;
+  use crate::site::output::DirectorySink;
+  use crate::site::render::IssueSink;
+  use crate::site::test_utils::TestSite;
+  use crate::site::SiteConfig;
+  use serde_json::{json, Value as JsonValue};
+  use std::fs;
+  use std::path::{Path, PathBuf};
+
+  #[test]
+  fn page_over_output_limit_is_aborted() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        r#"<html oeuvre-name="main"><body><oeuvre-slot oeuvre-name="body"/></body></html>"#,
+      )
+      .file(
+        "small.xml",
+        r#"<oeuvre-page oeuvre-template="main" oeuvre-path="small.html"><p oeuvre-slot="body">Hi</p></oeuvre-page>"#,
+      )
+      .file(
+        "big.xml",
+        &format!(
+          r#"<oeuvre-page oeuvre-template="main" oeuvre-path="big.html"><p oeuvre-slot="body">{}</p></oeuvre-page>"#,
+          "x".repeat(1000)
+        ),
+      )
+      .file(
+        "attrs.xml",
+        &format!(
+          r#"<oeuvre-page oeuvre-template="main" oeuvre-path="attrs.html"><p oeuvre-slot="body" title="{}">Hi</p></oeuvre-page>"#,
+          "x".repeat(1000)
+        ),
+      );
+    let config = "max_output_bytes = 100";
+    let loaded = site.build(config).unwrap();
+    assert!(site.output_exists("small.html"));
+    assert!(!site.output_exists("big.html"));
+    assert!(!site.output_exists("attrs.html"));
+
+    // Attributes are not counted while rendering, so this page is only caught as it's written.
+    let page = &loaded.pages[Path::new("attrs.html")];
+    let rendered = page.render(&loaded, &mut IssueSink::default()).unwrap();
+    assert_eq!(
+      page.serialize(&rendered, &loaded).err().unwrap().to_string(),
+      "Page attrs.html is larger than the max_output_bytes limit of 100 bytes"
+    );
+
+    let page = &loaded.pages[Path::new("big.html")];
+    assert_eq!(
+      page.render(&loaded, &mut IssueSink::default()).err().unwrap().to_string(),
+      "Page big.html is larger than the max_output_bytes limit of 100 bytes"
+    );
+  }
+
+  #[test]
+  fn runaway_expansion_is_aborted_while_rendering() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        &format!(
+          r#"<html oeuvre-name="main"><body>{}Hello{}</body></html>"#,
+          r#"<oeuvre-for oeuvre-dataset="items">"#.repeat(8),
+          "</oeuvre-for>".repeat(8)
+        ),
+      )
+      .file("index.xml", r#"<oeuvre-page oeuvre-template="main" oeuvre-path="index.html"/>"#)
+      .file("data/items.xml", r#"<oeuvre-dataset oeuvre-name="items"/>"#);
+    for index in 0..10 {
+      site.file(
+        &format!("data/items/{}.xml", index),
+        r#"<oeuvre-datarow oeuvre-dataset="items"/>"#,
+      );
+    }
+    // Without the limit, the page would say Hello a hundred million times.
+    let loaded = site.build("max_output_bytes = 1000").unwrap();
+    assert!(!site.output_exists("index.html"));
+    assert_eq!(
+      loaded.pages[Path::new("index.html")]
+        .render(&loaded, &mut IssueSink::default())
+        .err()
+        .unwrap()
+        .to_string(),
+      "Page index.html is larger than the max_output_bytes limit of 1000 bytes"
+    );
+  }
+
+  #[test]
+  fn hashed_page_is_listed_in_routes_manifest() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        r#"<html oeuvre-name="main"><body><oeuvre-slot oeuvre-name="body"/></body></html>"#,
+      )
+      .file(
+        "about.xml",
+        r#"<oeuvre-page oeuvre-template="main" oeuvre-path="about.html"><p oeuvre-slot="body">Hi</p></oeuvre-page>"#,
+      );
+    site.build("hash_pages = true").unwrap();
+    let page = "<!DOCTYPE html>\r\n<html><body><p>Hi</p></body></html>";
+    let hashed_name = format!("about.{}.html", &content_hash(page.as_bytes())[..8]);
+    assert_eq!(site.read_output(&hashed_name), page);
+    assert_eq!(
+      site.read_output("about.html"),
+      format!(
+        "<!DOCTYPE html>\r\n<html><head><meta content=\"0; url={}\" http-equiv=\"refresh\"></head></html>",
+        hashed_name
+      )
+    );
+    let routes: JsonValue = serde_json::from_str(&site.read_output("routes.json")).unwrap();
+    assert_eq!(
+      routes,
+      json!({ "routes": [{ "route": "about.html", "file": hashed_name }] })
+    );
+  }
+
+  #[test]
+  fn empty_render_is_reported_and_not_written() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        r#"<html oeuvre-name="main"><oeuvre-slot oeuvre-name="body"/></html>"#,
+      )
+      .file(
+        "empty.xml",
+        r#"<oeuvre-page oeuvre-template="main" oeuvre-path="empty.html"/>"#,
+      );
+    let loaded = site.load("").unwrap();
+    let page = loaded.pages.get(Path::new("empty.html")).unwrap();
+    let err = page
+      .write(&loaded, &DirectorySink::new(&loaded.output_dir), &mut IssueSink::default())
+      .err()
+      .unwrap();
+    assert_eq!(
+      err.to_string(),
+      "Page empty.html rendered to an empty document, so it was not written"
+    );
+    assert!(!site.output_exists("empty.html"));
+
+    site.build("allow_empty = true").unwrap();
+    assert_eq!(site.read_output("empty.html"), "<!DOCTYPE html>\r\n<html></html>");
+  }
+
+  #[test]
+  fn directory_without_index_gets_listing() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        r#"<html oeuvre-name="main"><body><oeuvre-slot oeuvre-name="body"/></body></html>"#,
+      )
+      .file(
+        "templates/listing.xml",
+        r#"<html oeuvre-name="listing"><body><h1><oeuvre-slot oeuvre-name="title"/></h1><oeuvre-slot oeuvre-name="listing"/></body></html>"#,
+      )
+      .file(
+        "index.xml",
+        r#"<oeuvre-page oeuvre-template="main" oeuvre-path="index.html"><p oeuvre-slot="body">Home</p></oeuvre-page>"#,
+      )
+      .file(
+        "blog/second.xml",
+        r#"<oeuvre-page oeuvre-template="main" oeuvre-path="second.html"><p oeuvre-slot="body">2</p></oeuvre-page>"#,
+      )
+      .file(
+        "blog/first.xml",
+        r#"<oeuvre-page oeuvre-template="main" oeuvre-path="first.html"><p oeuvre-slot="body">1</p></oeuvre-page>"#,
+      );
+    site.build("dir_index = \"listing\"").unwrap();
+    assert_eq!(
+      site.read_output("blog/index.html"),
+      concat!(
+        "<!DOCTYPE html>\r\n",
+        r#"<html><body><h1>/blog/</h1><ul><li><a href="first.html">first.html</a></li><li><a href="second.html">second.html</a></li></ul></body></html>"#
+      )
+    );
+    assert_eq!(
+      site.read_output("index.html"),
+      "<!DOCTYPE html>\r\n<html><body><p>Home</p></body></html>"
+    );
+  }
+
+  #[test]
+  fn output_paths_are_normalized_and_kept_within_the_output_directory() {
+    let source = Path::new("blog/post.xml");
+    let resolve = |attr_value| Page::resolve_output_path(attr_value, source);
+    assert_eq!(resolve("/about/index.html").unwrap(), Path::new("about/index.html"));
+    assert_eq!(resolve("/é.html").unwrap(), Path::new("é.html"));
+    assert_eq!(resolve("post.html").unwrap(), Path::new("blog/post.html"));
+    assert_eq!(resolve("./drafts/../post.html").unwrap(), Path::new("blog/post.html"));
+    assert_eq!(resolve("../about.html").unwrap(), Path::new("about.html"));
+    assert_eq!(resolve("/blog/../about.html").unwrap(), Path::new("about.html"));
+    for escaping in ["/../secret.html", "../../secret.html", "//etc/passwd", "/"] {
+      assert_eq!(
+        resolve(escaping).err().unwrap().to_string(),
+        format!(
+          "Page blog/post.xml has oeuvre-path {}, which is not within the output directory",
+          escaping
+        )
+      );
     }
   }
+
+  #[test]
+  fn generated_pages_never_replace_the_pages_of_the_site() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        r#"<html oeuvre-name="main"><body><oeuvre-slot oeuvre-name="body"/></body></html>"#,
+      )
+      .file(
+        "rust.xml",
+        r#"<oeuvre-page oeuvre-template="main" oeuvre-path="/tags/rust/index.html"><p oeuvre-slot="body">Mine</p></oeuvre-page>"#,
+      )
+      .file(
+        "data/posts.xml",
+        r#"<oeuvre-dataset oeuvre-name="posts"><oeuvre-field oeuvre-name="tags" oeuvre-type="string"/></oeuvre-dataset>"#,
+      )
+      .file(
+        "data/posts/a.xml",
+        r#"<oeuvre-datarow oeuvre-dataset="posts"><oeuvre-value oeuvre-name="tags">rust</oeuvre-value></oeuvre-datarow>"#,
+      );
+    let config = concat!(
+      "[[archives]]\n",
+      "dataset = \"posts\"\n",
+      "group_by = \"tags\"\n",
+      "template = \"main\"\n",
+      "path = \"tags\"\n",
+    );
+    let report = site.load(config).unwrap().render().unwrap();
+    assert_eq!(report.issue_count, 1);
+    assert_eq!(
+      site.read_output("tags/rust/index.html"),
+      "<!DOCTYPE html>\r\n<html><body><p>Mine</p></body></html>"
+    );
+    assert!(site.output_exists("tags/rust/page/1.html"));
+    let err = site.build(&format!("strict = true\n{}", config)).err().unwrap();
+    assert_eq!(err.to_string(), "Strict build failed with 1 render issue(s)");
+  }
+
+  #[test]
+  fn pages_with_the_same_output_path_are_reported() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        r#"<html oeuvre-name="main"><body><oeuvre-slot oeuvre-name="body"/></body></html>"#,
+      )
+      .file(
+        "a.xml",
+        r#"<oeuvre-page oeuvre-template="main" oeuvre-path="/index.html"><p oeuvre-slot="body">A</p></oeuvre-page>"#,
+      )
+      .file(
+        "b.xml",
+        r#"<oeuvre-page oeuvre-template="main" oeuvre-path="/index.html"><p oeuvre-slot="body">B</p></oeuvre-page>"#,
+      );
+    let collision = Diagnostic {
+      path: PathBuf::from("b.xml"),
+      position: None,
+      severity: Severity::Warning,
+      code: PATH_COLLISION,
+      message: "Page output path index.html is already used by a.xml, so this page is skipped"
+        .to_string(),
+    };
+    let loaded = site.build("").unwrap();
+    assert_eq!(loaded.path_collisions, [collision]);
+    assert_eq!(
+      site.read_output("index.html"),
+      "<!DOCTYPE html>\r\n<html><body><p>A</p></body></html>"
+    );
+    let err = site.build("strict = true").err().unwrap();
+    assert_eq!(err.to_string(), "Strict build failed with 1 output path collision(s)");
+  }
+
+  #[test]
+  fn pages_written_on_several_threads_match_those_written_on_one() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        r#"<html oeuvre-name="main"><body><oeuvre-slot oeuvre-name="body"/></body></html>"#,
+      )
+      .file(
+        "templates/listing.xml",
+        r#"<html oeuvre-name="listing"><body><oeuvre-slot oeuvre-name="listing"/></body></html>"#,
+      );
+    for section in 0..3 {
+      for page in 0..8 {
+        site.file(
+          &format!("section{}/page{}.xml", section, page),
+          &format!(
+            r#"<oeuvre-page oeuvre-template="main" oeuvre-path="page{}.html"><p oeuvre-slot="body">{} {}</p></oeuvre-page>"#,
+            page, section, page
+          ),
+        );
+      }
+    }
+    let outputs = |config: &str| {
+      let _ = fs::remove_dir_all(site.path("output"));
+      site.build(config).unwrap();
+      site.read_output_tree()
+    };
+    let config = "dir_index = \"listing\"\nhash_pages = true";
+    let sequential = outputs(&format!("{}\njobs = 1", config));
+    let parallel = outputs(&format!("{}\njobs = 4", config));
+    assert_eq!(sequential.len(), (24 + 3) * 2 + 1);
+    assert!(sequential == parallel);
+  }
+
+  #[test]
+  fn archives_are_paginated_for_each_group() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/tag.xml",
+        r#"<html oeuvre-name="tag"><body><h1><oeuvre-slot oeuvre-name="group"/></h1><oeuvre-slot oeuvre-name="items"/><oeuvre-slot oeuvre-name="pagination"/></body></html>"#,
+      )
+      .file(
+        "data/posts.xml",
+        r#"<oeuvre-dataset oeuvre-name="posts"><oeuvre-field oeuvre-name="title" oeuvre-type="string"/><oeuvre-field oeuvre-name="url" oeuvre-type="string"/><oeuvre-field oeuvre-name="tags" oeuvre-type="string"/></oeuvre-dataset>"#,
+      );
+    let rows = [
+      ("a", "rust, web"),
+      ("b", "rust"),
+      ("c", "rust, rust"),
+      ("d", ""),
+      ("e", "C++"),
+      ("f", "C, !!"),
+    ];
+    for (name, tags) in rows {
+      site.file(
+        &format!("data/posts/{}.xml", name),
+        &format!(
+          r#"<oeuvre-datarow oeuvre-dataset="posts"><oeuvre-value oeuvre-name="title">{0}</oeuvre-value><oeuvre-value oeuvre-name="url">/{0}.html</oeuvre-value><oeuvre-value oeuvre-name="tags">{1}</oeuvre-value></oeuvre-datarow>"#,
+          name, tags
+        ),
+      );
+    }
+    site
+      .build(concat!(
+        "[[archives]]\n",
+        "dataset = \"posts\"\n",
+        "group_by = \"tags\"\n",
+        "template = \"tag\"\n",
+        "path = \"tags\"\n",
+        "per_page = 2\n",
+      ))
+      .unwrap();
+    let page_one = concat!(
+      "<!DOCTYPE html>\r\n",
+      r#"<html><body><h1>rust</h1><ul><li><a href="/a.html">a</a></li><li><a href="/b.html">b</a></li></ul><nav><a href="/tags/rust/page/2.html" rel="next">Next</a></nav></body></html>"#
+    );
+    assert_eq!(site.read_output("tags/rust/page/1.html"), page_one);
+    assert_eq!(site.read_output("tags/rust/index.html"), page_one);
+    assert_eq!(
+      site.read_output("tags/rust/page/2.html"),
+      concat!(
+        "<!DOCTYPE html>\r\n",
+        r#"<html><body><h1>rust</h1><ul><li><a href="/c.html">c</a></li></ul><nav><a href="/tags/rust/page/1.html" rel="prev">Previous</a></nav></body></html>"#
+      )
+    );
+    assert_eq!(
+      site.read_output("tags/web/page/1.html"),
+      concat!(
+        "<!DOCTYPE html>\r\n",
+        r#"<html><body><h1>web</h1><ul><li><a href="/a.html">a</a></li></ul><nav></nav></body></html>"#
+      )
+    );
+    assert!(site.output_exists("tags/web/index.html"));
+    assert!(!site.output_exists("tags/web/page/2.html"));
+    assert!(!site.output_exists("tags/rust/page/3.html"));
+    // C++ has the same slug as C, which sorts first, and !! has none, so both are skipped.
+    assert_eq!(
+      site.read_output("tags/c/index.html"),
+      concat!(
+        "<!DOCTYPE html>\r\n",
+        r#"<html><body><h1>C</h1><ul><li><a href="/f.html">f</a></li></ul><nav></nav></body></html>"#
+      )
+    );
+    assert!(!site.output_exists("tags/index.html"));
+    assert!(!site.output_exists("tags/page/1.html"));
+    assert_eq!(std::fs::read_dir(site.path("output/tags")).unwrap().count(), 3);
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn pages_with_non_utf8_paths_do_not_collide() {
+    use std::ffi::OsStr;
+    use std::fs;
+    use std::os::unix::ffi::OsStrExt;
+    use tempfile::TempDir;
+
+    let dir = TempDir::new().unwrap();
+    let paths: Vec<_> = [&b"page\xfe.xml"[..], &b"page\xff.xml"[..]]
+      .iter()
+      .map(|name| dir.path().join(OsStr::from_bytes(name)))
+      .collect();
+    assert_eq!(paths[0].display().to_string(), paths[1].display().to_string());
+    for path in &paths {
+      fs::write(path, r#"<oeuvre-page oeuvre-template="main"/>"#).unwrap();
+    }
+    let (pages, _) = Page::load_many(&paths, &toml::from_str::<SiteConfig>("").unwrap());
+    assert_eq!(pages.len(), 2);
+    assert!(paths.iter().all(|path| pages.contains_key(path)));
+  }
+
+  #[test]
+  fn source_map_attributes_slot_content_to_page() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        r#"<html oeuvre-name="main"><body><oeuvre-slot oeuvre-name="body"/></body></html>"#,
+      )
+      .file(
+        "about.xml",
+        r#"<oeuvre-page oeuvre-template="main" oeuvre-path="about.html"><p oeuvre-slot="body">Hi</p></oeuvre-page>"#,
+      );
+    site.build("source_map = true").unwrap();
+    assert_eq!(
+      site.read_output("about.html"),
+      "<!DOCTYPE html>\r\n<html><body><p>Hi</p></body></html>"
+    );
+    let map: JsonValue = serde_json::from_str(&site.read_output("about.html.map")).unwrap();
+    assert_eq!(
+      map,
+      json!({
+        "file": "about.html",
+        "sections": [
+          { "start": 0, "end": 29, "line": 1, "source": "templates/main.xml" },
+          { "start": 29, "end": 38, "line": 2, "source": "about.xml", "slot": "body" },
+          { "start": 38, "end": 52, "line": 2, "source": "templates/main.xml" }
+        ]
+      })
+    );
+  }
+
+  #[test]
+  fn repeated_slot_name_is_warned_about() {
+    assert_eq!(Page::duplicate_slot_names("a, a"), ["a"]);
+    assert_eq!(Page::duplicate_slot_names("a,b, c"), Vec::<&str>::new());
+  }
+
+  #[test]
+  fn entities_are_written_without_double_encoding() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        r#"<html oeuvre-name="main"><body><oeuvre-slot oeuvre-name="body"/></body></html>"#,
+      )
+      .file(
+        "index.xml",
+        r#"<oeuvre-page oeuvre-template="main" oeuvre-path="index.html"><p oeuvre-slot="body" title="R&amp;D">R&amp;D &#169; Q&A</p></oeuvre-page>"#,
+      );
+    site.build("").unwrap();
+    assert_eq!(
+      site.read_output("index.html"),
+      concat!(
+        "<!DOCTYPE html>\r\n",
+        r#"<html><body><p title="R&amp;D">R&amp;D © Q&amp;A</p></body></html>"#
+      )
+    );
+  }
+
+  #[test]
+  fn output_format_lays_out_html_pages() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        "<html oeuvre-name=\"main\">\n  <body>\n    <oeuvre-slot oeuvre-name=\"body\"/>\n  </body>\n</html>",
+      )
+      .file(
+        "index.xml",
+        r#"<oeuvre-page oeuvre-template="main" oeuvre-path="index.html"><p oeuvre-slot="body">A  <i>b</i></p></oeuvre-page>"#,
+      );
+    site.build("output_format = \"minified\"").unwrap();
+    assert_eq!(
+      site.read_output("index.html"),
+      "<!DOCTYPE html>\r\n<html><body><p>A <i>b</i></p></body></html>"
+    );
+    site.build("output_format = \"pretty\"").unwrap();
+    assert_eq!(
+      site.read_output("index.html"),
+      "<!DOCTYPE html>\r\n<html>\r\n  <body>\r\n    <p>A  <i>b</i></p>\r\n  </body>\r\n</html>"
+    );
+  }
+
+  #[test]
+  fn doctype_can_be_omitted_or_replaced() {
+    let site = TestSite::new();
+    site
+      .file("templates/main.xml", r#"<html oeuvre-name="main"><body/></html>"#)
+      .file("index.xml", r#"<oeuvre-page oeuvre-template="main" oeuvre-path="index.html"/>"#);
+    site.build("doctype = \"\"").unwrap();
+    assert_eq!(site.read_output("index.html"), "<html><body></body></html>");
+    site
+      .build(r#"doctype = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n""#)
+      .unwrap();
+    assert_eq!(
+      site.read_output("index.html"),
+      "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<html><body></body></html>"
+    );
+  }
+
+  #[test]
+  fn output_extension_is_given_to_paths_without_one() {
+    let site = TestSite::new();
+    site
+      .file("templates/main.xml", r#"<html oeuvre-name="main"><body/></html>"#)
+      .file("about.xml", r#"<oeuvre-page oeuvre-template="main" oeuvre-path="/about"/>"#)
+      .file("feed.xml", r#"<oeuvre-page oeuvre-template="main" oeuvre-path="/feed.xml"/>"#)
+      .file(
+        "post.xml",
+        concat!(
+          r#"<oeuvre-page oeuvre-template="main" oeuvre-path="/post">"#,
+          r#"<oeuvre-format oeuvre-name="txt" oeuvre-path="/post-text"/>"#,
+          r#"<oeuvre-format oeuvre-name="html" oeuvre-path="/post-print"/></oeuvre-page>"#
+        ),
+      );
+    site.build("output_extension = \"htm\"").unwrap();
+    assert!(site.output_exists("about.htm"));
+    assert!(!site.output_exists("about"));
+    assert!(site.output_exists("feed.xml"));
+    assert!(site.output_exists("post.htm"));
+    assert!(site.output_exists("post-text.txt"));
+    assert!(site.output_exists("post-print.htm"));
+  }
+
+  fn declaration_site() -> TestSite {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        r#"<?xml version="1.0"?><html oeuvre-name="main"><body/></html>"#,
+      )
+      .file("index.xml", r#"<oeuvre-page oeuvre-template="main" oeuvre-path="index.html"/>"#);
+    site
+  }
+
+  #[test]
+  fn scoped_section_fills_namespaced_slots() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        r#"<html oeuvre-name="main"><aside><h2><oeuvre-slot oeuvre-name="sidebar.title"/></h2><oeuvre-slot oeuvre-name="sidebar.body"/></aside></html>"#,
+      )
+      .file(
+        "index.xml",
+        r#"<oeuvre-page oeuvre-template="main" oeuvre-path="index.html"><section oeuvre-slot-scope="sidebar"><oeuvre-fragment oeuvre-slot="title">Links</oeuvre-fragment><ul oeuvre-slot="body"><li>One</li></ul></section></oeuvre-page>"#,
+      );
+    site.build("").unwrap();
+    assert_eq!(
+      site.read_output("index.html"),
+      "<!DOCTYPE html>\r\n<html><aside><h2>Links</h2><ul><li>One</li></ul></aside></html>"
+    );
+  }
+
+  #[test]
+  fn alternate_format_renders_plain_text_without_doctype() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        r#"<html oeuvre-name="main"><h1><oeuvre-slot oeuvre-name="title"/></h1></html>"#,
+      )
+      .file(
+        "templates/plain.xml",
+        r#"<text oeuvre-name="plain">Title: <oeuvre-slot oeuvre-name="title"/> &amp; more</text>"#,
+      )
+      .file(
+        "x.xml",
+        r#"<oeuvre-page oeuvre-template="main" oeuvre-path="/x.html"><b oeuvre-slot="title">Hello</b><oeuvre-format oeuvre-name="txt" oeuvre-template="plain" oeuvre-path="/x.txt"/></oeuvre-page>"#,
+      );
+    site.build("").unwrap();
+    assert_eq!(
+      site.read_output("x.html"),
+      "<!DOCTYPE html>\r\n<html><h1><b>Hello</b></h1></html>"
+    );
+    assert_eq!(site.read_output("x.txt"), "Title: Hello & more");
+  }
+
+  #[test]
+  fn missing_template_renders_placeholder_when_configured() {
+    let site = TestSite::new();
+    site.file(
+      "index.xml",
+      r#"<oeuvre-page oeuvre-template="mian" oeuvre-path="index.html"><p oeuvre-slot="body">Hi</p><oeuvre-fragment oeuvre-slot="aside">Note</oeuvre-fragment></oeuvre-page>"#,
+    );
+    site.build("placeholder_on_missing_template = true").unwrap();
+    let message = "Page `index.html` requested template `mian`, which does not exist";
+    assert_eq!(
+      site.read_output("index.html"),
+      format!(
+        "<!DOCTYPE html>\r\n<html><head><title>{0}</title></head><body><p>{0}</p>{1}</body></html>",
+        message, "Note<p>Hi</p>"
+      )
+    );
+    let err = site.build("placeholder_on_missing_template = true\nstrict = true").err().unwrap();
+    assert_eq!(err.to_string(), "Strict build failed with 1 render issue(s)");
+  }
+
+  #[test]
+  fn source_comment_names_source_and_template() {
+    let site = declaration_site();
+    site.build("source_comments = true").unwrap();
+    assert_eq!(
+      site.read_output("index.html"),
+      "<!DOCTYPE html>\r\n<!-- oeuvre: src=index.xml template=main -->\r\n<html><body></body></html>"
+    );
+  }
+
+  #[test]
+  fn xml_declaration_is_dropped_by_default() {
+    let site = declaration_site();
+    site.build("").unwrap();
+    assert_eq!(site.read_output("index.html"), "<!DOCTYPE html>\r\n<html><body></body></html>");
+  }
+
+  #[test]
+  fn xml_declaration_is_kept_when_configured() {
+    let site = declaration_site();
+    site.build("xml_declaration = true").unwrap();
+    assert_eq!(
+      site.read_output("index.html"),
+      "<?xml version=\"1.0\"?>\r\n<!DOCTYPE html>\r\n<html><body></body></html>"
+    );
+  }
 }
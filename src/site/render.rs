@@ -1,124 +1,2900 @@
-use crate::site::Snippet;
+use crate::minidom::node::Node;
 use crate::minidom::Element;
-use log::error;
-use std::collections::HashMap;
+use crate::site::bundle::local_path;
+use crate::site::dataset::{DataRow, FieldValue};
+use crate::site::images::{parse_widths, width_suffixed};
+use crate::site::sanitize::append_sanitized;
+use crate::site::{Site, Snippet};
+use crate::site::{content_hash, load_xml};
+use anyhow::{anyhow, bail, Result};
+use log::{debug, error};
+use serde_json::{Map as JsonMap, Value as JsonValue};
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
-/// Injects slot values and snippet content into a template,
-/// performing template expansion on all child elements.
-pub fn render_template(
-  template_element: &Element,
-  slot_values: &HashMap<String, Element>,
-  snippets: &HashMap<String, Snippet>,
-) -> Element {
-  let mut result = initialize_element(template_element);
+/// The code for issues about oeuvre elements that are not recognized.
+pub const UNKNOWN_ELEMENT: &str = "unknown-element";
+/// The code for issues about oeuvre-assert elements whose slot is empty.
+const ASSERTION: &str = "assertion";
+/// The text of the comment that precedes a filled slot's content with the source_map
+/// option, followed by the slot's name.
+pub const SOURCE_MAP_START: &str = "oeuvre-map:";
+/// The text of the comment that follows a filled slot's content with the source_map option.
+pub const SOURCE_MAP_END: &str = "/oeuvre-map";
 
-  // Non-element child nodes are emitted unmodified. Oeuvre elements recieve
-  // special handling, but all other elements are emitted unmodified.
-  for node in template_element.nodes() {
-    match node.as_element() {
-      None => result.append_node(node.clone()),
-      Some(element) => match element.name() {
-        "oeuvre-include" => render_include(element, &mut result, slot_values, snippets),
-        "oeuvre-slot" => render_slot(element, &mut result, slot_values, snippets),
-        name if name.starts_with("oeuvre-") => {
-          error!("Unknown oeuvre element found: {}", name);
-        }
-        _ => {
-          append_element(element, &mut result, slot_values, snippets);
-        }
-      },
-    };
+/// Counts the problems found while rendering a page, so that strict builds can fail on them.
+/// Each problem has a code naming its kind, so that it can be allowed with an oeuvre-allow attribute.
+#[derive(Default)]
+pub struct IssueSink {
+  count: usize,
+  /// The messages held back to be logged with the rest of a page's log, if any are.
+  held: Option<Vec<String>>,
+}
+
+impl IssueSink {
+  /// Returns a sink that holds messages back, to be taken with `take_held`, rather than
+  /// logging them as they are recorded.
+  pub fn held() -> IssueSink {
+    IssueSink {
+      count: 0,
+      held: Some(Vec::new()),
+    }
+  }
+
+  /// Logs and records a problem found at `element`, unless the element lists `code`
+  /// in its oeuvre-allow attribute.
+  pub fn report(&mut self, element: &Element, code: &str, message: String) {
+    if !allows(element, code) {
+      self.record(message);
+    }
+  }
+
+  /// Logs and records a problem that cannot be allowed.
+  pub fn record(&mut self, message: String) {
+    match &mut self.held {
+      Some(held) => held.push(message),
+      None => error!("{}", message),
+    }
+    self.count += 1;
+  }
+
+  /// Removes and returns the messages held back so far.
+  pub fn take_held(&mut self) -> Vec<String> {
+    self.held.as_mut().map(std::mem::take).unwrap_or_default()
+  }
+
+  /// The number of problems recorded so far.
+  pub fn count(&self) -> usize {
+    self.count
+  }
+}
+
+/// Whether `element` suppresses issues with the given code
+/// by listing it in its comma- or space-separated oeuvre-allow attribute.
+pub fn allows(element: &Element, code: &str) -> bool {
+  element.attr("oeuvre-allow").is_some_and(|allowed| {
+    allowed
+      .split(|c: char| c == ',' || c.is_whitespace())
+      .any(|allowed| allowed == code)
+  })
+}
+
+/// A handler for custom oeuvre elements, registered with `Site::register_plugin`.
+/// Plugins are consulted in the order they were registered, after the built-in and
+/// shorthand elements and before an element would otherwise be reported as unknown.
+pub trait RenderPlugin: Send + Sync {
+  /// Whether this plugin renders elements named `name`.
+  fn handles(&self, name: &str) -> bool;
+
+  /// Renders `element` by appending its output to `target`, like the built-in elements.
+  fn render(&self, element: &Element, target: &mut Element, ctx: &mut RenderContext);
+}
+
+/// The state threaded through the rendering of a page: the slot values in scope,
+/// the site, the page's output path, the sink for any problems found, the names
+/// of the snippets being included and the output paths of the pages whose rendering
+/// embeds this one, both outermost first, whether text is being rendered from a template
+/// or snippet, and so is interpolated, and, shared by every context for the page, an
+/// estimate of the size of the page rendered so far and whether an embed was skipped
+/// because the page it named was already being rendered.
+pub struct RenderContext<'a> {
+  pub slot_values: &'a HashMap<String, Element>,
+  pub site: &'a Site,
+  pub page_path: &'a Path,
+  pub issues: &'a mut IssueSink,
+  pub snippet_stack: Vec<String>,
+  pub embedding_pages: Vec<PathBuf>,
+  interpolate_text: bool,
+  rendered_bytes: Rc<Cell<u64>>,
+  embed_cycle: Rc<Cell<bool>>,
+}
+
+impl<'a> RenderContext<'a> {
+  pub fn new(
+    slot_values: &'a HashMap<String, Element>,
+    site: &'a Site,
+    page_path: &'a Path,
+    issues: &'a mut IssueSink,
+  ) -> RenderContext<'a> {
+    RenderContext {
+      slot_values,
+      site,
+      page_path,
+      issues,
+      snippet_stack: Vec::new(),
+      embedding_pages: Vec::new(),
+      interpolate_text: true,
+      rendered_bytes: Rc::new(Cell::new(0)),
+      embed_cycle: Rc::new(Cell::new(false)),
+    }
+  }
+
+  /// Returns a context for rendering with `slot_values` in scope instead of this
+  /// context's own, which reports problems to the same sink and is within the same snippets.
+  pub fn with_slots<'b>(
+    &'b mut self,
+    slot_values: &'b HashMap<String, Element>,
+  ) -> RenderContext<'b> {
+    RenderContext {
+      slot_values,
+      site: self.site,
+      page_path: self.page_path,
+      issues: self.issues,
+      snippet_stack: self.snippet_stack.clone(),
+      embedding_pages: self.embedding_pages.clone(),
+      interpolate_text: self.interpolate_text,
+      rendered_bytes: Rc::clone(&self.rendered_bytes),
+      embed_cycle: Rc::clone(&self.embed_cycle),
+    }
+  }
+
+  /// Renders with `render`, interpolating text only if `interpolate_text` is true.
+  fn interpolating_text(&mut self, interpolate_text: bool, render: impl FnOnce(&mut Self)) {
+    let outer = std::mem::replace(&mut self.interpolate_text, interpolate_text);
+    render(self);
+    self.interpolate_text = outer;
+  }
+
+  /// Whether an embed was skipped because the page it named was already being rendered,
+  /// directly or within the rendering of another embed. Such a page renders differently
+  /// depending on which page of the cycle was rendered first.
+  pub fn hit_embed_cycle(&self) -> bool {
+    self.embed_cycle.get()
+  }
+
+  /// Adds `bytes` to the estimated size of the page and returns whether the page is
+  /// still within the site's max_output_bytes limit.
+  fn add_rendered_bytes(&self, bytes: usize) -> bool {
+    self.rendered_bytes.set(self.rendered_bytes.get() + bytes as u64);
+    !self.is_over_size_limit()
+  }
+
+  /// Whether the estimated size of the page has outgrown the site's max_output_bytes
+  /// limit. The estimate counts only text and element names, so the page's output is
+  /// always at least as large, except for whitespace that minifying removes.
+  pub fn is_over_size_limit(&self) -> bool {
+    self
+      .site
+      .config
+      .max_output_bytes
+      .is_some_and(|max| self.rendered_bytes.get() > max)
+  }
+
+  /// Returns the value of the slot named `name`, if the page fills it.
+  pub fn slot(&self, name: &str) -> Option<&'a Element> {
+    self.slot_values.get(name)
+  }
+
+  /// Returns the value of the global named `name`, if there is one.
+  pub fn global(&self, name: &str) -> Option<&'a str> {
+    self.site.config.globals.get(name).map(String::as_str)
+  }
+
+  /// Returns the snippet named `name`, if there is one.
+  pub fn snippet(&self, name: &str) -> Option<&'a Snippet> {
+    self.site.snippets.get(name)
+  }
+
+  /// Records a problem found at `element`, unless the element allows `code`.
+  pub fn report(&mut self, element: &Element, code: &str, message: String) {
+    self.issues.report(element, code, message);
+  }
+
+  /// Records a problem that cannot be allowed.
+  pub fn record(&mut self, message: String) {
+    self.issues.record(message);
+  }
+
+  /// Renders the contents of `element` into `target`, as a template's would be,
+  /// such as for a plugin to wrap them in an element of its own.
+  pub fn render_contents(&mut self, element: &Element, target: &mut Element) {
+    unwrap_fragment(element, target, self);
+  }
+}
+
+/// Injects slot values and snippet content into a template,
+/// performing template expansion on all child elements. The text
+/// of `pre` and `code` elements is never interpolated.
+pub fn render_template(template_element: &Element, ctx: &mut RenderContext) -> Element {
+  let mut result = initialize_element(template_element, ctx);
+  match template_element.name() {
+    "pre" | "code" => ctx.interpolating_text(false, |ctx| {
+      unwrap_fragment(template_element, &mut result, ctx)
+    }),
+    _ => unwrap_fragment(template_element, &mut result, ctx),
   }
   result
 }
 
 /// An oeuvre-include element will render the snippet named
 /// in its oeuvre-snippet attribute, or its own contents
-/// if no such snippet exists. The attribute must be present;
+/// if no such snippet exists, preceded by a comment with the
+/// debug_slots option. An oeuvre-select attribute of the form
+/// `#id` or `tag` renders only the first matching descendant of
+/// the snippet; if there is none, an error is logged and nothing
+/// is rendered. Children of the oeuvre-include element that name slots
+/// in their oeuvre-slot attributes fill those slots within the snippet,
+/// taking precedence over the page's slot values, and are otherwise
+/// ignored while the snippet exists. They are rendered where the include
+/// is, before the snippet, so a slot within them refers to the slot
+/// outside the snippet rather than to itself. The attribute must be present;
 /// otherwise, this function will log an error and render no
 /// content for this element.
-fn render_include(
-  element: &Element,
-  target: &mut Element,
-  slot_values: &HashMap<String, Element>,
-  snippets: &HashMap<String, Snippet>,
-) {
+fn render_include(element: &Element, target: &mut Element, ctx: &mut RenderContext) {
   match element.attr("oeuvre-snippet") {
-    Some(snippet_name) => match snippets.get(snippet_name) {
-      Some(snippet) => unwrap_fragment(&snippet.element, target, slot_values, snippets),
-      None => unwrap_fragment(element, target, slot_values, snippets),
+    Some(snippet_name) => match ctx.snippet(snippet_name) {
+      Some(snippet) => {
+        let mut local_slot_values = ctx.slot_values.clone();
+        for child in element.children() {
+          if let Some(slot_names) = child.attr("oeuvre-slot") {
+            let mut rendered = Element::bare("oeuvre-fragment", None::<String>);
+            match child.name() {
+              "oeuvre-fragment" => unwrap_fragment(child, &mut rendered, ctx),
+              _ => append_element(child, &mut rendered, ctx),
+            }
+            for slot_name in slot_names.split(',') {
+              local_slot_values.insert(slot_name.trim().to_string(), rendered.clone());
+            }
+          }
+        }
+        let ctx = &mut ctx.with_slots(&local_slot_values);
+        match element.attr("oeuvre-select") {
+          Some(selector) => match select(&snippet.element, selector.trim()) {
+            Some(selected) => {
+              within_snippet(snippet_name, ctx, |ctx| append_element(selected, target, ctx))
+            }
+            None => ctx.record(format!(
+              "Found no element matching {} in snippet {}",
+              selector, snippet_name
+            )),
+          },
+          None => within_snippet(snippet_name, ctx, |ctx| {
+            unwrap_fragment(&snippet.element, target, ctx)
+          }),
+        }
+      }
+      None => {
+        if ctx.site.config.debug_slots {
+          target.append_comment_node(format!(" missing snippet: {} ", snippet_name));
+        }
+        unwrap_fragment(element, target, ctx)
+      }
+    },
+    None => {
+      ctx.record(
+        "Found an oeuvre-include element without a target oeuvre-snippet attribute.".to_string(),
+      )
+    }
+  }
+}
+
+/// Renders part of the snippet named `snippet_name` with `render`, unless the snippet
+/// is already being included further out, which would never end, or including it would
+/// nest snippets deeper than the max_include_depth option. Either way, this function
+/// will record a problem naming the snippets involved and render nothing instead.
+fn within_snippet(
+  snippet_name: &str,
+  ctx: &mut RenderContext,
+  render: impl FnOnce(&mut RenderContext),
+) {
+  let chain = || {
+    ctx
+      .snippet_stack
+      .iter()
+      .map(String::as_str)
+      .chain(std::iter::once(snippet_name))
+      .collect::<Vec<_>>()
+      .join(" -> ")
+  };
+  if ctx.snippet_stack.iter().any(|name| name == snippet_name) {
+    let message = format!(
+      "Snippet {} includes itself on page {}: {}",
+      snippet_name,
+      ctx.page_path.display(),
+      chain()
+    );
+    ctx.record(message);
+    return;
+  }
+  if ctx.snippet_stack.len() >= ctx.site.config.max_include_depth {
+    let message = format!(
+      "Snippets are nested more than {} deep on page {}: {}",
+      ctx.site.config.max_include_depth,
+      ctx.page_path.display(),
+      chain()
+    );
+    ctx.record(message);
+    return;
+  }
+  ctx.snippet_stack.push(snippet_name.to_string());
+  ctx.interpolating_text(true, render);
+  ctx.snippet_stack.pop();
+}
+
+/// An oeuvre-embed element will render the page whose output path is named in its
+/// oeuvre-page attribute and append the first element of that page matching its
+/// oeuvre-select attribute, as with oeuvre-include, or else the contents of the
+/// page's body. Each page is rendered at most once, however many pages embed it.
+/// If the page does not exist or cannot be rendered, if it embeds itself, or if
+/// nothing matches, this function will log an error and render no content for this element.
+fn render_embed(element: &Element, target: &mut Element, ctx: &mut RenderContext) {
+  let page_path = match element.attr("oeuvre-page") {
+    Some(page_path) => page_path,
+    None => {
+      ctx.record(
+        "Found an oeuvre-embed element without a target oeuvre-page attribute.".to_string(),
+      );
+      return;
+    }
+  };
+  let page = match ctx.site.pages.get(Path::new(page_path.trim_start_matches('/'))) {
+    Some(page) => page,
+    None => {
+      ctx.record(format!(
+        "Found an oeuvre-embed element for undefined page {}.",
+        page_path
+      ));
+      return;
+    }
+  };
+  if page.path() == ctx.page_path || ctx.embedding_pages.iter().any(|path| path == page.path()) {
+    ctx.embed_cycle.set(true);
+    ctx.record(format!("Page {} could not be embedded, because it embeds itself", page_path));
+    return;
+  }
+  let mut embedding = ctx.embedding_pages.clone();
+  embedding.push(ctx.page_path.to_path_buf());
+  let rendered = match page.render_embedded(ctx.site, ctx.issues, &embedding) {
+    Ok((rendered, hit_embed_cycle)) => {
+      if hit_embed_cycle {
+        ctx.embed_cycle.set(true);
+      }
+      rendered
+    }
+    Err(err) => {
+      ctx.record(format!("Page {} could not be embedded. Cause: {}", page_path, err));
+      return;
+    }
+  };
+  match element.attr("oeuvre-select") {
+    Some(selector) => match select(&rendered, selector.trim()) {
+      Some(selected) => {
+        target.append_child(selected.clone());
+      }
+      None => ctx.record(format!(
+        "Found no element matching {} in page {}",
+        selector, page_path
+      )),
     },
     None => {
-      error!("Found an oeuvre-include element without a target oeuvre-snippet attribute.")
+      let body = select(&rendered, "body").unwrap_or(&rendered);
+      for node in body.nodes() {
+        target.append_node(node.clone());
+      }
     }
   }
 }
 
+/// Returns the first descendant of `element`, in document order, matching `selector`,
+/// which is either `#id` to match by id or a tag name.
+fn select<'a>(element: &'a Element, selector: &str) -> Option<&'a Element> {
+  for child in element.children() {
+    let matches = match selector.strip_prefix('#') {
+      Some(id) => child.attr("id") == Some(id),
+      None => child.name() == selector,
+    };
+    if matches {
+      return Some(child);
+    }
+    if let Some(selected) = select(child, selector) {
+      return Some(selected);
+    }
+  }
+  None
+}
+
+/// An oeuvre-include-text element will append the contents of the file
+/// named in its oeuvre-src attribute, relative to the input directory, as
+/// a single text node; the contents are escaped rather than parsed as markup.
+/// If the file cannot be read, this function will log an error and render
+/// the element's own contents instead. The attribute must be present;
+/// otherwise, this function will log an error and render no content for
+/// this element.
+fn render_include_text(element: &Element, target: &mut Element, ctx: &mut RenderContext) {
+  match element.attr("oeuvre-src") {
+    Some(src) => match fs::read_to_string(src) {
+      Ok(text) => target.append_text_node(text),
+      Err(err) => {
+        ctx.record(format!("Text file {} could not be included. Cause: {}", src, err));
+        unwrap_fragment(element, target, ctx);
+      }
+    },
+    None => {
+      ctx.record(
+        "Found an oeuvre-include-text element without a source oeuvre-src attribute.".to_string(),
+      )
+    }
+  }
+}
+
+/// An oeuvre-raw element will parse the text of the slot named in its
+/// oeuvre-name attribute, or of the global of that name, as markup and append
+/// the result, e.g. for HTML that a page provides as escaped text. The text may
+/// hold several elements, and any oeuvre elements within it are not expanded.
+/// If the site's sanitize option is set, the markup is first stripped of
+/// anything that is not on the sanitizer's allowlists. If there is no such slot
+/// or global, or its text cannot be parsed, this function will render the
+/// element's own contents instead; a parse error is also logged. The attribute
+/// must be present; otherwise, this function will log an error and render no
+/// content for this element.
+fn render_raw(element: &Element, target: &mut Element, ctx: &mut RenderContext) {
+  let slot_name = match element.attr("oeuvre-name") {
+    Some(slot_name) => slot_name,
+    None => {
+      ctx.record(
+        "Found an oeuvre-raw element without an identifying oeuvre-name attribute.".to_string(),
+      );
+      return;
+    }
+  };
+  let markup = match (ctx.slot(slot_name), ctx.global(slot_name)) {
+    (Some(slot_value), _) => slot_value.text(),
+    (None, Some(global)) => global.to_string(),
+    (None, None) => return unwrap_fragment(element, target, ctx),
+  };
+  if let Err(err) = append_markup(&markup, target, ctx) {
+    ctx.record(format!("Slot {} could not be parsed as markup. Cause: {}", slot_name, err));
+    unwrap_fragment(element, target, ctx);
+  }
+}
+
+/// An oeuvre-include-raw element will append the markup in the file named in
+/// its oeuvre-src attribute, relative to the input directory, like the text of
+/// an oeuvre-raw element's slot. If the file cannot be read or parsed, this
+/// function will log an error and render the element's own contents instead.
+/// The attribute must be present; otherwise, this function will log an error
+/// and render no content for this element.
+fn render_include_raw(element: &Element, target: &mut Element, ctx: &mut RenderContext) {
+  let src = match element.attr("oeuvre-src") {
+    Some(src) => src,
+    None => {
+      ctx.record(
+        "Found an oeuvre-include-raw element without a source oeuvre-src attribute.".to_string(),
+      );
+      return;
+    }
+  };
+  let result = fs::read_to_string(src)
+    .map_err(|err| err.to_string())
+    .and_then(|markup| append_markup(&markup, target, ctx));
+  if let Err(err) = result {
+    ctx.record(format!("Raw file {} could not be included. Cause: {}", src, err));
+    unwrap_fragment(element, target, ctx);
+  }
+}
+
+/// Parses `markup`, which may hold several elements, and appends its nodes to
+/// `target` without expanding them, sanitizing them first if the site's sanitize
+/// option is set. Nothing is appended if the markup cannot be parsed.
+fn append_markup(markup: &str, target: &mut Element, ctx: &RenderContext) -> Result<(), String> {
+  let fragment = format!("<oeuvre-fragment>{}</oeuvre-fragment>", markup)
+    .parse::<Element>()
+    .map_err(|err| err.to_string())?;
+  if ctx.site.config.sanitize {
+    append_sanitized(&fragment, target);
+  } else {
+    for node in fragment.nodes() {
+      target.append_node(node.clone());
+    }
+  }
+  Ok(())
+}
+
 /// An oeuvre-slot element will render the element or fragement
 /// provided by the oeuvre-page document being rendered, or its
-/// own contents if no such element or fragment exists. The attribute
-/// must be present; otherwise, this function will log an error and
-/// render no content for this element.
-fn render_slot(
-  element: &Element,
-  target: &mut Element,
+/// own contents if no such element or fragment exists. Values provided
+/// within a page's oeuvre-slot-scope sections are named `scope.name`.
+/// A slot the page does not fill may instead be filled as text by one of the
+/// site's globals. With the debug_slots option, a slot that is filled by
+/// neither is marked by a comment. A slot with no contents of its own may
+/// name a snippet to render instead in an oeuvre-default-snippet attribute.
+/// If the element names a snippet in an oeuvre-wrap-with
+/// attribute, the value is rendered within that snippet, wherever it has an
+/// oeuvre-slot named `content`; if there is no such snippet, the value is
+/// rendered directly. With the source_map option, a filled slot's content is
+/// enclosed in marker comments, which are removed once the page is serialized.
+/// The attribute must be present; otherwise, this function
+/// will log an error and render no content for this element.
+fn render_slot(element: &Element, target: &mut Element, ctx: &mut RenderContext) {
+  let slot_name = match element.attr("oeuvre-name") {
+    Some(slot_name) => slot_name,
+    None => {
+      ctx.record(
+        "Found an oeuvre-slot element without an identifying oeuvre-name attribute.".to_string(),
+      );
+      return;
+    }
+  };
+
+  let global_value;
+  let slot_value = match ctx.slot(slot_name) {
+    Some(slot_value) => slot_value,
+    None => match ctx.global(slot_name) {
+      Some(global) => {
+        let mut fragment = Element::bare("oeuvre-fragment", None::<String>);
+        fragment.append_text_node(global);
+        global_value = fragment;
+        &global_value
+      }
+      None => {
+        if ctx.site.config.debug_slots {
+          target.append_comment_node(format!(" missing slot: {} ", slot_name));
+        }
+        let has_contents = element
+          .nodes()
+          .any(|node| node.as_text().is_none_or(|text| !text.trim().is_empty()));
+        let default_snippet = element
+          .attr("oeuvre-default-snippet")
+          .and_then(|snippet_name| Some((snippet_name, ctx.snippet(snippet_name)?)));
+        return match default_snippet {
+          Some((snippet_name, snippet)) if !has_contents => {
+            within_snippet(snippet_name, ctx, |ctx| {
+              unwrap_fragment(&snippet.element, target, ctx)
+            })
+          }
+          _ => unwrap_fragment(element, target, ctx),
+        };
+      }
+    },
+  };
+
+  if ctx.site.config.source_map {
+    target.append_comment_node(format!("{}{}", SOURCE_MAP_START, slot_name));
+  }
+  let wrapper = element
+    .attr("oeuvre-wrap-with")
+    .and_then(|snippet_name| ctx.snippet(snippet_name));
+  match wrapper {
+    Some(snippet) => {
+      let mut local_slot_values = ctx.slot_values.clone();
+      local_slot_values.insert("content".to_string(), slot_value.clone());
+      unwrap_fragment(&snippet.element, target, &mut ctx.with_slots(&local_slot_values))
+    }
+    // Slot values come from pages and datasets rather than templates, so their text is
+    // written as it is.
+    None => ctx.interpolating_text(false, |ctx| match slot_value.name() {
+      "oeuvre-fragment" => {
+        unwrap_fragment(slot_value, target, ctx)
+      }
+      _ => append_element(slot_value, target, ctx),
+    }),
+  }
+  if ctx.site.config.source_map {
+    target.append_comment_node(SOURCE_MAP_END);
+  }
+}
+
+/// An oeuvre-json element is replaced by a JSON encoding of a value, escaped so that it
+/// can be embedded within a script element. With an oeuvre-name attribute, the value is
+/// the text of the slot or global of that name, or an object of the text of each slot
+/// within the scope of that name. With an oeuvre-dataset attribute, it is an array of
+/// the dataset's rows, as objects. Values that hold markup, such as fragment fields,
+/// cannot be encoded, so they are reported as issues and rendered as `null`.
+fn render_json(element: &Element, target: &mut Element, ctx: &mut RenderContext) {
+  let json = match (element.attr("oeuvre-name"), element.attr("oeuvre-dataset")) {
+    (Some(slot_name), _) => slot_json(slot_name, ctx.slot_values, ctx.site),
+    (None, Some(dataset_name)) => dataset_json(dataset_name, ctx.site),
+    (None, None) => Err(anyhow!(
+      "Found an oeuvre-json element without an oeuvre-name or oeuvre-dataset attribute"
+    )),
+  };
+  let json = match json {
+    Ok(json) => json,
+    Err(err) => {
+      ctx.record(format!("Page {}: {}", ctx.page_path.display(), err));
+      JsonValue::Null
+    }
+  };
+  target.append_text_node(escape_script_json(&json.to_string()));
+}
+
+/// Returns the value of the slot or global named `slot_name` as a JSON string or,
+/// failing that, the values of the slots within its scope as a JSON object.
+fn slot_json(
+  slot_name: &str,
   slot_values: &HashMap<String, Element>,
-  snippets: &HashMap<String, Snippet>,
-) {
-  match element.attr("oeuvre-name") {
-    Some(slot_name) => match slot_values.get(slot_name) {
-      Some(slot_value) => match slot_value.name() {
-        "oeuvre-fragment" => unwrap_fragment(slot_value, target, slot_values, snippets),
-        _ => append_element(slot_value, target, slot_values, snippets),
-      },
-      None => unwrap_fragment(element, target, slot_values, snippets),
+  site: &Site,
+) -> Result<JsonValue> {
+  if let Some(slot_value) = slot_values.get(slot_name) {
+    return Ok(JsonValue::String(plain_text(slot_name, slot_value)?));
+  }
+  if let Some(global) = site.config.globals.get(slot_name) {
+    return Ok(JsonValue::String(global.clone()));
+  }
+  let prefix = format!("{}.", slot_name);
+  let mut members = slot_values
+    .iter()
+    .filter_map(|(name, value)| name.strip_prefix(&prefix).map(|key| (key, name, value)))
+    .collect::<Vec<_>>();
+  if members.is_empty() {
+    bail!("Slot {} cannot be encoded as JSON, because it does not exist", slot_name);
+  }
+  members.sort_by_key(|(key, _, _)| *key);
+  let mut properties = JsonMap::new();
+  for (key, name, value) in members {
+    properties.insert(key.to_string(), JsonValue::String(plain_text(name, value)?));
+  }
+  Ok(JsonValue::Object(properties))
+}
+
+/// Returns the rows of the dataset named `dataset_name` as a JSON array of objects.
+fn dataset_json(dataset_name: &str, site: &Site) -> Result<JsonValue> {
+  let dataset = match site.datasets.get(dataset_name) {
+    Some(dataset) => dataset,
+    None => bail!(
+      "Dataset {} cannot be encoded as JSON, because it does not exist",
+      dataset_name
+    ),
+  };
+  let mut rows = Vec::new();
+  for row in &dataset.rows {
+    let mut fields = row.iter().collect::<Vec<_>>();
+    fields.sort_by_key(|(name, _)| *name);
+    let mut properties = JsonMap::new();
+    for (name, value) in fields {
+      match value {
+        FieldValue::String(text) => {
+          properties.insert(name.clone(), JsonValue::String(text.clone()));
+        }
+        FieldValue::Fragment(_) => bail!(
+          "Field {} of dataset {} holds markup, which cannot be encoded as JSON",
+          name,
+          dataset_name
+        ),
+      }
+    }
+    rows.push(JsonValue::Object(properties));
+  }
+  Ok(JsonValue::Array(rows))
+}
+
+/// Returns the text of `slot_value`, the value of the slot `slot_name`, or an error if it
+/// holds anything besides text.
+fn plain_text(slot_name: &str, slot_value: &Element) -> Result<String> {
+  if slot_value.name() != "oeuvre-fragment" || slot_value.children().next().is_some() {
+    bail!("Slot {} holds markup, which cannot be encoded as JSON", slot_name);
+  }
+  Ok(slot_value.texts().collect())
+}
+
+/// Escapes the characters of `json` that could end a script element or begin a comment,
+/// or that would otherwise be read as markup or line breaks, as JSON unicode escapes.
+fn escape_script_json(json: &str) -> String {
+  let mut escaped = String::with_capacity(json.len());
+  for c in json.chars() {
+    match c {
+      '<' | '>' | '&' | '\u{2028}' | '\u{2029}' => {
+        escaped.push_str(&format!("\\u{:04x}", c as u32))
+      }
+      c => escaped.push(c),
+    }
+  }
+  escaped
+}
+
+/// An oeuvre-assert element records an issue, with the message in its
+/// oeuvre-message attribute, if the slot named in its oeuvre-name attribute
+/// is absent or has no content besides whitespace. It renders nothing either way.
+/// The name attribute must be present; otherwise, this function will log an error.
+fn render_assert(element: &Element, ctx: &mut RenderContext) {
+  let slot_name = match element.attr("oeuvre-name") {
+    Some(slot_name) => slot_name,
+    None => {
+      ctx.record(
+        "Found an oeuvre-assert element without an identifying oeuvre-name attribute.".to_string(),
+      );
+      return;
+    }
+  };
+  if !is_filled(slot_name, ctx) {
+    let message = element
+      .attr("oeuvre-message")
+      .map(String::from)
+      .unwrap_or_else(|| format!("Slot {} is empty", slot_name));
+    ctx.report(
+      element,
+      ASSERTION,
+      format!(
+        "Assertion failed on page {}: {}",
+        ctx.page_path.display(),
+        message
+      ),
+    );
+  }
+}
+
+/// An oeuvre-if element renders its contents only if the slot named in its
+/// oeuvre-name attribute is filled with something besides whitespace, and an
+/// oeuvre-unless element only if it is not, so that a template can provide
+/// fallback content. Either may be nested within the other. The name attribute
+/// must be present; otherwise, this function will log an error and render no
+/// content for this element.
+fn render_if(element: &Element, target: &mut Element, ctx: &mut RenderContext) {
+  let slot_name = match element.attr("oeuvre-name") {
+    Some(slot_name) => slot_name,
+    None => {
+      ctx.record(format!(
+        "Found an {} element without an identifying oeuvre-name attribute.",
+        element.name()
+      ));
+      return;
+    }
+  };
+  if is_filled(slot_name, ctx) == (element.name() == "oeuvre-if") {
+    unwrap_fragment(element, target, ctx);
+  }
+}
+
+/// Whether the slot or global named `slot_name` has content besides whitespace.
+fn is_filled(slot_name: &str, ctx: &RenderContext) -> bool {
+  match ctx.slot(slot_name) {
+    Some(slot_value) => {
+      slot_value.name() != "oeuvre-fragment"
+        || slot_value
+          .nodes()
+          .any(|node| node.as_text().is_none_or(|text| !text.trim().is_empty()))
+    }
+    None => ctx.global(slot_name).is_some_and(|global| !global.trim().is_empty()),
+  }
+}
+
+/// An oeuvre-each-child element will render its own contents once for each
+/// child of the slot value named in its oeuvre-name attribute, with the
+/// `child` slot bound to that child. Text between the children is trimmed,
+/// and skipped if it is only whitespace. If the page does not fill the slot, nothing is
+/// rendered. The attribute must be present; otherwise, this function will
+/// log an error and render no content for this element.
+fn render_each_child(element: &Element, target: &mut Element, ctx: &mut RenderContext) {
+  let slot_value = match element.attr("oeuvre-name") {
+    Some(slot_name) => match ctx.slot(slot_name) {
+      Some(slot_value) => slot_value,
+      None => return,
     },
     None => {
-      error!("Found an oeuvre-slot element without an identifying oeuvre-name attribute.")
+      ctx.record(
+        "Found an oeuvre-each-child element without an identifying oeuvre-name attribute."
+          .to_string(),
+      );
+      return;
+    }
+  };
+
+  let mut local_slot_values = ctx.slot_values.clone();
+  for node in slot_value.nodes() {
+    let mut child = Element::bare("oeuvre-fragment", None::<String>);
+    match node.as_text() {
+      Some(text) if text.trim().is_empty() => continue,
+      Some(text) => child.append_text_node(text.trim()),
+      None => child.append_node(node.clone()),
+    }
+    local_slot_values.insert("child".to_string(), child);
+    unwrap_fragment(element, target, &mut ctx.with_slots(&local_slot_values));
+  }
+}
+
+/// An oeuvre-related element will render its own contents once for each row of
+/// the dataset named in its oeuvre-dataset attribute that shares a value of the
+/// comma-separated field named in its oeuvre-by attribute with the current row,
+/// which is the row whose `url` field matches the page being rendered. Each field
+/// of a related row is bound to the slot of the same name. Rows sharing more values
+/// come first, and at most oeuvre-limit rows are rendered, if it is given. If the
+/// page has no row, nothing is rendered. If an attribute is missing or the dataset
+/// does not exist, this function will log an error and render no content for this element.
+fn render_related(element: &Element, target: &mut Element, ctx: &mut RenderContext) {
+  let (dataset_name, field_name) = match (element.attr("oeuvre-dataset"), element.attr("oeuvre-by"))
+  {
+    (Some(dataset_name), Some(field_name)) => (dataset_name, field_name),
+    _ => {
+      ctx.record(
+        "Found an oeuvre-related element without both oeuvre-dataset and oeuvre-by attributes."
+          .to_string(),
+      );
+      return;
+    }
+  };
+  let dataset = match ctx.site.datasets.get(dataset_name) {
+    Some(dataset) => dataset,
+    None => {
+      ctx.record(format!(
+        "Found an oeuvre-related element for undefined dataset {}.",
+        dataset_name
+      ));
+      return;
+    }
+  };
+  let limit = match element
+    .attr("oeuvre-limit")
+    .map(|limit| limit.trim().parse::<usize>())
+  {
+    Some(Ok(limit)) => limit,
+    Some(Err(_)) => {
+      ctx.record(
+        "Found an oeuvre-related element whose oeuvre-limit is not a whole number.".to_string(),
+      );
+      return;
+    }
+    None => usize::MAX,
+  };
+
+  let values = |row: &DataRow| -> HashSet<String> {
+    row
+      .get(field_name)
+      .map(|value| value.text())
+      .unwrap_or_default()
+      .split(',')
+      .map(|value| value.trim().to_string())
+      .filter(|value| !value.is_empty())
+      .collect()
+  };
+  let is_current = |row: &DataRow| {
+    row
+      .get("url")
+      .is_some_and(|url| nav_url_matches(&url.text(), ctx.page_path))
+  };
+  let current_row = match dataset.rows.iter().find(|row| is_current(row)) {
+    Some(row) => row,
+    None => return,
+  };
+  let current_values = values(current_row);
+  let mut related: Vec<(usize, &DataRow)> = dataset
+    .rows
+    .iter()
+    .filter(|row| !is_current(row))
+    .map(|row| (values(row).intersection(&current_values).count(), row))
+    .filter(|(shared, _)| *shared > 0)
+    .collect();
+  // The sort is stable, so rows sharing as many values keep their dataset order.
+  related.sort_by_key(|(shared, _)| std::cmp::Reverse(*shared));
+
+  for (_, row) in related.into_iter().take(limit) {
+    let local_slot_values = row_slot_values(ctx.slot_values, row, ctx.site.config.sanitize);
+    unwrap_fragment(element, target, &mut ctx.with_slots(&local_slot_values));
+  }
+}
+
+/// An oeuvre-for element will render its own contents once for each row of the
+/// dataset named in its oeuvre-dataset attribute, in order, with each field of
+/// the row bound to the slot of the same name. The bindings only apply within the
+/// element. If the attribute is missing or the dataset does not exist, this
+/// function will log an error and render no content for this element.
+fn render_for(element: &Element, target: &mut Element, ctx: &mut RenderContext) {
+  let dataset_name = match element.attr("oeuvre-dataset") {
+    Some(dataset_name) => dataset_name,
+    None => {
+      ctx.record(
+        "Found an oeuvre-for element without a source oeuvre-dataset attribute.".to_string(),
+      );
+      return;
+    }
+  };
+  let dataset = match ctx.site.datasets.get(dataset_name) {
+    Some(dataset) => dataset,
+    None => {
+      ctx.record(format!(
+        "Found an oeuvre-for element for undefined dataset {}.",
+        dataset_name
+      ));
+      return;
+    }
+  };
+  for row in &dataset.rows {
+    let local_slot_values = row_slot_values(ctx.slot_values, row, ctx.site.config.sanitize);
+    unwrap_fragment(element, target, &mut ctx.with_slots(&local_slot_values));
+  }
+}
+
+/// An oeuvre-data element will render the value of the field named in its
+/// oeuvre-field attribute, from the row of the dataset named in its oeuvre-dataset
+/// attribute whose own oeuvre-name attribute matches its oeuvre-row attribute,
+/// as it is, without interpolating or expanding anything in it. If the row or
+/// field does not exist, the element's own contents are rendered instead. A missing attribute or an undefined dataset is also reported as a problem.
+fn render_data(element: &Element, target: &mut Element, ctx: &mut RenderContext) {
+  let attrs = (
+    element.attr("oeuvre-dataset"),
+    element.attr("oeuvre-row"),
+    element.attr("oeuvre-field"),
+  );
+  let (dataset_name, row_name, field_name) = match attrs {
+    (Some(dataset_name), Some(row_name), Some(field_name)) => (dataset_name, row_name, field_name),
+    _ => {
+      ctx.record(
+        "Found an oeuvre-data element without oeuvre-dataset, oeuvre-row and oeuvre-field attributes."
+          .to_string(),
+      );
+      return unwrap_fragment(element, target, ctx);
+    }
+  };
+  let dataset = match ctx.site.datasets.get(dataset_name) {
+    Some(dataset) => dataset,
+    None => {
+      ctx.record(format!(
+        "Found an oeuvre-data element for undefined dataset {}.",
+        dataset_name
+      ));
+      return unwrap_fragment(element, target, ctx);
+    }
+  };
+  match dataset.row(row_name).and_then(|row| row.get(field_name)) {
+    Some(value) => {
+      for node in field_fragment(value, ctx.site.config.sanitize).nodes() {
+        target.append_node(node.clone());
+      }
+    }
+    None => unwrap_fragment(element, target, ctx),
+  }
+}
+
+/// Returns a copy of `slot_values` with each field of `row` bound to the slot of
+/// the same name, sanitizing fragment fields if `sanitize` is set.
+fn row_slot_values(
+  slot_values: &HashMap<String, Element>,
+  row: &DataRow,
+  sanitize: bool,
+) -> HashMap<String, Element> {
+  let mut local_slot_values = slot_values.clone();
+  for (name, value) in row {
+    local_slot_values.insert(name.clone(), field_fragment(value, sanitize));
+  }
+  local_slot_values
+}
+
+/// Returns a fragment holding the text of a field value or, for fragment fields, its
+/// contents, which are sanitized if `sanitize` is set, since datasets may hold
+/// untrusted content.
+fn field_fragment(value: &FieldValue, sanitize: bool) -> Element {
+  let mut fragment = Element::bare("oeuvre-fragment", None::<String>);
+  match value {
+    FieldValue::String(text) => fragment.append_text_node(text.as_str()),
+    FieldValue::Fragment(value) if sanitize => append_sanitized(value, &mut fragment),
+    FieldValue::Fragment(value) => {
+      for node in value.nodes() {
+        fragment.append_node(node.clone());
+      }
+    }
+  }
+  fragment
+}
+
+/// An oeuvre-call element will render the contents of the macro named
+/// in its oeuvre-macro attribute, binding each of the macro's parameters
+/// to the value of the like-named attribute on the oeuvre-call element.
+/// Those bindings take precedence over the page's slot values. If the
+/// macro does not exist or a parameter is missing, this function will log
+/// an error and render no content for this element.
+fn render_call(element: &Element, target: &mut Element, ctx: &mut RenderContext) {
+  let macro_name = match element.attr("oeuvre-macro") {
+    Some(macro_name) => macro_name,
+    None => {
+      ctx.record(
+        "Found an oeuvre-call element without a target oeuvre-macro attribute.".to_string(),
+      );
+      return;
+    }
+  };
+  let called_macro = match ctx.site.macros.get(macro_name) {
+    Some(called_macro) => called_macro,
+    None => {
+      ctx.record(format!(
+        "Found an oeuvre-call element for undefined macro {}.",
+        macro_name
+      ));
+      return;
+    }
+  };
+
+  let mut local_slot_values = ctx.slot_values.clone();
+  for param in &called_macro.params {
+    match element.attr(param) {
+      Some(arg) => {
+        let mut value = Element::bare("oeuvre-fragment", None::<String>);
+        value.append_text_node(arg);
+        local_slot_values.insert(param.clone(), value);
+      }
+      None => {
+        ctx.record(format!(
+          "Found an oeuvre-call element for macro {} without a value for parameter {}.",
+          macro_name, param
+        ));
+        return;
+      }
+    }
+  }
+  unwrap_fragment(&called_macro.element, target, &mut ctx.with_slots(&local_slot_values));
+}
+
+/// A single entry of a navigation menu, as read from a data row.
+struct NavItem {
+  label: String,
+  url: String,
+  order: i64,
+  children: Vec<NavItem>,
+}
+
+/// An oeuvre-nav element will render the dataset named in its oeuvre-dataset
+/// attribute as a nested `<ul>` menu. Each row provides a `label`, a `url`,
+/// and an `order`, and may name the url of a top-level item in `parent`
+/// to be listed beneath it. Items are sorted by `order`, and the item whose
+/// url matches the output path of the page being rendered receives the
+/// `active` class. Other attributes are copied to the outer `<ul>`. Rows that
+/// cannot be placed are logged and skipped; if the attribute is missing or the
+/// dataset does not exist, this function will log an error and render no
+/// content for this element.
+fn render_nav(element: &Element, target: &mut Element, ctx: &mut RenderContext) {
+  let dataset_name = match element.attr("oeuvre-dataset") {
+    Some(dataset_name) => dataset_name,
+    None => {
+      ctx.record(
+        "Found an oeuvre-nav element without a source oeuvre-dataset attribute.".to_string(),
+      );
+      return;
+    }
+  };
+  let dataset = match ctx.site.datasets.get(dataset_name) {
+    Some(dataset) => dataset,
+    None => {
+      ctx.record(format!(
+        "Found an oeuvre-nav element for undefined dataset {}.",
+        dataset_name
+      ));
+      return;
+    }
+  };
+
+  let mut items = Vec::<NavItem>::new();
+  let mut nested_rows = Vec::new();
+  for row in &dataset.rows {
+    let field = |name: &str| row.get(name).map(|value| value.text());
+    let (label, url) = match (field("label"), field("url")) {
+      (Some(label), Some(url)) => (label, url),
+      _ => {
+        ctx.record(format!(
+          "Menu item in dataset {} requires both a label and a url.",
+          dataset_name
+        ));
+        continue;
+      }
+    };
+    let order = match field("order").unwrap_or_default().trim().parse() {
+      Ok(order) => order,
+      Err(_) => {
+        ctx.record(format!(
+          "Menu item {} in dataset {} requires an integer order.",
+          label, dataset_name
+        ));
+        continue;
+      }
+    };
+    let item = NavItem {
+      label,
+      url,
+      order,
+      children: Vec::new(),
+    };
+    match field("parent").filter(|parent| !parent.is_empty()) {
+      Some(parent) => nested_rows.push((parent, item)),
+      None => items.push(item),
+    }
+  }
+  for (parent, item) in nested_rows {
+    match items.iter_mut().find(|candidate| candidate.url == parent) {
+      Some(parent_item) => parent_item.children.push(item),
+      None => ctx.record(format!(
+        "Menu item {} in dataset {} has parent {}, which is not a top-level item.",
+        item.label, dataset_name, parent
+      )),
+    }
+  }
+
+  let mut menu = Element::bare("ul", element.ns());
+  for attr in element
+    .attrs()
+    .filter(|attr| !attr.0.starts_with("oeuvre-"))
+  {
+    menu.set_attr(attr.0, attr.1);
+  }
+  append_nav_items(&mut menu, items, ctx.page_path);
+  target.append_child(menu);
+}
+
+/// Appends an `<li>` for each of `items` to `list`, in order, with a nested
+/// `<ul>` for any children.
+fn append_nav_items(list: &mut Element, mut items: Vec<NavItem>, page_path: &Path) {
+  items.sort_by_key(|item| item.order);
+  for item in items {
+    let mut list_item = Element::bare("li", list.ns());
+    if nav_url_matches(&item.url, page_path) {
+      list_item.set_attr("class", "active");
+    }
+    let mut link = Element::bare("a", list.ns());
+    link.set_attr("href", &item.url);
+    link.append_text_node(item.label);
+    list_item.append_child(link);
+    if !item.children.is_empty() {
+      let mut sublist = Element::bare("ul", list.ns());
+      append_nav_items(&mut sublist, item.children, page_path);
+      list_item.append_child(sublist);
+    }
+    list.append_child(list_item);
+  }
+}
+
+/// Compares a menu url with a page's output path. Urls are taken relative to
+/// the site root, and a url naming a directory refers to its index.html.
+fn nav_url_matches(url: &str, page_path: &Path) -> bool {
+  let url = url.trim_start_matches('/');
+  if url.is_empty() || url.ends_with('/') {
+    page_path == Path::new(url).join("index.html")
+  } else {
+    page_path == Path::new(url)
+  }
+}
+
+/// Moves the contents of every oeuvre-head block rendered within `document` into
+/// its `<head>` element, in document order, creating the head if there is none.
+/// A `<meta>` or `<link>` element identical to one already in the head is dropped.
+pub fn hoist_head_blocks(document: &mut Element) {
+  let mut hoisted = Vec::<Node>::new();
+  take_head_blocks(document, &mut hoisted);
+  if hoisted.is_empty() {
+    return;
+  }
+
+  let head = head_mut(document);
+  let mut seen: HashSet<String> = head
+    .children()
+    .filter(|child| is_deduplicated(child))
+    .map(String::from)
+    .collect();
+  for node in hoisted {
+    if let Some(element) = node.as_element() {
+      if is_deduplicated(element) && !seen.insert(String::from(element)) {
+        continue;
+      }
+    }
+    head.append_node(node);
+  }
+}
+
+/// Adds a `<link rel="canonical">` with the page's absolute url to the head of
+/// `document`, a page rendered to `page_path`, unless the head already has one.
+pub fn add_canonical_link(document: &mut Element, site: &Site, page_path: &Path) {
+  let head = head_mut(document);
+  let has_canonical = head.children().any(|child| {
+    child.name() == "link"
+      && child.attr("rel").is_some_and(|rel| {
+        rel
+          .split_whitespace()
+          .any(|rel| rel.eq_ignore_ascii_case("canonical"))
+      })
+  });
+  if !has_canonical {
+    let mut link = Element::bare("link", head.ns());
+    link.set_attr("rel", "canonical");
+    link.set_attr("href", page_url(site, page_path));
+    head.append_child(link);
+  }
+}
+
+/// Replaces each `{{ counter.name }}` in the attribute values and text of `document`
+/// with the next value of the counter with that name, in document order. Text within
+/// pre and code elements is left as it is, as it is when interpolating. Counters start
+/// at 1 for each page, and an oeuvre-counter-reset element restarts the counter named
+/// in its oeuvre-name attribute. A reset without one is recorded as a problem in `issues`.
+/// An escaped counter, as in `\{{ counter.name }}`, is written without the backslash.
+pub fn number_counters(document: &mut Element, issues: &mut IssueSink) {
+  fn next_values(text: &str, counters: &mut HashMap<String, usize>) -> String {
+    interpolate_keeping_escapes(
+      text,
+      |expression| !is_counter(expression),
+      |expression| {
+        let counter = counters
+          .entry(expression.strip_prefix("counter.")?.to_string())
+          .or_insert(0);
+        *counter += 1;
+        Some(counter.to_string())
+      },
+    )
+  }
+
+  fn number(element: &mut Element, counters: &mut HashMap<String, usize>, issues: &mut IssueSink) {
+    let attrs: Vec<(String, String)> = element
+      .attrs()
+      .filter(|attr| attr.1.contains("{{"))
+      .map(|attr| (attr.0.to_string(), attr.1.to_string()))
+      .collect();
+    for (name, value) in attrs {
+      let value = next_values(&value, counters);
+      element.set_attr(name, value);
+    }
+    let numbers_text = !matches!(element.name(), "pre" | "code");
+    for node in element.take_nodes() {
+      match node {
+        Node::Element(child) if child.name() == "oeuvre-counter-reset" => {
+          match child.attr("oeuvre-name") {
+            Some(name) => {
+              counters.remove(name);
+            }
+            None => issues.record(
+              "Found an oeuvre-counter-reset element without an identifying oeuvre-name attribute."
+                .to_string(),
+            ),
+          }
+        }
+        Node::Element(mut child) => {
+          number(&mut child, counters, issues);
+          element.append_child(child);
+        }
+        Node::Text(text) if numbers_text && text.contains("{{") => {
+          element.append_text_node(next_values(&text, counters));
+        }
+        node => element.append_node(node),
+      }
+    }
+  }
+  number(document, &mut HashMap::new(), issues);
+}
+
+/// Returns the `<head>` element of `document`, creating it if there is none.
+fn head_mut(document: &mut Element) -> &mut Element {
+  if !document.children().any(|child| child.name() == "head") {
+    document.prepend_child(Element::bare("head", document.ns()));
+  }
+  document
+    .children_mut()
+    .find(|child| child.name() == "head")
+    .unwrap()
+}
+
+/// Removes the oeuvre-head blocks within `element` and adds their contents to `hoisted`.
+fn take_head_blocks(element: &mut Element, hoisted: &mut Vec<Node>) {
+  for node in element.take_nodes() {
+    match node {
+      Node::Element(mut child) if child.name() == "oeuvre-head" => {
+        take_head_blocks(&mut child, hoisted);
+        hoisted.extend(child.take_nodes());
+      }
+      Node::Element(mut child) => {
+        take_head_blocks(&mut child, hoisted);
+        element.append_child(child);
+      }
+      node => element.append_node(node),
+    }
+  }
+}
+
+fn is_deduplicated(element: &Element) -> bool {
+  element.name() == "meta" || element.name() == "link"
+}
+
+/// Replaces each `{{ expression }}` in `text` with the value of the expression.
+/// Expressions that are not recognized are left verbatim and logged at debug level,
+/// except for counters, which are numbered once the whole page is rendered, and so
+/// keep their escapes until then.
+fn interpolate(text: &str, ctx: &mut RenderContext) -> String {
+  interpolate_keeping_escapes(text, is_counter, |expression| {
+    let value = evaluate(expression, ctx);
+    if value.is_none() && !is_counter(expression) {
+      debug!(
+        "Left unrecognized placeholder {{{{ {} }}}} on page {}",
+        expression,
+        ctx.page_path.display()
+      );
+    }
+    value
+  })
+}
+
+/// Replaces each `{{ expression }}` in `text` with the value `evaluate` returns for
+/// the trimmed expression, leaving those for which it returns None verbatim.
+/// A placeholder preceded by a backslash, as in `\{{ name }}`, is written without
+/// the backslash and is not replaced.
+pub fn interpolate_with<F: FnMut(&str) -> Option<String>>(text: &str, evaluate: F) -> String {
+  interpolate_keeping_escapes(text, |_| false, evaluate)
+}
+
+/// Interpolates `text` like `interpolate_with`, except that escaped placeholders whose
+/// trimmed expression `keeps_escape` accepts keep their backslash, for a later pass.
+fn interpolate_keeping_escapes<K, F>(text: &str, keeps_escape: K, mut evaluate: F) -> String
+where
+  K: Fn(&str) -> bool,
+  F: FnMut(&str) -> Option<String>,
+{
+  let mut result = String::with_capacity(text.len());
+  let mut rest = text;
+  while let Some(start) = rest.find("{{") {
+    if let Some(escaped) = rest[..start].strip_suffix('\\') {
+      let expression = rest[start + 2..]
+        .find("}}")
+        .map(|offset| rest[start + 2..start + 2 + offset].trim());
+      result.push_str(escaped);
+      if expression.is_some_and(&keeps_escape) {
+        result.push('\\');
+      }
+      result.push_str("{{");
+      rest = &rest[start + 2..];
+      continue;
+    }
+    let end = match rest[start + 2..].find("}}") {
+      Some(offset) => start + 2 + offset,
+      None => break,
+    };
+    result.push_str(&rest[..start]);
+    match evaluate(rest[start + 2..end].trim()) {
+      Some(value) => result.push_str(&value),
+      None => result.push_str(&rest[start..end + 2]),
+    }
+    rest = &rest[end + 2..];
+  }
+  result.push_str(rest);
+  result
+}
+
+/// Whether an interpolated expression names a counter, which is numbered once the
+/// whole page is rendered rather than as it is interpolated.
+fn is_counter(expression: &str) -> bool {
+  expression.starts_with("counter.")
+}
+
+/// Returns the value of an interpolated expression, or None if it is not recognized.
+/// A name is replaced by the global of that name or else by the text of the slot.
+fn evaluate(expression: &str, ctx: &mut RenderContext) -> Option<String> {
+  let (site, page_path) = (ctx.site, ctx.page_path);
+  match expression {
+    "build.hash" => return Some(site.build_hash.clone()),
+    "page.path" => return Some(url_path(page_path)),
+    "page.url" => return Some(page_url(site, page_path)),
+    _ => (),
+  }
+  if let Some(global) = ctx.global(expression) {
+    return Some(global.to_string());
+  }
+  if let Some(slot_value) = ctx.slot(expression) {
+    return Some(text_content(slot_value));
+  }
+  if let Some(path) = call_argument(expression, "asset_hash") {
+    return Some(asset_hash(path, ctx));
+  }
+  None
+}
+
+/// Returns the text within `element` and all of its descendants.
+fn text_content(element: &Element) -> String {
+  element
+    .nodes()
+    .map(|node| match node {
+      Node::Element(child) => text_content(child),
+      Node::CData(text) => text.clone(),
+      node => node.as_text().unwrap_or_default().to_string(),
+    })
+    .collect()
+}
+
+/// Returns the site-relative url path of an output path, such as `/blog/post.html`.
+fn url_path(path: &Path) -> String {
+  path
+    .iter()
+    .map(|component| format!("/{}", component.to_string_lossy()))
+    .collect()
+}
+
+/// Returns the absolute url of the page rendered to `page_path`, joining the site's
+/// `base_url` with the page's url path.
+fn page_url(site: &Site, page_path: &Path) -> String {
+  let base_url = site.config.base_url.trim_end_matches('/');
+  format!("{}{}", base_url, url_path(page_path))
+}
+
+/// Returns the quoted argument of an expression like `function("argument")`.
+/// The argument may be enclosed in either double or single quotes.
+fn call_argument<'a>(expression: &'a str, function: &str) -> Option<&'a str> {
+  let argument = expression
+    .strip_prefix(function)?
+    .trim_start()
+    .strip_prefix('(')?
+    .strip_suffix(')')?
+    .trim();
+  ['"', '\'']
+    .iter()
+    .find_map(|quote| argument.strip_prefix(*quote)?.strip_suffix(*quote))
+}
+
+/// Returns a short hash of the contents of the file at `path`, relative to the
+/// input directory. If the file cannot be read, this function will record a problem
+/// and return an empty string.
+fn asset_hash(path: &str, ctx: &mut RenderContext) -> String {
+  match fs::read(path) {
+    Ok(contents) => content_hash(&contents)[..8].to_string(),
+    Err(err) => {
+      ctx.record(format!("Asset {} could not be hashed. Cause: {}", path, err));
+      String::new()
     }
   }
 }
 
 /// Creates a new element, copying the name and attributes of the
 /// template element – though oeuvre attributes will be omitted.
-fn initialize_element(template_element: &Element) -> Element {
+/// Attribute values are interpolated.
+fn initialize_element(template_element: &Element, ctx: &mut RenderContext) -> Element {
   let mut result = Element::bare(template_element.name(), template_element.ns());
   for attr in template_element
     .attrs()
     .filter(|attr| !attr.0.starts_with("oeuvre-"))
   {
-    result.set_attr(attr.0, attr.1);
+    result.set_attr(attr.0, interpolate(attr.1, ctx));
   }
   result
 }
 
+/// An img element with an oeuvre-inline attribute of `true` will be replaced
+/// by the svg element read from the local file named in its src attribute, so
+/// that the image can be styled with CSS. The img's other attributes are kept,
+/// and its alt text becomes an aria-label. If the file is not a local SVG or
+/// cannot be loaded, this function will log an error and render the img as is.
+fn render_inline_svg(element: &Element, target: &mut Element, ctx: &mut RenderContext) {
+  let img = render_template(element, ctx);
+  let inlined = match element.attr_bool("oeuvre-inline") {
+    Some(Ok(true)) => inline_svg(&img, ctx.site, ctx.page_path),
+    Some(Ok(false)) | None => {
+      target.append_child(img);
+      return;
+    }
+    Some(Err(err)) => Err(err.into()),
+  };
+  match inlined {
+    Ok(svg) => target.append_child(svg),
+    Err(err) => {
+      ctx.record(err.to_string());
+      target.append_child(img)
+    }
+  };
+}
+
+/// Returns the svg element from the local file that `img` links to, carrying over
+/// the img's attributes.
+fn inline_svg(img: &Element, site: &Site, page_path: &Path) -> Result<Element> {
+  let src = img.attr("src").unwrap_or_default();
+  let is_svg = |path: &PathBuf| {
+    path
+      .extension()
+      .is_some_and(|extension| extension.eq_ignore_ascii_case("svg"))
+  };
+  let path = match local_path(src, page_path).filter(is_svg) {
+    Some(path) => path,
+    None => bail!(
+      "Image {} is not a local SVG file, so it cannot be inlined",
+      src
+    ),
+  };
+  let mut svg = load_xml(&path, &site.config)?;
+  if svg.name() != "svg" {
+    bail!(
+      "Image {} does not contain an <svg> element, so it cannot be inlined",
+      src
+    );
+  }
+  svg.set_declaration(None);
+  for (name, value) in img.attrs() {
+    if name != "src" && name != "alt" {
+      svg.set_attr(name, value);
+    }
+  }
+  if let Some(alt) = img.attr("alt").filter(|alt| !alt.is_empty()) {
+    svg.set_attr("role", "img");
+    svg.set_attr("aria-label", alt);
+  }
+  Ok(svg)
+}
+
+/// An img element with an oeuvre-responsive attribute, a comma-separated list of
+/// widths, gets a srcset listing a resized copy of its local image at each width,
+/// which is written beside the original once every page is rendered. Its sizes
+/// attribute comes from oeuvre-sizes, or else defaults to `100vw`. If the widths
+/// are invalid or the image is not local, this function will log an error and
+/// render the img as is.
+fn render_responsive_image(element: &Element, target: &mut Element, ctx: &mut RenderContext) {
+  let mut img = render_template(element, ctx);
+  let src = img.attr("src").unwrap_or_default().to_string();
+  let widths = match parse_widths(element.attr("oeuvre-responsive").unwrap()) {
+    Ok(widths) => widths,
+    Err(err) => {
+      ctx.record(format!("Image {} could not be made responsive. Cause: {}", src, err));
+      target.append_child(img);
+      return;
+    }
+  };
+  let path = match local_path(&src, ctx.page_path) {
+    Some(path) => path,
+    None => {
+      ctx.record(format!(
+        "Image {} is not a local file, so it cannot be made responsive",
+        src
+      ));
+      target.append_child(img);
+      return;
+    }
+  };
+  let mut resized_images = ctx.site.resized_images.lock().unwrap();
+  let srcset = widths
+    .iter()
+    .map(|width| {
+      resized_images.insert((path.clone(), *width));
+      format!("{} {}w", width_suffixed(&src, *width), width)
+    })
+    .collect::<Vec<_>>()
+    .join(", ");
+  img.set_attr("srcset", srcset);
+  img.set_attr("sizes", element.attr("oeuvre-sizes").unwrap_or("100vw"));
+  target.append_child(img);
+}
+
+/// An element named in the elements option is shorthand for a snippet or for another
+/// element. With a snippet, the element is replaced by the snippet, whose `content`
+/// slot holds the element's contents and whose slots in the `attr` scope, such as
+/// `attr.title`, hold the element's attributes, so that they never hide the page's own
+/// slots. Otherwise, the element is renamed to the shorthand's tag, if it has one.
+/// Either way, the shorthand's class is added to the classes of the elements it
+/// expands to.
+fn render_shorthand(element: &Element, target: &mut Element, ctx: &mut RenderContext) {
+  let shorthand = &ctx.site.config.elements[element.name()];
+  if let Some(snippet_name) = &shorthand.snippet {
+    match ctx.snippet(snippet_name) {
+      Some(snippet) => {
+        let mut local_slot_values = ctx.slot_values.clone();
+        let mut content = Element::bare("oeuvre-fragment", None::<String>);
+        for node in element.nodes() {
+          content.append_node(node.clone());
+        }
+        local_slot_values.insert("content".to_string(), content);
+        for (name, value) in element.attrs() {
+          let mut fragment = Element::bare("oeuvre-fragment", None::<String>);
+          fragment.append_text_node(interpolate(value, ctx));
+          local_slot_values.insert(format!("attr.{}", name), fragment);
+        }
+        let mut expanded = Element::bare("oeuvre-fragment", None::<String>);
+        within_snippet(snippet_name, &mut ctx.with_slots(&local_slot_values), |ctx| {
+          unwrap_fragment(&snippet.element, &mut expanded, ctx)
+        });
+        if let Some(class) = &shorthand.class {
+          for child in expanded.children_mut() {
+            child.add_class(class);
+          }
+        }
+        for node in expanded.take_nodes() {
+          target.append_node(node);
+        }
+      }
+      None => ctx.record(format!(
+        "Shorthand element {} names snippet {}, which does not exist",
+        element.name(),
+        snippet_name
+      )),
+    }
+    return;
+  }
+  let mut rendered = render_template(element, ctx);
+  if let Some(tag) = &shorthand.tag {
+    rendered.set_name(tag.as_str());
+  }
+  if let Some(class) = &shorthand.class {
+    rendered.add_class(class);
+  }
+  target.append_child(rendered);
+}
+
 /// Performs template expansion on the provided element and
 /// appends the result to `target`.
-fn append_element(
-  element: &Element,
-  target: &mut Element,
-  slot_values: &HashMap<String, Element>,
-  snippets: &HashMap<String, Snippet>,
-) {
-  target.append_child(render_template(element, slot_values, snippets));
+fn append_element(element: &Element, target: &mut Element, ctx: &mut RenderContext) {
+  target.append_child(render_template(element, ctx));
+}
+
+/// Whether the node at `index` is whitespace-only text immediately before or after
+/// an oeuvre element that is replaced while rendering.
+fn is_control_whitespace(nodes: &[&Node], index: usize) -> bool {
+  let is_control = |node: Option<&&Node>| {
+    node
+      .and_then(|node| node.as_element())
+      .is_some_and(|element| {
+        element.name().starts_with("oeuvre-") && !allows(element, UNKNOWN_ELEMENT)
+      })
+  };
+  nodes[index]
+    .as_text()
+    .is_some_and(|text| text.trim().is_empty())
+    && (is_control(index.checked_sub(1).and_then(|index| nodes.get(index)))
+      || is_control(nodes.get(index + 1)))
+}
+
+/// A function that renders an oeuvre element in place, by appending to `target`.
+type ElementRenderer = fn(element: &Element, target: &mut Element, ctx: &mut RenderContext);
+
+/// The oeuvre elements that are replaced while rendering, each with the function that
+/// renders it. Source files are checked against this list as well.
+const ELEMENT_RENDERERS: &[(&str, ElementRenderer)] = &[
+  ("oeuvre-include", render_include),
+  ("oeuvre-slot", render_slot),
+  ("oeuvre-include-text", render_include_text),
+  ("oeuvre-raw", render_raw),
+  ("oeuvre-include-raw", render_include_raw),
+  ("oeuvre-call", render_call),
+  // Head blocks are rendered in place, then hoisted once the whole page is rendered.
+  ("oeuvre-head", |element, target, ctx| {
+    let mut block = Element::bare("oeuvre-head", None::<String>);
+    unwrap_fragment(element, &mut block, ctx);
+    target.append_child(block);
+  }),
+  ("oeuvre-each-child", render_each_child),
+  ("oeuvre-nav", render_nav),
+  ("oeuvre-assert", |element, _, ctx| render_assert(element, ctx)),
+  ("oeuvre-if", render_if),
+  ("oeuvre-unless", render_if),
+  ("oeuvre-json", render_json),
+  ("oeuvre-embed", render_embed),
+  ("oeuvre-related", render_related),
+  ("oeuvre-for", render_for),
+  ("oeuvre-data", render_data),
+  // Strings and counter resets are rendered in place, then resolved once the whole
+  // page is rendered.
+  ("oeuvre-t", append_unrendered),
+  ("oeuvre-counter-reset", append_unrendered),
+  // Macro definitions are registered when the site loads and render nothing in place.
+  ("oeuvre-macro", |_, _, _| ()),
+];
+
+/// Appends `element` as it is, to be resolved once the whole page is rendered.
+fn append_unrendered(element: &Element, target: &mut Element, _: &mut RenderContext) {
+  target.append_child(element.clone());
+}
+
+/// Returns the function that renders the oeuvre element named `name`, if it is one of
+/// the elements that are replaced while rendering.
+fn element_renderer(name: &str) -> Option<ElementRenderer> {
+  ELEMENT_RENDERERS
+    .iter()
+    .find(|(element_name, _)| *element_name == name)
+    .map(|(_, render)| *render)
+}
+
+/// Whether `name` is one of the oeuvre elements that are replaced while rendering.
+pub fn is_rendered_element(name: &str) -> bool {
+  element_renderer(name).is_some()
 }
 
 /// Performs template expansion on the children of the provided element and
-/// appends the results to `target`. This is used to enable syntax for providing
-/// HTML fragments as slot values (oeuvre-fragment) and for appending the
+/// appends the results to `target`. Besides expanding the children of templates,
+/// this is used to enable syntax for providing HTML fragments as slot values
+/// (oeuvre-fragment), for expanding snippets and macros, and for appending the
 /// fallback content provided by unmatched slots and includes.
-fn unwrap_fragment(
-  fragment: &Element,
-  target: &mut Element,
-  slot_values: &HashMap<String, Element>,
-  snippets: &HashMap<String, Snippet>,
-) {
-  for fragment_child in fragment.nodes() {
-    match fragment_child.as_element() {
-      None => target.append_node(fragment_child.clone()),
-      Some(fragment_child) => {
-        target.append_child(render_template(fragment_child, slot_values, snippets));
-      }
+fn unwrap_fragment(fragment: &Element, target: &mut Element, ctx: &mut RenderContext) {
+  // Non-element child nodes are emitted unmodified. Oeuvre elements recieve
+  // special handling, but all other elements are emitted unmodified.
+  let nodes: Vec<&Node> = fragment.nodes().collect();
+  for (index, node) in nodes.iter().enumerate() {
+    // Expansion stops as soon as the page outgrows the size limit, so that a runaway
+    // expansion fails quickly rather than after building an enormous document.
+    let size = match node.as_element() {
+      Some(element) => element.name().len(),
+      None => node.as_text().map_or(0, |text| text.trim().len()),
+    };
+    if !ctx.add_rendered_bytes(size) {
+      return;
+    }
+    match node.as_element() {
+      None if ctx.site.config.trim_control_whitespace && is_control_whitespace(&nodes, index) => {}
+      None => match node.as_text() {
+        Some(text) if ctx.interpolate_text => target.append_text_node(interpolate(text, ctx)),
+        _ => target.append_node((*node).clone()),
+      },
+      Some(element) => match element.name() {
+        name if element_renderer(name).is_some() => {
+          element_renderer(name).unwrap()(element, target, ctx)
+        }
+        "img" if element.attr("oeuvre-inline").is_some() => render_inline_svg(element, target, ctx),
+        "img" if element.attr("oeuvre-responsive").is_some() => {
+          render_responsive_image(element, target, ctx)
+        }
+        name if ctx.site.config.elements.contains_key(name) => {
+          render_shorthand(element, target, ctx)
+        }
+        name if ctx.site.plugins.iter().any(|plugin| plugin.handles(name)) => {
+          let plugin = ctx.site.plugins.iter().find(|plugin| plugin.handles(name)).unwrap();
+          plugin.render(element, target, ctx);
+        }
+        // An unknown element that is explicitly allowed is assumed to be deliberate,
+        // like a web component, and is emitted as though it were any other element.
+        name if name.starts_with("oeuvre-") && !allows(element, UNKNOWN_ELEMENT) => {
+          ctx.report(
+            element,
+            UNKNOWN_ELEMENT,
+            format!("Unknown oeuvre element found: {}", name),
+          );
+        }
+        _ => {
+          append_element(element, target, ctx);
+        }
+      },
     };
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::site::test_utils::TestSite;
+  use crate::site::Macro;
+
+  fn render(template: &str) -> String {
+    render_with_config(template, "")
+  }
+
+  fn render_with_config(template: &str, config: &str) -> String {
+    let test_site = TestSite::new();
+    let mut site = test_site.load(config).unwrap();
+    let template: Element = template.parse().unwrap();
+    Macro::collect(&template, &mut site.macros);
+    let slot_values = HashMap::new();
+    let mut issues = IssueSink::default();
+    let mut ctx = RenderContext::new(&slot_values, &site, Path::new("index.html"), &mut issues);
+    String::from(&render_template(&template, &mut ctx))
+  }
+
+  #[test]
+  fn include_text_appends_escaped_file_contents() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        r#"<html oeuvre-name="main"><body>Version <oeuvre-include-text oeuvre-src="VERSION"/></body></html>"#,
+      )
+      .file("index.xml", r#"<oeuvre-page oeuvre-template="main" oeuvre-path="index.html"/>"#)
+      .file("VERSION", "1.0 <beta> & more");
+    site.build("").unwrap();
+    assert_eq!(
+      site.read_output("index.html"),
+      "<!DOCTYPE html>\r\n<html><body>Version 1.0 &lt;beta> &amp; more</body></html>"
+    );
+  }
+
+  #[test]
+  fn raw_parses_the_text_of_a_slot_as_markup() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        r#"<html oeuvre-name="main"><body><oeuvre-raw oeuvre-name="body"/><oeuvre-raw oeuvre-name="footer">None</oeuvre-raw></body></html>"#,
+      )
+      .file(
+        "index.xml",
+        r#"<oeuvre-page oeuvre-template="main" oeuvre-path="index.html"><p oeuvre-slot="body">&lt;p onclick="steal()"&gt;Hi &lt;oeuvre-slot/&gt;&lt;/p&gt;</p></oeuvre-page>"#,
+      );
+    site.build("").unwrap();
+    assert_eq!(
+      site.read_output("index.html"),
+      concat!(
+        "<!DOCTYPE html>\r\n",
+        r#"<html><body><p onclick="steal()">Hi <oeuvre-slot></oeuvre-slot></p>None</body></html>"#
+      )
+    );
+    site.build("sanitize = true").unwrap();
+    assert_eq!(
+      site.read_output("index.html"),
+      "<!DOCTYPE html>\r\n<html><body><p>Hi </p>None</body></html>"
+    );
+  }
+
+  #[test]
+  fn raw_content_is_sanitized_when_configured() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        r#"<html oeuvre-name="main"><body><oeuvre-include-raw oeuvre-src="post.html"/></body></html>"#,
+      )
+      .file(
+        "index.xml",
+        r#"<oeuvre-page oeuvre-template="main" oeuvre-path="index.html"/>"#,
+      )
+      .file(
+        "post.html",
+        r#"<p onclick="steal()">Hi</p><script>steal()</script>"#,
+      );
+    site.build("").unwrap();
+    assert_eq!(
+      site.read_output("index.html"),
+      concat!(
+        "<!DOCTYPE html>\r\n",
+        r#"<html><body><p onclick="steal()">Hi</p><script>steal()</script></body></html>"#
+      )
+    );
+    site.build("sanitize = true").unwrap();
+    assert_eq!(
+      site.read_output("index.html"),
+      "<!DOCTYPE html>\r\n<html><body><p>Hi</p></body></html>"
+    );
+  }
+
+  #[test]
+  fn dataset_fragments_are_sanitized_when_configured() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        r#"<html oeuvre-name="main"><body><oeuvre-for oeuvre-dataset="posts"><oeuvre-slot oeuvre-name="body"/></oeuvre-for></body></html>"#,
+      )
+      .file("index.xml", r#"<oeuvre-page oeuvre-template="main" oeuvre-path="index.html"/>"#)
+      .file(
+        "data/posts.xml",
+        r#"<oeuvre-dataset oeuvre-name="posts"><oeuvre-field oeuvre-name="body" oeuvre-type="fragment"/></oeuvre-dataset>"#,
+      )
+      .file(
+        "data/posts/post.xml",
+        r#"<oeuvre-datarow oeuvre-dataset="posts"><div oeuvre-name="body"><p onclick="steal()">Hi</p><script>steal()</script></div></oeuvre-datarow>"#,
+      );
+    site.build("sanitize = true").unwrap();
+    assert_eq!(
+      site.read_output("index.html"),
+      "<!DOCTYPE html>\r\n<html><body><p>Hi</p></body></html>"
+    );
+  }
+
+  #[test]
+  fn each_child_wraps_every_child_of_a_slot() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        r#"<html oeuvre-name="main"><ul><oeuvre-each-child oeuvre-name="items"><li><oeuvre-slot oeuvre-name="child"/></li></oeuvre-each-child></ul></html>"#,
+      )
+      .file(
+        "index.xml",
+        r#"<oeuvre-page oeuvre-template="main" oeuvre-path="index.html"><oeuvre-fragment oeuvre-slot="items">
+          <a href="/one">One</a>
+          <b>Two</b>
+          Three
+        </oeuvre-fragment></oeuvre-page>"#,
+      );
+    site.build("").unwrap();
+    assert_eq!(
+      site.read_output("index.html"),
+      concat!(
+        "<!DOCTYPE html>\r\n<html><ul>",
+        r#"<li><a href="/one">One</a></li><li><b>Two</b></li><li>Three</li></ul></html>"#
+      )
+    );
+  }
+
+  #[test]
+  fn slot_value_is_wrapped_with_snippet() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        r#"<html oeuvre-name="main"><body><oeuvre-slot oeuvre-name="body" oeuvre-wrap-with="prose"/><oeuvre-slot oeuvre-name="aside" oeuvre-wrap-with="missing"/></body></html>"#,
+      )
+      .file(
+        "snippets/prose.xml",
+        r#"<oeuvre-snippet oeuvre-name="prose"><article class="prose"><oeuvre-slot oeuvre-name="content"/></article></oeuvre-snippet>"#,
+      )
+      .file(
+        "index.xml",
+        r#"<oeuvre-page oeuvre-template="main" oeuvre-path="index.html"><p oeuvre-slot="body">Hi</p><p oeuvre-slot="aside">Aside</p></oeuvre-page>"#,
+      );
+    site.build("").unwrap();
+    assert_eq!(
+      site.read_output("index.html"),
+      concat!(
+        "<!DOCTYPE html>\r\n<html><body>",
+        r#"<article class="prose"><p>Hi</p></article><p>Aside</p></body></html>"#
+      )
+    );
+  }
+
+  #[test]
+  fn head_blocks_are_hoisted_without_duplicates() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        r#"<html oeuvre-name="main"><head><meta charset="utf-8"/></head><body><oeuvre-include oeuvre-snippet="widget"/><oeuvre-include oeuvre-snippet="widget"/><oeuvre-head><meta charset="utf-8"/><title>Home</title></oeuvre-head></body></html>"#,
+      )
+      .file(
+        "snippets/widget.xml",
+        r#"<oeuvre-snippet oeuvre-name="widget"><oeuvre-head><link rel="stylesheet" href="/widget.css"/></oeuvre-head><div class="widget"/></oeuvre-snippet>"#,
+      )
+      .file("index.xml", r#"<oeuvre-page oeuvre-template="main" oeuvre-path="index.html"/>"#);
+    site.build("").unwrap();
+    assert_eq!(
+      site.read_output("index.html"),
+      concat!(
+        "<!DOCTYPE html>\r\n<html><head>",
+        r#"<meta charset="utf-8"><link href="/widget.css" rel="stylesheet"><title>Home</title>"#,
+        r#"</head><body><div class="widget"></div><div class="widget"></div></body></html>"#
+      )
+    );
+  }
+
+  #[test]
+  fn page_url_joins_base_url_and_output_path() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        r#"<html oeuvre-name="main"><link rel="canonical" href="{{ page.url }}"/><a href="{{ page.path }}"/></html>"#,
+      )
+      .file("blog/post.xml", r#"<oeuvre-page oeuvre-template="main" oeuvre-path="post.html"/>"#);
+    site.build("base_url = \"https://example.com/\"").unwrap();
+    assert_eq!(
+      site.read_output("blog/post.html"),
+      concat!(
+        "<!DOCTYPE html>\r\n<html>",
+        r#"<link href="https://example.com/blog/post.html" rel="canonical">"#,
+        r#"<a href="/blog/post.html"></a></html>"#
+      )
+    );
+  }
+
+  #[test]
+  fn canonical_link_is_added_unless_present() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        r#"<html oeuvre-name="main"><head><oeuvre-slot oeuvre-name="head"/></head></html>"#,
+      )
+      .file("blog/post.xml", r#"<oeuvre-page oeuvre-template="main" oeuvre-path="post.html"/>"#)
+      .file(
+        "about.xml",
+        r#"<oeuvre-page oeuvre-template="main" oeuvre-path="about.html"><link oeuvre-slot="head" rel="canonical" href="https://example.org/about"/></oeuvre-page>"#,
+      );
+    site
+      .build("base_url = \"https://example.com\"\ncanonical = true")
+      .unwrap();
+    assert_eq!(
+      site.read_output("blog/post.html"),
+      concat!(
+        "<!DOCTYPE html>\r\n<html><head>",
+        r#"<link href="https://example.com/blog/post.html" rel="canonical"></head></html>"#
+      )
+    );
+    assert_eq!(
+      site.read_output("about.html"),
+      concat!(
+        "<!DOCTYPE html>\r\n<html><head>",
+        r#"<link href="https://example.org/about" rel="canonical"></head></html>"#
+      )
+    );
+  }
+
+  #[test]
+  fn unmatched_slot_is_marked_in_debug_mode() {
+    let template = r#"<body><oeuvre-slot oeuvre-name="sidebar"/><oeuvre-include oeuvre-snippet="footer"/></body>"#;
+    assert_eq!(render(template), "<body/>");
+    assert_eq!(
+      render_with_config(template, "debug_slots = true"),
+      "<body><!-- missing slot: sidebar --><!-- missing snippet: footer --></body>"
+    );
+  }
+
+  #[test]
+  fn include_fills_snippet_slots_and_leaves_the_rest_to_fall_back() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        concat!(
+          r#"<html oeuvre-name="main"><body><oeuvre-include oeuvre-snippet="card">"#,
+          r#"<oeuvre-fragment oeuvre-slot="heading">Hello</oeuvre-fragment></oeuvre-include>"#,
+          r#"<oeuvre-include oeuvre-snippet="card"><em oeuvre-slot="footer">Bye</em></oeuvre-include>"#,
+          r#"<p><oeuvre-slot oeuvre-name="heading"/></p></body></html>"#
+        ),
+      )
+      .file(
+        "snippets/card.xml",
+        concat!(
+          r#"<oeuvre-snippet oeuvre-name="card"><div><h2><oeuvre-slot oeuvre-name="heading">Untitled</oeuvre-slot></h2>"#,
+          r#"<footer><oeuvre-slot oeuvre-name="footer">No footer</oeuvre-slot></footer></div></oeuvre-snippet>"#
+        ),
+      )
+      .file(
+        "index.xml",
+        concat!(
+          r#"<oeuvre-page oeuvre-template="main" oeuvre-path="index.html">"#,
+          r#"<oeuvre-fragment oeuvre-slot="heading">Page</oeuvre-fragment></oeuvre-page>"#
+        ),
+      );
+    site.build("").unwrap();
+    assert_eq!(
+      site.read_output("index.html"),
+      concat!(
+        "<!DOCTYPE html>\r\n<html><body>",
+        r#"<div><h2>Hello</h2><footer>No footer</footer></div>"#,
+        r#"<div><h2>Page</h2><footer><em>Bye</em></footer></div>"#,
+        r#"<p>Page</p></body></html>"#
+      )
+    );
+  }
+
+  #[test]
+  fn include_slots_that_refer_to_themselves_take_the_outer_value() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        concat!(
+          r#"<html oeuvre-name="main"><body><oeuvre-include oeuvre-snippet="card">"#,
+          r#"<oeuvre-fragment oeuvre-slot="heading">Re: <oeuvre-slot oeuvre-name="heading"/></oeuvre-fragment>"#,
+          r#"</oeuvre-include></body></html>"#
+        ),
+      )
+      .file(
+        "snippets/card.xml",
+        r#"<oeuvre-snippet oeuvre-name="card"><h2><oeuvre-slot oeuvre-name="heading"/></h2></oeuvre-snippet>"#,
+      )
+      .file(
+        "index.xml",
+        concat!(
+          r#"<oeuvre-page oeuvre-template="main" oeuvre-path="index.html">"#,
+          r#"<oeuvre-fragment oeuvre-slot="heading">Page</oeuvre-fragment></oeuvre-page>"#
+        ),
+      );
+    site.build("").unwrap();
+    assert_eq!(
+      site.read_output("index.html"),
+      "<!DOCTYPE html>\r\n<html><body><h2>Re: Page</h2></body></html>"
+    );
+  }
+
+  #[test]
+  fn include_selects_snippet_element_by_id() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        r##"<html oeuvre-name="main"><body><oeuvre-include oeuvre-snippet="components" oeuvre-select="#footer"/><oeuvre-include oeuvre-snippet="components" oeuvre-select="#missing"/></body></html>"##,
+      )
+      .file(
+        "snippets/components.xml",
+        r#"<oeuvre-snippet oeuvre-name="components"><section id="header"><h1>Site</h1></section><div><section id="footer"><p>Bye</p></section></div></oeuvre-snippet>"#,
+      )
+      .file("index.xml", r#"<oeuvre-page oeuvre-template="main" oeuvre-path="index.html"/>"#);
+    site.build("").unwrap();
+    assert_eq!(
+      site.read_output("index.html"),
+      concat!(
+        "<!DOCTYPE html>\r\n<html><body>",
+        r#"<section id="footer"><p>Bye</p></section></body></html>"#
+      )
+    );
+  }
+
+  #[test]
+  fn snippet_include_cycles_and_deep_nesting_render_nothing() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        r#"<html oeuvre-name="main"><body><oeuvre-include oeuvre-snippet="a"/></body></html>"#,
+      )
+      .file(
+        "snippets/a.xml",
+        r#"<oeuvre-snippet oeuvre-name="a"><div>A<oeuvre-include oeuvre-snippet="b"/></div></oeuvre-snippet>"#,
+      )
+      .file(
+        "snippets/b.xml",
+        r#"<oeuvre-snippet oeuvre-name="b"><div>B<oeuvre-include oeuvre-snippet="a"/></div></oeuvre-snippet>"#,
+      )
+      .file("index.xml", r#"<oeuvre-page oeuvre-template="main" oeuvre-path="index.html"/>"#);
+    site.build("").unwrap();
+    assert_eq!(
+      site.read_output("index.html"),
+      "<!DOCTYPE html>\r\n<html><body><div>A<div>B</div></div></body></html>"
+    );
+    let err = site.build("strict = true").err().unwrap();
+    assert_eq!(err.to_string(), "Strict build failed with 1 render issue(s)");
+
+    site.file(
+      "snippets/b.xml",
+      r#"<oeuvre-snippet oeuvre-name="b"><div>B<oeuvre-include oeuvre-snippet="c"/></div></oeuvre-snippet>"#,
+    );
+    site.file("snippets/c.xml", r#"<oeuvre-snippet oeuvre-name="c"><div>C</div></oeuvre-snippet>"#);
+    site.build("max_include_depth = 2").unwrap();
+    assert_eq!(
+      site.read_output("index.html"),
+      "<!DOCTYPE html>\r\n<html><body><div>A<div>B</div></div></body></html>"
+    );
+    site.build("").unwrap();
+    assert_eq!(
+      site.read_output("index.html"),
+      "<!DOCTYPE html>\r\n<html><body><div>A<div>B<div>C</div></div></div></body></html>"
+    );
+  }
+
+  #[test]
+  fn unfilled_slot_renders_default_snippet() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        r#"<html oeuvre-name="main"><body><oeuvre-slot oeuvre-name="hero" oeuvre-default-snippet="default-hero"/><oeuvre-slot oeuvre-name="aside" oeuvre-default-snippet="default-hero"><p>Inline</p></oeuvre-slot></body></html>"#,
+      )
+      .file(
+        "snippets/default-hero.xml",
+        r#"<oeuvre-snippet oeuvre-name="default-hero"><h1>Welcome</h1></oeuvre-snippet>"#,
+      )
+      .file("index.xml", r#"<oeuvre-page oeuvre-template="main" oeuvre-path="index.html"/>"#)
+      .file(
+        "about.xml",
+        r#"<oeuvre-page oeuvre-template="main" oeuvre-path="about.html"><h1 oeuvre-slot="hero">About</h1></oeuvre-page>"#,
+      );
+    site.build("").unwrap();
+    assert_eq!(
+      site.read_output("index.html"),
+      "<!DOCTYPE html>\r\n<html><body><h1>Welcome</h1><p>Inline</p></body></html>"
+    );
+    assert_eq!(
+      site.read_output("about.html"),
+      "<!DOCTYPE html>\r\n<html><body><h1>About</h1><p>Inline</p></body></html>"
+    );
+  }
+
+  #[test]
+  fn img_is_replaced_with_inlined_svg() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        r#"<html oeuvre-name="main"><body><img src="/assets/icon.svg" class="icon" alt="Star" oeuvre-inline="true"/><img src="/assets/missing.svg" oeuvre-inline="true"/></body></html>"#,
+      )
+      .file(
+        "assets/icon.svg",
+        "<?xml version=\"1.0\"?>\n<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 2 2\"><path d=\"M0 0h2v2z\"/></svg>",
+      )
+      .file("index.xml", r#"<oeuvre-page oeuvre-template="main" oeuvre-path="index.html"/>"#);
+    site.build("").unwrap();
+    assert_eq!(
+      site.read_output("index.html"),
+      concat!(
+        "<!DOCTYPE html>\r\n<html><body>",
+        r#"<svg xmlns="http://www.w3.org/2000/svg" aria-label="Star" class="icon" role="img" viewBox="0 0 2 2"><path d="M0 0h2v2z"></path></svg>"#,
+        r#"<img src="/assets/missing.svg"></body></html>"#
+      )
+    );
+  }
+
+  #[test]
+  fn call_expands_macro_with_argument() {
+    let result = render(
+      r#"<p><oeuvre-macro oeuvre-name="icon" oeuvre-params="name"><i class="icon"><oeuvre-slot oeuvre-name="name"/></i> <oeuvre-slot oeuvre-name="name"/></oeuvre-macro><oeuvre-call oeuvre-macro="icon" name="star"/></p>"#,
+    );
+    assert_eq!(result, r#"<p><i class="icon">star</i> star</p>"#);
+  }
+
+  #[test]
+  fn call_without_required_argument_renders_nothing() {
+    let result = render(
+      r#"<p><oeuvre-macro oeuvre-name="icon" oeuvre-params="name"><i/></oeuvre-macro><oeuvre-call oeuvre-macro="icon"/><oeuvre-call oeuvre-macro="missing" name="star"/></p>"#,
+    );
+    assert_eq!(result, "<p/>");
+  }
+
+  #[test]
+  fn allowed_unknown_element_does_not_fail_strict_build() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        r#"<html oeuvre-name="main"><oeuvre-widget oeuvre-allow="unknown-element" size="2"/></html>"#,
+      )
+      .file("index.xml", r#"<oeuvre-page oeuvre-template="main" oeuvre-path="index.html"/>"#);
+    site.build("strict = true").unwrap();
+    assert_eq!(
+      site.read_output("index.html"),
+      "<!DOCTYPE html>\r\n<html><oeuvre-widget size=\"2\"></oeuvre-widget></html>"
+    );
+  }
+
+  #[test]
+  fn malformed_oeuvre_elements_fail_only_a_strict_build() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        concat!(
+          r#"<html oeuvre-name="main"><p>Hi</p><oeuvre-include/><oeuvre-slot/><oeuvre-call oeuvre-macro="missing"/>"#,
+          r#"<oeuvre-counter-reset/><link href='/a.css?v={{ asset_hash("missing.css") }}'/></html>"#
+        ),
+      )
+      .file(
+        "index.xml",
+        r#"<oeuvre-page oeuvre-template="main" oeuvre-path="index.html"/>"#,
+      );
+    let report = site.load("").unwrap().render().unwrap();
+    assert_eq!(report.issue_count, 5);
+    assert_eq!(
+      site.read_output("index.html"),
+      "<!DOCTYPE html>\r\n<html><p>Hi</p><link href=\"/a.css?v=\"></html>"
+    );
+    let err = site.build("strict = true").err().unwrap();
+    assert_eq!(
+      err.to_string(),
+      "Strict build failed with 5 render issue(s)"
+    );
+  }
+
+  #[test]
+  fn unknown_element_fails_strict_build() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        r#"<html oeuvre-name="main"><oeuvre-widget/></html>"#,
+      )
+      .file(
+        "index.xml",
+        r#"<oeuvre-page oeuvre-template="main" oeuvre-path="index.html"/>"#,
+      );
+    let err = site.build("strict = true").err().unwrap();
+    assert_eq!(
+      err.to_string(),
+      "Strict build failed with 1 render issue(s)"
+    );
+  }
+
+  #[test]
+  fn assertion_fails_strict_build_when_slot_is_missing() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        r#"<html oeuvre-name="main"><oeuvre-assert oeuvre-name="title" oeuvre-message="Every page needs a title"/><h1><oeuvre-slot oeuvre-name="title"/></h1></html>"#,
+      )
+      .file(
+        "index.xml",
+        r#"<oeuvre-page oeuvre-template="main" oeuvre-path="index.html"><oeuvre-fragment oeuvre-slot="title">Home</oeuvre-fragment></oeuvre-page>"#,
+      );
+    site.build("strict = true").unwrap();
+    assert_eq!(
+      site.read_output("index.html"),
+      "<!DOCTYPE html>\r\n<html><h1>Home</h1></html>"
+    );
+
+    site.file(
+      "about.xml",
+      r#"<oeuvre-page oeuvre-template="main" oeuvre-path="about.html"/>"#,
+    );
+    let err = site.build("strict = true").err().unwrap();
+    assert_eq!(
+      err.to_string(),
+      "Strict build failed with 1 render issue(s)"
+    );
+  }
+
+  #[test]
+  fn if_and_unless_render_contents_depending_on_whether_a_slot_is_filled() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        concat!(
+          r#"<html oeuvre-name="main"><h1><oeuvre-slot oeuvre-name="title"/></h1>"#,
+          r#"<oeuvre-if oeuvre-name="subtitle"><h2><oeuvre-slot oeuvre-name="subtitle"/></h2>"#,
+          r#"<oeuvre-if oeuvre-name="author"><p>By <oeuvre-slot oeuvre-name="author"/></p></oeuvre-if>"#,
+          r#"<oeuvre-unless oeuvre-name="author"><p>Anonymous</p></oeuvre-unless></oeuvre-if>"#,
+          r#"<oeuvre-unless oeuvre-name="subtitle"><hr/></oeuvre-unless>"#,
+          r#"<oeuvre-if oeuvre-name="missing">Never</oeuvre-if><oeuvre-if>Never</oeuvre-if></html>"#
+        ),
+      )
+      .file(
+        "full.xml",
+        concat!(
+          r#"<oeuvre-page oeuvre-template="main" oeuvre-path="full.html">"#,
+          r#"<oeuvre-fragment oeuvre-slot="title">Title</oeuvre-fragment>"#,
+          r#"<oeuvre-fragment oeuvre-slot="subtitle">Subtitle</oeuvre-fragment>"#,
+          r#"<oeuvre-fragment oeuvre-slot="author">Ann</oeuvre-fragment></oeuvre-page>"#
+        ),
+      )
+      .file(
+        "anonymous.xml",
+        concat!(
+          r#"<oeuvre-page oeuvre-template="main" oeuvre-path="anonymous.html">"#,
+          r#"<oeuvre-fragment oeuvre-slot="title">Title</oeuvre-fragment>"#,
+          r#"<oeuvre-fragment oeuvre-slot="subtitle">Subtitle</oeuvre-fragment>"#,
+          r#"<oeuvre-fragment oeuvre-slot="author"> </oeuvre-fragment></oeuvre-page>"#
+        ),
+      )
+      .file(
+        "plain.xml",
+        concat!(
+          r#"<oeuvre-page oeuvre-template="main" oeuvre-path="plain.html">"#,
+          r#"<oeuvre-fragment oeuvre-slot="title">Title</oeuvre-fragment></oeuvre-page>"#
+        ),
+      );
+    site.build("").unwrap();
+    assert_eq!(
+      site.read_output("full.html"),
+      "<!DOCTYPE html>\r\n<html><h1>Title</h1><h2>Subtitle</h2><p>By Ann</p></html>"
+    );
+    assert_eq!(
+      site.read_output("anonymous.html"),
+      "<!DOCTYPE html>\r\n<html><h1>Title</h1><h2>Subtitle</h2><p>Anonymous</p></html>"
+    );
+    assert_eq!(
+      site.read_output("plain.html"),
+      "<!DOCTYPE html>\r\n<html><h1>Title</h1><hr></html>"
+    );
+  }
+
+  #[test]
+  fn related_renders_rows_sharing_tags() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        r#"<html oeuvre-name="main"><ul><oeuvre-related oeuvre-dataset="posts" oeuvre-by="tags" oeuvre-limit="2"><li><oeuvre-slot oeuvre-name="title"/></li></oeuvre-related></ul></html>"#,
+      )
+      .file(
+        "posts/a.xml",
+        r#"<oeuvre-page oeuvre-template="main" oeuvre-path="a.html"/>"#,
+      )
+      .file(
+        "data/posts.xml",
+        r#"<oeuvre-dataset oeuvre-name="posts"><oeuvre-field oeuvre-name="title" oeuvre-type="string"/><oeuvre-field oeuvre-name="url" oeuvre-type="string"/><oeuvre-field oeuvre-name="tags" oeuvre-type="string"/></oeuvre-dataset>"#,
+      );
+    let rows = [
+      ("a", "/posts/a.html", "rust, web"),
+      ("b", "/posts/b.html", "rust"),
+      ("c", "/posts/c.html", "web,rust"),
+      ("d", "/posts/d.html", "cooking"),
+      ("e", "/posts/e.html", "web"),
+    ];
+    for (name, url, tags) in rows {
+      site.file(
+        &format!("data/posts/{}.xml", name),
+        &format!(
+          r#"<oeuvre-datarow oeuvre-dataset="posts"><oeuvre-value oeuvre-name="title">{}</oeuvre-value><oeuvre-value oeuvre-name="url">{}</oeuvre-value><oeuvre-value oeuvre-name="tags">{}</oeuvre-value></oeuvre-datarow>"#,
+          name.to_uppercase(),
+          url,
+          tags
+        ),
+      );
+    }
+    site.build("").unwrap();
+    assert_eq!(
+      site.read_output("posts/a.html"),
+      "<!DOCTYPE html>\r\n<html><ul><li>C</li><li>B</li></ul></html>"
+    );
+  }
+
+  #[test]
+  fn for_renders_contents_once_per_dataset_row() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        concat!(
+          r#"<html oeuvre-name="main"><ul><oeuvre-for oeuvre-dataset="posts">"#,
+          r#"<li><oeuvre-slot oeuvre-name="title"/>: <oeuvre-slot oeuvre-name="summary"/></li>"#,
+          r#"</oeuvre-for></ul><h1><oeuvre-slot oeuvre-name="title"/></h1></html>"#
+        ),
+      )
+      .file(
+        "index.xml",
+        concat!(
+          r#"<oeuvre-page oeuvre-template="main" oeuvre-path="index.html">"#,
+          r#"<oeuvre-fragment oeuvre-slot="title">Posts</oeuvre-fragment></oeuvre-page>"#
+        ),
+      )
+      .file(
+        "data/posts.xml",
+        concat!(
+          r#"<oeuvre-dataset oeuvre-name="posts">"#,
+          r#"<oeuvre-field oeuvre-name="title" oeuvre-type="string"/>"#,
+          r#"<oeuvre-field oeuvre-name="summary" oeuvre-type="fragment"/></oeuvre-dataset>"#
+        ),
+      );
+    for (name, summary) in [("a", "<em>First</em>"), ("b", "Second"), ("c", "<b>3</b>rd")] {
+      site.file(
+        &format!("data/posts/{}.xml", name),
+        &format!(
+          concat!(
+            r#"<oeuvre-datarow oeuvre-dataset="posts"><oeuvre-value oeuvre-name="title">{}</oeuvre-value>"#,
+            r#"<oeuvre-value oeuvre-name="summary">{}</oeuvre-value></oeuvre-datarow>"#
+          ),
+          name.to_uppercase(),
+          summary
+        ),
+      );
+    }
+    site.build("").unwrap();
+    assert_eq!(
+      site.read_output("index.html"),
+      concat!(
+        "<!DOCTYPE html>\r\n",
+        r#"<html><ul><li>A: <em>First</em></li><li>B: Second</li><li>C: <b>3</b>rd</li></ul>"#,
+        r#"<h1>Posts</h1></html>"#
+      )
+    );
+  }
+
+  #[test]
+  fn data_renders_a_named_row_field_or_falls_back_to_its_contents() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        r#"<html oeuvre-name="main"><body><oeuvre-slot oeuvre-name="body"/></body></html>"#,
+      )
+      .file(
+        "index.xml",
+        concat!(
+          r#"<oeuvre-page oeuvre-template="main" oeuvre-path="index.html"><oeuvre-fragment oeuvre-slot="body">"#,
+          r#"<h1><oeuvre-data oeuvre-dataset="authors" oeuvre-row="wcwedin" oeuvre-field="name"/></h1>"#,
+          r#"<oeuvre-data oeuvre-dataset="authors" oeuvre-row="wcwedin" oeuvre-field="bio"/>"#,
+          r#"<p><oeuvre-data oeuvre-dataset="authors" oeuvre-row="nobody" oeuvre-field="name">Anonymous</oeuvre-data></p>"#,
+          r#"<p><oeuvre-data oeuvre-dataset="authors" oeuvre-row="wcwedin" oeuvre-field="email">No email</oeuvre-data></p>"#,
+          r#"</oeuvre-fragment></oeuvre-page>"#
+        ),
+      )
+      .file(
+        "data/authors.xml",
+        concat!(
+          r#"<oeuvre-dataset oeuvre-name="authors">"#,
+          r#"<oeuvre-field oeuvre-name="name" oeuvre-type="string"/>"#,
+          r#"<oeuvre-field oeuvre-name="bio" oeuvre-type="fragment"/>"#,
+          r#"<oeuvre-field oeuvre-name="email" oeuvre-type="string"/></oeuvre-dataset>"#
+        ),
+      )
+      .file(
+        "data/authors/wcwedin.xml",
+        concat!(
+          r#"<oeuvre-datarow oeuvre-dataset="authors" oeuvre-name="wcwedin">"#,
+          r#"<oeuvre-value oeuvre-name="name">W. C. Wedin</oeuvre-value>"#,
+          r#"<oeuvre-value oeuvre-name="bio"><p>Writes <em>{{ oeuvre }}</em>.</p></oeuvre-value></oeuvre-datarow>"#
+        ),
+      );
+    let report = site.load("").unwrap().render().unwrap();
+    assert_eq!(report.issue_count, 0);
+    assert_eq!(
+      site.read_output("index.html"),
+      concat!(
+        "<!DOCTYPE html>\r\n",
+        r#"<html><body><h1>W. C. Wedin</h1><p>Writes <em>{{ oeuvre }}</em>.</p>"#,
+        r#"<p>Anonymous</p><p>No email</p></body></html>"#
+      )
+    );
+  }
+
+  #[test]
+  fn counter_increments_and_resets_within_page() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        concat!(
+          r#"<html oeuvre-name="main">"#,
+          r#"<figure id="fig-{{ counter.figure }}"/>"#,
+          r#"<figure id="fig-{{ counter.figure }}"/>"#,
+          r#"<figure id="fig-{{ counter.figure }}" data-note="{{ counter.note }}"/>"#,
+          r#"<oeuvre-counter-reset oeuvre-name="figure"/>"#,
+          r#"<figure id="fig-{{ counter.figure }}"><figcaption>Figure {{ counter.figure }}</figcaption></figure>"#,
+          r#"<p>Use \{{ counter.figure }} or <code>{{ counter.figure }}</code></p>"#,
+          "</html>"
+        ),
+      )
+      .file(
+        "a.xml",
+        r#"<oeuvre-page oeuvre-template="main" oeuvre-path="a.html"/>"#,
+      )
+      .file(
+        "b.xml",
+        r#"<oeuvre-page oeuvre-template="main" oeuvre-path="b.html"/>"#,
+      );
+    site.build("").unwrap();
+    let expected = concat!(
+      "<!DOCTYPE html>\r\n<html>",
+      r#"<figure id="fig-1"></figure><figure id="fig-2"></figure>"#,
+      r#"<figure data-note="1" id="fig-3"></figure>"#,
+      r#"<figure id="fig-1"><figcaption>Figure 2</figcaption></figure>"#,
+      r#"<p>Use {{ counter.figure }} or <code>{{ counter.figure }}</code></p></html>"#
+    );
+    assert_eq!(site.read_output("a.html"), expected);
+    assert_eq!(site.read_output("b.html"), expected);
+  }
+
+  #[test]
+  fn shorthand_elements_expand_to_snippet_or_tag() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        r#"<html oeuvre-name="main"><body><oeuvre-slot oeuvre-name="body"/></body></html>"#,
+      )
+      .file(
+        "snippets/note.xml",
+        r#"<oeuvre-snippet oeuvre-name="note"><aside class="note"><b><oeuvre-slot oeuvre-name="attr.title"/></b><oeuvre-slot oeuvre-name="content"/><i><oeuvre-slot oeuvre-name="title"/></i></aside></oeuvre-snippet>"#,
+      )
+      .file(
+        "index.xml",
+        r#"<oeuvre-page oeuvre-template="main" oeuvre-path="index.html"><oeuvre-fragment oeuvre-slot="title">Home</oeuvre-fragment><oeuvre-fragment oeuvre-slot="body"><lead id="intro">Welcome</lead><note title="Careful">Mind the <em>gap</em></note><aside>As is</aside></oeuvre-fragment></oeuvre-page>"#,
+      );
+    site
+      .build(concat!(
+        "[elements]\n",
+        "note = { snippet = \"note\", class = \"boxed\" }\n",
+        "lead = { tag = \"p\", class = \"lead\" }\n",
+      ))
+      .unwrap();
+    // The note's title attribute doesn't hide the page's title slot.
+    assert_eq!(
+      site.read_output("index.html"),
+      concat!(
+        "<!DOCTYPE html>\r\n",
+        r#"<html><body><p class="lead" id="intro">Welcome</p><aside class="note boxed"><b>Careful</b>Mind the <em>gap</em><i>Home</i></aside><aside>As is</aside></body></html>"#
+      )
+    );
+  }
+
+  #[test]
+  fn held_issues_are_counted_and_taken_in_order() {
+    let mut issues = IssueSink::held();
+    issues.record("First".to_string());
+    issues.record("Second".to_string());
+    assert_eq!(issues.count(), 2);
+    assert_eq!(issues.take_held(), ["First", "Second"]);
+    assert!(issues.take_held().is_empty());
+  }
+
+  #[test]
+  fn render_context_carries_slots_snippets_and_issues() {
+    let test_site = TestSite::new();
+    test_site.file(
+      "snippets/note.xml",
+      r#"<oeuvre-snippet oeuvre-name="note"><aside><oeuvre-slot oeuvre-name="content"/></aside></oeuvre-snippet>"#,
+    );
+    let site = test_site.load("[globals]\nauthor = \"Ada\"\n").unwrap();
+    let mut slot_values = HashMap::new();
+    slot_values.insert("title".to_string(), "<h1>Hi</h1>".parse().unwrap());
+    let mut issues = IssueSink::default();
+    let mut ctx = RenderContext::new(&slot_values, &site, Path::new("index.html"), &mut issues);
+    assert_eq!(String::from(ctx.slot("title").unwrap()), "<h1>Hi</h1>");
+    assert_eq!(ctx.global("author"), Some("Ada"));
+    assert!(ctx.snippet("note").is_some());
+    assert!(ctx.snippet("missing").is_none());
+
+    let mut local_slot_values = slot_values.clone();
+    local_slot_values.insert("content".to_string(), "<p>Local</p>".parse().unwrap());
+    let template: Element =
+      r#"<div><oeuvre-include oeuvre-snippet="note"/><oeuvre-slot oeuvre-name="title"/><oeuvre-oops/></div>"#
+        .parse()
+        .unwrap();
+    let result = render_template(&template, &mut ctx.with_slots(&local_slot_values));
+    assert_eq!(
+      String::from(&result),
+      "<div><aside><p>Local</p></aside><h1>Hi</h1></div>"
+    );
+    assert!(ctx.slot("content").is_none());
+    assert_eq!(issues.count(), 1);
+  }
+
+  struct Badge;
+
+  impl RenderPlugin for Badge {
+    fn handles(&self, name: &str) -> bool {
+      name == "oeuvre-badge"
+    }
+
+    fn render(&self, element: &Element, target: &mut Element, ctx: &mut RenderContext) {
+      let mut badge = Element::bare("span", None::<String>);
+      badge.set_attr("class", element.attr("oeuvre-kind").unwrap_or("info"));
+      ctx.render_contents(element, &mut badge);
+      target.append_child(badge);
+    }
+  }
+
+  #[test]
+  fn plugins_render_custom_elements() {
+    let test_site = TestSite::new();
+    let mut site = test_site.load("").unwrap();
+    site.register_plugin(Box::new(Badge));
+    let template: Element = concat!(
+      r#"<p><oeuvre-badge oeuvre-kind="new">Fresh <oeuvre-slot oeuvre-name="what"/></oeuvre-badge>"#,
+      "<oeuvre-other/></p>"
+    )
+    .parse()
+    .unwrap();
+    let mut slot_values = HashMap::new();
+    slot_values.insert("what".to_string(), "<b>bread</b>".parse().unwrap());
+    let mut issues = IssueSink::default();
+    let mut ctx = RenderContext::new(&slot_values, &site, Path::new("index.html"), &mut issues);
+    assert_eq!(
+      String::from(&render_template(&template, &mut ctx)),
+      r#"<p><span class="new">Fresh <b>bread</b></span></p>"#
+    );
+    // The plugin's element is known, but the element no plugin handles is still reported.
+    assert_eq!(issues.count(), 1);
+  }
+
+  #[test]
+  fn json_is_escaped_for_script_context() {
+    assert_eq!(
+      render_with_config(
+        r#"<script>const DATA = <oeuvre-json oeuvre-name="payload"/>;</script>"#,
+        "[globals]\npayload = '</script><!-- & \"quoted\"'\n",
+      ),
+      r#"<script>const DATA = "\u003c/script\u003e\u003c!-- \u0026 \"quoted\"";</script>"#
+    );
+  }
+
+  #[test]
+  fn embed_splices_region_of_another_page() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        r#"<html oeuvre-name="main"><body><nav>Menu</nav><div id="content"><oeuvre-slot oeuvre-name="body"/></div></body></html>"#,
+      )
+      .file(
+        "blog/post.xml",
+        r#"<oeuvre-page oeuvre-template="main" oeuvre-path="post.html"><p oeuvre-slot="body">Post</p></oeuvre-page>"#,
+      )
+      .file(
+        "index.xml",
+        r##"<oeuvre-page oeuvre-template="main" oeuvre-path="index.html"><oeuvre-fragment oeuvre-slot="body"><h2>Recent</h2><oeuvre-embed oeuvre-page="/blog/post.html" oeuvre-select="#content"/></oeuvre-fragment></oeuvre-page>"##,
+      )
+      .file(
+        "loop.xml",
+        r#"<oeuvre-page oeuvre-template="main" oeuvre-path="loop.html"><oeuvre-fragment oeuvre-slot="body"><oeuvre-embed oeuvre-page="/loop.html"/></oeuvre-fragment></oeuvre-page>"#,
+      )
+      .file(
+        "ping.xml",
+        r##"<oeuvre-page oeuvre-template="main" oeuvre-path="ping.html"><oeuvre-fragment oeuvre-slot="body">Ping<oeuvre-embed oeuvre-page="/pong.html" oeuvre-select="#content"/></oeuvre-fragment></oeuvre-page>"##,
+      )
+      .file(
+        "pong.xml",
+        r##"<oeuvre-page oeuvre-template="main" oeuvre-path="pong.html"><oeuvre-fragment oeuvre-slot="body">Pong<oeuvre-embed oeuvre-page="/ping.html" oeuvre-select="#content"/></oeuvre-fragment></oeuvre-page>"##,
+      );
+    site.build("").unwrap();
+    assert_eq!(
+      site.read_output("index.html"),
+      concat!(
+        "<!DOCTYPE html>\r\n<html><body><nav>Menu</nav><div id=\"content\"><h2>Recent</h2>",
+        r#"<div id="content"><p>Post</p></div></div></body></html>"#
+      )
+    );
+    assert_eq!(
+      site.read_output("blog/post.html"),
+      concat!(
+        "<!DOCTYPE html>\r\n",
+        r#"<html><body><nav>Menu</nav><div id="content"><p>Post</p></div></body></html>"#
+      )
+    );
+    assert_eq!(
+      site.read_output("loop.html"),
+      "<!DOCTYPE html>\r\n<html><body><nav>Menu</nav><div id=\"content\"></div></body></html>"
+    );
+    // Whichever of two pages that embed each other is rendered first, the embed that
+    // would close the cycle renders nothing.
+    let ping = site.read_output("ping.html");
+    let pong = site.read_output("pong.html");
+    assert!(ping.contains("Ping<div id=\"content\">Pong"), "{}", ping);
+    assert!(pong.contains("Pong<div id=\"content\">Ping"), "{}", pong);
+  }
+
+  #[test]
+  fn a_page_embedded_by_pages_written_in_parallel_is_never_mistaken_for_a_cycle() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        r#"<html oeuvre-name="main"><body><oeuvre-slot oeuvre-name="body"/></body></html>"#,
+      )
+      .file(
+        "shared.xml",
+        r#"<oeuvre-page oeuvre-template="main" oeuvre-path="shared.html"><p oeuvre-slot="body">Shared</p></oeuvre-page>"#,
+      );
+    for index in 0..32 {
+      site.file(
+        &format!("page{}.xml", index),
+        &format!(
+          r#"<oeuvre-page oeuvre-template="main" oeuvre-path="page{}.html"><oeuvre-fragment oeuvre-slot="body"><oeuvre-embed oeuvre-page="/shared.html"/></oeuvre-fragment></oeuvre-page>"#,
+          index
+        ),
+      );
+    }
+    site.build("jobs = 8\nstrict = true").unwrap();
+    for index in 0..32 {
+      assert_eq!(
+        site.read_output(&format!("page{}.html", index)),
+        "<!DOCTYPE html>\r\n<html><body><p>Shared</p></body></html>"
+      );
+    }
+  }
+
+  #[test]
+  fn whitespace_around_control_elements_is_trimmed_when_configured() {
+    let template = "<html oeuvre-name=\"main\">\n<body>\n  <oeuvre-slot oeuvre-name=\"banner\"/>\n  <p>Hi</p>\n</body>\n</html>";
+    assert_eq!(
+      render(template),
+      "<html>\n<body>\n  \n  <p>Hi</p>\n</body>\n</html>"
+    );
+    assert_eq!(
+      render_with_config(template, "trim_control_whitespace = true"),
+      "<html>\n<body><p>Hi</p>\n</body>\n</html>"
+    );
+  }
+
+  #[test]
+  fn asset_hash_is_interpolated_into_attributes() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        r#"<html oeuvre-name="main"><link rel="stylesheet" href='/main.css?v={{ asset_hash("assets/main.css") }}'/></html>"#,
+      )
+      .file("index.xml", r#"<oeuvre-page oeuvre-template="main" oeuvre-path="index.html"/>"#)
+      .file("assets/main.css", "body { color: black; }");
+    site.build("").unwrap();
+    assert_eq!(
+      site.read_output("index.html"),
+      concat!(
+        "<!DOCTYPE html>\r\n",
+        r#"<html><link href="/main.css?v=3eda7e61" rel="stylesheet"></html>"#
+      )
+    );
+  }
+
+  #[test]
+  fn template_comments_survive_into_the_rendered_page() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        r#"<html oeuvre-name="main"><head><!-- x --><!--[if lt IE 9]><script src="html5shiv.js"></script><![endif]--></head><body><oeuvre-slot oeuvre-name="body"/></body></html>"#,
+      )
+      .file(
+        "index.xml",
+        r#"<oeuvre-page oeuvre-template="main" oeuvre-path="index.html"><p oeuvre-slot="body">Hi<!-- note --></p></oeuvre-page>"#,
+      );
+    site.build("").unwrap();
+    assert_eq!(
+      site.read_output("index.html"),
+      concat!(
+        "<!DOCTYPE html>\r\n",
+        r#"<html><head><!-- x --><!--[if lt IE 9]><script src="html5shiv.js"></script><![endif]--></head>"#,
+        r#"<body><p>Hi<!-- note --></p></body></html>"#
+      )
+    );
+  }
+
+  #[test]
+  fn cdata_script_bodies_are_written_verbatim() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        r#"<html oeuvre-name="main"><body><oeuvre-slot oeuvre-name="body"/></body></html>"#,
+      )
+      .file(
+        "index.xml",
+        r#"<oeuvre-page oeuvre-template="main" oeuvre-path="index.html"><script oeuvre-slot="body"><![CDATA[if (a < b && c) {}]]></script></oeuvre-page>"#,
+      );
+    site.build("").unwrap();
+    assert_eq!(
+      site.read_output("index.html"),
+      "<!DOCTYPE html>\r\n<html><body><script>if (a < b && c) {}</script></body></html>"
+    );
+  }
+
+  #[test]
+  fn slots_are_interpolated_into_attributes_and_text() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        concat!(
+          r#"<html oeuvre-name="main"><a href="/tags/{{ tag }}.html">More about {{ tag }}</a>"#,
+          r#"<p>{{ title }} by {{ site }}, not \{{ title }} or {{ unknown }}</p>"#,
+          r#"<pre>{{ tag }}</pre><oeuvre-slot oeuvre-name="body"/></html>"#
+        ),
+      )
+      .file(
+        "index.xml",
+        concat!(
+          r#"<oeuvre-page oeuvre-template="main" oeuvre-path="index.html">"#,
+          r#"<oeuvre-fragment oeuvre-slot="tag">rust</oeuvre-fragment>"#,
+          r#"<oeuvre-fragment oeuvre-slot="site">Unused</oeuvre-fragment>"#,
+          r#"<oeuvre-fragment oeuvre-slot="title">On <em>Rust</em></oeuvre-fragment>"#,
+          r#"<p oeuvre-slot="body">Written as {{ tag }} and \{{ tag }}</p></oeuvre-page>"#
+        ),
+      );
+    site.build("globals = { site = \"Oeuvre\" }").unwrap();
+    // Globals come before slots, and page content and code are never interpolated.
+    assert_eq!(
+      site.read_output("index.html"),
+      concat!(
+        "<!DOCTYPE html>\r\n",
+        r#"<html><a href="/tags/rust.html">More about rust</a>"#,
+        r#"<p>On Rust by Oeuvre, not {{ title }} or {{ unknown }}</p>"#,
+        r#"<pre>{{ tag }}</pre><p>Written as {{ tag }} and \{{ tag }}</p></html>"#
+      )
+    );
+  }
+
+  #[test]
+  fn nav_renders_nested_menu_with_active_item() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        r#"<html oeuvre-name="main"><oeuvre-nav oeuvre-dataset="menu" class="menu"/></html>"#,
+      )
+      .file(
+        "data/menu.xml",
+        r#"<oeuvre-dataset oeuvre-name="menu"><oeuvre-field oeuvre-name="label" oeuvre-type="string"/><oeuvre-field oeuvre-name="url" oeuvre-type="string"/><oeuvre-field oeuvre-name="order" oeuvre-type="string"/><oeuvre-field oeuvre-name="parent" oeuvre-type="string"/></oeuvre-dataset>"#,
+      )
+      .file(
+        "data/menu/home.xml",
+        r#"<oeuvre-datarow oeuvre-dataset="menu"><oeuvre-value oeuvre-name="label">Home</oeuvre-value><oeuvre-value oeuvre-name="url">/</oeuvre-value><oeuvre-value oeuvre-name="order">1</oeuvre-value></oeuvre-datarow>"#,
+      )
+      .file(
+        "data/menu/about.xml",
+        r#"<oeuvre-datarow oeuvre-dataset="menu"><oeuvre-value oeuvre-name="label">About</oeuvre-value><oeuvre-value oeuvre-name="url">/about/</oeuvre-value><oeuvre-value oeuvre-name="order">2</oeuvre-value></oeuvre-datarow>"#,
+      )
+      .file(
+        "data/menu/team.xml",
+        r#"<oeuvre-datarow oeuvre-dataset="menu"><oeuvre-value oeuvre-name="label">Team</oeuvre-value><oeuvre-value oeuvre-name="url">/about/team.html</oeuvre-value><oeuvre-value oeuvre-name="order">2</oeuvre-value><oeuvre-value oeuvre-name="parent">/about/</oeuvre-value></oeuvre-datarow>"#,
+      )
+      .file(
+        "data/menu/history.xml",
+        r#"<oeuvre-datarow oeuvre-dataset="menu"><oeuvre-value oeuvre-name="label">History</oeuvre-value><oeuvre-value oeuvre-name="url">/about/history.html</oeuvre-value><oeuvre-value oeuvre-name="order">1</oeuvre-value><oeuvre-value oeuvre-name="parent">/about/</oeuvre-value></oeuvre-datarow>"#,
+      )
+      .file(
+        "about/team.xml",
+        r#"<oeuvre-page oeuvre-template="main" oeuvre-path="/about/team.html"/>"#,
+      );
+    site.build("").unwrap();
+    assert_eq!(
+      site.read_output("about/team.html"),
+      concat!(
+        "<!DOCTYPE html>\r\n<html><ul class=\"menu\">",
+        "<li><a href=\"/\">Home</a></li>",
+        "<li><a href=\"/about/\">About</a><ul>",
+        "<li><a href=\"/about/history.html\">History</a></li>",
+        "<li class=\"active\"><a href=\"/about/team.html\">Team</a></li>",
+        "</ul></li></ul></html>"
+      )
+    );
+  }
+}
@@ -0,0 +1,159 @@
+use crate::minidom::node::Node;
+use crate::minidom::Element;
+
+/// Elements that are kept when sanitizing. Other elements are replaced by their contents,
+/// except for those in `DROPPED_ELEMENTS`.
+const ALLOWED_ELEMENTS: &[&str] = &[
+  "a",
+  "abbr",
+  "b",
+  "blockquote",
+  "br",
+  "caption",
+  "cite",
+  "code",
+  "dd",
+  "del",
+  "div",
+  "dl",
+  "dt",
+  "em",
+  "figcaption",
+  "figure",
+  "h1",
+  "h2",
+  "h3",
+  "h4",
+  "h5",
+  "h6",
+  "hr",
+  "i",
+  "img",
+  "ins",
+  "kbd",
+  "li",
+  "mark",
+  "ol",
+  "p",
+  "pre",
+  "q",
+  "s",
+  "small",
+  "span",
+  "strong",
+  "sub",
+  "sup",
+  "table",
+  "tbody",
+  "td",
+  "th",
+  "thead",
+  "tr",
+  "ul",
+];
+
+/// Elements that are removed along with their contents when sanitizing.
+const DROPPED_ELEMENTS: &[&str] = &[
+  "applet", "base", "embed", "form", "frame", "frameset", "iframe", "link", "meta", "noscript",
+  "object", "script", "style",
+];
+
+/// Attributes that are kept when sanitizing. All others, including event handlers, are removed.
+const ALLOWED_ATTRIBUTES: &[&str] = &[
+  "alt", "cite", "class", "colspan", "dir", "height", "href", "id", "lang", "rowspan", "src",
+  "title", "width",
+];
+
+/// Attributes whose values are urls, which are removed unless their scheme is allowed.
+const URL_ATTRIBUTES: &[&str] = &["cite", "href", "src"];
+
+/// Url schemes that are kept when sanitizing. Urls without a scheme are always kept.
+const ALLOWED_SCHEMES: &[&str] = &["http", "https", "mailto"];
+
+/// Appends a sanitized copy of the children of `fragment` to `target`, stripping any
+/// elements, attributes and urls that are not on the allowlists above. This is meant
+/// for untrusted content, so comments are removed as well.
+pub fn append_sanitized(fragment: &Element, target: &mut Element) {
+  for node in fragment.nodes() {
+    match node {
+      Node::Text(text) | Node::CData(text) => target.append_text_node(text.as_str()),
+      Node::Comment(_) => (),
+      Node::Element(element) => {
+        let name = element.name().to_ascii_lowercase();
+        if DROPPED_ELEMENTS.contains(&name.as_str()) {
+          continue;
+        }
+        if !ALLOWED_ELEMENTS.contains(&name.as_str()) {
+          append_sanitized(element, target);
+          continue;
+        }
+        let mut result = Element::bare(name, element.ns());
+        for (attr_name, attr_value) in element.attrs() {
+          if is_allowed_attribute(attr_name, attr_value) {
+            result.set_attr(attr_name, attr_value);
+          }
+        }
+        append_sanitized(element, &mut result);
+        target.append_child(result);
+      }
+    }
+  }
+}
+
+fn is_allowed_attribute(name: &str, value: &str) -> bool {
+  let name = name.to_ascii_lowercase();
+  if !ALLOWED_ATTRIBUTES.contains(&name.as_str()) {
+    return false;
+  }
+  if !URL_ATTRIBUTES.contains(&name.as_str()) {
+    return true;
+  }
+  // Browsers ignore whitespace and control characters within a scheme, as in `java\tscript:`.
+  let url: String = value
+    .chars()
+    .filter(|c| !c.is_whitespace() && !c.is_control())
+    .collect::<String>()
+    .to_ascii_lowercase();
+  match url.find([':', '/', '?', '#']) {
+    Some(index) if url[index..].starts_with(':') => ALLOWED_SCHEMES.contains(&&url[..index]),
+    _ => true,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn sanitize(markup: &str) -> String {
+    let fragment: Element = format!("<div>{}</div>", markup).parse().unwrap();
+    let mut result = Element::bare("div", None::<String>);
+    append_sanitized(&fragment, &mut result);
+    String::from(&result)
+  }
+
+  #[test]
+  fn script_and_event_handlers_are_stripped() {
+    assert_eq!(
+      sanitize(r#"<p onclick="steal()" class="intro">Hi<script>steal()</script></p>"#),
+      r#"<div><p class="intro">Hi</p></div>"#
+    );
+  }
+
+  #[test]
+  fn unsafe_urls_are_stripped() {
+    assert_eq!(
+      sanitize(
+        r#"<a href="java	script:steal()">x</a><a href="https://example.com/">y</a><a href="/about/">z</a>"#
+      ),
+      r#"<div><a>x</a><a href="https://example.com/">y</a><a href="/about/">z</a></div>"#
+    );
+  }
+
+  #[test]
+  fn unknown_elements_are_replaced_by_their_contents() {
+    assert_eq!(
+      sanitize(r#"<font color="red"><b>Bold</b></font><!-- note -->"#),
+      "<div><b>Bold</b></div>"
+    );
+  }
+}
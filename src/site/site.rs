@@ -1,30 +1,90 @@
-use glob::glob;
+use glob::{glob, Pattern};
 use itertools::Itertools;
 use path_clean::PathClean;
 use std::env;
 use std::path::PathBuf;
 
 use anyhow::{bail, Result};
-use log::{error, info};
-use std::collections::HashMap;
+use log::{error, info, warn};
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::fs;
+use std::io;
 use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+
+#[cfg(feature = "parallel")]
+use super::in_thread_pool;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
 
 use super::Page;
+use super::render::RenderPlugin;
+use crate::minidom::Element;
+use super::Macro;
 use super::Snippet;
 use super::Template;
 use super::Dataset;
-use super::SiteConfig;
+use super::{NestedOutputPolicy, SiteConfig};
+use super::site_config::AssetCompare;
+use super::check::{check, check_config_dataset_references, INVALID_ROW, ORPHANED_ROW};
+use super::feed::write_feeds;
+use super::images::write_resized_images;
+use super::locale::{load_string_tables, StringTable};
+use super::Diagnostic;
+use super::ContentHasher;
+use super::output::{
+  url_path, ArchiveSink, DirectorySink, DryRunSink, FileSize, ManifestSink, OutputSink, SizeSink,
+};
+
+/// A summary of a completed build.
+pub struct BuildReport {
+  /// The site's build hash.
+  pub hash: String,
+  /// The number of problems found while rendering pages.
+  pub issue_count: usize,
+  /// The paths and sizes in bytes of the largest output files, largest first,
+  /// up to the `report_largest` option.
+  pub largest_outputs: Vec<FileSize>,
+  /// The paths and sizes in bytes of the HTML pages larger than the `max_page_kb` budget.
+  pub over_budget: Vec<FileSize>,
+}
 
 /// All of the data necessary to render the site to disk, including parsed DOM
 /// trees of all document content.
 pub struct Site {
-    pub pages: HashMap<String, Page>,
+    pub pages: HashMap<PathBuf, Page>,
     pub templates: HashMap<String, Template>,
     pub snippets: HashMap<String, Snippet>,
+    pub macros: HashMap<String, Macro>,
     pub datasets: HashMap<String, Dataset>,
+    /// Diagnostics for data row files whose dataset was not loaded, that did not fit it,
+    /// or whose name was in use.
+    pub row_diagnostics: Vec<Diagnostic>,
+    /// Diagnostics for page files skipped because another page has the same output path.
+    pub path_collisions: Vec<Diagnostic>,
+    /// The string table of each configured locale, keyed by locale.
+    pub strings: HashMap<String, StringTable>,
     pub content_paths: Vec<PathBuf>,
+    /// The paths of every template, snippet, dataset, data row and page file,
+    /// including those that could not be loaded.
+    pub source_paths: Vec<PathBuf>,
+    /// The directory the site's input files are found in, which the dev server watches.
+    #[cfg(feature = "serve")]
+    pub input_dir: PathBuf,
     pub output_dir: PathBuf,
+    pub config: SiteConfig,
+    /// A hash of the paths and contents of all input files,
+    /// which is stable across identical builds.
+    pub build_hash: String,
+    /// Each page's rendered output, keyed by output path, kept once the page is
+    /// rendered to be embedded in another.
+    pub embedded_pages: HashMap<PathBuf, OnceLock<Element>>,
+    /// The images that pages have asked for resized copies of, by path and width.
+    pub resized_images: Mutex<BTreeSet<(PathBuf, u32)>>,
+    /// The local files that written pages link to, with the prune_unused_assets option.
+    pub referenced_paths: Mutex<HashSet<PathBuf>>,
+    /// Handlers for custom oeuvre elements, in the order they were registered.
+    pub plugins: Vec<Box<dyn RenderPlugin>>,
   }
   
   impl Site {
@@ -40,68 +100,398 @@ pub struct Site {
       let mut excluded_paths = Vec::<PathBuf>::new();
       // Discarding the returned value; `excluded_paths` will contain the same data anyway.
       Site::expand_glob(&config.exclude, &mut excluded_paths);
+      let ignore_patterns = Site::read_ignore_file(&input_dir);
+      Site::expand_glob(&ignore_patterns, &mut excluded_paths);
   
       info!("Looking for output directory");
-      let output_dir = Site::create_output_dir(starting_path, &config.output_dir)?;
+      Site::check_output_dir(
+        &input_dir,
+        &starting_path.join(&config.output_dir).clean(),
+        config.nested_output_dir,
+      )?;
+      if config.clean {
+        Site::check_clean_dir(
+          starting_path,
+          &input_dir,
+          &starting_path.join(&config.output_dir).clean(),
+        )?;
+      }
+      let output_dir = starting_path.join(&config.output_dir).clean();
+      info!("Using output directory {}", output_dir.display());
       let output_glob = format!("{}{}", &config.output_dir, "/**/*");
       // Discarding the returned value; we only need to add the output paths to `excluded_paths`.
       Site::expand_glob(&[output_glob], &mut excluded_paths);
   
       info!("Looking for templates {:?}", config.templates);
-      let template_paths: Vec<PathBuf> = Site::expand_glob(&config.templates, &mut excluded_paths);
+      let template_roots =
+        Site::expand_source_roots(&config.templates, &config.themes, &mut excluded_paths);
       info!("Reading templates");
-      let templates = Template::load_many(&template_paths);
+      let templates = Template::load_many(&template_roots, &config);
   
       info!("Looking for snippets {:?}", config.snippets);
-      let snippet_paths: Vec<PathBuf> = Site::expand_glob(&config.snippets, &mut excluded_paths);
+      let snippet_roots =
+        Site::expand_source_roots(&config.snippets, &config.themes, &mut excluded_paths);
       info!("Reading templates");
-      let snippets = Snippet::load_many(&snippet_paths);
+      let snippets = Snippet::load_many(&snippet_roots, &config);
+      info!("Registering macros");
+      let macros = Macro::load_many(&templates, &snippets);
   
       info!("Looking for datasets {:?}", config.datasets);
       let dataset_paths = Site::expand_glob(&config.datasets, &mut excluded_paths);
       info!("Reading datasets");
-      let datasets = Dataset::load_many(&dataset_paths);
+      let mut datasets = Dataset::load_many(&dataset_paths, &config);
   
-      info!("Looking for data rows {:?}", config.datasets);
+      info!("Looking for data rows {:?}", config.datarows);
       let datarow_paths = Site::expand_glob(&config.datarows, &mut excluded_paths);
       info!("Reading data rows");
-      Dataset::load_rows(&datarow_paths, &datasets);
+      let row_diagnostics = Dataset::load_rows(&datarow_paths, &mut datasets, &config);
   
-      info!("Looking for assets {:?}", config.pages);
+      info!("Reading string tables");
+      let strings = load_string_tables(&config.locales);
+      let locale_paths: Vec<PathBuf> = config.locales.values().map(PathBuf::from).collect();
+
+      info!("Looking for assets {:?}", config.assets);
       let content_paths = Site::expand_glob(&config.assets, &mut excluded_paths);
   
       info!("Looking for pages {:?}", config.pages);
       let page_paths = Site::expand_glob(&config.pages, &mut excluded_paths);
       info!("Reading pages");
-      let pages = Page::load_many(&page_paths);
+      let (pages, path_collisions) = Page::load_many(&page_paths, &config);
+      let embedded_pages = pages.keys().map(|path| (path.clone(), OnceLock::new())).collect();
+
+      let source_paths = [
+        template_roots.concat().as_slice(),
+        snippet_roots.concat().as_slice(),
+        dataset_paths.as_slice(),
+        datarow_paths.as_slice(),
+        page_paths.as_slice(),
+      ]
+      .concat();
+      let build_hash = Site::hash_inputs(
+        [source_paths.as_slice(), locale_paths.as_slice(), content_paths.as_slice()].concat(),
+        &config,
+      );
+      info!("Build hash is {}", build_hash);
   
       Ok(Site {
         pages,
         templates,
         snippets,
+        macros,
         datasets,
+        row_diagnostics,
+        path_collisions,
+        strings,
         content_paths,
+        source_paths,
+        #[cfg(feature = "serve")]
+        input_dir,
         output_dir,
+        config,
+        build_hash,
+        embedded_pages,
+        resized_images: Mutex::new(BTreeSet::new()),
+        referenced_paths: Mutex::new(HashSet::new()),
+        plugins: Vec::new(),
       })
     }
-  
-    /// Renders the site and writes the output to disk.
-    pub fn render(&self) -> Result<()> {
-      info!("Copying assets");
-      self.copy_assets();
+
+    /// Registers `plugin` to render the custom oeuvre elements it handles, ahead of any
+    /// plugins registered after it.
+    pub fn register_plugin(&mut self, plugin: Box<dyn RenderPlugin>) {
+      self.plugins.push(plugin);
+    }
+
+    /// Renders the site and writes the output to disk. Assets whose copies are up to
+    /// date, according to the asset_compare option, are not copied again. With the clean
+    /// option, the output directory is emptied first.
+    pub fn render(&self) -> Result<BuildReport> {
+      self.create_output_dir()?;
+      if self.config.clean {
+        info!("Cleaning output directory {}", self.output_dir.display());
+        if let Err(err) = Site::clean_output_dir(&self.output_dir) {
+          bail!(
+            "Output directory {} could not be cleaned. Cause: {}",
+            self.output_dir.display(),
+            err
+          );
+        }
+      }
+      if self.config.asset_compare != AssetCompare::Hash {
+        let compare = self.config.asset_compare;
+        return self.render_to(&DirectorySink::incremental(&self.output_dir, compare, HashMap::new()));
+      }
+      let hashes = load_asset_hashes();
+      let sink = DirectorySink::incremental(&self.output_dir, AssetCompare::Hash, hashes);
+      let report = self.render_to(&sink)?;
+      if let Err(err) = save_asset_hashes(&sink.hashes()) {
+        warn!("Could not write {}: {}", ASSET_HASHES_PATH, err);
+      }
+      Ok(report)
+    }
+
+    /// Renders only the pages loaded from `source_path`, relative to the input directory,
+    /// and writes them to the output directory, without copying assets. If `template` is
+    /// given, the pages are rendered with that template instead of their own.
+    pub fn render_only(
+      &mut self,
+      source_path: &Path,
+      template: Option<&str>,
+    ) -> Result<BuildReport> {
+      self.pages.retain(|_, page| page.source_path() == source_path);
+      if self.pages.is_empty() {
+        bail!("No page was loaded from {}", source_path.display());
+      }
+      if let Some(template) = template {
+        for page in self.pages.values_mut() {
+          page.set_template(template);
+        }
+        // The template changes the output as much as the config does.
+        let mut hasher = ContentHasher::new();
+        hasher.write(self.build_hash.as_bytes());
+        hasher.write(&[0]);
+        hasher.write(template.as_bytes());
+        self.build_hash = hasher.finish();
+      }
+      self.create_output_dir()?;
+      info!("Writing pages");
+      let issue_count = Page::write_many(self, &DirectorySink::new(&self.output_dir));
+      Ok(BuildReport {
+        hash: self.build_hash.clone(),
+        issue_count,
+        largest_outputs: Vec::new(),
+        over_budget: Vec::new(),
+      })
+    }
+
+    /// Renders the site as `render` would, reporting render issues as usual, but only logs
+    /// the path of each file that would be written or copied, without touching the output.
+    pub fn dry_run(&self) -> Result<BuildReport> {
+      if self.config.clean {
+        info!("Would clean output directory {}", self.output_dir.display());
+      }
+      self.render_to(&DryRunSink::new(&self.output_dir))
+    }
+
+    /// Renders the site and writes the output to a zip archive or gzipped tarball at
+    /// `archive_path`, as its extension names, instead of the output directory.
+    pub fn write_archive(&self, archive_path: &Path) -> Result<BuildReport> {
+      let archive = ArchiveSink::default();
+      let report = self.render_to(&archive)?;
+      if let Err(err) = archive.finish(archive_path) {
+        bail!("{} could not be written. Cause: {}", archive_path.display(), err);
+      }
+      Ok(report)
+    }
+
+    /// Renders the site and writes the output to `sink`, followed by the build
+    /// manifest if one is configured.
+    pub fn render_to(&self, sink: &dyn OutputSink) -> Result<BuildReport> {
+      match &self.config.build_manifest {
+        Some(manifest_path) => {
+          let manifest = ManifestSink::new(sink);
+          let report = self.write_output(&manifest)?;
+          info!("Writing build manifest {}", manifest_path);
+          sink.write(Path::new(manifest_path), manifest.to_json().as_bytes())?;
+          Ok(report)
+        }
+        None => self.write_output(sink),
+      }
+    }
+
+    fn write_output(&self, sink: &dyn OutputSink) -> Result<BuildReport> {
+      let row_count = |code| self.row_diagnostics.iter().filter(|diag| diag.code == code).count();
+      let orphan_count = row_count(ORPHANED_ROW);
+      if self.config.strict && orphan_count > 0 {
+        bail!("Strict build failed with {} orphaned data row(s)", orphan_count);
+      }
+      let invalid_count = row_count(INVALID_ROW);
+      if self.config.strict && invalid_count > 0 {
+        bail!("Strict build failed with {} invalid data row(s)", invalid_count);
+      }
+      if self.config.strict && !self.path_collisions.is_empty() {
+        bail!(
+          "Strict build failed with {} output path collision(s)",
+          self.path_collisions.len()
+        );
+      }
+      let unknown_datasets = check_config_dataset_references(self);
+      if self.config.strict && !unknown_datasets.is_empty() {
+        bail!(
+          "Strict build failed with {} config reference(s) to unknown datasets",
+          unknown_datasets.len()
+        );
+      }
+      let sizes = SizeSink::new(sink);
+      let sink: &dyn OutputSink = &sizes;
+      // Pruning unused assets depends on which pages link to them, so pages are written first.
+      if !self.config.prune_unused_assets {
+        info!("Copying assets");
+        self.copy_assets(sink, &self.content_paths);
+      }
       info!("Writing pages");
-      Page::write_many(self);
+      let issue_count = Page::write_many(self, sink);
+      if self.config.prune_unused_assets {
+        info!("Copying referenced assets");
+        let used_paths = self.used_asset_paths();
+        info!(
+          "- Pruned {} unused asset(s)",
+          self.content_paths.len() - used_paths.len()
+        );
+        self.copy_assets(sink, &used_paths);
+      }
+      if !self.resized_images.lock().unwrap().is_empty() {
+        info!("Writing resized images");
+        write_resized_images(self, sink);
+      }
+      if !self.config.feeds.is_empty() {
+        info!("Writing feeds");
+        write_feeds(self, sink);
+      }
+      if self.config.github_pages.is_some() {
+        info!("Writing GitHub Pages files");
+        self.write_github_pages_files(sink)?;
+      }
+      let (largest_outputs, over_budget) = self.report_sizes(sizes.by_size());
+      if self.config.strict && issue_count > 0 {
+        bail!("Strict build failed with {} render issue(s)", issue_count);
+      }
   
+      Ok(BuildReport {
+        hash: self.build_hash.clone(),
+        issue_count,
+        largest_outputs,
+        over_budget,
+      })
+    }
+
+    /// Returns the largest of `sizes`, the path and size of each output file sorted
+    /// largest first, and the pages over the `max_page_kb` budget, whatever their extension.
+    fn report_sizes(&self, sizes: Vec<FileSize>) -> (Vec<FileSize>, Vec<FileSize>) {
+      let largest = sizes.iter().take(self.config.report_largest).cloned().collect();
+      let over_budget = match self.config.max_page_kb {
+        Some(max_page_kb) => {
+          let page_paths: HashSet<String> =
+            self.pages.values().map(|page| url_path(page.path())).collect();
+          sizes
+            .into_iter()
+            .filter(|(path, size)| page_paths.contains(path) && *size > max_page_kb * 1024)
+            .collect()
+        }
+        None => Vec::new(),
+      };
+      (largest, over_budget)
+    }
+
+    /// Returns the warning for a page that is over the `max_page_kb` budget.
+    pub fn budget_warning(&self, (path, size): &FileSize) -> String {
+      format!(
+        "Page {} is {} bytes, over the max_page_kb budget of {} KB",
+        path,
+        size,
+        self.config.max_page_kb.unwrap_or_default()
+      )
+    }
+
+    /// Writes the `.nojekyll` marker, and the `CNAME` file if a domain is configured,
+    /// to the root of the output.
+    fn write_github_pages_files(&self, sink: &dyn OutputSink) -> Result<()> {
+      sink.write(Path::new(".nojekyll"), b"")?;
+      let github_pages = self.config.github_pages.as_ref().unwrap();
+      if let Some(cname) = &github_pages.cname {
+        sink.write(Path::new("CNAME"), format!("{}\n", cname.trim()).as_bytes())?;
+      }
       Ok(())
     }
+
+    /// Hashes the paths and contents of the input files in `paths`, in sorted order
+    /// so that the result does not depend on the order in which they were found, along
+    /// with `config`, as it is in effect after any command line overrides. The config is
+    /// hashed with its tables sorted, and without the path it was loaded from, so that the
+    /// hash does not depend on where the site is.
+    fn hash_inputs(mut paths: Vec<PathBuf>, config: &SiteConfig) -> String {
+      paths.sort();
+      let mut hasher = ContentHasher::new();
+      match serde_json::to_value(config) {
+        Ok(mut config) => {
+          config.sort_all_objects();
+          hasher.write(config.to_string().as_bytes());
+          hasher.write(&[0]);
+        }
+        Err(err) => error!("The config could not be hashed. Cause: {}", err),
+      }
+      for path in paths.iter().filter(|path| path.is_file()) {
+        match fs::read(path) {
+          Ok(contents) => {
+            hasher.write(path.to_string_lossy().as_bytes());
+            hasher.write(&[0]);
+            hasher.write(&contents);
+            hasher.write(&[0]);
+          }
+          Err(err) => error!("{} could not be hashed. Cause: {}", path.display(), err),
+        }
+      }
+      hasher.finish()
+    }
+
+    /// Checks the loaded site for problems, such as references to datasets that do not exist.
+    pub fn check(&self) -> Vec<Diagnostic> {
+      check(self)
+    }
+
+    /// Renders the page with the output path `page_path` as a single self-contained document.
+    pub fn render_bundle(&self, page_path: &str) -> Result<String> {
+      match self.pages.get(Path::new(page_path.trim_start_matches('/'))) {
+        Some(page) => page.render_bundle(self),
+        None => bail!("Page {} does not exist", page_path),
+      }
+    }
+
+    /// Writes the bundle for the page with the output path `page_path` beside where the
+    /// page itself would be written, and returns the path of the bundle.
+    pub fn write_bundle(&self, page_path: &str) -> Result<PathBuf> {
+      let bundle = self.render_bundle(page_path)?;
+      let bundle_path = self
+        .output_dir
+        .join(Path::new(page_path.trim_start_matches('/')).with_extension("bundle.html"))
+        .clean();
+      fs::create_dir_all(bundle_path.parent().unwrap())?;
+      fs::write(&bundle_path, bundle)?;
+      Ok(bundle_path)
+    }
   
-    /// Expands all glob patterns into file paths and returns the result.
+    /// Expands `glob_patterns` within each of the `themes` directories and then within
+    /// the input directory, returning the paths found in each, in order of precedence.
+    fn expand_source_roots(
+      glob_patterns: &[String],
+      themes: &[String],
+      excluded_paths: &mut Vec<PathBuf>,
+    ) -> Vec<Vec<PathBuf>> {
+      let mut roots = Vec::<Vec<PathBuf>>::new();
+      for theme in themes {
+        let theme_patterns: Vec<String> = glob_patterns
+          .iter()
+          .map(|pattern| format!("{}/{}", theme.trim_end_matches('/'), pattern))
+          .collect();
+        roots.push(Site::expand_glob(&theme_patterns, excluded_paths));
+      }
+      roots.push(Site::expand_glob(glob_patterns, excluded_paths));
+      roots
+    }
+
+    /// Expands all glob patterns into file paths and returns the result. Matched
+    /// directories are replaced by the files within them, recursively.
     /// Paths listed in `excluded_paths` will be ignored, and the result of the
     /// expansion will be appended to the `excluded_paths` list.
     fn expand_glob(glob_patterns: &[String], excluded_paths: &mut Vec<PathBuf>) -> Vec<PathBuf> {
       let found_paths = glob_patterns
         .iter()
-        .filter_map(|pattern| match glob(pattern) {
+        .map(|pattern| match pattern == "**" || pattern.ends_with("/**") {
+          // A trailing `**` only matches directories, so the files below them are asked for too.
+          true => format!("{}/*", pattern),
+          false => pattern.clone(),
+        })
+        .filter_map(|pattern| match glob(&pattern) {
           Ok(paths) => Some(
             paths
               .filter_map(move |item| match item {
@@ -119,6 +509,10 @@ pub struct Site {
           }
         })
         .flatten()
+        .flat_map(|path| match path.is_dir() {
+          true => Site::walk_files(&path),
+          false => vec![path],
+        })
         .unique()
         .sorted();
       // Remove excluded paths from the results.
@@ -142,6 +536,73 @@ pub struct Site {
       found_paths
     }
   
+    /// Returns the paths of the files within `dir` and its subdirectories. A directory
+    /// that cannot be read is logged and skipped.
+    fn walk_files(dir: &Path) -> Vec<PathBuf> {
+      let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) => {
+          error!("Directory {} could not be read. Cause: {}", dir.display(), err);
+          return Vec::new();
+        }
+      };
+      let mut files = Vec::new();
+      for entry in entries {
+        match entry.map(|entry| entry.path()) {
+          Ok(path) if path.is_dir() => files.extend(Site::walk_files(&path)),
+          Ok(path) => files.push(path),
+          Err(err) => error!("Directory {} could not be read. Cause: {}", dir.display(), err),
+        }
+      }
+      files
+    }
+
+    /// Reads the gitignore-style patterns in the input directory's `.oeuvreignore` file,
+    /// if there is one, and converts them into glob patterns relative to the input directory.
+    /// Negated patterns are not supported and will be skipped.
+    fn read_ignore_file(input_dir: &Path) -> Vec<String> {
+      const IGNORE_FILE_NAME: &str = ".oeuvreignore";
+      let path = input_dir.join(IGNORE_FILE_NAME);
+      let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Vec::new(),
+        Err(err) => {
+          error!("{} could not be opened. Cause: {}", path.display(), err);
+          return Vec::new();
+        }
+      };
+      info!("Using ignore file {}", path.display());
+
+      let mut glob_patterns = Vec::<String>::new();
+      for line in text.lines() {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+          continue;
+        }
+        if line.starts_with('!') {
+          error!("Negated ignore pattern {} is not supported", line);
+          continue;
+        }
+        let line = line.strip_prefix('\\').unwrap_or(line);
+        // As in gitignore, a trailing slash only matches directories, and any
+        // other slash anchors the pattern to the input directory.
+        let (pattern, dir_only) = match line.strip_suffix('/') {
+          Some(pattern) => (pattern, true),
+          None => (line, false),
+        };
+        let pattern = match pattern.strip_prefix('/') {
+          Some(pattern) => pattern.to_string(),
+          None if pattern.contains('/') => pattern.to_string(),
+          None => format!("**/{}", pattern),
+        };
+        if !dir_only {
+          glob_patterns.push(pattern.clone());
+        }
+        glob_patterns.push(format!("{}/**/*", pattern));
+      }
+      glob_patterns
+    }
+
     fn find_input_dir(start_dir: &Path, dir: &str) -> Result<PathBuf> {
       let dir = start_dir.join(dir);
       let dir = dir.clean();
@@ -153,7 +614,7 @@ pub struct Site {
     }
   
     fn use_dir(dir: &Path) -> Result<()> {
-      match env::set_current_dir(&dir) {
+      match env::set_current_dir(dir) {
         Ok(_) => Ok(()),
         Err(_) => {
           bail!("Could not change to directory {}", dir.display())
@@ -161,40 +622,591 @@ pub struct Site {
       }
     }
   
-    fn create_output_dir(config_dir: &Path, output_dir: &str) -> Result<PathBuf> {
-      let output_dir = config_dir.join(output_dir).clean();
-  
-      if output_dir.is_dir() {
-        info!("Output directory found {}", output_dir.display());
-        Ok(output_dir)
-      } else {
-        info!("Creating output directory {}", output_dir.display());
-        match fs::create_dir_all(&output_dir) {
-          Err(err) => bail!(
-            "Output directory {} could not be created. Cause: {}",
+    /// Warns about or refuses an output directory that lies within the input directory,
+    /// according to `policy`, since the input globs may then pick up earlier builds' output.
+    fn check_output_dir(
+      input_dir: &Path,
+      output_dir: &Path,
+      policy: NestedOutputPolicy,
+    ) -> Result<()> {
+      if !output_dir.starts_with(input_dir) {
+        return Ok(());
+      }
+      let message = format!(
+        "Output directory {} is inside input directory {}. Move it elsewhere or make sure it is excluded",
+        output_dir.display(),
+        input_dir.display()
+      );
+      match policy {
+        NestedOutputPolicy::Allow => Ok(()),
+        NestedOutputPolicy::Warn => {
+          warn!("{}", message);
+          Ok(())
+        }
+        NestedOutputPolicy::Error => bail!("{}", message),
+      }
+    }
+
+    /// Refuses to clean `output_dir` if it is, or contains, the config directory or the
+    /// input directory, since emptying it would then delete more than earlier builds' output.
+    fn check_clean_dir(config_dir: &Path, input_dir: &Path, output_dir: &Path) -> Result<()> {
+      // Symbolic links are resolved where possible, so that a link can't disguise an ancestor.
+      let resolve = |path: &Path| fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf().clean());
+      let output_dir = resolve(output_dir);
+      for (name, dir) in [("config", config_dir), ("input", input_dir)] {
+        if resolve(dir).starts_with(&output_dir) {
+          bail!(
+            "Output directory {} contains the {} directory {}, so it will not be cleaned",
             output_dir.display(),
-            err
-          ),
-          Ok(_) => Ok(output_dir),
+            name,
+            dir.display()
+          );
         }
       }
+      Ok(())
+    }
+
+    /// Removes everything within `output_dir`, but not the directory itself. Symbolic
+    /// links are removed rather than followed.
+    fn clean_output_dir(output_dir: &Path) -> io::Result<()> {
+      for entry in fs::read_dir(output_dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+          fs::remove_dir_all(entry.path())?;
+        } else {
+          fs::remove_file(entry.path())?;
+        }
+      }
+      Ok(())
+    }
+
+    /// Creates the output directory, unless it already exists. This is left until the
+    /// output is written, so that a dry run leaves no trace.
+    fn create_output_dir(&self) -> Result<()> {
+      if self.output_dir.is_dir() {
+        return Ok(());
+      }
+      info!("Creating output directory {}", self.output_dir.display());
+      if let Err(err) = fs::create_dir_all(&self.output_dir) {
+        bail!(
+          "Output directory {} could not be created. Cause: {}",
+          self.output_dir.display(),
+          err
+        );
+      }
+      Ok(())
     }
   
-    /// Copies the site's static assets files to the output directory.
-    fn copy_assets(&self) {
-      for path in &self.content_paths {
-        info!("- Copying file {}", path.display());
-        let dir = path.parent().unwrap();
-        if let Err(err) = fs::create_dir_all(self.output_dir.join(dir)) {
-          error!("-- {}", err);
-          continue;
-        };
-        if let Err(err) = fs::copy(path, self.output_dir.join(path)) {
-          error!("-- {}", err);
-          continue;
-        };
-  
-        info!("-- Copied file {}", path.display());
+    /// Returns the paths of the assets that written pages link to, along with those
+    /// matching the keep_assets patterns.
+    fn used_asset_paths(&self) -> Vec<PathBuf> {
+      let keep_patterns: Vec<Pattern> = self
+        .config
+        .keep_assets
+        .iter()
+        .filter_map(|pattern| match Pattern::new(pattern) {
+          Ok(pattern) => Some(pattern),
+          Err(err) => {
+            error!("Invalid keep_assets pattern {}: {}", pattern, err);
+            None
+          }
+        })
+        .collect();
+      let referenced_paths = self.referenced_paths.lock().unwrap();
+      self
+        .content_paths
+        .iter()
+        .filter(|path| {
+          referenced_paths.contains(*path)
+            || keep_patterns.iter().any(|pattern| pattern.matches_path(path))
+        })
+        .cloned()
+        .collect()
+    }
+
+    /// Copies `paths`, some of the site's static assets, to `sink`, on a pool of `jobs`
+    /// threads when built with the parallel feature. Failures are logged in path order once
+    /// every file has been attempted.
+    fn copy_assets(&self, sink: &dyn OutputSink, paths: &[PathBuf]) {
+      let copy = |path: &PathBuf| sink.copy(path, path);
+      #[cfg(feature = "parallel")]
+      let results: Vec<_> =
+        in_thread_pool(self.config.jobs, || paths.par_iter().map(copy).collect());
+      #[cfg(not(feature = "parallel"))]
+      let results: Vec<_> = paths.iter().map(copy).collect();
+
+      let copied = results.iter().filter(|result| result.is_ok()).count();
+      let mut errors: Vec<(&Path, io::Error)> = paths
+        .iter()
+        .zip(results)
+        .filter_map(|(path, result)| result.err().map(|err| (path.as_path(), err)))
+        .collect();
+      errors.sort_by_key(|(path, _)| *path);
+      for (path, err) in &errors {
+        error!("-- Could not copy file {}: {}", path.display(), err);
       }
+      info!("- Copied {} file(s), {} failed", copied, errors.len());
     }
-  }
\ No newline at end of file
+  }
+
+/// The build cache file that records the content hash of each asset as of its last
+/// copy, relative to the input directory.
+const ASSET_HASHES_PATH: &str = ".oeuvre-cache/asset-hashes";
+
+/// Reads the asset hashes recorded by the last build, one `<hash> <path>` pair per
+/// line. A missing or unreadable cache is treated as empty.
+fn load_asset_hashes() -> HashMap<PathBuf, String> {
+  let text = fs::read_to_string(ASSET_HASHES_PATH).unwrap_or_default();
+  text
+    .lines()
+    .filter_map(|line| line.split_once(' '))
+    .map(|(hash, path)| (PathBuf::from(path), hash.to_string()))
+    .collect()
+}
+
+/// Records `hashes` in the build cache for the next build.
+fn save_asset_hashes(hashes: &HashMap<PathBuf, String>) -> io::Result<()> {
+  let mut lines: Vec<String> = hashes
+    .iter()
+    .map(|(path, hash)| format!("{} {}\n", hash, path.display()))
+    .collect();
+  lines.sort();
+  let path = Path::new(ASSET_HASHES_PATH);
+  fs::create_dir_all(path.parent().unwrap())?;
+  fs::write(path, lines.concat())
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::site::test_utils::TestSite;
+  use crate::site::SiteConfig;
+  use std::fs;
+  use std::path::Path;
+  use std::time::{Duration, SystemTime};
+
+  const TEMPLATE: &str = r#"<html oeuvre-name="main"><body><oeuvre-slot oeuvre-name="body"/></body></html>"#;
+  const PAGE: &str = r#"<oeuvre-page oeuvre-template="main"><p oeuvre-slot="body">Hi</p></oeuvre-page>"#;
+
+  #[test]
+  fn assets_are_copied_concurrently() {
+    let site = TestSite::new();
+    site.file("templates/main.xml", TEMPLATE).file("index.xml", PAGE);
+    for i in 0..200 {
+      site.file(&format!("assets/{}/{}.txt", i % 7, i), &format!("asset {}", i).repeat(i));
+    }
+    site.build("jobs = 4").unwrap();
+    for i in 0..200 {
+      assert_eq!(
+        site.read_output(&format!("assets/{}/{}.txt", i % 7, i)),
+        format!("asset {}", i).repeat(i)
+      );
+    }
+  }
+
+  #[test]
+  fn excluded_paths_are_left_out_of_every_input_category() {
+    let site = TestSite::new();
+    site
+      .file("templates/main.xml", TEMPLATE)
+      .file("index.xml", PAGE)
+      .file("posts/_draft.xml", PAGE)
+      .file("assets/notes.txt.swp", "swap")
+      .file("assets/site.css", "css");
+    site.build(r#"exclude = ["**/_*.xml", "**/*.swp"]"#).unwrap();
+    assert!(site.output_exists("index.xml"));
+    assert!(!site.output_exists("posts/_draft.xml"));
+    assert!(!site.output_exists("assets/notes.txt.swp"));
+    assert_eq!(site.read_output("assets/site.css"), "css");
+  }
+
+  #[test]
+  fn directories_matched_by_asset_globs_are_copied_recursively() {
+    let site = TestSite::new();
+    site
+      .file("templates/main.xml", TEMPLATE)
+      .file("index.xml", PAGE)
+      .file("static/a.txt", "a")
+      .file("static/css/b.css", "b")
+      .file("static/css/fonts/c.woff", "c")
+      .file("media/d.txt", "d");
+    site.build(r#"assets = ["static/**", "media"]"#).unwrap();
+    assert_eq!(site.read_output("static/a.txt"), "a");
+    assert_eq!(site.read_output("static/css/b.css"), "b");
+    assert_eq!(site.read_output("static/css/fonts/c.woff"), "c");
+    assert_eq!(site.read_output("media/d.txt"), "d");
+  }
+
+  #[test]
+  fn touched_but_unchanged_assets_are_not_recopied_when_comparing_hashes() {
+    let site = TestSite::new();
+    site.file("templates/main.xml", TEMPLATE).file("index.xml", PAGE).file("assets/a.txt", "a");
+    let touch = || {
+      let file = fs::File::options().write(true).open(site.path("assets/a.txt")).unwrap();
+      file.set_modified(SystemTime::now() + Duration::from_secs(60)).unwrap();
+    };
+    site.build(r#"asset_compare = "hash""#).unwrap();
+    // Marks the existing copy, so that copying it again would be noticed.
+    site.file("output/assets/a.txt", "copy");
+    touch();
+    site.build(r#"asset_compare = "hash""#).unwrap();
+    assert_eq!(site.read_output("assets/a.txt"), "copy");
+    site.build(r#"asset_compare = "mtime""#).unwrap();
+    assert_eq!(site.read_output("assets/a.txt"), "a");
+
+    site.file("assets/a.txt", "changed");
+    site.build(r#"asset_compare = "hash""#).unwrap();
+    assert_eq!(site.read_output("assets/a.txt"), "changed");
+  }
+
+  #[test]
+  fn unused_assets_are_pruned_when_configured() {
+    let site = TestSite::new();
+    site
+      .file("templates/main.xml", TEMPLATE)
+      .file(
+        "index.xml",
+        r#"<oeuvre-page oeuvre-template="main" oeuvre-path="index.html"><img oeuvre-slot="body" src="assets/used.png"/></oeuvre-page>"#,
+      )
+      .file(
+        "about.xml",
+        r#"<oeuvre-page oeuvre-template="main" oeuvre-path="about.html"><oeuvre-fragment oeuvre-slot="body"><img src="https://example.com/assets/my%20photo.png"/><a href="/assets/cv.pdf#top">CV</a></oeuvre-fragment></oeuvre-page>"#,
+      )
+      .file(
+        "big.xml",
+        &format!(
+          r#"<oeuvre-page oeuvre-template="main" oeuvre-path="big.html"><img oeuvre-slot="body" src="assets/orphan.png" alt="{}"/></oeuvre-page>"#,
+          "x".repeat(2000)
+        ),
+      )
+      .file("assets/used.png", "used")
+      .file("assets/unused.png", "unused")
+      .file("assets/my photo.png", "photo")
+      .file("assets/cv.pdf", "cv")
+      .file("assets/orphan.png", "orphan")
+      .file("assets/fonts/body.woff2", "font");
+    site
+      .build(concat!(
+        "base_url = \"https://example.com/\"\n",
+        "prune_unused_assets = true\n",
+        "max_output_bytes = 1000\n",
+        "keep_assets = [\"assets/fonts/**/*\"]"
+      ))
+      .unwrap();
+    assert!(site.output_exists("assets/used.png"));
+    assert!(!site.output_exists("assets/unused.png"));
+    assert!(site.output_exists("assets/my photo.png"));
+    assert!(site.output_exists("assets/cv.pdf"));
+    // A page that isn't written doesn't keep the assets it would have linked to.
+    assert!(!site.output_exists("big.html"));
+    assert!(!site.output_exists("assets/orphan.png"));
+    assert!(site.output_exists("assets/fonts/body.woff2"));
+  }
+
+  #[test]
+  fn largest_outputs_are_ranked_and_budget_is_checked() {
+    let site = TestSite::new();
+    site
+      .file("templates/main.xml", TEMPLATE)
+      .file(
+        "small.xml",
+        r#"<oeuvre-page oeuvre-template="main" oeuvre-path="small.html"><p oeuvre-slot="body">Hi</p></oeuvre-page>"#,
+      )
+      .file(
+        "big.xml",
+        &format!(
+          r#"<oeuvre-page oeuvre-template="main" oeuvre-path="big.html"><p oeuvre-slot="body">{}</p></oeuvre-page>"#,
+          "x".repeat(2000)
+        ),
+      )
+      .file(
+        "big-text.xml",
+        &format!(
+          r#"<oeuvre-page oeuvre-template="main" oeuvre-path="big.txt"><p oeuvre-slot="body">{}</p></oeuvre-page>"#,
+          "x".repeat(1500)
+        ),
+      )
+      .file("assets/logo.svg", &"y".repeat(500))
+      .file("assets/big.svg", &"y".repeat(3000));
+    let site = site.load("report_largest = 2\nmax_page_kb = 1").unwrap();
+    let report = site.render().unwrap();
+    assert_eq!(
+      report.largest_outputs,
+      vec![("assets/big.svg".to_string(), 3000), ("big.html".to_string(), 2050)]
+    );
+    // Pages are checked whatever their extension, and assets never are.
+    assert_eq!(
+      report.over_budget,
+      vec![("big.html".to_string(), 2050), ("big.txt".to_string(), 1550)]
+    );
+    assert_eq!(
+      site.budget_warning(&report.over_budget[0]),
+      "Page big.html is 2050 bytes, over the max_page_kb budget of 1 KB"
+    );
+  }
+
+  #[test]
+  fn local_snippet_overrides_theme_snippet() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        r#"<html oeuvre-name="main"><body><oeuvre-include oeuvre-snippet="button"/><oeuvre-include oeuvre-snippet="card"/></body></html>"#,
+      )
+      .file("index.xml", r#"<oeuvre-page oeuvre-template="main" oeuvre-path="index.html"/>"#)
+      .file("snippets/button.xml", r#"<oeuvre-snippet oeuvre-name="button"><b>Local</b></oeuvre-snippet>"#)
+      .file("theme/snippets/button.xml", r#"<oeuvre-snippet oeuvre-name="button"><i>Theme</i></oeuvre-snippet>"#)
+      .file("theme/snippets/card.xml", r#"<oeuvre-snippet oeuvre-name="card"><p>Card</p></oeuvre-snippet>"#);
+    site.build("themes = [\"theme\"]").unwrap();
+    assert_eq!(
+      site.read_output("index.html"),
+      "<!DOCTYPE html>\r\n<html><body><b>Local</b><p>Card</p></body></html>"
+    );
+    assert!(!site.output_exists("theme/snippets/button.html"));
+  }
+
+  #[test]
+  fn only_page_renders_with_overridden_template() {
+    let site = TestSite::new();
+    site
+      .file("templates/main.xml", TEMPLATE)
+      .file(
+        "templates/experimental.xml",
+        r#"<html oeuvre-name="experimental"><main class="wide"><oeuvre-slot oeuvre-name="body"/></main></html>"#,
+      )
+      .file("index.xml", PAGE)
+      .file("about.xml", PAGE);
+    let mut loaded = site.load("").unwrap();
+    let hash = loaded.build_hash.clone();
+    let report = loaded.render_only(Path::new("index.xml"), Some("experimental")).unwrap();
+    assert_ne!(report.hash, hash);
+    assert_eq!(
+      site.read_output("index.xml"),
+      concat!(
+        "<!DOCTYPE html>\r\n",
+        r#"<html><main class="wide"><p>Hi</p></main></html>"#
+      )
+    );
+    assert!(!site.output_exists("about.xml"));
+    assert!(loaded.render_only(Path::new("missing.xml"), None).is_err());
+  }
+
+  #[test]
+  fn nested_output_dir_follows_policy() {
+    let site = TestSite::new();
+    site
+      .file("src/templates/main.xml", TEMPLATE)
+      .file("src/index.xml", PAGE);
+
+    let nested = "dir = \"src\"\noutput_dir = \"src/output\"\n";
+    assert!(site.load(nested).is_ok());
+    assert!(site.load(&format!("{}nested_output_dir = \"allow\"", nested)).is_ok());
+    let err = site.load(&format!("{}nested_output_dir = \"error\"", nested)).err().unwrap();
+    assert!(err.to_string().starts_with("Output directory"));
+    assert!(site.load("dir = \"src\"\nnested_output_dir = \"error\"").is_ok());
+  }
+
+  #[test]
+  fn clean_build_removes_stale_output() {
+    let site = TestSite::new();
+    site
+      .file("src/templates/main.xml", TEMPLATE)
+      .file("src/index.xml", PAGE)
+      .file("output/old/removed.html", "stale");
+
+    site.build("dir = \"src\"").unwrap();
+    assert!(site.output_exists("old/removed.html"));
+    site.build("dir = \"src\"\nclean = true").unwrap();
+    assert!(!site.output_exists("old"));
+    assert!(site.output_exists("index.xml"));
+    assert!(site.path("src/index.xml").exists());
+  }
+
+  #[test]
+  fn dry_run_writes_nothing() {
+    let site = TestSite::new();
+    site
+      .file("src/templates/main.xml", TEMPLATE)
+      .file("src/index.xml", PAGE)
+      .file("src/assets/site.css", "css");
+
+    let loaded = site.load("dir = \"src\"\nclean = true").unwrap();
+    let report = loaded.dry_run().unwrap();
+    assert_eq!(report.issue_count, 0);
+    assert!(!site.path("output").exists());
+
+    site.file("src/broken.xml", r#"<oeuvre-page oeuvre-template="missing"/>"#);
+    let loaded = site.load("dir = \"src\"").unwrap();
+    assert!(loaded.dry_run().unwrap().issue_count > 0);
+    assert!(!site.path("output").exists());
+  }
+
+  #[test]
+  fn clean_build_refuses_to_empty_the_config_or_input_directory() {
+    let site = TestSite::new();
+    site
+      .file("src/templates/main.xml", TEMPLATE)
+      .file("src/index.xml", PAGE);
+
+    let configs = ["output_dir = \".\"", "output_dir = \"..\"", "dir = \"src\"\noutput_dir = \"src\""];
+    for config in configs {
+      let err = site.load(&format!("{}\nclean = true", config)).err().unwrap();
+      assert!(err.to_string().ends_with("so it will not be cleaned"), "{}", err);
+    }
+    assert!(site.path("src/index.xml").exists());
+    assert!(site.load("dir = \"src\"\noutput_dir = \"src/output\"\nclean = true").is_ok());
+  }
+
+  #[test]
+  fn ignore_file_excludes_matching_files() {
+    let site = TestSite::new();
+    site
+      .file("templates/main.xml", TEMPLATE)
+      .file("index.xml", PAGE)
+      .file("drafts/post.xml", PAGE)
+      .file("notes.xml", PAGE)
+      .file(".oeuvreignore", "# Drafts and scratch files\ndrafts/\nnotes.xml\n");
+    let site = site.load("").unwrap();
+
+    assert!(site.pages.contains_key(Path::new("index.xml")));
+    assert!(!site.pages.contains_key(Path::new("drafts/post.xml")));
+    assert!(!site.pages.contains_key(Path::new("notes.xml")));
+  }
+
+  #[test]
+  fn build_hash_is_stable_and_tracks_sources() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        r#"<html oeuvre-name="main"><meta name="build" content="{{ build.hash }}"/></html>"#,
+      )
+      .file("index.xml", r#"<oeuvre-page oeuvre-template="main" oeuvre-path="index.html"/>"#)
+      .file("assets/main.css", "body { margin: 0; }");
+    let first = site.build("").unwrap().build_hash;
+    assert_eq!(site.build("").unwrap().build_hash, first);
+    assert_eq!(
+      site.read_output("index.html"),
+      format!("<!DOCTYPE html>\r\n<html><meta content=\"{}\" name=\"build\"></html>", first)
+    );
+
+    site.file("assets/main.css", "body { margin: 1em; }");
+    assert_ne!(site.build("").unwrap().build_hash, first);
+  }
+
+  #[test]
+  fn build_hash_tracks_the_config_file() {
+    let site = TestSite::new();
+    site.file("index.xml", "<oeuvre-page/>").file("site.toml", "strict = false");
+    let hash = || {
+      let config = SiteConfig::load(&site.path("site.toml")).unwrap();
+      site.load_config(config).unwrap().build_hash
+    };
+    let first = hash();
+    assert_eq!(hash(), first);
+
+    site.file("site.toml", "strict = true");
+    assert_ne!(hash(), first);
+  }
+
+  #[test]
+  fn identical_builds_write_identical_output() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/main.xml",
+        concat!(
+          r#"<html oeuvre-name="main" lang="en" data-z="z" data-a="a"><head><title><oeuvre-slot oeuvre-name="title"/></title>"#,
+          r#"<script>const META = <oeuvre-json oeuvre-name="meta"/>; const POSTS = <oeuvre-json oeuvre-dataset="posts"/>;</script></head>"#,
+          r#"<body><oeuvre-nav oeuvre-dataset="posts" class="nav" id="nav"/><oeuvre-slot oeuvre-name="body"/></body></html>"#,
+        ),
+      )
+      .file(
+        "templates/tag.xml",
+        r#"<html oeuvre-name="tag"><body><oeuvre-slot oeuvre-name="items"/><oeuvre-slot oeuvre-name="pagination"/></body></html>"#,
+      )
+      .file(
+        "templates/listing.xml",
+        r#"<html oeuvre-name="listing"><body><oeuvre-slot oeuvre-name="listing"/></body></html>"#,
+      )
+      .file(
+        "snippets/note.xml",
+        r#"<oeuvre-snippet oeuvre-name="note"><aside><b><oeuvre-slot oeuvre-name="title"/></b><oeuvre-slot oeuvre-name="content"/></aside></oeuvre-snippet>"#,
+      )
+      .file(
+        "data/posts.xml",
+        concat!(
+          r#"<oeuvre-dataset oeuvre-name="posts"><oeuvre-field oeuvre-name="title" oeuvre-type="string"/>"#,
+          r#"<oeuvre-field oeuvre-name="label" oeuvre-type="string"/><oeuvre-field oeuvre-name="url" oeuvre-type="string"/>"#,
+          r#"<oeuvre-field oeuvre-name="order" oeuvre-type="string"/><oeuvre-field oeuvre-name="tags" oeuvre-type="string"/></oeuvre-dataset>"#,
+        ),
+      )
+      .file("assets/main.css", "body { margin: 0; }");
+    for i in 0..12 {
+      site
+        .file(
+          &format!("data/posts/{}.xml", i),
+          &format!(
+            concat!(
+              r#"<oeuvre-datarow oeuvre-dataset="posts"><oeuvre-value oeuvre-name="title">Post {0}</oeuvre-value>"#,
+              r#"<oeuvre-value oeuvre-name="label">Post {0}</oeuvre-value><oeuvre-value oeuvre-name="url">/blog/{0}.html</oeuvre-value>"#,
+              r#"<oeuvre-value oeuvre-name="order">{0}</oeuvre-value><oeuvre-value oeuvre-name="tags">t{1}, t{2}</oeuvre-value></oeuvre-datarow>"#,
+            ),
+            i,
+            i % 3,
+            i % 5
+          ),
+        )
+        .file(
+          &format!("blog/{}.xml", i),
+          &format!(
+            concat!(
+              r#"<oeuvre-page oeuvre-template="main" oeuvre-path="{0}.html"><oeuvre-fragment oeuvre-slot="title">Post {0}</oeuvre-fragment>"#,
+              r#"<oeuvre-fragment oeuvre-slot-scope="meta"><oeuvre-fragment oeuvre-slot="b">{0}</oeuvre-fragment><oeuvre-fragment oeuvre-slot="a">x</oeuvre-fragment></oeuvre-fragment>"#,
+              r#"<oeuvre-fragment oeuvre-slot="body"><note title="N{0}" data-k="k" class="c">Body <a href="../assets/main.css">css</a></note></oeuvre-fragment></oeuvre-page>"#,
+            ),
+            i
+          ),
+        );
+    }
+    let config = concat!(
+      "dir_index = \"listing\"\n",
+      "build_manifest = \"manifest.json\"\n",
+      "source_map = true\n",
+      "elements = { note = { snippet = \"note\" } }\n",
+      "[[feeds]]\n",
+      "dataset = \"posts\"\n",
+      "path = \"feed.json\"\n",
+      "title = \"Posts\"\n",
+      "[[archives]]\n",
+      "dataset = \"posts\"\n",
+      "group_by = \"tags\"\n",
+      "template = \"tag\"\n",
+      "path = \"tags\"\n",
+      "per_page = 2\n",
+    );
+    site.build(config).unwrap();
+    let first = site.read_output_tree();
+    assert!(first.len() > 40);
+    fs::remove_dir_all(site.path("output")).unwrap();
+    site.build(config).unwrap();
+    let second = site.read_output_tree();
+    assert_eq!(first.keys().collect::<Vec<_>>(), second.keys().collect::<Vec<_>>());
+    for (path, contents) in &first {
+      assert!(&second[path] == contents, "{} differs between builds", path.display());
+    }
+  }
+
+  #[test]
+  fn github_pages_files_are_written_when_configured() {
+    let site = TestSite::new();
+    site
+      .file("templates/main.xml", TEMPLATE)
+      .file("index.xml", PAGE);
+    site.build("").unwrap();
+    assert!(!site.output_exists(".nojekyll"));
+
+    site.build("github_pages = { cname = \"example.com\" }").unwrap();
+    assert_eq!(site.read_output(".nojekyll"), "");
+    assert_eq!(site.read_output("CNAME"), "example.com\n");
+  }
+}
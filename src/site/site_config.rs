@@ -1,12 +1,16 @@
 
 
 use anyhow::{bail, Result};
-use serde_derive::Deserialize;
+use itertools::Itertools;
+use serde::Serialize;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::thread;
 
 /// A configuration object used for deserlializing corresponding toml config files.
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
 pub struct SiteConfig {
   #[serde(default = "SiteConfig::default_dir")]
   pub dir: String,
@@ -26,6 +30,205 @@ pub struct SiteConfig {
   pub assets: Vec<String>,
   #[serde(default = "SiteConfig::default_pages")]
   pub pages: Vec<String>,
+  #[serde(default = "SiteConfig::default_xml_declaration")]
+  pub xml_declaration: bool,
+  #[serde(default = "SiteConfig::default_doctype")]
+  pub doctype: String,
+  #[serde(default = "SiteConfig::default_output_extension")]
+  pub output_extension: Option<String>,
+  #[serde(default = "SiteConfig::default_max_file_bytes")]
+  pub max_file_bytes: Option<u64>,
+  #[serde(default = "SiteConfig::default_source_comments")]
+  pub source_comments: bool,
+  #[serde(default = "SiteConfig::default_nested_output_dir")]
+  pub nested_output_dir: NestedOutputPolicy,
+  #[serde(default = "SiteConfig::default_clean")]
+  pub clean: bool,
+  #[serde(default = "SiteConfig::default_bundle_max_image_bytes")]
+  pub bundle_max_image_bytes: u64,
+  #[serde(default = "SiteConfig::default_strict")]
+  pub strict: bool,
+  #[serde(default = "SiteConfig::default_sanitize")]
+  pub sanitize: bool,
+  #[serde(default = "SiteConfig::default_github_pages")]
+  pub github_pages: Option<GitHubPagesConfig>,
+  #[serde(default = "SiteConfig::default_placeholder_on_missing_template")]
+  pub placeholder_on_missing_template: bool,
+  #[serde(default = "SiteConfig::default_globals")]
+  pub globals: HashMap<String, String>,
+  #[serde(default = "SiteConfig::default_base_url")]
+  pub base_url: String,
+  #[serde(default = "SiteConfig::default_jobs")]
+  pub jobs: usize,
+  #[serde(default = "SiteConfig::default_canonical")]
+  pub canonical: bool,
+  #[serde(default = "SiteConfig::default_debug_slots")]
+  pub debug_slots: bool,
+  #[serde(default = "SiteConfig::default_build_manifest")]
+  pub build_manifest: Option<String>,
+  #[serde(default = "SiteConfig::default_themes")]
+  pub themes: Vec<String>,
+  #[serde(default = "SiteConfig::default_feeds")]
+  pub feeds: Vec<FeedConfig>,
+  #[serde(default = "SiteConfig::default_max_output_bytes")]
+  pub max_output_bytes: Option<u64>,
+  #[serde(default = "SiteConfig::default_locales")]
+  pub locales: HashMap<String, String>,
+  #[serde(default = "SiteConfig::default_locale")]
+  pub locale: Option<String>,
+  #[serde(default = "SiteConfig::default_locale_dirs")]
+  pub locale_dirs: bool,
+  #[serde(default = "SiteConfig::default_default_locale")]
+  pub default_locale: Option<String>,
+  #[serde(default = "SiteConfig::default_hash_pages")]
+  pub hash_pages: bool,
+  #[serde(default = "SiteConfig::default_source_map")]
+  pub source_map: bool,
+  #[serde(default = "SiteConfig::default_trim_control_whitespace")]
+  pub trim_control_whitespace: bool,
+  #[serde(default = "SiteConfig::default_attr_quotes")]
+  pub attr_quotes: AttrQuoteStyle,
+  #[serde(default = "SiteConfig::default_output_format")]
+  pub output_format: OutputFormat,
+  #[serde(default = "SiteConfig::default_dir_index")]
+  pub dir_index: Option<String>,
+  #[serde(default = "SiteConfig::default_allow_empty")]
+  pub allow_empty: bool,
+  #[serde(default = "SiteConfig::default_prune_unused_assets")]
+  pub prune_unused_assets: bool,
+  #[serde(default = "SiteConfig::default_keep_assets")]
+  pub keep_assets: Vec<String>,
+  #[serde(default = "SiteConfig::default_elements")]
+  pub elements: HashMap<String, ShorthandConfig>,
+  #[serde(default = "SiteConfig::default_report_largest")]
+  pub report_largest: usize,
+  #[serde(default = "SiteConfig::default_max_page_kb")]
+  pub max_page_kb: Option<u64>,
+  #[serde(default = "SiteConfig::default_transforms")]
+  pub transforms: HashMap<String, Vec<TransformConfig>>,
+  #[serde(default = "SiteConfig::default_asset_compare")]
+  pub asset_compare: AssetCompare,
+  #[serde(default = "SiteConfig::default_archives")]
+  pub archives: Vec<ArchiveConfig>,
+  #[serde(default = "SiteConfig::default_max_include_depth")]
+  pub max_include_depth: usize,
+  /// The file this config was loaded from, if any. The build hash leaves it out, since it
+  /// depends on where the site is.
+  #[serde(skip)]
+  pub path: Option<PathBuf>,
+}
+
+/// Options for deploying to GitHub Pages. When present, a `.nojekyll` marker is
+/// written to the output directory, along with a `CNAME` file if a domain is given.
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct GitHubPagesConfig {
+  pub cname: Option<String>,
+}
+
+/// A custom element that authors can use as shorthand. It expands to the named
+/// snippet or, failing that, is renamed to `tag`. Either way, `class` is added to the
+/// classes of the elements it expands to.
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct ShorthandConfig {
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub snippet: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub tag: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub class: Option<String>,
+}
+
+/// A step that sets `field` on each row of a dataset as it is loaded, to the result of
+/// interpolating `value` with the row's fields. With `if_missing`, rows that already
+/// have the field are left as they are.
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct TransformConfig {
+  pub field: String,
+  pub value: String,
+  #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+  pub if_missing: bool,
+}
+
+/// A feed listing the rows of a dataset, written to `path` in the output.
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct FeedConfig {
+  pub dataset: String,
+  pub path: String,
+  pub title: String,
+  #[serde(default = "FeedConfig::default_format")]
+  pub format: FeedFormat,
+}
+
+impl FeedConfig {
+  fn default_format() -> FeedFormat {
+    FeedFormat::Json
+  }
+}
+
+/// Paginated archives of a dataset's rows, grouped by each distinct value of the
+/// comma-separated `group_by` field, with pages written within `path`.
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct ArchiveConfig {
+  pub dataset: String,
+  pub group_by: String,
+  pub template: String,
+  pub path: String,
+  #[serde(default = "ArchiveConfig::default_per_page")]
+  pub per_page: usize,
+}
+
+impl ArchiveConfig {
+  fn default_per_page() -> usize {
+    10
+  }
+}
+
+/// The document formats that feeds can be written in.
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum FeedFormat {
+  /// JSON Feed 1.1.
+  Json,
+}
+
+/// How to respond when the output directory lies within the input directory.
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum NestedOutputPolicy {
+  Allow,
+  Warn,
+  Error,
+}
+
+/// The character that delimits attribute values in the output.
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum AttrQuoteStyle {
+  Double,
+  Single,
+}
+
+/// How the whitespace of HTML pages is laid out in the output.
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+  Preserve,
+  Pretty,
+  Minified,
+}
+
+/// How an asset already in the output directory is found to be up to date, so that
+/// it isn't copied again.
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum AssetCompare {
+  /// Every asset is copied on every build.
+  Always,
+  /// An asset is up to date if its copy was modified no earlier than it was.
+  Mtime,
+  /// An asset is up to date if its content hash matches the one recorded in the
+  /// build cache when it was last copied.
+  Hash,
 }
 
 impl SiteConfig {
@@ -51,21 +254,412 @@ impl SiteConfig {
     ["data/*/**/*.xml".to_string()].to_vec()
   }
   fn default_assets() -> Vec<String> {
-    ["assets/**".to_string()].to_vec()
+    ["assets/**/*".to_string()].to_vec()
   }
   fn default_pages() -> Vec<String> {
     ["**/*.xml".to_string()].to_vec()
   }
+  fn default_xml_declaration() -> bool {
+    false
+  }
+  fn default_doctype() -> String {
+    "<!DOCTYPE html>\r\n".to_string()
+  }
+  fn default_output_extension() -> Option<String> {
+    None
+  }
+  fn default_max_file_bytes() -> Option<u64> {
+    None
+  }
+  fn default_source_comments() -> bool {
+    false
+  }
+  fn default_nested_output_dir() -> NestedOutputPolicy {
+    NestedOutputPolicy::Warn
+  }
+  fn default_clean() -> bool {
+    false
+  }
+  fn default_bundle_max_image_bytes() -> u64 {
+    32768
+  }
+  fn default_strict() -> bool {
+    false
+  }
+  fn default_sanitize() -> bool {
+    false
+  }
+  fn default_github_pages() -> Option<GitHubPagesConfig> {
+    None
+  }
+  fn default_placeholder_on_missing_template() -> bool {
+    false
+  }
+  fn default_globals() -> HashMap<String, String> {
+    HashMap::new()
+  }
+  fn default_base_url() -> String {
+    String::new()
+  }
+  fn default_jobs() -> usize {
+    thread::available_parallelism().map_or(1, |jobs| jobs.get())
+  }
+  fn default_canonical() -> bool {
+    false
+  }
+  fn default_debug_slots() -> bool {
+    false
+  }
+  fn default_build_manifest() -> Option<String> {
+    None
+  }
+  fn default_themes() -> Vec<String> {
+    Vec::new()
+  }
+  fn default_feeds() -> Vec<FeedConfig> {
+    Vec::new()
+  }
+  fn default_max_output_bytes() -> Option<u64> {
+    None
+  }
+  fn default_locales() -> HashMap<String, String> {
+    HashMap::new()
+  }
+  fn default_locale() -> Option<String> {
+    None
+  }
+  fn default_locale_dirs() -> bool {
+    false
+  }
+  fn default_default_locale() -> Option<String> {
+    None
+  }
+  fn default_hash_pages() -> bool {
+    false
+  }
+  fn default_source_map() -> bool {
+    false
+  }
+  fn default_trim_control_whitespace() -> bool {
+    false
+  }
+  fn default_attr_quotes() -> AttrQuoteStyle {
+    AttrQuoteStyle::Double
+  }
+  fn default_output_format() -> OutputFormat {
+    OutputFormat::Preserve
+  }
+  fn default_dir_index() -> Option<String> {
+    None
+  }
+  fn default_allow_empty() -> bool {
+    false
+  }
+  fn default_prune_unused_assets() -> bool {
+    false
+  }
+  fn default_keep_assets() -> Vec<String> {
+    Vec::new()
+  }
+  fn default_elements() -> HashMap<String, ShorthandConfig> {
+    HashMap::new()
+  }
+  fn default_report_largest() -> usize {
+    5
+  }
+  fn default_max_page_kb() -> Option<u64> {
+    None
+  }
+  fn default_transforms() -> HashMap<String, Vec<TransformConfig>> {
+    HashMap::new()
+  }
+  fn default_asset_compare() -> AssetCompare {
+    AssetCompare::Always
+  }
+  fn default_archives() -> Vec<ArchiveConfig> {
+    Vec::new()
+  }
+  fn default_max_include_depth() -> usize {
+    64
+  }
+
+  /// Returns a toml document that sets every option to its default value, with a comment
+  /// describing each. Options that are unset or machine-dependent by default are commented out.
+  pub fn default_toml() -> String {
+    let mut lines = Vec::<String>::new();
+    let mut option = |comment: &str, name: &str, value: Option<String>| {
+      lines.push(format!("# {}", comment));
+      match value {
+        Some(value) => lines.push(format!("{} = {}", name, value)),
+        None => lines.push(format!("# {} = ...", name)),
+      }
+    };
+    option(
+      "The input directory, relative to this file's path.",
+      "dir",
+      Some(toml_value(SiteConfig::default_dir())),
+    );
+    option(
+      "The output directory, relative to the input directory.",
+      "output_dir",
+      Some(toml_value(SiteConfig::default_output_dir())),
+    );
+    option(
+      "Glob patterns for files in the input directory that should be ignored.",
+      "exclude",
+      Some(toml_value(SiteConfig::default_exclude())),
+    );
+    option(
+      "Glob patterns for template files.",
+      "templates",
+      Some(toml_value(SiteConfig::default_templates())),
+    );
+    option(
+      "Glob patterns for snippet files.",
+      "snippets",
+      Some(toml_value(SiteConfig::default_snippets())),
+    );
+    option(
+      "Glob patterns for dataset files.",
+      "datasets",
+      Some(toml_value(SiteConfig::default_datasets())),
+    );
+    option(
+      "Glob patterns for data row files.",
+      "datarows",
+      Some(toml_value(SiteConfig::default_datarows())),
+    );
+    option(
+      "Glob patterns for static assets files. Matched directories, such as those matched by `assets/**`, are copied with everything in them.",
+      "assets",
+      Some(toml_value(SiteConfig::default_assets())),
+    );
+    option(
+      "Glob patterns for page files.",
+      "pages",
+      Some(toml_value(SiteConfig::default_pages())),
+    );
+    option(
+      "Whether to keep the XML declaration from each page's template.",
+      "xml_declaration",
+      Some(toml_value(SiteConfig::default_xml_declaration())),
+    );
+    option(
+      "The doctype written verbatim before the root element of each HTML page, line break included. Empty for none.",
+      "doctype",
+      Some(toml_value(SiteConfig::default_doctype())),
+    );
+    option(
+      "The extension given to an `oeuvre-path` that has none, such as \"html\" for `/about`. An `oeuvre-format` element's path gets the format's own extension instead, unless the format is HTML. Unset by default.",
+      "output_extension",
+      SiteConfig::default_output_extension().map(toml_value),
+    );
+    option(
+      "The size limit, in bytes, for any template, snippet, dataset or page file. Unlimited by default.",
+      "max_file_bytes",
+      SiteConfig::default_max_file_bytes().map(toml_value),
+    );
+    option(
+      "Whether to begin each page with a comment naming its source file and template.",
+      "source_comments",
+      Some(toml_value(SiteConfig::default_source_comments())),
+    );
+    option(
+      "What to do when the output directory is inside the input directory: \"allow\", \"warn\" or \"error\".",
+      "nested_output_dir",
+      Some(toml_value(SiteConfig::default_nested_output_dir())),
+    );
+    option(
+      "Whether to remove everything in the output directory before writing a build, so that files from deleted pages don't linger.",
+      "clean",
+      Some(toml_value(SiteConfig::default_clean())),
+    );
+    option(
+      "The size limit, in bytes, for images inlined into a bundled page. Larger images stay linked.",
+      "bundle_max_image_bytes",
+      Some(toml_value(SiteConfig::default_bundle_max_image_bytes())),
+    );
+    option(
+      "Whether to fail the build if any problems are found while rendering pages, if any data rows belong to a dataset that does not exist or do not fit its fields, if the config names a dataset that does not exist, or if two pages have the same output path.",
+      "strict",
+      Some(toml_value(SiteConfig::default_strict())),
+    );
+    option(
+      "Whether to strip unsafe markup from content included with oeuvre-raw or oeuvre-include-raw, and from dataset fragment fields.",
+      "sanitize",
+      Some(toml_value(SiteConfig::default_sanitize())),
+    );
+    option(
+      "The files needed to deploy to GitHub Pages, e.g. { cname = \"example.com\" }. Not written by default.",
+      "github_pages",
+      SiteConfig::default_github_pages().map(|_| "{}".to_string()),
+    );
+    option(
+      "Whether a page whose template does not exist is written as a placeholder, rather than skipped.",
+      "placeholder_on_missing_template",
+      Some(toml_value(SiteConfig::default_placeholder_on_missing_template())),
+    );
+    option(
+      "Named strings that fill any slot a page leaves empty and can be interpolated with `{{ name }}`.",
+      "globals",
+      Some(toml_value(SiteConfig::default_globals())),
+    );
+    option(
+      "The absolute url of the site's root, e.g. \"https://example.com/\", used by `{{ page.url }}`.",
+      "base_url",
+      Some(toml_value(SiteConfig::default_base_url())),
+    );
+    option(
+      "When built with the parallel feature, the number of files to copy and pages to write at once. Defaults to the number of available cores.",
+      "jobs",
+      None,
+    );
+    option(
+      "Whether to add a canonical link with its absolute url to each HTML page that lacks one.",
+      "canonical",
+      Some(toml_value(SiteConfig::default_canonical())),
+    );
+    option(
+      "Whether to mark slots and includes that nothing fills with a comment, for debugging layouts.",
+      "debug_slots",
+      Some(toml_value(SiteConfig::default_debug_slots())),
+    );
+    option(
+      "The output path of a JSON manifest listing each output file's size and hash. Not written by default.",
+      "build_manifest",
+      SiteConfig::default_build_manifest().map(toml_value),
+    );
+    option(
+      "Directories, relative to the input directory, to load templates and snippets from as well, lowest precedence first.",
+      "themes",
+      Some(toml_value(SiteConfig::default_themes())),
+    );
+    option(
+      "Feeds listing the rows of a dataset, e.g. [{ dataset = \"posts\", path = \"feed.json\", title = \"Posts\", format = \"json\" }].",
+      "feeds",
+      Some(toml_value(SiteConfig::default_feeds())),
+    );
+    option(
+      "The size limit, in bytes, for any rendered page, which is skipped if it exceeds it. Unlimited by default.",
+      "max_output_bytes",
+      SiteConfig::default_max_output_bytes().map(toml_value),
+    );
+    option(
+      "String tables for oeuvre-t elements, e.g. { en = \"locales/en.toml\" }, keyed by locale.",
+      "locales",
+      Some(toml_value(SiteConfig::default_locales())),
+    );
+    option(
+      "The locale whose strings oeuvre-t elements use on pages that don't name one. Unset by default.",
+      "locale",
+      SiteConfig::default_locale().map(toml_value),
+    );
+    option(
+      "Whether to render every page once for each locale, within a directory named after the locale.",
+      "locale_dirs",
+      Some(toml_value(SiteConfig::default_locale_dirs())),
+    );
+    option(
+      "With locale_dirs, the locale whose pages are written at the root instead. Unset by default.",
+      "default_locale",
+      SiteConfig::default_default_locale().map(toml_value),
+    );
+    option(
+      "Whether to write HTML pages under content-hashed names, with redirect stubs and a routes.json manifest.",
+      "hash_pages",
+      Some(toml_value(SiteConfig::default_hash_pages())),
+    );
+    option(
+      "Whether to write a JSON map beside each page naming the source file of each of its sections.",
+      "source_map",
+      Some(toml_value(SiteConfig::default_source_map())),
+    );
+    option(
+      "Whether to drop whitespace-only text immediately before or after oeuvre elements in templates.",
+      "trim_control_whitespace",
+      Some(toml_value(SiteConfig::default_trim_control_whitespace())),
+    );
+    option(
+      "The quote character that delimits attribute values in the output: \"double\" or \"single\". The same character is escaped within values.",
+      "attr_quotes",
+      Some(toml_value(SiteConfig::default_attr_quotes())),
+    );
+    option(
+      "How the whitespace of HTML pages is laid out: \"preserve\" to keep it as rendered, \"pretty\" to indent nested blocks, or \"minified\" to collapse it. The contents of pre, textarea, script and style elements are always kept as they are.",
+      "output_format",
+      Some(toml_value(SiteConfig::default_output_format())),
+    );
+    option(
+      "The template of the listing page written to each output directory that has pages but no index.html. Unset by default.",
+      "dir_index",
+      SiteConfig::default_dir_index().map(toml_value),
+    );
+    option(
+      "Whether to write pages that render to an empty document, rather than reporting them.",
+      "allow_empty",
+      Some(toml_value(SiteConfig::default_allow_empty())),
+    );
+    option(
+      "Whether to copy only the assets that pages link to with href, src or srcset.",
+      "prune_unused_assets",
+      Some(toml_value(SiteConfig::default_prune_unused_assets())),
+    );
+    option(
+      "With prune_unused_assets, glob patterns for assets to copy even if no page links to them.",
+      "keep_assets",
+      Some(toml_value(SiteConfig::default_keep_assets())),
+    );
+    option(
+      "Custom elements that expand to a snippet or to another element with a class, e.g. { lead = { tag = \"p\", class = \"lead\" } }.",
+      "elements",
+      Some(toml_value(SiteConfig::default_elements())),
+    );
+    option(
+      "The number of largest output files to list after each build.",
+      "report_largest",
+      Some(toml_value(SiteConfig::default_report_largest())),
+    );
+    option(
+      "A size budget, in kilobytes, for pages, whatever their extension. Larger pages are reported with a warning. Unset by default.",
+      "max_page_kb",
+      SiteConfig::default_max_page_kb().map(toml_value),
+    );
+    option(
+      "Steps that set a field on each row of a dataset as it is loaded, keyed by dataset, e.g. { posts = [{ field = \"year\", value = \"{{ year(date) }}\" }] }.",
+      "transforms",
+      Some(toml_value(SiteConfig::default_transforms())),
+    );
+    option(
+      "How to tell that an asset's existing copy is up to date, so it isn't copied again: \"always\" copies, \"mtime\" or \"hash\".",
+      "asset_compare",
+      Some(toml_value(SiteConfig::default_asset_compare())),
+    );
+    option(
+      "Paginated archives of a dataset's rows for each value of a field, e.g. [{ dataset = \"posts\", group_by = \"tags\", template = \"tag\", path = \"tags\", per_page = 10 }].",
+      "archives",
+      Some(toml_value(SiteConfig::default_archives())),
+    );
+    option(
+      "How deeply snippets may include other snippets before an include is skipped as a problem.",
+      "max_include_depth",
+      Some(toml_value(SiteConfig::default_max_include_depth())),
+    );
+    lines.push(String::new());
+    lines.join("\n")
+  }
 
   /// Deserializes the toml file at the given path into a SiteConfig,
   /// or returns an error if the file could not be read and parsed.
   pub fn load(path: &Path) -> Result<SiteConfig> {
-    let config_file = match fs::read_to_string(&path) {
+    let config_file = match fs::read_to_string(path) {
       Ok(text) => text,
       Err(err) => bail!("{} could not be opened. Cause: {}", path.display(), err),
     };
     match toml::from_str::<SiteConfig>(&config_file) {
-      Ok(el) => Ok(el),
+      Ok(config) => Ok(SiteConfig {
+        path: Some(path.to_path_buf()),
+        ..config
+      }),
       Err(err) => bail!(
         "{} could not be parsed as a config file. Cause: {}",
         path.display(),
@@ -74,3 +668,80 @@ impl SiteConfig {
     }
   }
 }
+
+/// Formats `value` as it would appear on the right-hand side of a toml key,
+/// writing tables inline.
+fn toml_value<T: Serialize>(value: T) -> String {
+  fn inline(value: &toml::Value) -> String {
+    match value {
+      toml::Value::Table(table) if table.is_empty() => "{}".to_string(),
+      toml::Value::Table(table) => format!(
+        "{{ {} }}",
+        table.iter().map(|(key, value)| format!("{} = {}", key, inline(value))).join(", ")
+      ),
+      value => value.to_string(),
+    }
+  }
+  inline(&toml::Value::try_from(value).unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde::de::{self, Deserialize, Visitor};
+
+  /// A deserializer that fails as soon as it is asked for a struct, after noting the
+  /// names of the struct's fields, which serde derives from its declaration.
+  struct FieldNames<'a>(&'a mut &'static [&'static str]);
+
+  impl<'de> de::Deserializer<'de> for FieldNames<'_> {
+    type Error = de::value::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Self::Error> {
+      Err(de::Error::custom("expected a struct"))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+      self,
+      _: &'static str,
+      fields: &'static [&'static str],
+      _: V,
+    ) -> Result<V::Value, Self::Error> {
+      *self.0 = fields;
+      Err(de::Error::custom("only field names are read"))
+    }
+
+    serde::forward_to_deserialize_any! {
+      bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+      option unit unit_struct newtype_struct seq tuple tuple_struct map enum identifier
+      ignored_any
+    }
+  }
+
+  #[test]
+  fn default_toml_lists_every_field() {
+    let mut field_names: &[&str] = &[];
+    assert!(SiteConfig::deserialize(FieldNames(&mut field_names)).is_err());
+    assert!(!field_names.is_empty());
+    let toml = SiteConfig::default_toml();
+    let missing: Vec<&str> = field_names
+      .iter()
+      .copied()
+      .filter(|name| {
+        !toml.lines().any(|line| {
+          let line = line.trim_start_matches("# ");
+          line.strip_prefix(name).is_some_and(|rest| rest.starts_with(" = "))
+        })
+      })
+      .collect();
+    assert_eq!(missing, Vec::<&str>::new());
+  }
+
+  #[test]
+  fn default_toml_round_trips_to_defaults() {
+    assert_eq!(
+      toml::from_str::<SiteConfig>(&SiteConfig::default_toml()).unwrap(),
+      toml::from_str::<SiteConfig>("").unwrap()
+    );
+  }
+}
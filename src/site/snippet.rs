@@ -1,8 +1,8 @@
-use super::load_xml;
+use super::{load_xml, validate_root, SiteConfig};
 use crate::minidom::Element;
-use crate::PathBuf;
+use std::path::PathBuf;
 use anyhow::{bail, Result};
-use log::{error, info};
+use log::{debug, error, info};
 use std::collections::HashMap;
 use std::path::Path;
 
@@ -10,17 +10,13 @@ use std::path::Path;
 pub struct Snippet {
   pub element: Element,
   pub name: String,
+  pub path: PathBuf,
 }
 
 impl Snippet {
-  fn new(element: Element, snippets: &HashMap<String, Snippet>) -> Result<Snippet> {
-    let name = match element.attr("oeuvre-name") {
-      None => {
-        bail!("Snippet requires a root element with an oeuvre-name attribute");
-      }
-      Some(attr_value) => attr_value,
-    }
-    .to_string();
+  fn new(element: Element, path: &Path, snippets: &HashMap<String, Snippet>) -> Result<Snippet> {
+    validate_root(&element, "snippet", Some("oeuvre-snippet"), "oeuvre-name")?;
+    let name = element.attr("oeuvre-name").unwrap().to_string();
 
     if snippets.contains_key(&name) {
       bail!(
@@ -29,33 +25,53 @@ impl Snippet {
     );
     }
 
-    Ok(Snippet { element, name })
+    Ok(Snippet {
+      element,
+      name,
+      path: path.to_path_buf(),
+    })
   }
 
-  fn load(path: &Path, snippets: &HashMap<String, Snippet>) -> Result<Snippet> {
-    let element = load_xml(path)?;
-    Snippet::new(element, snippets)
+  fn load(path: &Path, snippets: &HashMap<String, Snippet>, config: &SiteConfig) -> Result<Snippet> {
+    let element = load_xml(path, config)?;
+    Snippet::new(element, path, snippets)
   }
 
-  /// Loads and parses the snippets indicated by `snippet_paths` and returns them
-  /// in a HashMap using the snippet name as the key.
-  pub fn load_many(snippet_paths: &[PathBuf]) -> HashMap<String, Snippet> {
+  /// Loads and parses the snippets indicated by `snippet_roots` and returns them
+  /// in a HashMap using the snippet name as the key. Each root is a list of paths from
+  /// one source directory, such as a theme; a snippet from a later root overrides any
+  /// snippet of the same name from an earlier one.
+  pub fn load_many(snippet_roots: &[Vec<PathBuf>], config: &SiteConfig) -> HashMap<String, Snippet> {
     let mut snippets = HashMap::<String, Snippet>::new();
-    for snippet_path in snippet_paths {
-      info!("- Reading {}", snippet_path.display());
-      let snippet = match Snippet::load(snippet_path, &snippets) {
-        Ok(snippet) => snippet,
-        Err(err) => {
-          error!("-- {}", err);
-          continue;
+    for snippet_paths in snippet_roots {
+      let mut root_snippets = HashMap::<String, Snippet>::new();
+      for snippet_path in snippet_paths {
+        info!("- Reading {}", snippet_path.display());
+        let snippet = match Snippet::load(snippet_path, &root_snippets, config) {
+          Ok(snippet) => snippet,
+          Err(err) => {
+            error!("-- {}", err);
+            continue;
+          }
+        };
+        info!(
+          "-- Loaded snippet {} from {}",
+          snippet.name,
+          snippet_path.display(),
+        );
+        root_snippets.insert(snippet.name.clone(), snippet);
+      }
+      for (name, snippet) in root_snippets {
+        if let Some(overridden) = snippets.get(&name) {
+          debug!(
+            "-- Snippet {} from {} overrides the one from {}",
+            name,
+            snippet.path.display(),
+            overridden.path.display()
+          );
         }
-      };
-      info!(
-        "-- Loaded snippet {} from {}",
-        snippet.name,
-        snippet_path.display(),
-      );
-      snippets.insert(snippet.name.clone(), snippet);
+        snippets.insert(name, snippet);
+      }
     }
     snippets
   }
@@ -1,26 +1,24 @@
-use super::load_xml;
+use super::{load_xml, validate_root, SiteConfig};
+use crate::minidom::node::Node;
 use crate::minidom::Element;
-use crate::PathBuf;
+use std::path::PathBuf;
 use anyhow::{bail, Result};
-use log::{error, info};
+use itertools::Itertools;
+use log::{debug, error, info};
 use std::collections::HashMap;
 use std::path::Path;
 
 /// An element representing an HTML document root with fillable slot elements.
 pub struct Template {
   pub element: Element,
-  name: String,
+  pub name: String,
+  pub path: PathBuf,
 }
 
 impl Template {
-  fn new(element: Element, templates: &HashMap<String, Template>) -> Result<Template> {
-    let name = match element.attr("oeuvre-name") {
-      None => {
-        bail!("Template requires a root element with an oeuvre-name attribute");
-      }
-      Some(attr_value) => attr_value,
-    }
-    .to_string();
+  fn new(element: Element, path: &Path, templates: &HashMap<String, Template>) -> Result<Template> {
+    validate_root(&element, "template", None, "oeuvre-name")?;
+    let name = element.attr("oeuvre-name").unwrap().to_string();
 
     if templates.contains_key(&name) {
       bail!(
@@ -29,34 +27,244 @@ impl Template {
     );
     }
 
-    Ok(Template { element, name })
+    Ok(Template {
+      element,
+      name,
+      path: path.to_path_buf(),
+    })
   }
 
-  fn load(template_path: &Path, templates: &HashMap<String, Template>) -> Result<Template> {
-    let element = load_xml(template_path)?;
-    Template::new(element, templates)
+  fn load(template_path: &Path, templates: &HashMap<String, Template>, config: &SiteConfig) -> Result<Template> {
+    let element = load_xml(template_path, config)?;
+    Template::new(element, template_path, templates)
   }
 
-  /// Loads and parses the template indicated by `template_paths` and returns them
-  /// in a HashMap using the template name as the key.
-  pub fn load_many(template_paths: &[PathBuf]) -> HashMap<String, Template> {
+  /// Loads and parses the templates indicated by `template_roots` and returns them
+  /// in a HashMap using the template name as the key. Each root is a list of paths from
+  /// one source directory, such as a theme; a template from a later root overrides any
+  /// template of the same name from an earlier one. Templates that extend others are
+  /// resolved once every root is loaded.
+  pub fn load_many(template_roots: &[Vec<PathBuf>], config: &SiteConfig) -> HashMap<String, Template> {
     let mut templates = HashMap::<String, Template>::new();
-    for template_path in template_paths {
-      info!("- Reading {}", template_path.display());
-      let template = match Template::load(template_path, &templates) {
-        Ok(template) => template,
-        Err(err) => {
-          error!("-- {}", err);
-          continue;
+    for template_paths in template_roots {
+      let mut root_templates = HashMap::<String, Template>::new();
+      for template_path in template_paths {
+        info!("- Reading {}", template_path.display());
+        let template = match Template::load(template_path, &root_templates, config) {
+          Ok(template) => template,
+          Err(err) => {
+            error!("-- {}", err);
+            continue;
+          }
+        };
+        info!(
+          "-- Loaded template {} from {}",
+          template.name,
+          template_path.display(),
+        );
+        root_templates.insert(template.name.clone(), template);
+      }
+      for (name, template) in root_templates {
+        if let Some(overridden) = templates.get(&name) {
+          debug!(
+            "-- Template {} from {} overrides the one from {}",
+            name,
+            template.path.display(),
+            overridden.path.display()
+          );
         }
-      };
-      info!(
-        "-- Loaded template {} from {}",
-        template.name,
-        template_path.display(),
-      );
-      templates.insert(template.name.clone(), template);
+        templates.insert(name, template);
+      }
     }
+    Template::resolve_extends(&mut templates);
     templates
   }
+
+  /// Resolves each template whose root names a parent template in its oeuvre-extends
+  /// attribute. The template's element becomes a copy of its parent's, resolved in turn,
+  /// with the parent's slots filled by the top-level children of the template that name
+  /// them in their oeuvre-slot attributes; slots that the template does not fill are left
+  /// for pages to fill. The attributes of the template's root are added to those of its
+  /// parent's, replacing any of the same name. Templates whose parent does not exist or
+  /// whose oeuvre-extends attributes form a cycle, or lead to one, are removed.
+  fn resolve_extends(templates: &mut HashMap<String, Template>) {
+    let parents: HashMap<String, String> = templates
+      .iter()
+      .filter_map(|(name, template)| {
+        let parent_name = template.element.attr("oeuvre-extends")?;
+        Some((name.clone(), parent_name.to_string()))
+      })
+      .collect();
+    let mut pending: Vec<&String> = parents.keys().sorted().collect();
+    while !pending.is_empty() {
+      // A template is ready once its parent is resolved or is not waiting to be.
+      let (ready, waiting): (Vec<&String>, Vec<&String>) =
+        pending.iter().partition(|name| !pending.contains(&&parents[**name]));
+      if ready.is_empty() {
+        for name in waiting {
+          error!("-- {}", Template::cycle_message(name, &parents));
+          templates.remove(name);
+        }
+        break;
+      }
+
+      for name in ready {
+        let parent_name = &parents[name];
+        let mut element = match templates.get(parent_name) {
+          Some(parent) => parent.element.clone(),
+          None => {
+            error!(
+              "-- Template {} extends template {}, which does not exist",
+              name, parent_name
+            );
+            templates.remove(name);
+            continue;
+          }
+        };
+        let template = templates.get_mut(name).unwrap();
+        let mut slot_values = HashMap::<&str, &Element>::new();
+        for child in template.element.children() {
+          if let Some(slot_names) = child.attr("oeuvre-slot") {
+            for slot_name in slot_names.split(',') {
+              slot_values.insert(slot_name.trim(), child);
+            }
+          }
+        }
+        Template::fill_slots(&mut element, &slot_values);
+        for (attr_name, value) in template.element.attrs() {
+          if attr_name != "oeuvre-extends" {
+            element.set_attr(attr_name, value);
+          }
+        }
+        template.element = element;
+      }
+      pending = waiting;
+    }
+  }
+
+  /// Describes the oeuvre-extends cycle that the template `name` is part of, or else the
+  /// one that its ancestors form, by following `parents` from `name` until a template
+  /// repeats. The template's ancestors must form a cycle.
+  fn cycle_message(name: &str, parents: &HashMap<String, String>) -> String {
+    let mut chain = vec![name];
+    loop {
+      let parent_name = parents[*chain.last().unwrap()].as_str();
+      if let Some(start) = chain.iter().position(|ancestor| *ancestor == parent_name) {
+        let cycle = chain[start..].iter().chain([&parent_name]).join(" -> ");
+        return match start {
+          0 => format!("Template {} extends itself: {}", name, cycle),
+          _ => format!(
+            "Template {} extends template {}, which extends itself: {}",
+            name, chain[start], cycle
+          ),
+        };
+      }
+      chain.push(parent_name);
+    }
+  }
+
+  /// Replaces each oeuvre-slot element within `element` whose oeuvre-name is a key of
+  /// `slot_values` with its value, or with the contents of its value if it is an oeuvre-fragment.
+  fn fill_slots(element: &mut Element, slot_values: &HashMap<&str, &Element>) {
+    for node in element.take_nodes() {
+      match node {
+        Node::Element(child) if child.name() == "oeuvre-slot" => {
+          match child.attr("oeuvre-name").and_then(|name| slot_values.get(name)) {
+            Some(value) if value.name() == "oeuvre-fragment" => {
+              for value_node in value.nodes() {
+                element.append_node(value_node.clone());
+              }
+            }
+            Some(value) => element.append_node(Node::Element((*value).clone())),
+            None => element.append_node(Node::Element(child)),
+          }
+        }
+        Node::Element(mut child) => {
+          Template::fill_slots(&mut child, slot_values);
+          element.append_node(Node::Element(child));
+        }
+        node => element.append_node(node),
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::Template;
+  use crate::site::test_utils::TestSite;
+  use std::collections::HashMap;
+
+  #[test]
+  fn templates_extend_their_parents_through_several_levels() {
+    let site = TestSite::new();
+    site
+      .file(
+        "templates/base.xml",
+        concat!(
+          r#"<html oeuvre-name="base" lang="en"><head><title><oeuvre-slot oeuvre-name="title">Site</oeuvre-slot></title></head>"#,
+          r#"<body><oeuvre-slot oeuvre-name="body"/><footer><oeuvre-slot oeuvre-name="footer">Base footer</oeuvre-slot></footer></body></html>"#
+        ),
+      )
+      .file(
+        "templates/article.xml",
+        concat!(
+          r#"<html oeuvre-name="article" oeuvre-extends="base">"#,
+          r#"<article oeuvre-slot="body"><h1><oeuvre-slot oeuvre-name="title"/></h1><oeuvre-slot oeuvre-name="content"/></article>"#,
+          r#"</html>"#
+        ),
+      )
+      .file(
+        "templates/post.xml",
+        concat!(
+          r#"<html oeuvre-name="post" oeuvre-extends="article" class="post" lang="fr">"#,
+          r#"<oeuvre-fragment oeuvre-slot="footer">Post footer</oeuvre-fragment></html>"#
+        ),
+      )
+      .file(
+        "index.xml",
+        concat!(
+          r#"<oeuvre-page oeuvre-template="post" oeuvre-path="index.html">"#,
+          r#"<oeuvre-fragment oeuvre-slot="title">Hello</oeuvre-fragment><p oeuvre-slot="content">Hi</p></oeuvre-page>"#
+        ),
+      );
+    site.build("strict = true").unwrap();
+    assert_eq!(
+      site.read_output("index.html"),
+      concat!(
+        "<!DOCTYPE html>\r\n",
+        r#"<html class="post" lang="fr"><head><title>Hello</title></head>"#,
+        r#"<body><article><h1>Hello</h1><p>Hi</p></article><footer>Post footer</footer></body></html>"#
+      )
+    );
+  }
+
+  #[test]
+  fn templates_with_a_missing_or_cyclic_parent_are_not_loaded() {
+    let site = TestSite::new();
+    site
+      .file("templates/a.xml", r#"<html oeuvre-name="a" oeuvre-extends="b"/>"#)
+      .file("templates/b.xml", r#"<html oeuvre-name="b" oeuvre-extends="a"/>"#)
+      .file("templates/c.xml", r#"<html oeuvre-name="c" oeuvre-extends="a"/>"#)
+      .file("templates/d.xml", r#"<html oeuvre-name="d" oeuvre-extends="missing"/>"#)
+      .file("templates/e.xml", r#"<html oeuvre-name="e"/>"#);
+    let site = site.load("").unwrap();
+    assert_eq!(site.templates.keys().collect::<Vec<_>>(), ["e"]);
+  }
+
+  #[test]
+  fn extends_cycles_are_described_from_where_they_start() {
+    let parents: HashMap<String, String> = [("a", "b"), ("b", "a"), ("c", "d"), ("d", "a")]
+      .iter()
+      .map(|(name, parent)| (name.to_string(), parent.to_string()))
+      .collect();
+    assert_eq!(
+      Template::cycle_message("a", &parents),
+      "Template a extends itself: a -> b -> a"
+    );
+    assert_eq!(
+      Template::cycle_message("c", &parents),
+      "Template c extends template a, which extends itself: a -> b -> a"
+    );
+  }
 }
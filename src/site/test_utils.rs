@@ -0,0 +1,88 @@
+use super::{Site, SiteConfig};
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use tempfile::TempDir;
+
+/// Held for the lifetime of each `TestSite`, because loading a site changes the working directory.
+static CWD_LOCK: Mutex<()> = Mutex::new(());
+
+/// A throwaway site directory for tests that need to load and render a whole site.
+pub struct TestSite {
+  dir: TempDir,
+  _guard: MutexGuard<'static, ()>,
+}
+
+impl TestSite {
+  pub fn new() -> TestSite {
+    let guard = CWD_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+    TestSite {
+      dir: TempDir::new().unwrap(),
+      _guard: guard,
+    }
+  }
+
+  /// Writes `contents` to `path` within the site directory, creating parent directories as needed.
+  pub fn file(&self, path: &str, contents: &str) -> &TestSite {
+    let path = self.dir.path().join(path);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, contents).unwrap();
+    self
+  }
+
+  /// Returns the full path of `path` within the site directory.
+  pub fn path(&self, path: &str) -> PathBuf {
+    self.dir.path().join(path)
+  }
+
+  /// Loads the site using `config` as the contents of its config file.
+  pub fn load(&self, config: &str) -> Result<Site> {
+    self.load_config(toml::from_str::<SiteConfig>(config)?)
+  }
+
+  /// Loads the site using an already parsed `config`.
+  pub fn load_config(&self, config: SiteConfig) -> Result<Site> {
+    Site::load(config, self.dir.path())
+  }
+
+  /// Loads and renders the site using `config` as the contents of its config file.
+  pub fn build(&self, config: &str) -> Result<Site> {
+    let site = self.load(config)?;
+    site.render()?;
+    Ok(site)
+  }
+
+  /// Reads `path` from the default output directory.
+  pub fn read_output(&self, path: &str) -> String {
+    fs::read_to_string(self.dir.path().join("output").join(path)).unwrap()
+  }
+
+  /// Whether `path` exists within the default output directory.
+  pub fn output_exists(&self, path: &str) -> bool {
+    self.dir.path().join("output").join(path).exists()
+  }
+
+  /// Returns the contents of every file within the default output directory, keyed by
+  /// path relative to it.
+  pub fn read_output_tree(&self) -> BTreeMap<PathBuf, Vec<u8>> {
+    let output_dir = self.dir.path().join("output");
+    let mut files = BTreeMap::new();
+    read_tree(&output_dir, &output_dir, &mut files);
+    files
+  }
+}
+
+/// Adds the contents of every file within `dir` to `files`, keyed by path relative to `root`.
+fn read_tree(dir: &Path, root: &Path, files: &mut BTreeMap<PathBuf, Vec<u8>>) {
+  for entry in fs::read_dir(dir).unwrap() {
+    let path = entry.unwrap().path();
+    if path.is_dir() {
+      read_tree(&path, root, files);
+    } else {
+      let contents = fs::read(&path).unwrap();
+      files.insert(path.strip_prefix(root).unwrap().to_path_buf(), contents);
+    }
+  }
+}